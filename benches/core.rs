@@ -0,0 +1,173 @@
+use std::{env, hint::black_box, io::{self, Cursor}, time::Instant};
+
+use hexagony::{
+    Builder, Direction, Dispatch, Error, Op, Runtime, coords, examples,
+    memory::{HexMemory, Memory, SpillMemory, Stack, Tape},
+    wrap,
+};
+
+/// Number of timed samples of each benchmark, of which the median is reported.
+const SAMPLES: usize = 15;
+
+/// Settings from the command line.
+struct Options<'a> {
+    /// Only benchmarks whose names contain this are run.
+    filter: Option<&'a str>,
+    /// Whether each benchmark runs its operation once, untimed, to check that it works.
+    test: bool,
+}
+
+/// Runs `f`, which performs an operation `n` times, and prints the median time per operation,
+/// unless the filter is set and not part of `name`.
+fn measure(options: &Options<'_>, name: &str, n: u64, mut f: impl FnMut(u64)) {
+    if options.filter.is_some_and(|filter| !name.contains(filter)) {
+        return;
+    }
+    if options.test {
+        f(1);
+        println!("{:<40} ok", name);
+        return;
+    }
+    // Warm up caches and let memory grow to its working size
+    f(n);
+    let mut times: Vec<f64> = (0..SAMPLES).map(|_| {
+        let start = Instant::now();
+        f(n);
+        start.elapsed().as_secs_f64()
+    }).collect();
+    times.sort_by(f64::total_cmp);
+    let nanos = times[SAMPLES / 2] * 1e9 / n as f64;
+    println!("{:<40} {:>14.1} ns/op", name, nanos);
+}
+
+/// Walks the MP back and forth along a line, incrementing every edge it passes.
+fn walk(mem: &mut dyn Memory, n: u64) {
+    for i in 0..n {
+        match i / 64 % 2 {
+            0 => mem.move_right(),
+            _ => mem.move_left(),
+        }
+        *mem.get_mut() += 1;
+    }
+}
+
+/// Returns a runtime whose current edge has neighbours of `left` and `right` decimal digits.
+fn operands(left: usize, right: usize) -> Runtime<'static> {
+    let mut rt = Runtime::new(io::empty(), io::sink());
+    let digits = |rt: &mut Runtime<'_>, count: usize| {
+        for _ in 0..count / 19 {
+            rt.execute_digits(19, 1_234_567_890_123_456_789).unwrap();
+        }
+        if !count.is_multiple_of(19) {
+            rt.execute_digits((count % 19) as u32, 7).unwrap();
+        }
+    };
+    // `'` and `"` undo `{` and `}`, so both neighbours are set from the starting edge
+    rt.execute(Op::MPLeft).unwrap();
+    digits(&mut rt, left);
+    rt.execute(Op::MPBackLeft).unwrap();
+    rt.execute(Op::MPRight).unwrap();
+    digits(&mut rt, right);
+    rt.execute(Op::MPBackRight).unwrap();
+    rt
+}
+
+/// Runs `src` for exactly `ticks` ticks, for programs that never terminate.
+fn run_ticks(src: &str, ticks: u64) {
+    let result = Builder::new(src).output(io::sink()).max_ticks(ticks).build().unwrap().run();
+    assert!(matches!(result, Err(Error::TickLimitExceeded)));
+}
+
+fn memory(options: &Options<'_>) {
+    let mut hex = HexMemory::new();
+    measure(options, "memory/hex/set_get", 1_000_000, |n| {
+        for i in 0..n {
+            hex.set(i.into());
+            black_box(hex.get());
+        }
+    });
+    measure(options, "memory/hex/neighbours", 1_000_000, |n| {
+        for _ in 0..n {
+            black_box((hex.get_left(), hex.get_right()));
+        }
+    });
+    measure(options, "memory/hex/walk", 1_000_000, |n| walk(&mut hex, n));
+    measure(options, "memory/tape/walk", 1_000_000, |n| walk(&mut Tape::new(), n));
+    measure(options, "memory/stack/walk", 1_000_000, |n| walk(&mut Stack::new(), n));
+    let mut spill = SpillMemory::new(32).unwrap();
+    measure(options, "memory/spill/walk", 100_000, |n| walk(&mut spill, n));
+}
+
+fn wrapping(options: &Options<'_>) {
+    let size = 50;
+    let edge: Vec<_> = coords::ring(size - 1).flat_map(|coords| Direction::all().map(move |dir| (coords, dir))).collect();
+    measure(options, "wrapping/edge_cells", edge.len() as u64 * 10, |n| {
+        for (i, &(coords, dir)) in edge.iter().cycle().take(n as usize).enumerate() {
+            black_box(wrap(size, coords, dir, i % 2 == 0));
+        }
+    });
+    // IPs on small grids leave the grid every few ticks, and on big ones mostly move through the interior
+    measure(options, "wrapping/run_size_2", 1_000_000, |n| run_ticks(". .\n. . .\n. .", n));
+    let big = ".".repeat(3 * 100 * 99 + 1);
+    measure(options, "wrapping/run_size_100", 1_000_000, |n| run_ticks(&big, n));
+}
+
+fn arithmetic(options: &Options<'_>) {
+    let ops = [("add", Op::Add), ("sub", Op::Subtract), ("mul", Op::Multiply), ("div", Op::Divide), ("mod", Op::Modulo)];
+    for &(digits, n) in &[(10, 1_000_000), (1000, 100_000), (100_000, 100)] {
+        for &(name, op) in &ops {
+            let mut rt = operands(digits, digits / 2 + 1);
+            measure(options, &format!("arithmetic/{}/{}_digits", name, digits), n, |n| {
+                for _ in 0..n {
+                    rt.execute(op).unwrap();
+                }
+            });
+        }
+    }
+    let mut rt = operands(0, 0);
+    measure(options, "arithmetic/digits", 1_000_000, |n| {
+        for i in 0..n {
+            // Both neighbours are 0, so multiplying starts over every few digits to keep the value small
+            if i % 16 == 0 {
+                rt.execute(Op::Multiply).unwrap();
+            }
+            rt.execute(Op::Digit(7)).unwrap();
+        }
+    });
+}
+
+fn programs(options: &Options<'_>) {
+    for example in examples::examples() {
+        measure(options, &format!("programs/{}", example.name), 100, |n| {
+            for _ in 0..n {
+                black_box(example.run_sample(1_000_000).unwrap());
+            }
+        });
+    }
+    let primes = examples::find("primes").unwrap().source();
+    for &(name, dispatch) in &[("match", Dispatch::Match), ("table", Dispatch::Table)] {
+        measure(options, &format!("programs/primes_100003/{}", name), 5, |n| {
+            for _ in 0..n {
+                Builder::new(primes)
+                    .input(Cursor::new("100003"))
+                    .output(io::sink())
+                    .dispatch(dispatch)
+                    .build()
+                    .unwrap()
+                    .run()
+                    .unwrap();
+            }
+        });
+    }
+}
+
+fn main() {
+    // Cargo passes `--bench` to benchmarks but not to tests, and anything else is a filter on the names of the benchmarks
+    let args: Vec<String> = env::args().skip(1).collect();
+    let filter = args.iter().find(|arg| !arg.starts_with('-')).map(String::as_str);
+    let options = Options { filter, test: !args.iter().any(|arg| arg == "--bench") };
+    memory(&options);
+    wrapping(&options);
+    arithmetic(&options);
+    programs(&options);
+}
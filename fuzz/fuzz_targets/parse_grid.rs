@@ -0,0 +1,15 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use hexagony::Grid;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(src) = std::str::from_utf8(data) {
+        if let Ok(grid) = src.parse::<Grid>() {
+            // A parsed grid must print as source code that parses back to the same grid
+            let printed = grid.to_string();
+            let reparsed: Grid = printed.parse().expect("printed grid failed to parse");
+            assert_eq!(reparsed.to_string(), printed);
+        }
+    }
+});
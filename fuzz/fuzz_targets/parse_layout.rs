@@ -0,0 +1,19 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use hexagony::{Builder, SourceFormat};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(src) = std::str::from_utf8(data) {
+        for &format in &[SourceFormat::Padded, SourceFormat::DoubleSpaced] {
+            let hex = Builder::new(src).source_format(format).input(&b""[..]).output(std::io::sink()).build();
+            if let Ok(hex) = hex {
+                // A laid out grid must print as a padded layout that parses back to the same grid
+                let printed = hex.grid().to_string();
+                let reparsed = Builder::new(&printed).source_format(SourceFormat::Padded).output(std::io::sink()).build();
+                let reparsed = reparsed.expect("printed grid failed to parse");
+                assert_eq!(reparsed.grid().to_linear(), hex.grid().to_linear());
+            }
+        }
+    }
+});
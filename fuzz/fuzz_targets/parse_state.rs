@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use hexagony::state::SaveState;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(src) = std::str::from_utf8(data) {
+        if let Ok(state) = src.parse::<SaveState>() {
+            let _ = state.to_string().parse::<SaveState>();
+        }
+    }
+});
@@ -0,0 +1,233 @@
+use std::{collections::{HashMap, HashSet}, fmt};
+
+use crate::{
+    Builder, Error, IP, Wrapping,
+    compile::step,
+    coords::PointAxial,
+    direction::{Direction, redirect},
+    grid::{Grid, Op},
+};
+
+/// Default upper bound on the number of abstract states explored by `check_termination`.
+pub const DEFAULT_MAX_STATES: usize = 1_000_000;
+
+/// Result of a termination check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Termination {
+    /// Every possible execution reaches a terminate instruction or a runtime error
+    Terminates,
+    /// No possible execution ever terminates
+    Loops,
+    /// The analysis could not decide either way
+    Unknown,
+}
+
+/// Abstract interpreter state: the location of every IP and the index of the active IP.
+///
+/// Memory is not tracked, so every memory-dependent choice is treated as nondeterministic.
+type State = (Vec<(PointAxial, Direction)>, usize);
+
+/// Visitation status of a state during the depth-first search.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    OnStack,
+    Done,
+}
+
+/// Classifies whether a program terminates by exploring an abstraction of its state space
+/// that tracks the IPs but not the memory.
+///
+/// At most `max_states` abstract states are explored before giving up with `Termination::Unknown`.
+/// Use `Builder::check_termination` to check a program with other settings.
+pub fn check_termination(src: &str, max_states: usize) -> Result<Termination, Error> {
+    Builder::new(src).check_termination(max_states)
+}
+
+/// Classifies whether a program with the given wrapping rule and starting IPs terminates, as described by
+/// `check_termination`.
+pub(crate) fn termination(grid: &Grid, wrapping: Wrapping, starts: &[IP], max_states: usize) -> Termination {
+    let start: State = (starts.iter().map(|ip| (ip.coords, ip.dir)).collect(), 0);
+    if deterministic_loop(grid, wrapping, start.clone()) {
+        return Termination::Loops;
+    }
+    // Iterative DFS looking for reachable cycles and exits
+    let mut marks: HashMap<State, Mark> = HashMap::new();
+    let mut can_exit = can_exit_at(grid, &start);
+    let mut stack = vec![(start.clone(), successors(grid, wrapping, &start), 0)];
+    marks.insert(start, Mark::OnStack);
+    let mut cyclic = false;
+    while let Some((state, succs, i)) = stack.last_mut() {
+        if *i == succs.len() {
+            marks.insert(state.clone(), Mark::Done);
+            stack.pop();
+            continue;
+        }
+        let next = succs[*i].clone();
+        *i += 1;
+        match marks.get(&next) {
+            Some(Mark::OnStack) => cyclic = true,
+            Some(Mark::Done) => (),
+            None => {
+                if marks.len() >= max_states {
+                    return Termination::Unknown;
+                }
+                marks.insert(next.clone(), Mark::OnStack);
+                can_exit |= can_exit_at(grid, &next);
+                let succs = successors(grid, wrapping, &next);
+                stack.push((next, succs, 0));
+            }
+        }
+    }
+    match (cyclic, can_exit) {
+        (false, _) => Termination::Terminates,
+        (true, false) => Termination::Loops,
+        (true, true) => Termination::Unknown,
+    }
+}
+
+/// Returns whether execution can stop at the given state, either by terminating or with a runtime error.
+fn can_exit_at(grid: &Grid, state: &State) -> bool {
+    let (ips, ip_idx) = state;
+    matches!(grid.get(ips[*ip_idx].0).0, Op::Terminate | Op::Divide | Op::Modulo)
+}
+
+/// Returns whether the program's execution is independent of memory up until it repeats a state,
+/// without any opportunity to exit along the way.
+fn deterministic_loop(grid: &Grid, wrapping: Wrapping, start: State) -> bool {
+    let mut seen = HashSet::new();
+    let mut state = start;
+    loop {
+        if !seen.insert(state.clone()) {
+            return true;
+        }
+        if can_exit_at(grid, &state) {
+            return false;
+        }
+        let mut succs = successors(grid, wrapping, &state);
+        match succs.pop() {
+            Some(next) if succs.is_empty() => state = next,
+            _ => return false,
+        }
+    }
+}
+
+/// Returns all abstract states that could follow the given state.
+fn successors(grid: &Grid, wrapping: Wrapping, state: &State) -> Vec<State> {
+    let (ips, ip_idx) = state;
+    let (ip_idx, count) = (*ip_idx, ips.len());
+    let (coords, dir) = ips[ip_idx];
+    let op = grid.get(coords).0;
+    // Possible directions after executing the op
+    let dirs = match op {
+        Op::Terminate => return Vec::new(),
+        Op::Redir(redir) => dedup(vec![redirect(dir, redir, false), redirect(dir, redir, true)]),
+        _ => vec![dir],
+    };
+    // Possible coordinates after moving (twice for a jump)
+    let steps = if let Op::Jump = op { 2 } else { 1 };
+    let mut moves = Vec::new();
+    for dir in dirs {
+        let mut positions = vec![coords];
+        for _ in 0..steps {
+            positions = dedup(positions.into_iter().flat_map(|p| {
+                vec![wrapping.wrap(grid.size(), p, dir, false), wrapping.wrap(grid.size(), p, dir, true)]
+            }).collect());
+        }
+        moves.extend(positions.into_iter().map(|p| (p, dir)));
+    }
+    // Possible IPs to switch to
+    let next_idxs = match op {
+        Op::IPPrev => vec![(ip_idx + count - 1) % count],
+        Op::IPNext => vec![(ip_idx + 1) % count],
+        Op::IPSelect => (0..count).collect(),
+        _ => vec![ip_idx],
+    };
+    let mut succs = Vec::new();
+    for &ip in &moves {
+        for &next_idx in &next_idxs {
+            let mut next_ips = ips.clone();
+            next_ips[ip_idx] = ip;
+            succs.push((next_ips, next_idx));
+        }
+    }
+    succs
+}
+
+/// Returns every cell that an IP can reach, assuming every memory-dependent choice can go either way.
+///
+/// Only the first of the `starts` runs at first, and any other IP runs from its starting state once an IP that runs
+/// can switch to it.
+pub(crate) fn reachable_cells(grid: &Grid, wrapping: Wrapping, starts: &[IP]) -> HashSet<PointAxial> {
+    reachable_states(grid, wrapping, starts).into_iter().map(|(_, coords, _)| coords).collect()
+}
+
+/// Returns the index, position and direction of every IP state that `reachable_cells` explores, in the order
+/// they are first reached.
+pub(crate) fn reachable_states(grid: &Grid, wrapping: Wrapping, starts: &[IP]) -> Vec<(usize, PointAxial, Direction)> {
+    let count = starts.len();
+    let start = |idx: usize| (idx, starts[idx].coords, starts[idx].dir);
+    let mut seen = HashSet::new();
+    let mut order = Vec::new();
+    let mut stack = vec![start(0)];
+    while let Some(state) = stack.pop() {
+        if !seen.insert(state) {
+            continue;
+        }
+        order.push(state);
+        let (idx, coords, dir) = state;
+        let op = grid.get(coords).0;
+        match op {
+            Op::Terminate => continue,
+            Op::IPPrev => stack.push(start((idx + count - 1) % count)),
+            Op::IPNext => stack.push(start((idx + 1) % count)),
+            Op::IPSelect => stack.extend((0..count).map(start)),
+            _ => (),
+        }
+        for &positive in &[false, true] {
+            let (coords, dir) = step(grid, wrapping, (coords, dir), positive);
+            stack.push((idx, coords, dir));
+        }
+    }
+    order
+}
+
+/// Removes duplicate elements from a short vector while preserving order.
+fn dedup<T: PartialEq>(v: Vec<T>) -> Vec<T> {
+    let mut out = Vec::with_capacity(v.len());
+    for x in v {
+        if !out.contains(&x) {
+            out.push(x);
+        }
+    }
+    out
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Termination::Terminates => "definitely terminates",
+            Termination::Loops => "definitely loops",
+            Termination::Unknown => "unknown",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    #[test]
+    fn analysis_follows_the_configured_wrapping() {
+        // On a torus, IP 0 never leaves the top row to reach the `@`
+        assert_eq!(check_termination("..@", DEFAULT_MAX_STATES).unwrap(), Termination::Terminates);
+        let torus = || Builder::new("..@").wrapping(Wrapping::Torus);
+        assert_eq!(torus().check_termination(DEFAULT_MAX_STATES).unwrap(), Termination::Loops);
+        let (grid, broken) = torus().expand(3).unwrap();
+        assert!(broken.is_empty());
+        let run = Builder::from_grid(grid).wrapping(Wrapping::Torus).output(io::sink()).max_ticks(1000).build().unwrap().run();
+        assert!(matches!(run, Err(Error::TickLimitExceeded)));
+        assert_eq!(Builder::new("..@").ip_count(3).check_termination(DEFAULT_MAX_STATES).unwrap(), Termination::Terminates);
+    }
+}
@@ -0,0 +1,109 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use rug::Integer;
+
+use crate::{Error, IP, Op, coords::PointAxial, grid::{Grid, SourceFormat, layout}, json::json_string, observer::Observer};
+
+/// Number of times each cell was executed, which can still be read while the interpreter
+/// owns a clone of it as an observer.
+#[derive(Clone, Default)]
+pub struct CellCounts(Rc<RefCell<HashMap<PointAxial, u64>>>);
+
+impl CellCounts {
+    /// Returns the number of times the cell at the given coordinates was executed.
+    pub fn get(&self, coords: PointAxial) -> u64 {
+        self.0.borrow().get(&coords).copied().unwrap_or(0)
+    }
+}
+
+impl Observer for CellCounts {
+    fn on_tick(&mut self, _tick: &Integer, _ip_idx: usize, ip: IP, _op: Op) -> Result<(), Error> {
+        *self.0.borrow_mut().entry(ip.coords).or_insert(0) += 1;
+        Ok(())
+    }
+
+    fn watches_memory(&self) -> bool {
+        false
+    }
+}
+
+/// Returns the index of the character in `src` that each cell was parsed from in a format, in row-major
+/// order, or `None` for the no-ops that pad the source to a full hexagon or fill its blank cells.
+pub fn source_indices(src: &str, format: SourceFormat, grid: &Grid) -> Vec<Option<usize>> {
+    let format = match format {
+        SourceFormat::Auto => SourceFormat::detect(src),
+        format => format,
+    };
+    if let Ok((_, cells)) = layout(src, format) {
+        let indices: HashMap<_, _> = cells.into_iter().map(|(row, col, _, _, index)| ((row, col), index)).collect();
+        return grid.cells().map(|(coords, _, _)| indices.get(&grid.axial_to_index(coords)).copied()).collect();
+    }
+    let mut indices: Vec<_> = src.chars()
+        .enumerate()
+        .filter(|&(_, c)| !c.is_whitespace() && c != '`')
+        .map(|(i, _)| Some(i))
+        .collect();
+    indices.resize(grid.cells().count(), None);
+    indices
+}
+
+/// Describes every cell of a grid parsed from `src` as JSON, for editors that draw overlays on a program.
+///
+/// Each cell has its axial coordinates, the index of the character it came from in `src` written in
+/// `format` (see `source_indices`), its command and `Category`, whether it has a debug flag, and how
+/// many times it was executed if `counts` is given.
+pub fn to_json(src: &str, format: SourceFormat, grid: &Grid, counts: Option<&CellCounts>) -> String {
+    let cells: Vec<_> = grid.cells()
+        .zip(source_indices(src, format, grid))
+        .map(|((coords, op, debug), index)| {
+            let mut cell = format!(
+                r#"{{"q": {}, "r": {}, "index": {}, "op": {}, "category": "{}", "debug": {}"#,
+                coords.0,
+                coords.1,
+                index.map_or("null".to_string(), |i| i.to_string()),
+                json_string(&op.to_string()),
+                op.category().name(),
+                debug,
+            );
+            if let Some(counts) = counts {
+                cell += &format!(r#", "count": {}"#, counts.get(coords));
+            }
+            cell + "}"
+        })
+        .collect();
+    format!("{{\"size\": {}, \"cells\": [\n  {}\n]}}\n", grid.size(), cells.join(",\n  "))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::{Builder, DEFAULT_MAX_SIZE, Dialect};
+
+    #[test]
+    fn source_indices_skip_blank_cells() {
+        let src = " H ;\n;   i\n  `@";
+        let grid = Grid::parse_with(src, SourceFormat::Auto, Dialect::Standard, DEFAULT_MAX_SIZE, |_| false).unwrap();
+        let expected = [Some(1), Some(3), Some(5), None, Some(9), None, Some(14)];
+        assert_eq!(source_indices(src, SourceFormat::Auto, &grid), expected);
+        assert_eq!(source_indices(src, SourceFormat::Padded, &grid), expected);
+        let src = "H;\n;i`@";
+        let grid: Grid = src.parse().unwrap();
+        let expected = [Some(0), Some(1), Some(3), Some(4), Some(6), None, None];
+        assert_eq!(source_indices(src, SourceFormat::Linear, &grid), expected);
+    }
+
+    #[test]
+    fn annotations_list_every_cell_with_its_count() {
+        let src = "1`!\n)!@";
+        let grid: Grid = src.parse().unwrap();
+        let counts = CellCounts::default();
+        Builder::new(src).output(io::sink()).observer(counts.clone()).build().unwrap().run().unwrap();
+        let json = to_json(src, SourceFormat::Linear, &grid, Some(&counts));
+        assert!(json.starts_with("{\"size\": 2, \"cells\": [\n"));
+        let debug = r#"{"q": 1, "r": -1, "index": 2, "op": "!", "category": "io", "debug": true, "count": 1},"#;
+        let padding = r#"{"q": 0, "r": 1, "index": null, "op": ".", "category": "nop", "debug": false, "count": 0}"#;
+        assert!(json.contains(debug) && json.contains(padding), "{}", json);
+        assert!(!to_json(src, SourceFormat::Linear, &grid, None).contains("count"));
+    }
+}
@@ -0,0 +1,108 @@
+use std::{fmt, io::{self, Cursor}, time::{Duration, Instant}};
+use rug::Integer;
+
+use crate::{Builder, Error, Op, annotate::CellCounts, grid::Grid};
+
+/// Timings of a program run repeatedly with the same input.
+pub struct BenchResult {
+    /// Number of ticks in each run
+    pub ticks: Integer,
+    /// Number of ticks in each run that executed a command other than a no-op
+    pub instructions: u64,
+    /// Wall time of each run, from fastest to slowest
+    pub times: Vec<Duration>,
+}
+
+/// Runs a program `iterations` times with `input`, discarding its output.
+///
+/// `configure` is applied to the builder of every run, such as to choose a memory backend.
+/// A first, untimed run counts the executed instructions and checks that the program terminates,
+/// so it is worth giving programs that might not a tick limit.
+pub fn bench(
+    src: &str,
+    input: &[u8],
+    iterations: usize,
+    configure: impl Fn(Builder<'static>) -> Builder<'static>,
+) -> Result<BenchResult, Error> {
+    let grid: Grid = src.parse()?;
+    let run = |counts: Option<CellCounts>| {
+        let mut builder = configure(Builder::from_grid(grid.clone()).input(Cursor::new(input.to_vec())).output(io::sink()));
+        if let Some(counts) = counts {
+            builder = builder.observer(counts);
+        }
+        let mut hex = builder.build()?;
+        hex.run()?;
+        Ok::<_, Error>(hex.stats().ticks)
+    };
+    let counts = CellCounts::default();
+    let ticks = run(Some(counts.clone()))?;
+    let instructions = grid.cells().filter(|&(_, op, _)| op != Op::Nop).map(|(coords, _, _)| counts.get(coords)).sum();
+    let mut times = Vec::with_capacity(iterations);
+    for _ in 0..iterations {
+        let start = Instant::now();
+        run(None)?;
+        times.push(start.elapsed());
+    }
+    times.sort();
+    Ok(BenchResult { ticks, instructions, times })
+}
+
+impl BenchResult {
+    /// Returns the median wall time of a run.
+    pub fn median(&self) -> Duration {
+        match self.times.len() {
+            0 => Duration::ZERO,
+            n if n % 2 == 1 => self.times[n / 2],
+            n => (self.times[n / 2 - 1] + self.times[n / 2]) / 2,
+        }
+    }
+
+    /// Returns the mean wall time of a run.
+    pub fn mean(&self) -> Duration {
+        match self.times.len() {
+            0 => Duration::ZERO,
+            n => self.times.iter().sum::<Duration>() / n as u32,
+        }
+    }
+
+    /// Returns the number of ticks executed per second, based on the median time.
+    pub fn ticks_per_sec(&self) -> f64 {
+        self.ticks.to_f64() / self.median().as_secs_f64()
+    }
+
+    /// Returns the number of instructions executed per second, based on the median time.
+    pub fn instructions_per_sec(&self) -> f64 {
+        self.instructions as f64 / self.median().as_secs_f64()
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (min, max) = match (self.times.first(), self.times.last()) {
+            (Some(&min), Some(&max)) => (min, max),
+            _ => return writeln!(f, "No runs"),
+        };
+        writeln!(f, "Runs:           {}", self.times.len())?;
+        writeln!(f, "Ticks:          {} ({} instructions)", self.ticks, self.instructions)?;
+        writeln!(f, "Time:           min {:?}, median {:?}, mean {:?}, max {:?}", min, self.median(), self.mean(), max)?;
+        writeln!(f, "Ticks/s:        {:.0}", self.ticks_per_sec())?;
+        writeln!(f, "Instructions/s: {:.0}", self.instructions_per_sec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn benchmarks_count_ticks_and_instructions() {
+        let result = bench("1.!@", b"", 5, |builder| builder).unwrap();
+        assert_eq!((result.ticks.to_u64(), result.instructions, result.times.len()), (Some(3), 3, 5));
+        assert!(result.times.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(matches!(bench("1.!@", b"", 5, |builder| builder.max_ticks(2)), Err(Error::TickLimitExceeded)));
+        let times = [1, 2, 3, 10].iter().map(|&ms| Duration::from_millis(ms)).collect();
+        let result = BenchResult { ticks: Integer::from(100), instructions: 50, times };
+        assert_eq!((result.median(), result.mean()), (Duration::from_micros(2500), Duration::from_millis(4)));
+        assert_eq!((result.ticks_per_sec(), result.instructions_per_sec()), (40_000.0, 20_000.0));
+    }
+}
@@ -0,0 +1,677 @@
+use std::{collections::HashMap, io::{self, Read, Write}, path::PathBuf, sync::atomic::AtomicBool, time::{Duration, Instant}};
+use rug::Integer;
+
+use crate::{AbortHandle, Termination, analysis, expand::{self, BrokenPath}, trim::{self, Trimmed}, mirror::{self, Mirrored}, Axis, CostModel, compile::{self, Program}, counter::Counter, Dispatch, Error, Hexagony, IP, IntEof, Runtime, ValueType, Wrapping, binary_stdout, handler_table, custom::CustomOp, observer::Observer, grid::{DEFAULT_MAX_SIZE, Dialect, Grid, Op, SourceFormat}, initial_ips, memory::{AnyMemory, MemoryModel, SpillMemory}, perimeter_ips, spec::SpecVersion, fingerprint::Fnv, runtime::SoftLimit, stats::IPCounters, state::{Checkpoints, SaveState}, trace};
+
+/// A builder for configuring a Hexagony interpreter before running it.
+///
+/// By default, the program reads from STDIN and writes raw bytes to STDOUT.
+pub struct Builder<'a> {
+    src: String,
+    grid: Option<Grid>,
+    debug_level: u8,
+    explain: bool,
+    tick_range: (u64, Option<u64>),
+    max_ticks: Option<u64>,
+    cost_model: CostModel,
+    dispatch: Dispatch,
+    deadline: Option<(Instant, u64)>,
+    max_output: Option<u64>,
+    memory_limit: Option<usize>,
+    soft_memory_limit: Option<SoftLimit<'a>>,
+    utf8: bool,
+    byte_eof: Option<i64>,
+    int_eof: Option<IntEof>,
+    echo_input: bool,
+    slurp_input: bool,
+    input: Box<dyn Read + 'a>,
+    output: Box<dyn Write + 'a>,
+    input_log: Option<Box<dyn Write + 'a>>,
+    output_log: Option<Box<dyn Write + 'a>>,
+    state: Option<SaveState>,
+    checkpoints: Option<Checkpoints>,
+    dump_flag: Option<&'a AtomicBool>,
+    abort: Option<AbortHandle>,
+    fingerprint: bool,
+    trace: Option<Box<dyn Write + 'a>>,
+    delay: Option<Duration>,
+    follow_ip: Option<usize>,
+    custom_ops: HashMap<char, Box<dyn CustomOp + 'a>>,
+    dialect: Dialect,
+    source_format: SourceFormat,
+    max_size: usize,
+    random_seed: Option<u64>,
+    env: Vec<(String, String)>,
+    process_env: bool,
+    wrapping: Wrapping,
+    ip_count: Option<usize>,
+    ip_starts: Option<Box<dyn Fn(usize) -> Vec<IP> + 'a>>,
+    memory_model: MemoryModel,
+    spill_memory: Option<usize>,
+    spec_version: SpecVersion,
+    value_type: ValueType,
+    observers: Vec<Box<dyn Observer + 'a>>,
+}
+
+impl<'a> Builder<'a> {
+    /// Creates a new `Builder` for the given source code with default settings.
+    pub fn new(src: &str) -> Builder<'a> {
+        Builder {
+            src: src.to_string(),
+            grid: None,
+            debug_level: 0,
+            explain: false,
+            tick_range: (0, None),
+            max_ticks: None,
+            cost_model: CostModel::Uniform,
+            dispatch: Dispatch::Match,
+            deadline: None,
+            max_output: None,
+            memory_limit: None,
+            soft_memory_limit: None,
+            utf8: false,
+            byte_eof: None,
+            int_eof: None,
+            echo_input: false,
+            slurp_input: false,
+            input: Box::new(io::stdin()),
+            output: binary_stdout(),
+            input_log: None,
+            output_log: None,
+            state: None,
+            checkpoints: None,
+            dump_flag: None,
+            abort: None,
+            fingerprint: false,
+            trace: None,
+            delay: None,
+            follow_ip: None,
+            custom_ops: HashMap::new(),
+            dialect: Dialect::Standard,
+            source_format: SourceFormat::Linear,
+            max_size: DEFAULT_MAX_SIZE,
+            random_seed: None,
+            env: Vec::new(),
+            process_env: false,
+            wrapping: Wrapping::Standard,
+            ip_count: None,
+            ip_starts: None,
+            memory_model: MemoryModel::Hex,
+            spill_memory: None,
+            spec_version: SpecVersion::LATEST,
+            value_type: ValueType::Unbounded,
+            observers: Vec::new(),
+        }
+    }
+
+    /// Creates a new `Builder` for an already parsed grid.
+    pub(crate) fn from_grid(grid: Grid) -> Builder<'a> {
+        Builder { grid: Some(grid), ..Builder::new("") }
+    }
+
+    /// Creates a new `Builder` that resumes a program from a save state.
+    ///
+    /// The program should be given the same input as the run that saved the state,
+    /// since the bytes it had already read are skipped when it is built. The dialect, wrapping
+    /// rule, value type, memory model and IPs are restored from the state.
+    pub fn resume(state: SaveState) -> Builder<'a> {
+        Builder {
+            grid: Some(state.grid.clone()),
+            dialect: state.dialect,
+            wrapping: state.wrapping,
+            value_type: state.value_type,
+            state: Some(state),
+            ..Builder::new("")
+        }
+    }
+
+    /// Sets the debug level.
+    ///
+    /// If the `debug_level` is 1, debug info will be printed when an instruction
+    /// with a debug flag is executed.
+    ///
+    /// If the `debug_level` is 2, debug info will be printed when executing any instruction.
+    pub fn debug_level(mut self, debug_level: u8) -> Self {
+        self.debug_level = debug_level;
+        self
+    }
+
+    /// Enables printing a plain-English explanation of every executed instruction.
+    pub fn explain(mut self, explain: bool) -> Self {
+        self.explain = explain;
+        self
+    }
+
+    /// Limits debug info and explanations to ticks from `start` up to (but not including) `end`.
+    pub fn tick_range(mut self, start: u64, end: Option<u64>) -> Self {
+        self.tick_range = (start, end);
+        self
+    }
+
+    /// Limits debug info and explanations to ticks where the IP with index `idx` is active,
+    /// with a marker whenever it hands control to another IP and when it gets it back.
+    pub fn follow_ip(mut self, idx: usize) -> Self {
+        self.follow_ip = Some(idx);
+        self
+    }
+
+    /// Aborts the program with `Error::DeadlineExceeded` if it's still running at `deadline`.
+    ///
+    /// The clock is only checked every `check_every` ticks, so the program may overrun the deadline
+    /// by that many ticks, but no watchdog thread is needed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `check_every` is 0.
+    pub fn deadline(mut self, deadline: Instant, check_every: u64) -> Self {
+        assert!(check_every > 0, "deadline must be checked at least every tick");
+        self.deadline = Some((deadline, check_every));
+        self
+    }
+
+    /// Sets how many ticks of the `max_ticks` budget each instruction costs, which is one with the
+    /// default `CostModel::Uniform`.
+    pub fn cost_model(mut self, cost_model: CostModel) -> Self {
+        self.cost_model = cost_model;
+        self
+    }
+
+    /// Sets how the interpreter finds the code for each command, which is `Dispatch::Match` by default.
+    ///
+    /// Both give the same results; `bench` can tell which one is faster for a program.
+    pub fn dispatch(mut self, dispatch: Dispatch) -> Self {
+        self.dispatch = dispatch;
+        self
+    }
+
+    /// Aborts the program with `Error::TickLimitExceeded` once it has run for `max_ticks` ticks.
+    pub fn max_ticks(mut self, max_ticks: u64) -> Self {
+        self.max_ticks = Some(max_ticks);
+        self
+    }
+
+    /// Aborts the program with `Error::OutputLimitExceeded` once it tries to write more than `max_output` bytes.
+    pub fn max_output(mut self, max_output: u64) -> Self {
+        self.max_output = Some(max_output);
+        self
+    }
+
+    /// Aborts the program with `Error::MemoryLimitExceeded` once its memory uses more than about
+    /// `bytes` bytes, counting every stored cell and the digits of its value.
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Calls `callback` with the number of bytes memory uses whenever that rises above about `bytes` bytes,
+    /// letting the program keep running.
+    pub fn soft_memory_limit(mut self, bytes: usize, callback: impl FnMut(usize) + 'a) -> Self {
+        self.soft_memory_limit = Some((bytes, Box::new(callback)));
+        self
+    }
+
+    /// Makes `,` read a Unicode code point and `;` write a code point, both encoded as UTF-8.
+    ///
+    /// Invalid input decodes to U+FFFD, and values that aren't valid code points after taking
+    /// them modulo 0x110000 are written as U+FFFD.
+    pub fn utf8(mut self, utf8: bool) -> Self {
+        self.utf8 = utf8;
+        self
+    }
+
+    /// Sets the value that `,` reads at EOF, which is -1 by default.
+    ///
+    /// Other interpreters use 0 or 256.
+    pub fn byte_eof(mut self, value: i64) -> Self {
+        self.byte_eof = Some(value);
+        self
+    }
+
+    /// Sets what `?` does when it reaches EOF before finding a number, which is `IntEof::Zero` by default.
+    pub fn int_eof(mut self, int_eof: IntEof) -> Self {
+        self.int_eof = Some(int_eof);
+        self
+    }
+
+    /// Echoes the input consumed by each `,` and `?` to STDERR, marked with the op that read it.
+    pub fn echo_input(mut self, echo_input: bool) -> Self {
+        self.echo_input = echo_input;
+        self
+    }
+
+    /// Sets whether to read all of the input when the interpreter is built, so that commands read it
+    /// from memory instead of waiting on the source (off by default).
+    ///
+    /// An error reading the input is returned by `build` instead of by the command that reads past it.
+    /// Input that never ends, such as an interactive terminal, makes `build` block forever.
+    pub fn slurp_input(mut self, slurp_input: bool) -> Self {
+        self.slurp_input = slurp_input;
+        self
+    }
+
+    /// Sets the source of the program's input.
+    pub fn input(mut self, input: impl Read + 'a) -> Self {
+        self.input = Box::new(input);
+        self
+    }
+
+    /// Sets the destination of the program's output.
+    pub fn output(mut self, output: impl Write + 'a) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// Records every byte the program reads to `log`, along with the tick it was read on.
+    ///
+    /// The input can be recovered from the log with `record::replay_input`.
+    pub fn record_input(mut self, log: impl Write + 'a) -> Self {
+        self.input_log = Some(Box::new(log));
+        self
+    }
+
+    /// Writes a save state to a file in `dir` every `every` ticks, deleting older files
+    /// so that at most `keep` of them remain.
+    ///
+    /// Each file is named after the tick it was saved on, like `tick-1000.state`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `every` or `keep` is 0.
+    pub fn checkpoints(mut self, every: u64, dir: impl Into<PathBuf>, keep: usize) -> Self {
+        assert!(every > 0 && keep > 0, "checkpoint interval and count must be positive");
+        self.checkpoints = Some(Checkpoints::new(every, dir.into(), keep));
+        self
+    }
+
+    /// Prints the state of the program to STDERR, like `Hexagony::dump`, whenever `flag` is set,
+    /// then clears it and keeps running.
+    ///
+    /// The flag is only checked every 1024 ticks, so the dump may come a few ticks after it's set.
+    pub fn dump_when(mut self, flag: &'a AtomicBool) -> Self {
+        self.dump_flag = Some(flag);
+        self
+    }
+
+    /// Stops the program with `Error::Aborted` at the start of the first tick after `handle` is triggered.
+    pub fn abort_handle(mut self, handle: AbortHandle) -> Self {
+        self.abort = Some(handle);
+        self
+    }
+
+    /// Records every tick and all consumed input to `trace` in a binary format,
+    /// which can be checked against a new run with `trace::replay`.
+    ///
+    /// Traces of resumed programs can't be replayed, since they don't start at the first tick.
+    pub fn record_trace(mut self, trace: impl Write + 'a) -> Self {
+        self.trace = Some(Box::new(trace));
+        self
+    }
+
+    /// Enables hashing the execution for `Hexagony::fingerprint`.
+    pub fn fingerprint(mut self, fingerprint: bool) -> Self {
+        self.fingerprint = fingerprint;
+        self
+    }
+
+    /// Sleeps for `delay` after every tick, flushing the output first, to run a program in slow motion.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Logs every byte the program writes to `log`, along with the tick, IP and cell that wrote it.
+    pub fn log_output(mut self, log: impl Write + 'a) -> Self {
+        self.output_log = Some(Box::new(log));
+        self
+    }
+
+    /// Sets the commands the source code may use, which are only the standard ones by default.
+    ///
+    /// Extension commands are rejected with `Error::ExtensionOp` in `Dialect::Standard`.
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Sets the shape the source code is written in, which is `SourceFormat::Linear` by default.
+    /// `SourceFormat::Auto` detects the shape from the code.
+    ///
+    /// Code that isn't laid out in a given hexagon format is rejected with `Error::ParseError`.
+    pub fn source_format(mut self, format: SourceFormat) -> Self {
+        self.source_format = format;
+        self
+    }
+
+    /// Rejects source code that needs a grid with a side length over `max_size` with
+    /// `Error::ProgramTooLarge`, instead of the default `DEFAULT_MAX_SIZE`. Grids of resumed save
+    /// states are checked the same way.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sets the revision of the specification to run the program under, which is `SpecVersion::LATEST` by default.
+    ///
+    /// The revision decides the defaults for settings such as `byte_eof`. Every revision so far has the
+    /// same commands, so a revision that adds some will need to reject them in older ones.
+    pub fn spec_version(mut self, version: SpecVersion) -> Self {
+        self.spec_version = version;
+        self
+    }
+
+    /// Seeds the random numbers of `Op::Random`, to make runs that use it reproducible.
+    pub fn random_seed(mut self, seed: u64) -> Self {
+        self.random_seed = Some(seed);
+        self
+    }
+
+    /// Sets the rule for where IPs go when they leave the grid, which is `Wrapping::Standard` by default.
+    ///
+    /// Compiled programs and the analyses run through the builder, such as `check_termination`, follow it too.
+    pub fn wrapping(mut self, wrapping: Wrapping) -> Self {
+        self.wrapping = wrapping;
+        self
+    }
+
+    /// Runs the program with `count` IPs instead of six, spread evenly around the edge of the grid
+    /// as described by `perimeter_ips`.
+    ///
+    /// `[` and `]` cycle through all of them, and `#` selects the current edge modulo `count`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` is 0 or more than 256.
+    pub fn ip_count(mut self, count: usize) -> Self {
+        assert!((1..=256).contains(&count), "IP count must be from 1 to 256");
+        self.ip_count = Some(count);
+        self
+    }
+
+    /// Runs the program with the IPs that `starts` returns for the side length of the grid, instead of
+    /// the six of `initial_ips`, so that a dialect can choose how many IPs there are and where they start.
+    ///
+    /// This takes precedence over `ip_count`. Building fails with `Error::IncompatibleSettings` if there
+    /// are no IPs or more than 256, if one starts outside the grid, or if the run is traced, since traces
+    /// only record the number of IPs.
+    pub fn ip_starts(mut self, starts: impl Fn(usize) -> Vec<IP> + 'a) -> Self {
+        self.ip_starts = Some(Box::new(starts));
+        self
+    }
+
+    /// Sets the layout of memory, which is the standard `MemoryModel::Hex` by default.
+    ///
+    /// Every command keeps its meaning in terms of the current cell and its neighbours,
+    /// so the same program can be compared across models.
+    pub fn memory_model(mut self, model: MemoryModel) -> Self {
+        self.memory_model = model;
+        self
+    }
+
+    /// Sets the range of values memory edges can hold, which is `ValueType::Unbounded` by default.
+    ///
+    /// With `ValueType::Byte`, arithmetic wraps around like the cells of brainfuck.
+    pub fn value_type(mut self, value_type: ValueType) -> Self {
+        self.value_type = value_type;
+        self
+    }
+
+    /// Keeps only about `hot_edges` memory edges in RAM, spilling the least recently used ones to a
+    /// temporary file as described by `SpillMemory`.
+    ///
+    /// `build` fails with `Error::IncompatibleSettings` for the other memory models and for programs
+    /// resumed from a save state.
+    pub fn spill_memory(mut self, hot_edges: usize) -> Self {
+        self.spill_memory = Some(hot_edges);
+        self
+    }
+
+    /// Sets a variable that the extension command `Op::Env` can look up, which takes precedence
+    /// over the environment variables of the process.
+    pub fn env_var(mut self, name: &str, value: &str) -> Self {
+        self.env.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Lets the extension command `Op::Env` look up the environment variables of the process,
+    /// which it can't by default.
+    pub fn process_env(mut self, process_env: bool) -> Self {
+        self.process_env = process_env;
+        self
+    }
+
+    /// Makes `c` a command that runs `op`, which can read and write the current memory edge and I/O.
+    ///
+    /// Custom commands are only recognized in source code given to `Builder::new`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `c` is already a command (including extension commands), whitespace or the debug flag `` ` ``.
+    pub fn custom_op(mut self, c: char, op: impl CustomOp + 'a) -> Self {
+        let taken = Op::from_char(c).is_some() || Op::from_extension_char(c).is_some();
+        assert!(!taken && !c.is_whitespace() && c != '`', "`{}` can't be a custom command", c);
+        self.custom_ops.insert(c, Box::new(op));
+        self
+    }
+
+    /// Adds an observer that is called back as the program runs, after any observers added before it.
+    pub fn observer(mut self, observer: impl Observer + 'a) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    /// Parses the source code with the configured dialect and format.
+    fn parse(&mut self) -> Result<Grid, Error> {
+        match self.grid.take() {
+            Some(grid) if grid.size() > self.max_size => Err(Error::ProgramTooLarge { size: grid.size(), limit: self.max_size }),
+            Some(grid) => Ok(grid),
+            None => Grid::parse_with(&self.src, self.source_format, self.dialect, self.max_size, |c| self.custom_ops.contains_key(&c)),
+        }
+    }
+
+    /// Parses the source code and compiles it ahead of time into a function that runs it on some input
+    /// and returns its output, as described by `compile::compile`.
+    ///
+    /// The compiled program follows the configured dialect, source format, wrapping rule and IP count.
+    /// Settings that only the interpreter supports, such as memory models or limits, make this fail with
+    /// `Error::IncompatibleSettings`; the input, output and debugging settings are ignored.
+    pub fn compile(mut self) -> Result<impl Fn(&[u8]) -> Result<Vec<u8>, Error>, Error> {
+        Ok(compile::runner(self.program()?))
+    }
+
+    /// Parses the source code and generates a Rust function with the given name that runs it, as described
+    /// by `compile::to_rust`, with the same settings as `compile`.
+    pub fn to_rust(mut self, name: &str) -> Result<String, Error> {
+        Ok(compile::generate(&self.program()?, name))
+    }
+
+    /// Parses the source code and classifies whether it terminates, as described by `check_termination`,
+    /// following the configured dialect, source format, wrapping rule and IPs.
+    pub fn check_termination(mut self, max_states: usize) -> Result<Termination, Error> {
+        let grid = self.parse()?;
+        let starts = self.start_ips(grid.size())?;
+        Ok(analysis::termination(&grid, self.wrapping, &starts, max_states))
+    }
+
+    /// Parses the source code and pads it to a hexagon of side length `size`, as described by `expand::expand`,
+    /// keeping the paths that IPs follow under the configured wrapping rule from the configured starting IPs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is smaller than the side length of the program.
+    pub fn expand(mut self, size: usize) -> Result<(Grid, Vec<BrokenPath>), Error> {
+        let grid = self.parse()?;
+        let (starts, new_starts) = (self.start_ips(grid.size())?, self.start_ips(size)?);
+        if starts.len() != new_starts.len() {
+            return Err(Error::IncompatibleSettings("the expanded program must start with as many IPs".to_string()));
+        }
+        Ok(expand::expand_grid(&grid, size, self.wrapping, &starts, &new_starts))
+    }
+
+    /// Parses the source code and removes its trailing no-ops, as described by `trim::trim`, checking which
+    /// removed cells IPs can reach under the configured wrapping rule from the configured starting IPs.
+    pub fn trim(mut self) -> Result<Trimmed, Error> {
+        let grid = self.parse()?;
+        let starts = self.start_ips(grid.size())?;
+        trim::trim_grid(grid, self.wrapping, &starts)
+    }
+
+    /// Parses the source code and reflects it across an axis, as described by `mirror::mirror`, checking the paths
+    /// that IPs follow under the configured wrapping rule from the configured starting IPs.
+    pub fn mirror(mut self, axis: Axis) -> Result<Mirrored, Error> {
+        let grid = self.parse()?;
+        let starts = self.start_ips(grid.size())?;
+        mirror::mirror_grid(&grid, self.wrapping, &starts, axis)
+    }
+
+    /// Compiles the program for `compile` and `to_rust`, rejecting the settings they can't honour.
+    fn program(&mut self) -> Result<Program, Error> {
+        let unsupported = [
+            ("UTF-8 output", self.utf8),
+            ("EOF values", self.byte_eof.is_some() || self.int_eof.is_some()),
+            ("spec versions", self.spec_version != SpecVersion::LATEST),
+            ("output limits", self.max_output.is_some()),
+            ("memory limits", self.memory_limit.is_some() || self.soft_memory_limit.is_some()),
+            ("memory models", self.memory_model != MemoryModel::Hex || self.spill_memory.is_some()),
+            ("value types", self.value_type != ValueType::Unbounded),
+            ("custom commands", !self.custom_ops.is_empty()),
+            ("environment variables", !self.env.is_empty() || self.process_env),
+            ("random seeds", self.random_seed.is_some()),
+            ("save states", self.state.is_some()),
+        ];
+        if let Some((name, _)) = unsupported.iter().find(|(_, set)| *set) {
+            return Err(Error::IncompatibleSettings(format!("compiled programs don't support {}", name)));
+        }
+        let grid = self.parse()?;
+        let ips = self.start_ips(grid.size())?;
+        Ok(Program::new(&grid, self.wrapping, &ips))
+    }
+
+    /// Returns the IPs a program with the given side length starts with, checking any custom ones.
+    fn start_ips(&self, size: usize) -> Result<Vec<IP>, Error> {
+        let ips = match (&self.ip_starts, self.ip_count.unwrap_or(self.spec_version.capabilities().ips)) {
+            (Some(starts), _) => starts(size),
+            (None, 6) => initial_ips(size).to_vec(),
+            (None, count) => perimeter_ips(size, count),
+        };
+        if !(1..=256).contains(&ips.len()) {
+            return Err(Error::IncompatibleSettings(format!("programs need from 1 to 256 IPs, not {}", ips.len())));
+        }
+        if let Some(ip) = ips.iter().find(|ip| !ip.coords.in_bounds(size)) {
+            return Err(Error::IncompatibleSettings(format!("an IP starts at {}, outside the grid", ip.coords)));
+        }
+        Ok(ips)
+    }
+
+    /// Parses the source code and creates the configured interpreter.
+    pub fn build(mut self) -> Result<Hexagony<'a>, Error> {
+        let grid = self.parse()?;
+        let mut ips = self.start_ips(grid.size())?;
+        if self.ip_starts.is_some() && self.trace.is_some() {
+            return Err(Error::IncompatibleSettings("traces can't record where custom IPs start".to_string()));
+        }
+        let caps = self.spec_version.capabilities();
+        let (byte_eof, int_eof) = (self.byte_eof.unwrap_or(caps.byte_eof), self.int_eof.unwrap_or(caps.int_eof));
+        let input = match self.slurp_input {
+            true => {
+                let mut buf = Vec::new();
+                let mut input = self.input;
+                input.read_to_end(&mut buf)?;
+                Box::new(io::Cursor::new(buf))
+            }
+            false => self.input,
+        };
+        let mut rt = Runtime::from_boxed(input, self.output);
+        rt.set_utf8(self.utf8);
+        rt.set_max_output(self.max_output);
+        rt.set_memory_limit(self.memory_limit);
+        if let Some((limit, callback)) = self.soft_memory_limit {
+            rt.set_soft_memory_limit(limit, callback);
+        }
+        rt.set_byte_eof(byte_eof);
+        rt.set_int_eof(int_eof);
+        rt.set_echo_input(self.echo_input);
+        rt.set_memory_model(self.memory_model);
+        if let Some(hot_edges) = self.spill_memory {
+            if self.memory_model != MemoryModel::Hex {
+                return Err(Error::IncompatibleSettings(format!("the {} memory model can't be spilled to disk", self.memory_model.name())));
+            }
+            if self.state.is_some() {
+                return Err(Error::IncompatibleSettings("resumed programs can't spill memory to disk".to_string()));
+            }
+            rt.mem = AnyMemory::Spill(SpillMemory::new(hot_edges)?);
+        }
+        rt.set_value_type(self.value_type);
+        rt.custom_ops = self.custom_ops;
+        for (name, value) in &self.env {
+            rt.set_env_var(name, value);
+        }
+        rt.set_process_env(self.process_env);
+        if let Some(seed) = self.random_seed {
+            rt.set_random_seed(seed);
+        }
+        if self.fingerprint {
+            rt.hash_output();
+        }
+        let mut trace = self.trace;
+        if let Some(w) = &mut trace {
+            let settings = trace::Settings {
+                utf8: self.utf8,
+                byte_eof,
+                int_eof,
+                dialect: self.dialect,
+                wrapping: self.wrapping,
+                ip_count: ips.len(),
+                value_type: self.value_type,
+                memory_model: self.memory_model,
+                random_seed: rt.random_seed(),
+            };
+            trace::write_header(w, &grid, &settings)?;
+        }
+        let mut ip_idx = 0;
+        let mut tick = Integer::new();
+        if let Some(state) = self.state {
+            rt.skip_input(state.input_position)?;
+            rt.mem = state.mem;
+            ips = state.ips;
+            ip_idx = state.ip_idx;
+            tick = state.tick;
+        }
+        if let Some(idx) = self.follow_ip.filter(|&idx| idx >= ips.len()) {
+            return Err(Error::IncompatibleSettings(format!("can't follow IP {} of a program with {} IPs", idx, ips.len())));
+        }
+        Ok(Hexagony {
+            cost: Counter::new(&tick),
+            handlers: match self.dispatch {
+                Dispatch::Match => None,
+                Dispatch::Table => Some(handler_table(&grid)),
+            },
+            ip_stats: vec![IPCounters::new(grid.size()); ips.len()],
+            ip_room: vec![0; ips.len()],
+            ips,
+            grid,
+            rt,
+            ip_idx,
+            tick: Counter::new(&tick),
+            debug_level: self.debug_level,
+            explain: self.explain,
+            tick_range: self.tick_range,
+            max_ticks: self.max_ticks,
+            cost_model: self.cost_model,
+            deadline: self.deadline,
+            input_log: self.input_log,
+            checkpoints: self.checkpoints,
+            dump_flag: self.dump_flag,
+            abort: self.abort,
+            op_hash: if self.fingerprint { Some(Fnv::new()) } else { None },
+            trace,
+            output_log: self.output_log,
+            delay: self.delay,
+            follow_ip: self.follow_ip,
+            followed_away: self.follow_ip.is_some_and(|idx| idx != ip_idx),
+            dialect: self.dialect,
+            wrapping: self.wrapping,
+            watch_memory: self.observers.iter().any(|observer| observer.watches_memory()),
+            observers: self.observers,
+        })
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{Builder, Error};
+
+/// A named pair of input and expected output for a Hexagony program.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TestCase {
+    pub name: String,
+    pub input: Vec<u8>,
+    pub output: Vec<u8>,
+}
+
+impl TestCase {
+    /// Runs a program with this test case's input, returning whether it terminated
+    /// within `max_ticks` ticks and produced the expected output.
+    pub fn check(&self, src: &str, max_ticks: u64) -> bool {
+        matches!(self.run(src, max_ticks), Ok(output) if output == self.output)
+    }
+
+    /// Runs a program with this test case's input for at most `max_ticks` ticks and returns its output.
+    pub fn run(&self, src: &str, max_ticks: u64) -> Result<Vec<u8>, Error> {
+        let mut output = Vec::new();
+        Builder::new(src)
+            .input(&self.input[..])
+            .output(&mut output)
+            .max_ticks(max_ticks)
+            .build()?
+            .run()?;
+        Ok(output)
+    }
+}
+
+/// Parses a list of test cases.
+///
+/// Each test case starts with its name in square brackets, followed by `input` and `output`
+/// strings in double quotes. Strings may contain the escapes `\n`, `\r`, `\t`, `\0`, `\\`, `\"`
+/// and `\xHH`, and a missing string is treated as empty. Lines starting with `#` are comments.
+///
+/// ```text
+/// # Echoes its input
+/// [cat]
+/// input = "Hello\n"
+/// output = "Hello\n"
+/// ```
+pub fn parse_cases(text: &str) -> Result<Vec<TestCase>, Error> {
+    let mut cases: Vec<TestCase> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let err = |msg: &str| Error::TestCaseError(i + 1, msg.to_string());
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[') {
+            let name = name.strip_suffix(']').ok_or_else(|| err("expected ']'"))?;
+            cases.push(TestCase { name: name.trim().to_string(), ..Default::default() });
+            continue;
+        }
+        let case = cases.last_mut().ok_or_else(|| err("expected a test case name in square brackets"))?;
+        let (key, value) = line.split_once('=').ok_or_else(|| err("expected '='"))?;
+        let value = unescape(value.trim()).ok_or_else(|| err("invalid string"))?;
+        match key.trim() {
+            "input" => case.input = value,
+            "output" => case.output = value,
+            _ => return Err(err("expected 'input' or 'output'")),
+        }
+    }
+    Ok(cases)
+}
+
+/// Decodes a double-quoted string with escapes into bytes.
+fn unescape(s: &str) -> Option<Vec<u8>> {
+    let s = s.strip_prefix('"')?.strip_suffix('"')?;
+    let mut bytes = Vec::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '"' => return None,
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '0' => '\0',
+                '\\' => '\\',
+                '"' => '"',
+                'x' => {
+                    let hex: String = chars.by_ref().take(2).collect();
+                    if hex.len() != 2 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                        return None;
+                    }
+                    bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+                    continue;
+                }
+                _ => return None,
+            },
+            c => c,
+        };
+        let mut buf = [0; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+    Some(bytes)
+}
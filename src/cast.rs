@@ -0,0 +1,105 @@
+use std::{cell::RefCell, io::{self, Cursor, Write}, rc::Rc};
+
+use crate::{Builder, Error, Hexagony, coords::PointAxial, grid::Grid, json::json_string};
+
+/// Number of lines of program output shown below the grid in each frame.
+const OUTPUT_LINES: usize = 10;
+
+/// Records a run of a program as an asciinema cast (asciicast v2), with one frame per tick that
+/// redraws the grid with the active IP highlighted, followed by the output so far.
+///
+/// The program reads from `input` and is stopped after `max_ticks` ticks. Frames are
+/// `frame_time` seconds apart in the cast, regardless of how long each tick really took.
+///
+/// A runtime error ends the cast with a frame describing it, and is then returned.
+pub fn record_cast(src: &str, input: &[u8], max_ticks: u64, frame_time: f64, cast: &mut impl Write) -> Result<(), Error> {
+    let grid: Grid = src.parse()?;
+    let size = grid.size();
+    let output = SharedBuffer::default();
+    let mut hex = Builder::from_grid(grid)
+        .input(Cursor::new(input.to_vec()))
+        .output(output.clone())
+        .max_ticks(max_ticks)
+        .build()?;
+    let (width, height) = ((4 * size).max(80), 2 * size + 3 + OUTPUT_LINES);
+    writeln!(cast, r#"{{"version": 2, "width": {}, "height": {}}}"#, width, height)?;
+    writeln!(cast, "[0.000, \"o\", {}]", json_string(&render(&hex, "Tick 0", &[])))?;
+    let mut time = frame_time;
+    loop {
+        let result = hex.step();
+        let status = match &result {
+            Ok(true) => "Terminated".to_string(),
+            Ok(false) => format!("Tick {}", hex.tick),
+            Err(e) => format!("Error: {}", e),
+        };
+        let frame = render(&hex, &status, &output.0.borrow());
+        writeln!(cast, "[{:.3}, \"o\", {}]", time, json_string(&frame))?;
+        time += frame_time;
+        match result {
+            Ok(false) => (),
+            Ok(true) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Renders a frame: the grid with the active IP highlighted, a status line, and the last
+/// lines of output.
+fn render(hex: &Hexagony, status: &str, output: &[u8]) -> String {
+    // Clear the screen and move to the top left
+    let mut frame = String::from("\x1b[H\x1b[2J");
+    let size = hex.grid.size() as isize;
+    let active = hex.ips[hex.ip_idx].coords;
+    for r in -(size - 1)..size {
+        frame += &" ".repeat(r.unsigned_abs());
+        let qs = (-(size - 1)).max(-(size - 1) - r)..=(size - 1).min(size - 1 - r);
+        for q in qs {
+            let (op, _) = hex.grid.get(PointAxial(q, r));
+            match PointAxial(q, r) == active {
+                true => frame += &format!(" \x1b[7m{}\x1b[0m", op),
+                false => frame += &format!(" {}", op),
+            }
+        }
+        frame += "\r\n";
+    }
+    frame += &format!("\r\n{}\r\nOutput:\r\n", status);
+    let output = String::from_utf8_lossy(output);
+    let lines: Vec<_> = output.split('\n').collect();
+    frame += &lines[lines.len().saturating_sub(OUTPUT_LINES)..].join("\r\n");
+    frame
+}
+
+/// Program output that can still be read while the interpreter owns a clone of it.
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn casts_have_a_frame_per_tick() {
+        let mut cast = Vec::new();
+        record_cast("1!@", b"", 100, 0.5, &mut cast).unwrap();
+        let cast = String::from_utf8(cast).unwrap();
+        let lines: Vec<_> = cast.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], r#"{"version": 2, "width": 80, "height": 17}"#);
+        assert!(lines[1].starts_with("[0.000, \"o\", \"\\u001b[H\\u001b[2J  \\u001b[7m1\\u001b[0m !"), "{}", lines[1]);
+        assert!(lines[4].starts_with("[1.500, ") && lines[4].ends_with("Terminated\\r\\nOutput:\\r\\n1\"]"), "{}", lines[4]);
+        let mut cast = Vec::new();
+        assert!(matches!(record_cast("1:@", b"", 100, 0.5, &mut cast), Err(Error::ZeroDivisionError(Some(_)))));
+        assert!(String::from_utf8(cast).unwrap().contains("Error: Division by zero"));
+    }
+}
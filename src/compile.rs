@@ -0,0 +1,359 @@
+use std::{collections::{HashMap, VecDeque}, fmt::Write};
+
+use crate::{
+    Builder, Error, IP, Runtime, Wrapping,
+    coords::PointAxial,
+    direction::{Direction, redirect},
+    grid::{Grid, Op},
+};
+
+/// Maximum number of cells executed by the code of a single state of a compiled program.
+const MAX_FUSED: usize = 32;
+
+/// Parses a program once and returns a function that runs it on some input and returns its output.
+///
+/// The program is compiled into the same state machine that `to_rust` generates code for, with
+/// its control flow resolved ahead of time and straight-line paths fused into superinstructions.
+/// Use `Builder::compile` to compile it with other settings.
+pub fn compile(src: &str) -> Result<impl Fn(&[u8]) -> Result<Vec<u8>, Error>, Error> {
+    Builder::new(src).compile()
+}
+
+/// Returns a function that runs a compiled program on some input and returns its output.
+pub(crate) fn runner(program: Program) -> impl Fn(&[u8]) -> Result<Vec<u8>, Error> {
+    move |input: &[u8]| {
+        let mut output = Vec::new();
+        let mut rt = Runtime::new(input, &mut output);
+        program.run(&mut rt)?;
+        drop(rt);
+        Ok(output)
+    }
+}
+
+/// Generates the source code of a Rust function with the given name that runs a program
+/// on some input and returns its output.
+///
+/// The generated function has the signature `fn(&[u8]) -> Result<Vec<u8>, hexagony::Error>`.
+/// Its control flow is resolved ahead of time into a state machine over the positions and
+/// directions each IP can reach, leaving only memory and I/O to `hexagony::Runtime`.
+///
+/// Each state runs the straight-line path that follows it, up to the next branch, terminate,
+/// IP switch or repeated state, as a single superinstruction. Along the path, runs of digits
+/// become one `Runtime::execute_digits` call, runs of MP moves one `Runtime::execute_moves`
+/// call and runs of `;` one `Runtime::execute_writes` call.
+///
+/// Use `Builder::to_rust` to compile the program with other settings.
+pub fn to_rust(src: &str, name: &str) -> Result<String, Error> {
+    Builder::new(src).to_rust(name)
+}
+
+/// Generates the source code of a Rust function for a compiled program, as described by `to_rust`.
+pub(crate) fn generate(program: &Program, name: &str) -> String {
+    let count = program.starts.len();
+    let mut code = String::new();
+    writeln!(code, "/// Runs a Hexagony program compiled ahead of time by `hexagony::compile::to_rust`.").unwrap();
+    writeln!(code, "pub fn {}(input: &[u8]) -> Result<Vec<u8>, hexagony::Error> {{", name).unwrap();
+    writeln!(code, "    let mut output = Vec::new();").unwrap();
+    writeln!(code, "    let mut rt = hexagony::Runtime::new(input, &mut output);").unwrap();
+    let start_ids: Vec<_> = program.starts.iter().map(usize::to_string).collect();
+    writeln!(code, "    let mut ips: [usize; {}] = [{}];", start_ids.len(), start_ids.join(", ")).unwrap();
+    let switches_ip = program.states.iter().any(|state| matches!(state.exit, Exit::Next { switch: Some(_), .. }));
+    writeln!(code, "    let {}ip = 0;", if switches_ip { "mut " } else { "" }).unwrap();
+    writeln!(code, "    loop {{").unwrap();
+    writeln!(code, "        match ips[ip] {{").unwrap();
+    for (id, state) in program.states.iter().enumerate() {
+        writeln!(code, "            // `{}` at {} moving {}", state.op, state.coords, state.dir).unwrap();
+        let (pos, neg, switch) = match state.exit {
+            Exit::Terminate => {
+                writeln!(code, "            {} => break,", id).unwrap();
+                continue;
+            }
+            Exit::Next { pos, neg, switch } => (pos, neg, switch),
+        };
+        writeln!(code, "            {} => {{", id).unwrap();
+        if state.cells > 1 {
+            writeln!(code, "                // Fused with the next {} cells", state.cells - 1).unwrap();
+        }
+        for instr in &state.body {
+            writeln!(code, "                {}", instr.to_rust()).unwrap();
+        }
+        if neg == pos {
+            writeln!(code, "                ips[ip] = {};", pos).unwrap();
+        } else {
+            writeln!(code, "                ips[ip] = if rt.positive() {{ {} }} else {{ {} }};", pos, neg).unwrap();
+        }
+        match switch {
+            Some(Op::IPPrev) => writeln!(code, "                ip = (ip + {}) % {};", count - 1, count).unwrap(),
+            Some(Op::IPNext) => writeln!(code, "                ip = (ip + 1) % {};", count).unwrap(),
+            Some(_) => writeln!(code, "                ip = rt.selected_ip({});", count).unwrap(),
+            None => (),
+        }
+        writeln!(code, "            }}").unwrap();
+    }
+    writeln!(code, "            _ => unreachable!(),").unwrap();
+    writeln!(code, "        }}").unwrap();
+    writeln!(code, "    }}").unwrap();
+    writeln!(code, "    rt.flush()?;").unwrap();
+    writeln!(code, "    drop(rt);").unwrap();
+    writeln!(code, "    Ok(output)").unwrap();
+    writeln!(code, "}}").unwrap();
+    code
+}
+
+/// A program compiled into a state machine over the positions and directions its IPs can reach,
+/// which `compile` runs and `to_rust` generates code for.
+pub(crate) struct Program {
+    states: Vec<State>,
+    /// The state each IP starts in
+    starts: Vec<usize>,
+}
+
+/// The superinstruction run by an IP that reaches a position and direction.
+struct State {
+    coords: PointAxial,
+    dir: Direction,
+    op: Op,
+    /// Number of cells on the straight-line path that the state runs
+    cells: usize,
+    body: Vec<Instr>,
+    exit: Exit,
+}
+
+/// Where an IP goes after running a state.
+#[derive(Clone, Copy)]
+enum Exit {
+    Terminate,
+    /// The next state if the current edge is positive or not, and the op that switches IPs, if any
+    Next { pos: usize, neg: usize, switch: Option<Op> },
+}
+
+/// A step of the straight-line code of a state.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Instr {
+    Op(Op),
+    Digits(u32, u64),
+    Moves(Vec<Op>),
+    Writes(u64),
+}
+
+impl Program {
+    /// Compiles a grid whose IPs start at `ips` and leave it by `wrapping`, numbering every reachable
+    /// IP state in breadth-first order.
+    pub(crate) fn new(grid: &Grid, wrapping: Wrapping, ips: &[IP]) -> Program {
+        let starts: Vec<_> = ips.iter().map(|ip| (ip.coords, ip.dir)).collect();
+        let mut ids = HashMap::new();
+        let mut reached = Vec::new();
+        let mut queue: VecDeque<_> = starts.iter().copied().collect();
+        while let Some(state) = queue.pop_front() {
+            if ids.contains_key(&state) {
+                continue;
+            }
+            ids.insert(state, reached.len());
+            reached.push(state);
+            if let Op::Terminate = grid.get(state.0).0 {
+                continue;
+            }
+            queue.push_back(step(grid, wrapping, state, false));
+            queue.push_back(step(grid, wrapping, state, true));
+        }
+        let states = reached.into_iter().map(|start| {
+            let op = grid.get(start.0).0;
+            if let Op::Terminate = op {
+                return State { coords: start.0, dir: start.1, op, cells: 1, body: Vec::new(), exit: Exit::Terminate };
+            }
+            let path = fused_path(grid, wrapping, start);
+            let last = *path.last().unwrap();
+            let switch = Some(grid.get(last.0).0).filter(|op| matches!(op, Op::IPPrev | Op::IPNext | Op::IPSelect));
+            State {
+                coords: start.0,
+                dir: start.1,
+                op,
+                cells: path.len(),
+                body: fuse(path.iter().map(|&(coords, _)| grid.get(coords).0)),
+                exit: Exit::Next { pos: ids[&step(grid, wrapping, last, true)], neg: ids[&step(grid, wrapping, last, false)], switch },
+            }
+        }).collect();
+        Program { states, starts: starts.iter().map(|s| ids[s]).collect() }
+    }
+
+    /// Runs the program until it terminates.
+    pub(crate) fn run(&self, rt: &mut Runtime) -> Result<(), Error> {
+        let mut ips = self.starts.clone();
+        let mut ip = 0;
+        loop {
+            let state = &self.states[ips[ip]];
+            for instr in &state.body {
+                instr.execute(rt)?;
+            }
+            let (pos, neg, switch) = match state.exit {
+                Exit::Terminate => break,
+                Exit::Next { pos, neg, switch } => (pos, neg, switch),
+            };
+            ips[ip] = if pos == neg || rt.positive() { pos } else { neg };
+            match switch {
+                Some(Op::IPPrev) => ip = (ip + ips.len() - 1) % ips.len(),
+                Some(Op::IPNext) => ip = (ip + 1) % ips.len(),
+                Some(_) => ip = rt.selected_ip(ips.len()),
+                None => (),
+            }
+        }
+        rt.flush()
+    }
+}
+
+impl Instr {
+    fn execute(&self, rt: &mut Runtime) -> Result<(), Error> {
+        match self {
+            Instr::Op(op) => rt.execute(*op),
+            Instr::Digits(count, digits) => rt.execute_digits(*count, *digits),
+            Instr::Moves(moves) => rt.execute_moves(moves),
+            Instr::Writes(count) => rt.execute_writes(*count),
+        }
+    }
+
+    /// Returns the Rust statement that executes the instruction.
+    fn to_rust(&self) -> String {
+        match self {
+            Instr::Op(op) => format!("rt.execute(hexagony::Op::{:?})?;", op),
+            Instr::Digits(count, digits) => format!("rt.execute_digits({}, {})?;", count, digits),
+            Instr::Moves(moves) => {
+                let moves: Vec<_> = moves.iter().map(|op| format!("hexagony::Op::{:?}", op)).collect();
+                format!("rt.execute_moves(&[{}])?;", moves.join(", "))
+            }
+            Instr::Writes(count) => format!("rt.execute_writes({})?;", count),
+        }
+    }
+}
+
+/// Returns the states an IP passes through from `start` without branching, ending before a terminate,
+/// a repeated state, or after `MAX_FUSED` states, a branch or an IP switch.
+fn fused_path(grid: &Grid, wrapping: Wrapping, start: (PointAxial, Direction)) -> Vec<(PointAxial, Direction)> {
+    let mut path = vec![start];
+    let mut state = start;
+    while path.len() < MAX_FUSED && !matches!(grid.get(state.0).0, Op::IPPrev | Op::IPNext | Op::IPSelect) {
+        let next = step(grid, wrapping, state, true);
+        if next != step(grid, wrapping, state, false) || grid.get(next.0).0 == Op::Terminate || path.contains(&next) {
+            break;
+        }
+        path.push(next);
+        state = next;
+    }
+    path
+}
+
+/// Turns the ops along a straight-line path into instructions, skipping the ops that only affect
+/// control flow and fusing runs of digits, MP moves and `;`.
+pub(crate) fn fuse(ops: impl Iterator<Item = Op>) -> Vec<Instr> {
+    let is_move = |op| matches!(op, Op::MPLeft | Op::MPRight | Op::MPBackLeft | Op::MPBackRight | Op::MPReverse);
+    let mut instrs = Vec::new();
+    for op in ops {
+        match (instrs.last_mut(), op) {
+            (_, Op::Nop | Op::Jump | Op::Redir(_) | Op::IPPrev | Op::IPNext | Op::IPSelect) => (),
+            // Digits fit in a `u64` as long as there are at most 19 of them
+            (Some(Instr::Digits(count, digits)), Op::Digit(d)) if *count < 19 => {
+                *count += 1;
+                *digits = *digits * 10 + u64::from(d);
+            }
+            (Some(Instr::Op(Op::Digit(first))), Op::Digit(d)) => {
+                let first = u64::from(*first);
+                *instrs.last_mut().unwrap() = Instr::Digits(2, first * 10 + u64::from(d));
+            }
+            // Reversing twice does nothing
+            (Some(Instr::Moves(moves)), Op::MPReverse) if moves.last() == Some(&Op::MPReverse) => {
+                moves.pop();
+                if moves.is_empty() {
+                    instrs.pop();
+                }
+            }
+            (Some(Instr::Moves(moves)), _) if is_move(op) => moves.push(op),
+            (Some(Instr::Op(prev)), _) if is_move(*prev) && is_move(op) => {
+                let prev = *prev;
+                match (prev, op) {
+                    (Op::MPReverse, Op::MPReverse) => { instrs.pop(); }
+                    _ => *instrs.last_mut().unwrap() = Instr::Moves(vec![prev, op]),
+                }
+            }
+            (Some(Instr::Writes(count)), Op::WriteByte) => *count += 1,
+            (Some(Instr::Op(Op::WriteByte)), Op::WriteByte) => *instrs.last_mut().unwrap() = Instr::Writes(2),
+            _ => instrs.push(Instr::Op(op)),
+        }
+    }
+    instrs
+}
+
+/// Returns the position and direction of an IP after it executes the op at its current position,
+/// given whether the current memory edge is positive afterwards and how IPs leave the grid.
+pub(crate) fn step(grid: &Grid, wrapping: Wrapping, (coords, dir): (PointAxial, Direction), positive: bool) -> (PointAxial, Direction) {
+    let op = grid.get(coords).0;
+    let dir = match op {
+        Op::Redir(redir) => redirect(dir, redir, positive),
+        _ => dir,
+    };
+    let mut coords = wrapping.wrap(grid.size(), coords, dir, positive);
+    if let Op::Jump = op {
+        coords = wrapping.wrap(grid.size(), coords, dir, positive);
+    }
+    (coords, dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{examples, memory};
+
+    #[test]
+    fn compiled_programs_match_the_interpreter() {
+        let digits = format!("1234567{}123456789012|{}!@", ".".repeat(50), ".".repeat(50));
+        let shuffles = "1{2}...........3{\"}'=6...........=!}@";
+        let cancelled = "1{2}...........3\"4'5==...........=!}@";
+        let writes = "72;;;;;)=;;;={;;'@";
+        let programs = examples::examples().iter().map(|example| (example.source(), example.sample_input));
+        for (src, input) in programs.chain(vec![(&*digits, ""), (shuffles, ""), (cancelled, ""), (writes, "")]) {
+            let mut expected = Vec::new();
+            Builder::new(src).input(input.as_bytes()).output(&mut expected).max_ticks(100_000).build().unwrap().run().unwrap();
+            assert_eq!(compile(src).unwrap()(input.as_bytes()).unwrap(), expected, "program {:?}", src);
+        }
+        assert!(to_rust(&digits, "digits").unwrap().contains("rt.execute_digits(19, 1234567123456789012)?;"));
+        assert!(to_rust(shuffles, "shuffles").unwrap().contains("rt.execute_moves(&[hexagony::Op::MPLeft, hexagony::Op::MPBackLeft, "));
+        assert!(to_rust(writes, "writes").unwrap().contains("rt.execute_writes(5)?;"));
+
+        let (mut fused, mut single) = (Vec::new(), Vec::new());
+        let mut rt = Runtime::new(&b""[..], &mut fused);
+        rt.execute(Op::Digit(7)).unwrap();
+        rt.execute_digits(25, 1234567890123456789).unwrap();
+        rt.execute(Op::WriteInt).unwrap();
+        drop(rt);
+        let mut rt = Runtime::new(&b""[..], &mut single);
+        rt.execute(Op::Digit(7)).unwrap();
+        for _ in 0..6 {
+            rt.execute(Op::Digit(0)).unwrap();
+        }
+        rt.execute_digits(19, 1234567890123456789).unwrap();
+        rt.execute(Op::WriteInt).unwrap();
+        drop(rt);
+        assert_eq!(fused, single);
+    }
+
+    #[test]
+    fn compiled_programs_keep_wrapping_and_ip_count() {
+        let mut compared = 0;
+        for example in examples::examples() {
+            for (wrapping, ip_count) in [(Wrapping::Torus, 6), (Wrapping::AlwaysPositive, 3), (Wrapping::Standard, 12)] {
+                let builder = || Builder::new(example.source()).wrapping(wrapping).ip_count(ip_count);
+                let mut expected = Vec::new();
+                let run = builder().input(example.sample_input.as_bytes()).output(&mut expected).max_ticks(100_000).build().unwrap().run();
+                if run.is_ok() {
+                    assert_eq!(builder().compile().unwrap()(example.sample_input.as_bytes()).unwrap(), expected);
+                    compared += 1;
+                }
+            }
+        }
+        assert!(compared > 0);
+        let code = Builder::new("]#[@").ip_count(3).to_rust("three").unwrap();
+        assert!(code.contains("let mut ips: [usize; 3]") && code.contains("rt.selected_ip(3)"));
+        match Builder::new("@").memory_model(memory::MemoryModel::Tape).to_rust("tape") {
+            Err(Error::IncompatibleSettings(message)) => assert_eq!(message, "compiled programs don't support memory models"),
+            _ => panic!("expected incompatible settings"),
+        }
+    }
+}
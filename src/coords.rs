@@ -1,9 +1,133 @@
-use std::{fmt, ops::{Add, AddAssign, Sub, SubAssign}};
+use std::{fmt, ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign}};
 
-/// An axial coordinate pair.
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+use crate::direction::{Axis, Direction};
+
+/// An axial coordinate pair `(q, r)`.
+///
+/// `q` increases to the east and `r` increases to the south-east, with `(0, 0)` at the center of the grid.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct PointAxial(pub isize, pub isize);
 
+/// A cube coordinate triple `(x, y, z)`, where `x + y + z == 0` for every valid point.
+///
+/// `x` and `z` match the `q` and `r` of the equivalent `PointAxial`, and `y` is `-x - z`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PointCube(pub isize, pub isize, pub isize);
+
+impl PointAxial {
+    /// The center of the grid.
+    pub const ORIGIN: PointAxial = PointAxial(0, 0);
+
+    /// Returns the number of grid spaces between two points.
+    pub fn distance(self, other: PointAxial) -> usize {
+        self.to_cube().distance(other.to_cube())
+    }
+
+    /// Returns the six adjacent points, in the clockwise order of `Direction::from_index`.
+    pub fn neighbors(self) -> [PointAxial; 6] {
+        let mut neighbors = [self; 6];
+        for (i, neighbor) in neighbors.iter_mut().enumerate() {
+            *neighbor += Direction::from_index(i).to_vector();
+        }
+        neighbors
+    }
+
+    /// Converts the point to cube coordinates.
+    ///
+    /// `y` wraps around if `-q - r` doesn't fit in an `isize`, giving a point that isn't valid.
+    pub fn to_cube(self) -> PointCube {
+        let PointAxial(q, r) = self;
+        PointCube(q, q.wrapping_neg().wrapping_sub(r), r)
+    }
+
+    /// Returns the mirror image of the point across an axis through the center of the grid.
+    pub fn reflect(self, axis: Axis) -> PointAxial {
+        let PointAxial(q, r) = self;
+        match axis {
+            Axis::Horizontal => PointAxial(q + r, -r),
+            Axis::Vertical => PointAxial(-q - r, r),
+        }
+    }
+
+    /// Returns whether the point lies inside a grid of the given side length.
+    pub fn in_bounds(self, size: usize) -> bool {
+        // Check the sum without overflowing, since the point may come from untrusted input
+        let PointAxial(q, r) = self;
+        q.unsigned_abs() < size && r.unsigned_abs() < size && q.checked_add(r).is_some_and(|y| y.unsigned_abs() < size)
+    }
+
+    /// Returns how many steps the point can take in direction `dir` before the next one would leave
+    /// a grid of the given side length, assuming the point lies inside it.
+    pub fn steps_to_edge(self, dir: Direction, size: usize) -> usize {
+        let PointCube(x, y, z) = self.to_cube();
+        let PointCube(dx, dy, dz) = dir.to_vector().to_cube();
+        let limit = size as isize - 1;
+        [(x, dx), (y, dy), (z, dz)].iter()
+            .map(|&(c, dc)| match dc.signum() {
+                1 => (limit - c) as usize,
+                -1 => (limit + c) as usize,
+                _ => usize::MAX,
+            })
+            .min()
+            .unwrap()
+    }
+}
+
+impl PointCube {
+    /// Converts the point to axial coordinates, discarding `y`.
+    pub fn to_axial(self) -> PointAxial {
+        PointAxial(self.0, self.2)
+    }
+
+    /// Returns whether the coordinates sum to zero.
+    pub fn is_valid(self) -> bool {
+        self.0 as i128 + self.1 as i128 + self.2 as i128 == 0
+    }
+
+    /// Returns the number of grid spaces between two points.
+    pub fn distance(self, other: PointCube) -> usize {
+        let PointCube(x, y, z) = self;
+        let PointCube(ox, oy, oz) = other;
+        x.abs_diff(ox).max(y.abs_diff(oy)).max(z.abs_diff(oz))
+    }
+
+    /// Returns whether the point lies inside a grid of the given side length.
+    pub fn in_bounds(self, size: usize) -> bool {
+        let PointCube(x, y, z) = self;
+        x.unsigned_abs() < size && y.unsigned_abs() < size && z.unsigned_abs() < size
+    }
+}
+
+/// Returns an iterator over the cells at distance `k` from the center of the grid, starting at
+/// the north-west corner of the ring and going clockwise.
+pub fn ring(k: usize) -> impl Iterator<Item = PointAxial> {
+    let mut coords = Direction::NorthWest.to_vector() * k as isize;
+    let mut cells = Vec::with_capacity(6 * k.max(1));
+    if k == 0 {
+        cells.push(coords);
+    }
+    for dir in (0..6).map(Direction::from_index) {
+        for _ in 0..k {
+            cells.push(coords);
+            coords += dir.to_vector();
+        }
+    }
+    cells.into_iter()
+}
+
+/// Returns an iterator over the cells of a grid of the given side length, going outwards
+/// from the center one ring at a time.
+pub fn spiral(size: usize) -> impl Iterator<Item = PointAxial> {
+    (0..size).flat_map(ring)
+}
+
+/// Returns an iterator over the cells of a grid of the given side length in row-major order,
+/// which is the order of the source code.
+pub fn rows(size: usize) -> impl Iterator<Item = PointAxial> {
+    let n = size as isize - 1;
+    (-n..=n).flat_map(move |r| ((-n - r).max(-n)..=(n - r).min(n)).map(move |q| PointAxial(q, r)))
+}
+
 impl Add for PointAxial {
     type Output = Self;
 
@@ -34,8 +158,74 @@ impl SubAssign for PointAxial {
     }
 }
 
+impl From<PointCube> for PointAxial {
+    fn from(point: PointCube) -> Self {
+        point.to_axial()
+    }
+}
+
+impl From<PointAxial> for PointCube {
+    fn from(point: PointAxial) -> Self {
+        point.to_cube()
+    }
+}
+
+impl Mul<isize> for PointAxial {
+    type Output = Self;
+
+    fn mul(self, rhs: isize) -> Self {
+        PointAxial(self.0 * rhs, self.1 * rhs)
+    }
+}
+
+impl Neg for PointAxial {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        PointAxial(-self.0, -self.1)
+    }
+}
+
 impl fmt::Display for PointAxial {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "({}, {})", self.0, self.1)
     }
 }
+
+impl fmt::Display for PointCube {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}, {}, {})", self.0, self.1, self.2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_to_edge_stops_before_leaving() {
+        for size in 1..7 {
+            for coords in rows(size) {
+                for dir in Direction::all() {
+                    let steps = coords.steps_to_edge(dir, size) as isize;
+                    assert!((coords + dir.to_vector() * steps).in_bounds(size));
+                    assert!(!(coords + dir.to_vector() * (steps + 1)).in_bounds(size));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cube_coordinates_are_valid_unless_they_overflow() {
+        for coords in rows(4) {
+            assert!(coords.to_cube().is_valid());
+            assert_eq!(coords.to_cube().to_axial(), coords);
+        }
+        assert!(PointAxial(isize::MAX, isize::MIN).to_cube().is_valid());
+        assert!(!PointAxial(isize::MIN, 0).to_cube().is_valid());
+        assert!(!PointAxial(-isize::MAX, -2).to_cube().is_valid());
+        assert!(!PointCube(isize::MAX, isize::MAX, 2).is_valid());
+        assert!(PointCube(isize::MAX, isize::MIN, 1).is_valid());
+        assert!(!PointAxial(isize::MIN, isize::MIN).to_cube().in_bounds(3));
+    }
+}
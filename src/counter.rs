@@ -0,0 +1,103 @@
+use std::fmt;
+use rug::{Assign, Integer};
+
+/// A count of ticks that adds in a `u64`, and only moves to an `Integer` once it would overflow,
+/// so counting stays cheap in the interpreter loop without a limit on how long programs run.
+#[derive(Clone)]
+pub(crate) struct Counter {
+    small: u64,
+    /// The count once it has overflowed `small`, which is unused from then on
+    big: Option<Integer>,
+    /// The count as an `Integer`, refreshed by `get`
+    view: Integer,
+}
+
+impl Counter {
+    /// Creates a new `Counter` starting at the given count.
+    pub(crate) fn new(start: &Integer) -> Counter {
+        let (small, big) = match start.to_u64() {
+            Some(small) => (small, None),
+            None => (0, Some(start.clone())),
+        };
+        Counter { small, big, view: Integer::new() }
+    }
+
+    /// Adds `n` to the count.
+    pub(crate) fn add(&mut self, n: u64) {
+        match &mut self.big {
+            Some(big) => *big += n,
+            None => match self.small.checked_add(n) {
+                Some(sum) => self.small = sum,
+                None => self.big = Some(Integer::from(self.small) + n),
+            },
+        }
+    }
+
+    /// Returns a reference to the count as an `Integer`.
+    ///
+    /// Only the first call allocates while the count fits in a `u64`.
+    pub(crate) fn get(&mut self) -> &Integer {
+        match &self.big {
+            Some(big) => big,
+            None => {
+                self.view.assign(self.small);
+                &self.view
+            }
+        }
+    }
+
+    /// Returns the count as a new `Integer`.
+    pub(crate) fn to_integer(&self) -> Integer {
+        match &self.big {
+            Some(big) => big.clone(),
+            None => Integer::from(self.small),
+        }
+    }
+
+    /// Returns whether the count is at least `n`.
+    pub(crate) fn at_least(&self, n: u64) -> bool {
+        self.big.is_some() || self.small >= n
+    }
+
+    /// Returns whether the count is divisible by `n`.
+    pub(crate) fn is_divisible(&self, n: u64) -> bool {
+        match &self.big {
+            Some(big) => big.is_divisible(&Integer::from(n)),
+            None => self.small.is_multiple_of(n),
+        }
+    }
+
+    /// Sets `value` to the count without allocating while it fits in a `u64`.
+    pub(crate) fn assign_to(&self, value: &mut Integer) {
+        match &self.big {
+            Some(big) => value.assign(big),
+            None => value.assign(self.small),
+        }
+    }
+}
+
+impl fmt::Display for Counter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.big {
+            Some(big) => write!(f, "{}", big),
+            None => write!(f, "{}", self.small),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_moves_to_integer_on_overflow() {
+        let mut counter = Counter::new(&Integer::from(u64::MAX - 1));
+        counter.add(1);
+        assert_eq!(*counter.get(), u64::MAX);
+        counter.add(3);
+        assert_eq!(*counter.get(), Integer::from(u64::MAX) + 3);
+        assert!(counter.at_least(u64::MAX));
+        assert!(counter.is_divisible(2));
+        assert_eq!(counter.to_string(), "18446744073709551618");
+    }
+}
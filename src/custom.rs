@@ -0,0 +1,123 @@
+use rug::Integer;
+
+use crate::{Error, Runtime, memory::Memory};
+
+/// An instruction provided by the embedding program, registered for a character that isn't
+/// a command with `Builder::custom_op`.
+///
+/// Closures taking a `&mut Context` implement this trait, so most instructions don't need a type
+/// of their own:
+///
+/// ```
+/// use hexagony::{Builder, custom::Context};
+///
+/// let mut output = Vec::new();
+/// // `D` would set the current edge to 68, so use a character that isn't a command
+/// Builder::new("5€!@")
+///     .output(&mut output)
+///     .custom_op('€', |ctx: &mut Context| {
+///         let doubled = ctx.get().clone() * 2;
+///         ctx.set(doubled);
+///         Ok(())
+///     })
+///     .build()?
+///     .run()?;
+/// assert_eq!(output, b"10");
+/// # Ok::<(), hexagony::Error>(())
+/// ```
+pub trait CustomOp {
+    /// Executes the instruction.
+    ///
+    /// Returning an error stops the program with it, like a runtime error of a built-in command.
+    fn execute(&mut self, ctx: &mut Context) -> Result<(), Error>;
+}
+
+impl<F: FnMut(&mut Context) -> Result<(), Error>> CustomOp for F {
+    fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        self(ctx)
+    }
+}
+
+/// The memory and I/O of a running program, as seen by a custom instruction.
+pub struct Context<'r, 'a> {
+    rt: &'r mut Runtime<'a>,
+}
+
+impl<'r, 'a> Context<'r, 'a> {
+    /// Creates a new `Context` for a runtime.
+    pub(crate) fn new(rt: &'r mut Runtime<'a>) -> Context<'r, 'a> {
+        Context { rt }
+    }
+
+    /// Returns the value of the current memory edge.
+    pub fn get(&self) -> &Integer {
+        self.rt.mem.get()
+    }
+
+    /// Returns the value of the left neighbour of the current memory edge.
+    pub fn left(&self) -> &Integer {
+        self.rt.mem.get_left()
+    }
+
+    /// Returns the value of the right neighbour of the current memory edge.
+    pub fn right(&self) -> &Integer {
+        self.rt.mem.get_right()
+    }
+
+    /// Sets the current memory edge to the given value.
+    pub fn set(&mut self, value: impl Into<Integer>) {
+        self.rt.mem.set(value.into());
+    }
+
+    /// Reads the next byte of input, or `None` at EOF.
+    pub fn read_byte(&mut self) -> Result<Option<u8>, Error> {
+        self.rt.next_byte()
+    }
+
+    /// Writes bytes to the output, counting them towards the output limit.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.rt.write(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Builder;
+
+    #[test]
+    fn custom_ops_use_memory_and_io() {
+        use std::{cell::Cell, rc::Rc};
+
+        /// Echoes a byte of input twice, counting its calls.
+        struct Echo(Rc<Cell<usize>>);
+
+        impl CustomOp for Echo {
+            fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
+                self.0.set(self.0.get() + 1);
+                let byte = ctx.read_byte()?.unwrap_or(b'-');
+                ctx.write(&[byte, byte])
+            }
+        }
+
+        let calls = Rc::default();
+        let mut output = Vec::new();
+        Builder::new("1ßΣ!@")
+            .input(&b"a"[..])
+            .output(&mut output)
+            .custom_op('ß', Echo(Rc::clone(&calls)))
+            .custom_op('Σ', |ctx: &mut Context| {
+                let value = ctx.get().clone() * 10 + ctx.left() + ctx.right() + 7;
+                ctx.set(value);
+                Ok(())
+            })
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+        assert_eq!(output, b"aa17");
+        assert_eq!(calls.get(), 1);
+        let fail = |_: &mut Context| Err(Error::Aborted);
+        assert!(matches!(Builder::new("ß@").custom_op('ß', fail).build().unwrap().run(), Err(Error::Aborted)));
+    }
+}
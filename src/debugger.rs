@@ -0,0 +1,172 @@
+use std::{collections::HashSet, io::{self, Read}, sync::atomic::{AtomicBool, Ordering}};
+
+use crate::{Error, Hexagony, coords::PointAxial};
+
+/// Program input for a debugging session that prompts on STDERR and reads a line from STDIN
+/// whenever the program needs more input, so it never competes with debugger commands.
+///
+/// An empty read from STDIN (Ctrl-D) is passed to the program as EOF.
+#[derive(Default)]
+pub struct PromptInput {
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for PromptInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.line.len() {
+            eprint!("program requests input: ");
+            let mut line = String::new();
+            io::stdin().read_line(&mut line)?;
+            self.line = line.into_bytes();
+            self.pos = 0;
+        }
+        let n = (&self.line[self.pos..]).read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// An interactive debugger that reads commands from STDIN and writes to STDERR.
+pub struct Debugger<'h, 'a> {
+    hex: &'h mut Hexagony<'a>,
+    breakpoints: HashSet<PointAxial>,
+    /// Why the program can't run any further, if it can't
+    stopped: Option<&'static str>,
+    interrupt: Option<&'h AtomicBool>,
+}
+
+const HELP: &str = "\
+Commands:
+  s, step [N]      Executes N ticks (default: 1)
+  c, continue      Runs until a breakpoint or the end of the program
+  b, break [Q R]   Toggles a breakpoint at cell (Q, R), or lists breakpoints
+  i, ips           Prints the position of every IP, with its ticks and cells visited
+  m, mem           Prints the memory
+  g, grid [coords] Prints the grid with row labels, or with every cell's coordinates
+  q, quit          Stops debugging
+  h, help          Prints this message
+An empty line repeats a single step.";
+
+impl<'h, 'a> Debugger<'h, 'a> {
+    /// Creates a new `Debugger` for an interpreter.
+    ///
+    /// The interpreter should read its input from a `PromptInput` if STDIN is a terminal.
+    pub fn new(hex: &'h mut Hexagony<'a>) -> Debugger<'h, 'a> {
+        Debugger { hex, breakpoints: HashSet::new(), stopped: None, interrupt: None }
+    }
+
+    /// Makes `step` and `continue` stop early and print the state of the program when `flag` is set,
+    /// such as by `signal::catch_interrupt` on Ctrl-C. The flag is cleared before each command.
+    pub fn interrupt_when(mut self, flag: &'h AtomicBool) -> Self {
+        self.interrupt = Some(flag);
+        self
+    }
+
+    /// Reads and runs commands until the user quits or STDIN ends.
+    ///
+    /// Returns `Err` if a runtime error occurred in the program.
+    pub fn run(&mut self) -> Result<(), Error> {
+        eprintln!("Type `help` for a list of commands.");
+        self.show_position();
+        loop {
+            eprint!("(hxg) ");
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line)? == 0 {
+                eprintln!();
+                return Ok(());
+            }
+            let words: Vec<_> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => self.step(1)?,
+                ["s", rest @ ..] | ["step", rest @ ..] => match rest {
+                    [] => self.step(1)?,
+                    [n] => match n.parse() {
+                        Ok(n) => self.step(n)?,
+                        Err(_) => eprintln!("Invalid tick count: {}", n),
+                    },
+                    _ => eprintln!("Usage: step [N]"),
+                },
+                ["c"] | ["continue"] => self.step(u64::MAX)?,
+                ["b"] | ["break"] => {
+                    let mut breakpoints: Vec<_> = self.breakpoints.iter().map(|p| (p.1, p.0)).collect();
+                    breakpoints.sort_unstable();
+                    for (r, q) in breakpoints {
+                        eprintln!("Breakpoint at {}", PointAxial(q, r));
+                    }
+                }
+                ["b", q, r] | ["break", q, r] => match (q.parse(), r.parse()) {
+                    (Ok(q), Ok(r)) if PointAxial(q, r).in_bounds(self.hex.grid.size()) => {
+                        let coords = PointAxial(q, r);
+                        if self.breakpoints.remove(&coords) {
+                            eprintln!("Removed breakpoint at {}", coords);
+                        } else {
+                            self.breakpoints.insert(coords);
+                            eprintln!("Added breakpoint at {}", coords);
+                        }
+                    }
+                    _ => eprintln!("Invalid cell: {} {}", q, r),
+                },
+                ["i"] | ["ips"] => {
+                    for (i, (ip, stats)) in self.hex.ips.iter().zip(&self.hex.ip_stats).enumerate() {
+                        let active = if i == self.hex.ip_idx { '!' } else { ' ' };
+                        eprintln!(
+                            "{} {}: {}, {} ({} ticks, {} cells visited)",
+                            active, i, ip.coords, ip.dir, stats.ticks, stats.visited.len(),
+                        );
+                    }
+                }
+                ["m"] | ["mem"] => eprintln!("{}", self.hex.rt.mem),
+                ["g"] | ["grid"] => eprint!("{}", self.hex.grid.annotated(false)),
+                ["g", "coords"] | ["grid", "coords"] => eprint!("{}", self.hex.grid.annotated(true)),
+                ["q"] | ["quit"] => return Ok(()),
+                ["h"] | ["help"] => eprintln!("{}", HELP),
+                _ => eprintln!("Unknown command. Type `help` for a list of commands."),
+            }
+        }
+    }
+
+    /// Reads and runs commands to inspect a program that stopped with a runtime error,
+    /// without letting it run any further.
+    pub fn post_mortem(&mut self) -> Result<(), Error> {
+        self.stopped = Some("The program stopped with an error.");
+        self.run()
+    }
+
+    /// Executes up to `ticks` ticks, stopping early at the end of the program or at a breakpoint.
+    fn step(&mut self, ticks: u64) -> Result<(), Error> {
+        if let Some(reason) = self.stopped {
+            eprintln!("{}", reason);
+            return Ok(());
+        }
+        if let Some(flag) = self.interrupt {
+            flag.store(false, Ordering::Relaxed);
+        }
+        for i in 0..ticks {
+            if i > 0 && self.breakpoints.contains(&self.hex.ips[self.hex.ip_idx].coords) {
+                eprintln!("Hit breakpoint");
+                break;
+            }
+            if self.interrupt.is_some_and(|flag| flag.load(Ordering::Relaxed)) {
+                eprint!("\nInterrupted\n{}", self.hex.dump());
+                break;
+            }
+            if self.hex.step()? {
+                // Separate the debugger output from any program output without a trailing newline
+                eprintln!("\nThe program has terminated.");
+                self.stopped = Some("The program has terminated.");
+                return Ok(());
+            }
+        }
+        self.hex.rt.flush()?;
+        self.show_position();
+        Ok(())
+    }
+
+    /// Prints the tick number and the position of the current IP.
+    fn show_position(&self) {
+        let ip = &self.hex.ips[self.hex.ip_idx];
+        let (op, _) = self.hex.grid.get(ip.coords);
+        eprintln!("Tick {}: IP {} at {} facing {}, next command `{}`", self.hex.tick, self.hex.ip_idx, ip.coords, ip.dir, op);
+    }
+}
@@ -0,0 +1,55 @@
+use std::fmt;
+
+use crate::{coords::PointAxial, grid::{Grid, Op, cell_source}};
+
+/// A cell whose `Op` or debug flag differs between two grids.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CellDiff {
+    pub coords: PointAxial,
+    /// The `Op` and debug flag of the cell in the first grid
+    pub old: (Op, bool),
+    /// The `Op` and debug flag of the cell in the second grid
+    pub new: (Op, bool),
+}
+
+/// Returns every cell that differs between two grids, in row-major order of the larger one.
+///
+/// The grids are aligned on their centers, so a cell has the same coordinates in both. Cells outside
+/// the smaller grid count as no-ops, as if it were padded with rings of `.` to the size of the larger.
+pub fn diff(old: &Grid, new: &Grid) -> Vec<CellDiff> {
+    let cell = |grid: &Grid, coords| match grid.contains(coords) {
+        true => grid.get(coords),
+        false => (Op::Nop, false),
+    };
+    let larger = if old.size() >= new.size() { old } else { new };
+    larger.cells()
+        .map(|(coords, _, _)| CellDiff { coords, old: cell(old, coords), new: cell(new, coords) })
+        .filter(|diff| diff.old != diff.new)
+        .collect()
+}
+
+impl fmt::Display for CellDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ((old, old_debug), (new, new_debug)) = (self.old, self.new);
+        write!(f, "{}: {} -> {}", self.coords, cell_source(old, old_debug), cell_source(new, new_debug))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_diffs_align_cells_and_pad_the_smaller_grid() {
+        let parse = |src: &str| src.parse::<Grid>().unwrap();
+        let small = parse("1!@");
+        assert!(diff(&small, &parse("....1!..@")).is_empty());
+        let changed: Vec<_> = diff(&small, &parse("1`!)........@")).iter().map(|diff| diff.to_string()).collect();
+        assert_eq!(changed, [
+            "(0, -2): . -> 1", "(1, -2): . -> `!", "(2, -2): . -> )", "(0, -1): 1 -> .", "(1, -1): ! -> .",
+            "(-1, 0): @ -> .", "(2, 0): . -> @",
+        ]);
+        let flagged = diff(&small, &parse("1`!@"));
+        assert_eq!(flagged, [CellDiff { coords: PointAxial(1, -1), old: (Op::WriteInt, false), new: (Op::WriteInt, true) }]);
+    }
+}
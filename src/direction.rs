@@ -1,11 +1,11 @@
-use std::fmt;
+use std::{fmt, str::FromStr};
 
-use crate::coords::PointAxial;
+use crate::{Error, coords::PointAxial};
 use Direction::*;
 use Redirect::*;
 
 /// Subset of instructions that change the direction of the current IP.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Redirect {
     MirrorHori,
     MirrorVert,
@@ -16,7 +16,7 @@ pub enum Redirect {
 }
 
 /// Possible directions of travel for each IP.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Direction {
     NorthEast,
     NorthWest,
@@ -26,10 +26,90 @@ pub enum Direction {
     East,
 }
 
+/// A line through the center of the grid that a program can be reflected across.
+///
+/// Mirrors only lie at the angles of `_`, `/`, `|` and `\`, which are only reflected onto each other
+/// across these two lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    /// The line through the left and right corners
+    Horizontal,
+    /// The line through the middles of the top and bottom edges
+    Vertical,
+}
+
+impl Redirect {
+    /// Returns an iterator over all redirects.
+    pub fn all() -> impl Iterator<Item = Redirect> {
+        [MirrorHori, MirrorVert, MirrorForw, MirrorBack, BranchLeft, BranchRight].iter().copied()
+    }
+
+    /// Returns the mirror image of the redirect across an axis.
+    ///
+    /// IPs that hit it from the mirror image of a direction leave in the mirror image of the direction
+    /// they would have left in, except that branches turn the other way for the same sign.
+    pub fn reflect(self, axis: Axis) -> Redirect {
+        match (self, axis) {
+            (MirrorForw, _) => MirrorBack,
+            (MirrorBack, _) => MirrorForw,
+            (BranchLeft, Axis::Vertical) => BranchRight,
+            (BranchRight, Axis::Vertical) => BranchLeft,
+            (redir, _) => redir,
+        }
+    }
+}
+
 impl Direction {
+    /// Returns an iterator over all directions.
+    pub fn all() -> impl Iterator<Item = Direction> {
+        [NorthEast, NorthWest, West, SouthWest, SouthEast, East].iter().copied()
+    }
+
+    /// Returns the index of the direction in clockwise order, starting with `East` at 0.
+    pub fn index(self) -> usize {
+        match self {
+            East => 0,
+            SouthEast => 1,
+            SouthWest => 2,
+            West => 3,
+            NorthWest => 4,
+            NorthEast => 5,
+        }
+    }
+
+    /// Returns the direction with the given index in clockwise order, starting with `East` at 0.
+    ///
+    /// The index wraps around, so 6 is `East` again.
+    pub fn from_index(index: usize) -> Direction {
+        [East, SouthEast, SouthWest, West, NorthWest, NorthEast][index % 6]
+    }
+
+    /// Returns the direction after turning `n` sixths of a full turn clockwise.
+    pub fn rotate_cw(self, n: usize) -> Direction {
+        Direction::from_index(self.index() + n % 6)
+    }
+
+    /// Returns the direction after turning `n` sixths of a full turn counterclockwise.
+    pub fn rotate_ccw(self, n: usize) -> Direction {
+        Direction::from_index(self.index() + 6 - n % 6)
+    }
+
+    /// Returns the mirror image of the direction across an axis.
+    pub fn reflect(self, axis: Axis) -> Direction {
+        match axis {
+            Axis::Horizontal => Direction::from_index(6 - self.index()),
+            Axis::Vertical => Direction::from_index(9 - self.index()),
+        }
+    }
+
+    /// Returns the opposite direction.
+    pub fn opposite(self) -> Direction {
+        self.rotate_cw(3)
+    }
+
     /// Returns a `PointAxial` representing one grid space of movement
     /// in a given direction.
-    pub fn to_vector(&self) -> PointAxial {
+    pub fn to_vector(self) -> PointAxial {
         match self {
             NorthEast => PointAxial(1, -1),
             NorthWest => PointAxial(0, -1),
@@ -84,6 +164,74 @@ pub fn redirect(dir: Direction, redir: Redirect, positive: bool) -> Direction {
     }
 }
 
+/// Returns an iterator over every entry `(dir, redir, positive, result)` of the redirect table,
+/// where `result` is `redirect(dir, redir, positive)`.
+pub fn redirect_table() -> impl Iterator<Item = (Direction, Redirect, bool, Direction)> {
+    Direction::all().flat_map(|dir| Redirect::all().flat_map(move |redir| {
+        [false, true].iter().map(move |&positive| (dir, redir, positive, redirect(dir, redir, positive)))
+    }))
+}
+
+/// Returns every `Direction` that is reflected into `result` when it hits `redir`
+/// with the given sign of the current memory edge.
+pub fn redirect_sources(result: Direction, redir: Redirect, positive: bool) -> Vec<Direction> {
+    Direction::all().filter(|&dir| redirect(dir, redir, positive) == result).collect()
+}
+
+impl From<Direction> for usize {
+    fn from(dir: Direction) -> usize {
+        dir.index()
+    }
+}
+
+impl FromStr for Direction {
+    type Err = Error;
+
+    /// Parses an abbreviated direction name such as `NE`, or a full name such as `NorthEast`,
+    /// ignoring case.
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "ne" | "northeast" => NorthEast,
+            "nw" | "northwest" => NorthWest,
+            "w" | "west" => West,
+            "sw" | "southwest" => SouthWest,
+            "se" | "southeast" => SouthEast,
+            "e" | "east" => East,
+            _ => return Err(Error::ParseError("direction", s.to_string())),
+        })
+    }
+}
+
+impl FromStr for Redirect {
+    type Err = Error;
+
+    /// Parses the source code character of a redirect, such as `/`.
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Ok(match s {
+            "_" => MirrorHori,
+            "|" => MirrorVert,
+            "/" => MirrorForw,
+            "\\" => MirrorBack,
+            "<" => BranchLeft,
+            ">" => BranchRight,
+            _ => return Err(Error::ParseError("redirect", s.to_string())),
+        })
+    }
+}
+
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            MirrorHori => '_',
+            MirrorVert => '|',
+            MirrorForw => '/',
+            MirrorBack => '\\',
+            BranchLeft => '<',
+            BranchRight => '>',
+        })
+    }
+}
+
 impl fmt::Display for Direction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", match self {
@@ -96,3 +244,40 @@ impl fmt::Display for Direction {
         })
     }
 }
+
+/// Implements serde support for a type through its `Display` and `FromStr` implementations.
+macro_rules! serde_via_str {
+    ($($ty:ty),*) => {$(
+        #[cfg(feature = "serde")]
+        impl serde::Serialize for $ty {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de> serde::Deserialize<'de> for $ty {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+            }
+        }
+    )*};
+}
+
+serde_via_str!(Direction, Redirect);
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use serde::{Deserialize, de::{IntoDeserializer, value::Error as ValueError}};
+
+    use super::*;
+
+    #[test]
+    fn directions_and_redirects_deserialize_by_name() {
+        let de = |s: &'static str| IntoDeserializer::<ValueError>::into_deserializer(s);
+        assert_eq!(Direction::deserialize(de("NorthEast")).unwrap(), Direction::NorthEast);
+        assert_eq!(Direction::deserialize(de("sw")).unwrap(), Direction::SouthWest);
+        assert_eq!(Redirect::deserialize(de("<")).unwrap(), Redirect::BranchLeft);
+        assert!(Direction::deserialize(de("up")).unwrap_err().to_string().contains("up"));
+    }
+}
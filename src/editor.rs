@@ -0,0 +1,260 @@
+use std::{fs, io::{self, Read, Write}, mem::MaybeUninit, path::PathBuf};
+
+use crate::{Builder, Direction, Error, coords::{self, PointAxial}, grid::Grid};
+
+/// Maximum number of ticks a program runs for when run from the editor.
+const MAX_TICKS: u64 = 1_000_000;
+
+const HELP: &str = "\
+Arrows: move W/E and NW/SE   PgUp/PgDn: move NE/SW   Backspace/Del: delete
+Ctrl-R: run   Ctrl-S: save   Ctrl-Q: quit";
+
+/// A terminal editor that lays out a program as a hexagon while it's being written.
+///
+/// Typed commands are inserted at the cursor, shifting the rest of the program along, and the
+/// hexagon grows or shrinks to fit. Whitespace and debug flags are discarded when a file is opened.
+pub struct Editor {
+    path: PathBuf,
+    ops: Vec<char>,
+    cursor: usize,
+    message: String,
+}
+
+/// A key read from the terminal.
+pub(crate) enum Key {
+    Char(char),
+    Move(Direction),
+    Backspace,
+    Delete,
+    Ctrl(u8),
+    Other,
+}
+
+impl Editor {
+    /// Creates a new `Editor` for a source file, which doesn't need to exist yet.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Editor, Error> {
+        let path = path.into();
+        let src = match fs::read_to_string(&path) {
+            Ok(src) => src,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let ops = src.chars().filter(|&c| !c.is_whitespace() && c != '`').collect();
+        Ok(Editor { path, ops, cursor: 0, message: String::new() })
+    }
+
+    /// Runs the editor until the user quits.
+    pub fn run(&mut self) -> Result<(), Error> {
+        let _raw = RawMode::enable()?;
+        let mut stdin = io::stdin();
+        loop {
+            self.draw()?;
+            match read_key(&mut stdin)? {
+                Key::Ctrl(b'q') => break,
+                Key::Ctrl(b's') => self.save()?,
+                Key::Ctrl(b'r') => self.run_program(&mut stdin)?,
+                key => self.press(key),
+            }
+        }
+        print!("\x1b[H\x1b[2J");
+        Ok(io::stdout().flush()?)
+    }
+
+    /// Edits the program or moves the cursor for a key that doesn't need the terminal.
+    pub(crate) fn press(&mut self, key: Key) {
+        match key {
+            Key::Move(dir) => self.move_cursor(dir),
+            Key::Char(c) if crate::Op::from_char(c).is_some() => {
+                // Pad with no-ops when typing past the end of the program
+                while self.ops.len() < self.cursor {
+                    self.ops.push('.');
+                }
+                self.ops.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Char(c) => self.message = format!("`{}` is not a command", c),
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                if self.cursor < self.ops.len() {
+                    self.ops.remove(self.cursor);
+                }
+            }
+            Key::Delete if self.cursor < self.ops.len() => {
+                self.ops.remove(self.cursor);
+            }
+            _ => (),
+        }
+        // Keep the cursor within the hexagon that fits the program, or just past its end
+        self.cursor = self.cursor.min(self.ops.len().max(cells(fit(self.ops.len())) - 1));
+    }
+
+    /// Returns the side length of the hexagon to show, which fits the program and the cursor.
+    fn size(&self) -> usize {
+        fit(self.ops.len().max(self.cursor + 1))
+    }
+
+    /// Moves the cursor one cell in a direction, if that cell is in the grid.
+    fn move_cursor(&mut self, dir: Direction) {
+        let size = self.size();
+        let coords = coords::rows(size).nth(self.cursor).unwrap() + dir.to_vector();
+        if let Some(i) = coords::rows(size).position(|c| c == coords) {
+            self.cursor = i;
+        }
+    }
+
+    /// Returns the program as source code.
+    pub(crate) fn source(&self) -> String {
+        self.ops.iter().collect()
+    }
+
+    /// Writes the program to its file as a hexagon.
+    fn save(&mut self) -> Result<(), Error> {
+        let grid: Grid = self.source().parse()?;
+        fs::write(&self.path, grid.to_string())?;
+        self.message = format!("Saved {}", self.path.display());
+        Ok(())
+    }
+
+    /// Runs the program with no input and shows its output until a key is pressed.
+    fn run_program(&mut self, stdin: &mut io::Stdin) -> Result<(), Error> {
+        let mut output = Vec::new();
+        let result = Builder::new(&self.source())
+            .input(io::empty())
+            .output(&mut output)
+            .max_ticks(MAX_TICKS)
+            .build()
+            .and_then(|mut hex| hex.run());
+        let mut out = io::stdout();
+        write!(out, "\x1b[H\x1b[2JOutput:\r\n{}\r\n\r\n", String::from_utf8_lossy(&output).replace('\n', "\r\n"))?;
+        match result {
+            Ok(()) => write!(out, "The program terminated.")?,
+            Err(e) => write!(out, "Error: {}", e)?,
+        }
+        write!(out, "\r\nPress any key to return to the editor.")?;
+        out.flush()?;
+        read_key(stdin)?;
+        self.message.clear();
+        Ok(())
+    }
+
+    /// Redraws the screen.
+    fn draw(&self) -> Result<(), Error> {
+        let size = self.size();
+        let mut screen = String::from("\x1b[H\x1b[2J");
+        let mut row = None;
+        for (i, coords) in coords::rows(size).enumerate() {
+            if row != Some(coords.1) {
+                if row.is_some() {
+                    screen += "\r\n";
+                }
+                row = Some(coords.1);
+                screen += &" ".repeat(coords.1.unsigned_abs());
+            }
+            let c = self.ops.get(i).copied().unwrap_or('.');
+            match i == self.cursor {
+                true => screen += &format!(" \x1b[7m{}\x1b[0m", c),
+                false => screen += &format!(" {}", c),
+            }
+        }
+        let PointAxial(q, r) = coords::rows(size).nth(self.cursor).unwrap();
+        screen += &format!(
+            "\r\n\r\n{}  size {}  {} ops  cell ({}, {})\r\n{}\r\n{}",
+            self.path.display(), size, self.ops.len(), q, r, HELP.replace('\n', "\r\n"), self.message,
+        );
+        let mut out = io::stdout();
+        out.write_all(screen.as_bytes())?;
+        Ok(out.flush()?)
+    }
+}
+
+/// Returns the number of cells in a hexagon of the given side length.
+fn cells(size: usize) -> usize {
+    3 * size * (size - 1) + 1
+}
+
+/// Returns the side length of the smallest hexagon with at least `len` cells.
+fn fit(len: usize) -> usize {
+    (1..).find(|&n| cells(n) >= len).unwrap()
+}
+
+/// Reads a key press, decoding the escape sequences of arrows and other special keys.
+fn read_key(stdin: &mut io::Stdin) -> Result<Key, Error> {
+    let mut next = || -> io::Result<Option<u8>> {
+        let mut buf = [0];
+        Ok(match stdin.read(&mut buf)? {
+            0 => None,
+            _ => Some(buf[0]),
+        })
+    };
+    Ok(match next()? {
+        // Treat the end of the input like Ctrl-Q
+        None => Key::Ctrl(b'q'),
+        Some(b'\x1b') => match (next()?, next()?) {
+            (Some(b'['), Some(b'A')) => Key::Move(Direction::NorthWest),
+            (Some(b'['), Some(b'B')) => Key::Move(Direction::SouthEast),
+            (Some(b'['), Some(b'C')) => Key::Move(Direction::East),
+            (Some(b'['), Some(b'D')) => Key::Move(Direction::West),
+            (Some(b'['), Some(c @ (b'3' | b'5' | b'6'))) => match (next()?, c) {
+                (Some(b'~'), b'3') => Key::Delete,
+                (Some(b'~'), b'5') => Key::Move(Direction::NorthEast),
+                (Some(b'~'), b'6') => Key::Move(Direction::SouthWest),
+                _ => Key::Other,
+            },
+            _ => Key::Other,
+        },
+        Some(127) | Some(8) => Key::Backspace,
+        Some(b @ 1..=26) => Key::Ctrl(b - 1 + b'a'),
+        Some(b) if b.is_ascii() => Key::Char(b as char),
+        Some(_) => Key::Other,
+    })
+}
+
+/// Puts the terminal into raw mode until dropped, so keys are read as they're pressed.
+struct RawMode(libc::termios);
+
+impl RawMode {
+    /// Enables raw mode, remembering the previous settings.
+    fn enable() -> Result<RawMode, Error> {
+        unsafe {
+            let mut termios = MaybeUninit::uninit();
+            if libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            let original = termios.assume_init();
+            let mut raw = original;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &raw) != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            Ok(RawMode(original))
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &self.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn editor_keys_insert_delete_and_move_along_the_hexagon() {
+        let path = std::env::temp_dir().join(format!("hexagony-editor-{}.hxg", std::process::id()));
+        let mut editor = Editor::open(&path).unwrap();
+        // From (-1, 0) to (-1, 1), padding the cells in between with no-ops
+        for key in [Key::Char('1'), Key::Char('!'), Key::Move(Direction::SouthEast), Key::Char('@')] {
+            editor.press(key);
+        }
+        assert_eq!(editor.source(), "1!...@");
+        for key in [Key::Backspace, Key::Move(Direction::NorthEast), Key::Char(')'), Key::Delete, Key::Char(' ')] {
+            editor.press(key);
+        }
+        assert_eq!(editor.source(), "1!.).");
+    }
+}
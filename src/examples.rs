@@ -0,0 +1,114 @@
+use std::io::Cursor;
+
+use crate::{Builder, Error};
+
+/// A classic Hexagony program bundled with the crate.
+pub struct Example {
+    pub name: &'static str,
+    pub description: &'static str,
+    source: &'static str,
+    /// Input that the program can be run with, such as in tests.
+    pub sample_input: &'static str,
+    /// Output of the program for `sample_input`.
+    pub sample_output: &'static str,
+}
+
+/// Every bundled example, from simplest to most complex.
+const EXAMPLES: &[Example] = &[
+    Example {
+        name: "cat",
+        description: "Prints its input unchanged.",
+        source: r#"
+ \ ;
+_ ) (
+ @ ,
+"#,
+        sample_input: "Hello, World!\n",
+        sample_output: "Hello, World!\n",
+    },
+    Example {
+        name: "truth-machine",
+        description: "Reads a number and prints it once if it's 0, or forever if it's 1.",
+        source: r#"
+ ? _
+! < @
+ > .
+"#,
+        sample_input: "0",
+        sample_output: "0",
+    },
+    Example {
+        name: "hello",
+        description: "Prints `Hello, World!`.",
+        source: r#"
+   H ; e ;
+  l ; d ; *
+ ; r ; o ; W
+l ; ; o ; * 4
+ 3 3 ; @ . >
+  ; 2 3 < \
+   4 ; * /
+"#,
+        sample_input: "",
+        sample_output: "Hello, World!",
+    },
+    Example {
+        name: "primes",
+        description: "Reads a number n >= 2 and prints 1 if it's prime, or 0 if not, by trial division.",
+        source: r#"
+    ? " } = )
+   . . . . . .
+  > } = : ! @ .
+ \ ) : = } & ) \
+& = $ > ( ' % < .
+ . . \ . . . } /
+  . . . . . . .
+   . . . . . .
+    . . . . .
+"#,
+        sample_input: "97",
+        sample_output: "1",
+    },
+];
+
+impl Example {
+    /// Returns the source code of this example, laid out as a hexagon.
+    pub fn source(&self) -> &'static str {
+        self.source.trim_start_matches('\n')
+    }
+
+    /// Runs this example with `sample_input` and returns its output.
+    pub fn run_sample(&self, max_ticks: u64) -> Result<Vec<u8>, Error> {
+        let mut output = Vec::new();
+        Builder::new(self.source)
+            .input(Cursor::new(self.sample_input.as_bytes()))
+            .output(&mut output)
+            .max_ticks(max_ticks)
+            .build()?
+            .run()?;
+        Ok(output)
+    }
+}
+
+/// Returns every bundled example.
+pub fn examples() -> &'static [Example] {
+    EXAMPLES
+}
+
+/// Returns the bundled example with the given name.
+pub fn find(name: &str) -> Option<&'static Example> {
+    EXAMPLES.iter().find(|example| example.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn examples_produce_sample_output() {
+        for example in examples() {
+            let output = example.run_sample(100_000).unwrap();
+            assert_eq!(output, example.sample_output.as_bytes(), "example {}", example.name);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::fmt;
+
+use crate::{
+    Builder, Error, IP, Wrapping,
+    compile::step,
+    coords::{self, PointAxial},
+    direction::{Direction, Redirect},
+    grid::{Grid, Op},
+};
+
+/// A path that an IP follows in the original program but no longer follows after expansion.
+#[derive(Clone, Copy)]
+pub struct BrokenPath {
+    /// The index of the IP if the path leads from its initial state
+    start: Option<usize>,
+    /// The position and direction of the IP before the path
+    from: (PointAxial, Direction),
+    /// Whether the current memory edge is positive along the path
+    positive: bool,
+    /// The position and direction of the IP at the end of the path
+    to: (PointAxial, Direction),
+}
+
+/// Pads a program to a hexagon of the given side length, placing mirrors in the new cells so that
+/// IPs that wrap around the edges of the original program still reach the same cells.
+///
+/// Every placement of the original program within the larger hexagon is tried, and the one that
+/// preserves the most paths is kept. Paths that couldn't be preserved are returned alongside the
+/// expanded grid, using the coordinates of the expanded grid.
+///
+/// # Panics
+///
+/// Panics if `size` is smaller than the side length of the program.
+pub fn expand(src: &str, size: usize) -> Result<(Grid, Vec<BrokenPath>), Error> {
+    Builder::new(src).expand(size)
+}
+
+/// Expands a program, as described by `expand`, whose IPs follow the given wrapping rule and start
+/// from `starts` in the original program and `new_starts` in the expanded one.
+pub(crate) fn expand_grid(old: &Grid, size: usize, wrapping: Wrapping, starts: &[IP], new_starts: &[IP]) -> (Grid, Vec<BrokenPath>) {
+    assert!(size >= old.size(), "cannot expand a program to a smaller hexagon");
+    let offsets = coords::spiral(size - old.size() + 1);
+    offsets
+        .map(|offset| place(old, size, offset, wrapping, (starts, new_starts)))
+        .min_by_key(|(_, broken)| broken.len())
+        .unwrap()
+}
+
+/// Copies a program into a larger grid at the given offset and tries to preserve its paths.
+fn place(old: &Grid, size: usize, offset: PointAxial, wrapping: Wrapping, starts: (&[IP], &[IP])) -> (Grid, Vec<BrokenPath>) {
+    let mut grid = Grid::new(size);
+    for (coords, op, debug) in old.cells() {
+        grid.set(coords + offset, (op, debug));
+    }
+    let inside = |p: PointAxial| old.contains(p - offset);
+    let paths = paths(old, offset, wrapping, starts);
+    let mut kept = Vec::new();
+    for path in &paths {
+        if !follows(&grid, wrapping, &inside, path) {
+            match mend(&grid, wrapping, &inside, path, &kept) {
+                Some(mended) => grid = mended,
+                None => continue,
+            }
+        }
+        kept.push(*path);
+    }
+    let broken = paths.into_iter().filter(|path| !follows(&grid, wrapping, &inside, path)).collect();
+    (grid, broken)
+}
+
+/// Returns every path that an IP can follow in the original program, translated by `offset`.
+///
+/// These are the paths from each initial IP state to the original initial state, and the
+/// single steps between every reachable state in the original program.
+fn paths(old: &Grid, offset: PointAxial, wrapping: Wrapping, (old_starts, new_starts): (&[IP], &[IP])) -> Vec<BrokenPath> {
+    let old_starts: Vec<_> = old_starts.iter().map(|ip| (ip.coords, ip.dir)).collect();
+    let new_starts: Vec<_> = new_starts.iter().map(|ip| (ip.coords, ip.dir)).collect();
+    let mut states = vec![old_starts[0]];
+    let mut i = 0;
+    let mut switches_ip = false;
+    while i < states.len() {
+        let state = states[i];
+        i += 1;
+        match old.get(state.0).0 {
+            Op::Terminate => continue,
+            Op::IPPrev | Op::IPNext | Op::IPSelect if !switches_ip => {
+                switches_ip = true;
+                states.extend_from_slice(&old_starts[1..]);
+            }
+            _ => (),
+        }
+        for &positive in &[false, true] {
+            let next = step(old, wrapping, state, positive);
+            if !states.contains(&next) {
+                states.push(next);
+            }
+        }
+    }
+    let moved = |(coords, dir): (PointAxial, Direction)| (coords + offset, dir);
+    let mut paths = Vec::new();
+    let ips = if switches_ip { old_starts.len() } else { 1 };
+    for ip in 0..ips {
+        // The first IP starts with an empty memory edge
+        for &positive in &[false, true][..if ip == 0 { 1 } else { 2 }] {
+            paths.push(BrokenPath { start: Some(ip), from: new_starts[ip], positive, to: moved(old_starts[ip]) });
+        }
+    }
+    for &state in &states {
+        if let Op::Terminate = old.get(state.0).0 {
+            continue;
+        }
+        for &positive in &[false, true] {
+            let to = moved(step(old, wrapping, state, positive));
+            paths.push(BrokenPath { start: None, from: moved(state), positive, to });
+        }
+    }
+    paths
+}
+
+/// Returns whether an IP in the expanded grid follows a path.
+fn follows(grid: &Grid, wrapping: Wrapping, inside: &impl Fn(PointAxial) -> bool, path: &BrokenPath) -> bool {
+    let trail = trail(grid, wrapping, inside, path);
+    trail.last().filter(|&&(coords, _)| inside(coords)) == Some(&path.to)
+}
+
+/// Returns the states an IP passes through in the expanded grid while following a path,
+/// ending at the first state inside the original program or when the IP gets stuck in a loop.
+fn trail(grid: &Grid, wrapping: Wrapping, inside: &impl Fn(PointAxial) -> bool, path: &BrokenPath) -> Vec<(PointAxial, Direction)> {
+    let mut state = match path.start {
+        Some(_) => path.from,
+        None => step(grid, wrapping, path.from, path.positive),
+    };
+    let mut trail = vec![state];
+    let limit = 6 * grid.cells().count();
+    while !inside(state.0) && trail.len() <= limit {
+        state = step(grid, wrapping, state, path.positive);
+        trail.push(state);
+    }
+    trail
+}
+
+/// Tries placing one or two mirrors in empty cells along a path so that an IP follows it,
+/// without breaking any of the `kept` paths.
+fn mend(grid: &Grid, wrapping: Wrapping, inside: &impl Fn(PointAxial) -> bool, path: &BrokenPath, kept: &[BrokenPath]) -> Option<Grid> {
+    let mirrors = [Redirect::MirrorHori, Redirect::MirrorVert, Redirect::MirrorForw, Redirect::MirrorBack];
+    let empty = |grid: &Grid| {
+        let mut cells: Vec<_> = trail(grid, wrapping, inside, path).into_iter()
+            .map(|(coords, _)| coords)
+            .filter(|&coords| !inside(coords) && matches!(grid.get(coords).0, Op::Nop))
+            .collect();
+        cells.dedup();
+        cells
+    };
+    let works = |grid: &Grid| follows(grid, wrapping, inside, path) && kept.iter().all(|p| follows(grid, wrapping, inside, p));
+    for first in empty(grid) {
+        for &m1 in &mirrors {
+            let mut once = grid.clone();
+            once.set(first, (Op::Redir(m1), false));
+            if works(&once) {
+                return Some(once);
+            }
+            for second in empty(&once) {
+                for &m2 in &mirrors {
+                    let mut twice = once.clone();
+                    twice.set(second, (Op::Redir(m2), false));
+                    if works(&twice) {
+                        return Some(twice);
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+impl fmt::Display for BrokenPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ((from, from_dir), (to, to_dir)) = (self.from, self.to);
+        let sign = if self.positive { "positive" } else { "non-positive" };
+        match self.start {
+            Some(ip) => write!(f, "IP {} starting at {} moving {} with a {} memory edge no longer reaches {} moving {}",
+                ip, from, from_dir, sign, to, to_dir),
+            None => write!(f, "IP at {} moving {} with a {} memory edge no longer reaches {} moving {}",
+                from, from_dir, sign, to, to_dir),
+        }
+    }
+}
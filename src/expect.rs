@@ -0,0 +1,181 @@
+use std::{
+    io::{self, Read, Write},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{Builder, Error, grid::Grid};
+
+/// A program running on another thread that can be scripted like a conversation:
+/// wait until it prints some text, then send it some input.
+///
+/// Dropping the session closes the program's input and output, which ends it the next time
+/// it reads past the end of its input or writes anything. A program stuck in a loop that
+/// does neither keeps running on its thread, so consider a tick limit.
+pub struct Session {
+    input: Option<Sender<Vec<u8>>>,
+    output: Receiver<Vec<u8>>,
+    buffer: Vec<u8>,
+    handle: Option<JoinHandle<Result<(), Error>>>,
+}
+
+impl Session {
+    /// Starts running a program, aborting it after `max_ticks` ticks if given.
+    pub fn spawn(src: &str, max_ticks: Option<u64>) -> Result<Session, Error> {
+        let grid: Grid = src.parse()?;
+        let (input_tx, input_rx) = mpsc::channel();
+        let (output_tx, output_rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let input = ChannelReader { rx: input_rx, buffer: Vec::new(), pos: 0 };
+            let mut builder = Builder::from_grid(grid).input(input).output(ChannelWriter(output_tx));
+            if let Some(max_ticks) = max_ticks {
+                builder = builder.max_ticks(max_ticks);
+            }
+            builder.build()?.run()
+        });
+        Ok(Session { input: Some(input_tx), output: output_rx, buffer: Vec::new(), handle: Some(handle) })
+    }
+
+    /// Sends input to the program.
+    ///
+    /// Input sent after closing the input or after the program ended is ignored.
+    pub fn send(&mut self, input: &[u8]) {
+        if let Some(tx) = &self.input {
+            tx.send(input.to_vec()).ok();
+        }
+    }
+
+    /// Closes the program's input, so reading past the input sent so far gives EOF.
+    pub fn close_input(&mut self) {
+        self.input = None;
+    }
+
+    /// Waits until the program's output contains `text`, for at most `timeout`.
+    ///
+    /// Returns the output before `text`, discarding it and `text` from the session so the
+    /// next call only searches output that came after.
+    ///
+    /// Fails with `Error::ExpectFailed` if the timeout passes or the program ends first,
+    /// or with the program's own error if it failed.
+    pub fn expect(&mut self, text: &[u8], timeout: Duration) -> Result<Vec<u8>, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(pos) = find(&self.buffer, text) {
+                let rest = self.buffer.split_off(pos + text.len());
+                self.buffer.truncate(pos);
+                return Ok(std::mem::replace(&mut self.buffer, rest));
+            }
+            match self.output.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(bytes) => self.buffer.extend(bytes),
+                Err(RecvTimeoutError::Timeout) => return Err(Error::ExpectFailed(format!(
+                    "timed out waiting for {:?}, output so far: {:?}",
+                    String::from_utf8_lossy(text), String::from_utf8_lossy(&self.buffer),
+                ))),
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.join()?;
+                    return Err(Error::ExpectFailed(format!(
+                        "program ended without printing {:?}, output so far: {:?}",
+                        String::from_utf8_lossy(text), String::from_utf8_lossy(&self.buffer),
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Closes the program's input and waits for at most `timeout` for it to end.
+    ///
+    /// Returns all output that hasn't been consumed by `expect`.
+    pub fn finish(mut self, timeout: Duration) -> Result<Vec<u8>, Error> {
+        self.close_input();
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.output.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(bytes) => self.buffer.extend(bytes),
+                Err(RecvTimeoutError::Timeout) => return Err(Error::ExpectFailed(format!(
+                    "timed out waiting for the program to end, output so far: {:?}",
+                    String::from_utf8_lossy(&self.buffer),
+                ))),
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.join()?;
+                    return Ok(std::mem::take(&mut self.buffer));
+                }
+            }
+        }
+    }
+
+    /// Waits for the program's thread and returns its result.
+    fn join(&mut self) -> Result<(), Error> {
+        match self.handle.take() {
+            Some(handle) => handle.join().expect("interpreter thread panicked"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns the position of the first occurrence of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Program input received from a `Session`.
+struct ChannelReader {
+    rx: Receiver<Vec<u8>>,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buffer.len() {
+            match self.rx.recv() {
+                Ok(bytes) => {
+                    self.buffer = bytes;
+                    self.pos = 0;
+                }
+                // The session closed the input
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = (&self.buffer[self.pos..]).read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Program output sent to a `Session`.
+struct ChannelWriter(Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.0.send(buf.to_vec()) {
+            Ok(()) => Ok(buf.len()),
+            Err(_) => Err(io::Error::new(io::ErrorKind::BrokenPipe, "session closed")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_wait_for_output_before_sending_input() {
+        let timeout = Duration::from_secs(5);
+        let mut session = Session::spawn("1!?)!@", Some(1000)).unwrap();
+        assert_eq!(session.expect(b"1", timeout).unwrap(), b"");
+        session.send(b"41\n");
+        assert_eq!(session.finish(timeout).unwrap(), b"42");
+        let mut session = Session::spawn(",!@", None).unwrap();
+        assert!(matches!(session.expect(b"0", Duration::from_millis(50)), Err(Error::ExpectFailed(_))));
+        session.close_input();
+        assert!(matches!(session.expect(b"0", timeout), Err(Error::ExpectFailed(msg)) if msg.contains("ended")));
+    }
+}
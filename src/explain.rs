@@ -0,0 +1,99 @@
+use rug::Integer;
+
+use crate::{direction::Direction, grid::Op};
+
+/// Values of interest captured immediately before an instruction is executed.
+pub struct Snapshot {
+    pub left: Integer,
+    pub current: Integer,
+    pub right: Integer,
+    pub dir: Direction,
+}
+
+/// Returns a one-line plain-English description of what an instruction did,
+/// given the state before it was executed, the new value of the current memory edge,
+/// the new direction of the IP and the index of the next IP.
+pub fn explain(op: Op, before: &Snapshot, value: &Integer, dir: Direction, next_idx: usize) -> String {
+    let Snapshot { left, current, right, .. } = before;
+    let sign = if *current > 0 { "positive" } else { "not positive" };
+    let desc = match op {
+        Op::Nop => "do nothing".to_string(),
+        Op::Terminate => "terminate the program".to_string(),
+        Op::Letter(b) => format!("set current edge to {} ('{}')", b, b as char),
+        Op::Digit(d) => format!("set current edge to {} * 10 + {} = {}", current, d, value),
+        Op::Increment => format!("increment current edge from {} to {}", current, value),
+        Op::Decrement => format!("decrement current edge from {} to {}", current, value),
+        Op::Add => format!("set current edge to left ({}) + right ({}) = {}", left, right, value),
+        Op::Subtract => format!("set current edge to left ({}) - right ({}) = {}", left, right, value),
+        Op::Multiply => format!("set current edge to left ({}) * right ({}) = {}", left, right, value),
+        Op::Divide => format!("set current edge to left ({}) / right ({}) = {}", left, right, value),
+        Op::Modulo => format!("set current edge to left ({}) % right ({}) = {}", left, right, value),
+        Op::Negate => format!("negate current edge from {} to {}", current, value),
+        Op::ReadByte => format!("read a byte into current edge: {}", value),
+        Op::ReadInt => format!("read an integer into current edge: {}", value),
+        Op::WriteByte => {
+            let b = current.mod_u(256) as u8;
+            format!("write byte {} ({:?})", b, b as char)
+        }
+        Op::WriteInt => format!("write integer {}", current),
+        Op::Jump => "skip next cell".to_string(),
+        Op::Redir(_) if dir == before.dir => format!("keep moving {}", dir),
+        Op::Redir(_) => format!("redirect IP from {} to {} (current edge is {})", before.dir, dir, sign),
+        Op::IPPrev => format!("switch to previous IP {}", next_idx),
+        Op::IPNext => format!("switch to next IP {}", next_idx),
+        Op::IPSelect => format!("switch to IP {} (selected by current edge {})", next_idx, current),
+        Op::MPLeft => "move MP to left neighbour".to_string(),
+        Op::MPRight => "move MP to right neighbour".to_string(),
+        Op::MPBackLeft => "move MP backwards to the left".to_string(),
+        Op::MPBackRight => "move MP backwards to the right".to_string(),
+        Op::MPReverse => "reverse direction of MP".to_string(),
+        Op::MPBranch if *current > 0 => "current edge is positive, move MP to right neighbour".to_string(),
+        Op::MPBranch => "current edge is not positive, move MP to left neighbour".to_string(),
+        Op::MemCopy if *current > 0 => format!("current edge is positive, copy right neighbour ({})", value),
+        Op::MemCopy => format!("current edge is not positive, copy left neighbour ({})", value),
+        Op::Random => format!("set current edge to a random value: {}", value),
+        Op::Tick => format!("set current edge to the current tick {}", value),
+        Op::Env => format!("look up variable {} and set current edge to its value: {}", current, value),
+        Op::Custom(_) => format!("run custom instruction, current edge {} -> {}", current, value),
+    };
+    format!("`{}`: {}", op, desc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::direction::Redirect;
+
+    #[test]
+    fn explanations_describe_each_op_with_its_values() {
+        let before = Snapshot { left: Integer::from(7), current: Integer::from(3), right: Integer::from(-2), dir: Direction::East };
+        let cases = [
+            (Op::Add, 5, Direction::East, 0, "`+`: set current edge to left (7) + right (-2) = 5"),
+            (Op::Subtract, 9, Direction::East, 0, "`-`: set current edge to left (7) - right (-2) = 9"),
+            (Op::Multiply, -14, Direction::East, 0, "`*`: set current edge to left (7) * right (-2) = -14"),
+            (Op::Divide, -4, Direction::East, 0, "`:`: set current edge to left (7) / right (-2) = -4"),
+            (Op::Modulo, -1, Direction::East, 0, "`%`: set current edge to left (7) % right (-2) = -1"),
+            (Op::Digit(4), 34, Direction::East, 0, "`4`: set current edge to 3 * 10 + 4 = 34"),
+            (Op::Increment, 4, Direction::East, 0, "`)`: increment current edge from 3 to 4"),
+            (Op::Negate, -3, Direction::East, 0, "`~`: negate current edge from 3 to -3"),
+            (Op::MPLeft, 3, Direction::East, 0, "`{`: move MP to left neighbour"),
+            (Op::MPBackRight, 3, Direction::East, 0, "`'`: move MP backwards to the right"),
+            (Op::MPBranch, 3, Direction::East, 0, "`^`: current edge is positive, move MP to right neighbour"),
+            (Op::MemCopy, -2, Direction::East, 0, "`&`: current edge is positive, copy right neighbour (-2)"),
+            (Op::Redir(Redirect::BranchLeft), 3, Direction::SouthEast, 0,
+                "`<`: redirect IP from E to SE (current edge is positive)"),
+            (Op::Redir(Redirect::MirrorHori), 3, Direction::East, 0, "`_`: keep moving E"),
+            (Op::IPNext, 3, Direction::East, 1, "`]`: switch to next IP 1"),
+            (Op::IPPrev, 3, Direction::East, 5, "`[`: switch to previous IP 5"),
+            (Op::IPSelect, 3, Direction::East, 3, "`#`: switch to IP 3 (selected by current edge 3)"),
+        ];
+        for &(op, value, dir, next_idx, expected) in &cases {
+            assert_eq!(explain(op, &before, &Integer::from(value), dir, next_idx), expected);
+        }
+        let zero = Snapshot { current: Integer::new(), ..before };
+        assert_eq!(explain(Op::MPBranch, &zero, &Integer::new(), Direction::East, 0),
+            "`^`: current edge is not positive, move MP to left neighbour");
+        assert_eq!(explain(Op::MemCopy, &zero, &Integer::from(7), Direction::East, 0),
+            "`&`: current edge is not positive, copy left neighbour (7)");
+    }
+}
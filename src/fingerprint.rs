@@ -0,0 +1,37 @@
+/// A 64-bit FNV-1a hash, which unlike `DefaultHasher` gives the same result on every platform
+/// and Rust version, so fingerprints can be compared between builds.
+#[derive(Clone, Copy)]
+pub(crate) struct Fnv(u64);
+
+impl Fnv {
+    /// Creates a new `Fnv` hash of no bytes.
+    pub(crate) fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    /// Adds bytes to the hash.
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    /// Returns the hash of the bytes written so far.
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Builder;
+
+    #[test]
+    fn fingerprints_hash_coordinates_as_64_bit() {
+        let mut output = Vec::new();
+        let mut hex = Builder::new("?)!@").input(&b"41"[..]).output(&mut output).fingerprint(true).build().unwrap();
+        hex.run().unwrap();
+        // Pinned, so that the hash stays the same on 32-bit platforms
+        assert_eq!(hex.fingerprint().unwrap(), Some(15850352547570953480));
+    }
+}
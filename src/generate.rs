@@ -0,0 +1,387 @@
+use std::collections::HashSet;
+
+use crate::{
+    coords::{self, PointAxial},
+    direction::{Direction, Redirect, redirect},
+    grid::{Grid, Op},
+    initial_ips, wrap,
+};
+
+/// A source of randomness for the generators in this module.
+///
+/// Any `FnMut() -> u64` closure is an `Rng`, which makes it easy to adapt other random number generators.
+pub trait Rng {
+    /// Returns the next random 64-bit value.
+    fn next_u64(&mut self) -> u64;
+}
+
+impl<F: FnMut() -> u64> Rng for F {
+    fn next_u64(&mut self) -> u64 {
+        self()
+    }
+}
+
+/// A small, fast, seedable pseudorandom number generator (SplitMix64).
+pub struct SplitMix64(pub u64);
+
+impl Rng for SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Returns the default op weights for `random_grid`: every op except letters with a weight of 1.
+pub fn default_weights() -> Vec<(Op, u32)> {
+    Op::all().filter(|op| !matches!(op, Op::Letter(_))).map(|op| (op, 1)).collect()
+}
+
+/// Returns a grid of the given side length filled with random ops.
+///
+/// Each op is chosen with a probability proportional to its weight.
+///
+/// # Panics
+///
+/// Panics if the weights sum to zero.
+pub fn random_grid(size: usize, rng: &mut impl Rng, weights: &[(Op, u32)]) -> Grid {
+    let total: u64 = weights.iter().map(|&(_, w)| w as u64).sum();
+    assert!(total > 0, "op weights must not sum to zero");
+    let mut grid = Grid::new(size);
+    for coords in coords::rows(size) {
+        let mut x = rng.next_u64() % total;
+        let op = weights.iter().find_map(|&(op, w)| {
+            if x < w as u64 {
+                return Some(op);
+            }
+            x -= w as u64;
+            None
+        }).unwrap();
+        grid.set(coords, (op, false));
+    }
+    grid
+}
+
+/// Returns the source code of a program that prints the given bytes and terminates.
+///
+/// The program is not necessarily optimal. Each byte is built in a single memory edge
+/// using letters and digits before being written with `;`, and the resulting code is
+/// laid out along a path through the smallest hexagon that fits it.
+pub fn text_printer(text: &[u8]) -> String {
+    let mut ops = Vec::new();
+    let mut value = 0;
+    for &b in text {
+        ops.extend(set_value(value, b));
+        ops.push(Op::WriteByte);
+        value = b;
+    }
+    ops.push(Op::Terminate);
+    let min_size = (1..).find(|n| 3 * n * (n - 1) + 1 >= ops.len()).unwrap();
+    (min_size..)
+        .find_map(|size| lay_out(&ops, size).or_else(|| lay_out_snake(&ops, size)))
+        .unwrap()
+        .to_string()
+}
+
+/// Returns the shortest sequence of letters and digits that changes a memory edge
+/// congruent to `from` (mod 256) into one congruent to `to`.
+fn set_value(from: u8, to: u8) -> Vec<Op> {
+    if from == to {
+        return Vec::new();
+    }
+    if to.is_ascii_alphabetic() {
+        return vec![Op::Letter(to)];
+    }
+    // Try appending digits to the current value before resetting it with a letter
+    let starts = std::iter::once((None, from))
+        .chain((b'A'..=b'Z').chain(b'a'..=b'z').map(|c| (Some(c), c)));
+    let mut best: Option<Vec<Op>> = None;
+    for (letter, start) in starts {
+        for n_digits in 1..=3 {
+            let scale = 10u32.pow(n_digits);
+            // Digits to append so that start * 10^n + digits = to (mod 256)
+            let digits = (to as u32 + 256 - (start as u32 * scale) % 256) % 256;
+            if digits >= scale {
+                continue;
+            }
+            let mut ops: Vec<_> = letter.map(Op::Letter).into_iter().collect();
+            let padded = format!("{:0width$}", digits, width = n_digits as usize);
+            ops.extend(padded.bytes().map(|d| Op::Digit(d - b'0')));
+            if best.as_ref().is_none_or(|b| ops.len() < b.len()) {
+                best = Some(ops);
+            }
+            break;
+        }
+    }
+    best.unwrap()
+}
+
+/// Places a sequence of ops along the path the first IP takes through an empty grid of the given size.
+///
+/// Returns `None` if the path revisits a cell before all ops have been placed.
+fn lay_out(ops: &[Op], size: usize) -> Option<Grid> {
+    let mut grid = Grid::new(size);
+    let mut visited = HashSet::new();
+    let ip = &initial_ips(size)[0];
+    let mut coords = ip.coords;
+    // Only a memory edge of zero is non-positive, since letters and digits never make it negative
+    let mut positive = false;
+    for &op in ops {
+        if !visited.insert(coords) {
+            return None;
+        }
+        grid.set(coords, (op, false));
+        positive |= match op {
+            Op::Letter(_) => true,
+            Op::Digit(d) => d > 0,
+            _ => false,
+        };
+        coords = wrap(size, coords, ip.dir, positive);
+    }
+    Some(grid)
+}
+
+/// Places a sequence of ops along a path that snakes through the rows of a grid of the given size,
+/// inserting redirects wherever the path turns.
+///
+/// Returns `None` if the path ends before all ops have been placed.
+fn lay_out_snake(ops: &[Op], size: usize) -> Option<Grid> {
+    let mut grid = Grid::new(size);
+    let path = snake(size);
+    let mut ops = ops.iter();
+    let mut in_dir = initial_ips(size)[0].dir;
+    for (i, &coords) in path.iter().enumerate() {
+        let out_dir = match path.get(i + 1) {
+            Some(&next) => Direction::all().find(|d| coords + d.to_vector() == next).unwrap(),
+            None => in_dir,
+        };
+        if in_dir == out_dir {
+            match ops.next() {
+                Some(&op) => grid.set(coords, (op, false)),
+                None => return Some(grid),
+            }
+        } else {
+            // Use a redirect that turns the same way regardless of the current memory edge
+            let redir = Redirect::all()
+                .find(|&r| redirect(in_dir, r, false) == out_dir && redirect(in_dir, r, true) == out_dir)
+                .unwrap();
+            grid.set(coords, (Op::Redir(redir), false));
+        }
+        in_dir = out_dir;
+    }
+    match ops.next() {
+        Some(_) => None,
+        None => Some(grid),
+    }
+}
+
+/// Returns the cells of a grid of the given size in the order of a path that runs east along the
+/// first row, then west along the second row, and so on, stepping diagonally between rows.
+fn snake(size: usize) -> Vec<PointAxial> {
+    let n = size as isize - 1;
+    let mut path = Vec::new();
+    let mut q = 0;
+    for r in -n..=n {
+        let (q_min, q_max) = ((-n - r).max(-n), (n - r).min(n));
+        if (r + n) % 2 == 0 {
+            path.extend((q..=q_max).map(|q| PointAxial(q, r)));
+            // Step south-west onto the next row
+            q = q_max - 1;
+        } else {
+            path.extend((q_min..=q).rev().map(|q| PointAxial(q, r)));
+            // Step south-east onto the next row
+            q = q_min;
+        }
+    }
+    path
+}
+
+/// A classic program structure that `scaffold` can lay out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// Setup code followed by a loop that repeats while the current edge is positive
+    Loop,
+    /// Code on IP 0 that hands over to IP 1 with `]`, which hands back to IP 0 with `[`
+    TwoIp,
+    /// A loop that reads a byte with `,` and stops at EOF
+    IoLoop,
+}
+
+impl Pattern {
+    /// Returns the smallest side length that fits the pattern.
+    pub fn min_size(self) -> usize {
+        match self {
+            Pattern::Loop => 4,
+            Pattern::TwoIp => 3,
+            Pattern::IoLoop => 5,
+        }
+    }
+}
+
+/// A skeleton program from `scaffold`, and the cells left empty for each part of the program.
+pub struct Scaffold {
+    pub grid: Grid,
+    /// A description of each part and its cells, in the order IPs run through them
+    pub parts: Vec<(&'static str, Vec<PointAxial>)>,
+}
+
+/// Returns a skeleton program of the given side length, with the mirrors, branches, `@` and
+/// IP switches of a pattern in place and everything else left as no-ops to be filled in.
+///
+/// # Panics
+///
+/// Panics if `size` is smaller than `pattern.min_size()`.
+pub fn scaffold(size: usize, pattern: Pattern) -> Scaffold {
+    assert!(size >= pattern.min_size(), "the pattern needs a side length of at least {}", pattern.min_size());
+    let n = size as isize - 1;
+    let mut grid = Grid::new(size);
+    let row = |r, qs: std::ops::RangeInclusive<isize>| qs.map(move |q| PointAxial(q, r));
+    let parts = match pattern {
+        Pattern::TwoIp => {
+            grid.set(PointAxial(n - 1, -n), (Op::IPNext, false));
+            grid.set(PointAxial(n, 0), (Op::IPPrev, false));
+            grid.set(PointAxial(n - 1, 0), (Op::Terminate, false));
+            // IP 1 starts in the top-right corner, which IP 0 also runs through after IP 1 hands back
+            let ip1 = (-n + 1..=-1).map(|r| PointAxial(n, r)).collect();
+            vec![
+                ("IP 0, before handing over to IP 1", row(-n, 0..=n - 2).collect()),
+                ("IP 1, running down the right edge", ip1),
+                ("IP 0 again, after IP 1 hands back", row(0, -n..=n - 2).collect()),
+            ]
+        }
+        Pattern::Loop | Pattern::IoLoop => {
+            // IP 0 runs along the top row, wraps to the middle row and drops down two rows to the loop,
+            // which runs east along one row, tests the current edge and comes back west along the next
+            let (first, last) = (-n + 1, n - 3);
+            let cells = [
+                (PointAxial(-n + 2, 0), Op::Redir(Redirect::MirrorBack)),
+                (PointAxial(first, 1), Op::Redir(Redirect::MirrorVert)),
+                (PointAxial(first, 2), Op::Redir(Redirect::BranchRight)),
+                (PointAxial(last, 2), Op::Redir(Redirect::BranchLeft)),
+                (PointAxial(last + 1, 1), Op::Terminate),
+                (PointAxial(last, 3), Op::Redir(Redirect::MirrorForw)),
+                (PointAxial(-n, 3), Op::Redir(Redirect::MirrorBack)),
+            ];
+            for &(coords, op) in &cells {
+                grid.set(coords, (op, false));
+            }
+            let setup = row(-n, 0..=n).chain(row(0, -n..=-n + 1)).collect();
+            match pattern {
+                Pattern::Loop => vec![
+                    ("Setup", setup),
+                    ("Loop body, before testing the current edge", row(2, first + 1..=last - 1).collect()),
+                    ("Loop body, running west after the test", row(3, first..=last - 1).rev().collect()),
+                ],
+                _ => {
+                    // Add 1 to the byte before the test, so that only EOF stops the loop, and take it away after
+                    let ops = [
+                        (PointAxial(last - 2, 2), Op::ReadByte),
+                        (PointAxial(last - 1, 2), Op::Increment),
+                        (PointAxial(last - 1, 3), Op::Decrement),
+                        (PointAxial(first, 3), Op::WriteByte),
+                    ];
+                    for &(coords, op) in &ops {
+                        grid.set(coords, (op, false));
+                    }
+                    vec![
+                        ("Setup", setup),
+                        ("Before each byte is read", row(2, first + 1..=last - 3).collect()),
+                        ("Each byte, running west before it's written", row(3, first + 1..=last - 2).rev().collect()),
+                    ]
+                }
+            }
+        }
+    };
+    Scaffold { grid, parts }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Builder;
+
+    /// Runs a program without input and returns its output.
+    fn output(src: &str) -> Vec<u8> {
+        let mut output = Vec::new();
+        Builder::new(src).input(&b""[..]).output(&mut output).max_ticks(100_000).build().unwrap().run().unwrap();
+        output
+    }
+
+    #[test]
+    fn random_grids_follow_the_seed_and_weights() {
+        let weights = [(Op::Increment, 3), (Op::WriteInt, 1), (Op::Terminate, 0), (Op::Nop, 2)];
+        let grid = random_grid(6, &mut SplitMix64(42), &weights);
+        assert_eq!(grid.to_linear(), random_grid(6, &mut SplitMix64(42), &weights).to_linear());
+        assert_ne!(grid.to_linear(), random_grid(6, &mut SplitMix64(43), &weights).to_linear());
+        assert_eq!(grid.find(Op::Terminate).count(), 0);
+        assert!(grid.cells().all(|(_, op, _)| weights.iter().any(|&(other, w)| other == op && w > 0)));
+        // Any closure works as a source of randomness
+        let mut zeros = || 0;
+        assert!(random_grid(3, &mut zeros, &weights).cells().all(|(_, op, _)| op == Op::Increment));
+    }
+
+    #[test]
+    #[should_panic(expected = "op weights must not sum to zero")]
+    fn random_grids_need_some_weight() {
+        random_grid(2, &mut SplitMix64(0), &[(Op::Nop, 0), (Op::Terminate, 0)]);
+    }
+
+    #[test]
+    fn text_printers_print_their_text() {
+        let is_redir = |op| matches!(op, Op::Redir(_));
+        // Short text fits along the first IP's path without any redirects
+        let src = text_printer(b"Hi!");
+        assert_eq!(output(&src), b"Hi!");
+        assert_eq!(src.parse::<Grid>().unwrap().find_all(is_redir).count(), 0);
+        // Longer text snakes through the rows, turning wherever the path does
+        let text = b"Hello, World! Generated programs should print exactly this text, including\n\x00\xff bytes.";
+        let src = text_printer(text);
+        assert_eq!(output(&src), &text[..]);
+        assert!(src.parse::<Grid>().unwrap().find_all(is_redir).count() > 0);
+        for len in [0, 1, 10, 40, 100, 300] {
+            let text: Vec<u8> = (0..len).map(|i| (i * 37 % 256) as u8).collect();
+            assert_eq!(output(&text_printer(&text)), text);
+        }
+    }
+
+    #[test]
+    fn snakes_only_turn_where_a_redirect_turns_either_way() {
+        for size in 1..=12 {
+            let path = snake(size);
+            assert!(path.iter().all(|coords| coords.in_bounds(size)));
+            assert!(path.windows(2).all(|pair| Direction::all().any(|dir| pair[0] + dir.to_vector() == pair[1])));
+            // Turns take up cells, so as many ops as there are cells only fit without any, but the whole path is
+            // walked, finding a redirect for every turn
+            let ops = vec![Op::Nop; path.len()];
+            assert_eq!(lay_out_snake(&ops, size).is_some(), size == 1);
+        }
+    }
+
+    #[test]
+    fn scaffolds_run_their_patterns() {
+        let run = |grid: &Grid, input: &'static str| {
+            let mut output = Vec::new();
+            let hex = Builder::new(&grid.to_linear()).input(input.as_bytes()).output(&mut output).max_ticks(100_000);
+            hex.build().unwrap().run().unwrap();
+            output
+        };
+        for size in 4..=6 {
+            let mut scaffold = scaffold(size, Pattern::Loop);
+            for (part, op) in scaffold.parts.iter().zip(&[Op::Digit(3), Op::Decrement, Op::WriteInt]) {
+                scaffold.grid.set(part.1[0], (*op, false));
+            }
+            assert_eq!(run(&scaffold.grid, ""), b"21");
+        }
+        for size in 3..=5 {
+            let mut scaffold = scaffold(size, Pattern::TwoIp);
+            for (part, op) in scaffold.parts.iter().zip(&[Op::Digit(7), Op::WriteInt, Op::WriteInt]) {
+                scaffold.grid.set(part.1[0], (*op, false));
+            }
+            assert_eq!(run(&scaffold.grid, ""), b"77");
+        }
+        let cat = scaffold(5, Pattern::IoLoop);
+        assert_eq!(run(&cat.grid, "a\0b\n"), b"a\0b\n");
+    }
+}
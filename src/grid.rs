@@ -1,9 +1,14 @@
 use std::{fmt, str::FromStr};
 
-use crate::{Error, coords::PointAxial, direction::Redirect};
+use crate::{Error, coords::PointAxial, direction::{Axis, Direction, Redirect}};
 use Op::*;
 
+/// Default upper bound on the side length of a grid parsed from source code,
+/// which fits programs of up to 748,501 commands.
+pub const DEFAULT_MAX_SIZE: usize = 500;
+
 /// A pointy-topped hexagonal grid of instructions.
+#[derive(Clone)]
 pub struct Grid {
     size: usize,
     grid: Vec<Vec<(Op, bool)>>,
@@ -15,7 +20,7 @@ impl Grid {
         let diameter = 2 * size - 1;
         let s = size - 1;
         let grid = (0..diameter).map(|i| {
-            let b = if s > i { s - i } else { i - s };
+            let b = s.abs_diff(i);
             vec![(Nop, false); diameter - b]
         }).collect();
         Grid { size, grid }
@@ -32,8 +37,251 @@ impl Grid {
         self.grid[row][col]
     }
 
+    /// Sets the `Op` and debug flag at the given grid coordinates.
+    pub fn set(&mut self, coords: PointAxial, cell: (Op, bool)) {
+        let (row, col) = self.axial_to_index(coords);
+        self.grid[row][col] = cell;
+    }
+
+    /// Copies the cells of `other` that hold a command or debug flag into this grid, with the center of
+    /// `other` placed at the coordinates `at`. Debug flags are added to those already in this grid.
+    ///
+    /// Nothing is copied if a cell would land outside this grid, which is an `Error::CellOutsideGrid`,
+    /// or on a cell holding a different command, which is an `Error::CellCollision`.
+    pub fn embed(&mut self, other: &Grid, at: PointAxial) -> Result<(), Error> {
+        let cells: Vec<_> = other.cells()
+            .filter(|&(_, op, debug)| op != Nop || debug)
+            .map(|(coords, op, debug)| (coords + at, op, debug))
+            .collect();
+        for &(coords, op, _) in &cells {
+            if !self.contains(coords) {
+                return Err(Error::CellOutsideGrid(coords));
+            }
+            let old = self.get(coords).0;
+            if old != Nop && op != Nop && old != op {
+                return Err(Error::CellCollision(coords, old, op));
+            }
+        }
+        for (coords, op, debug) in cells {
+            let (old, old_debug) = self.get(coords);
+            self.set(coords, (if op == Nop { old } else { op }, debug || old_debug));
+        }
+        Ok(())
+    }
+
+    /// Returns the mirror image of the grid across an axis, with every command replaced by its mirror image
+    /// as described by `Op::reflect`.
+    ///
+    /// Use `mirror::mirror` to also reflect where IPs start and check that the program behaves the same.
+    pub fn reflect(&self, axis: Axis) -> Grid {
+        let mut grid = Grid::new(self.size);
+        for (coords, op, debug) in self.cells() {
+            grid.set(coords.reflect(axis), (op.reflect(axis), debug));
+        }
+        grid
+    }
+
+    /// Returns whether the given coordinates lie inside the grid.
+    pub fn contains(&self, coords: PointAxial) -> bool {
+        coords.in_bounds(self.size)
+    }
+
+    /// Returns an iterator over the coordinates, `Op` and debug flag of every cell in row-major order.
+    pub fn cells(&self) -> impl Iterator<Item = (PointAxial, Op, bool)> + '_ {
+        let s = self.size as isize - 1;
+        self.grid.iter().enumerate().flat_map(move |(row, cells)| {
+            let r = row as isize - s;
+            let q_min = (-s - r).max(-s);
+            cells.iter().enumerate().map(move |(col, &(op, debug))| (PointAxial(q_min + col as isize, r), op, debug))
+        })
+    }
+
+    /// Returns the grid as compact source code on one line, which parses back to the same grid.
+    ///
+    /// Debug flags are written as a backtick before their cell, like in the `Display` form. Trailing
+    /// no-ops are left out as long as the code still needs a hexagon of the same side length.
+    pub fn to_linear(&self) -> String {
+        let cells: Vec<_> = self.cells().map(|(_, op, debug)| (op, debug)).collect();
+        // A hexagon of side length n is the smallest to fit more than 3(n - 1)(n - 2) + 1 cells
+        let min_len = match self.size {
+            1 => 1,
+            n => 3 * (n - 1) * (n - 2) + 2,
+        };
+        let len = cells.iter().rposition(|&cell| cell != (Nop, false)).map_or(0, |i| i + 1).max(min_len);
+        cells[..len].iter().map(|&(op, debug)| cell_source(op, debug)).collect()
+    }
+
+    /// Returns the grid laid out like its `Display` form, with each row labelled by its `r` coordinate
+    /// and the range of `q` coordinates from its first to its last cell.
+    ///
+    /// If `legend` is set, the coordinates of every cell are listed below the grid, one row per line.
+    pub fn annotated(&self, legend: bool) -> String {
+        let s = self.size as isize - 1;
+        let label_width = format!("r={}", -s).len();
+        let mut out = String::new();
+        for (row, line) in self.grid.iter().enumerate() {
+            let r = row as isize - s;
+            let q_min = (-s - r).max(-s);
+            let q_max = q_min + line.len() as isize - 1;
+            out += &format!("{:<width$} {}", format!("r={}", r), " ".repeat(2 * self.size - 1 - line.len()), width = label_width);
+            for (op, dbg) in line {
+                out += &format!("{}{}", if *dbg { '`' } else { ' ' }, op);
+            }
+            out += &format!("{}   q={}..{}\n", " ".repeat(2 * self.size - 1 - line.len()), q_min, q_max);
+        }
+        if legend {
+            out.push('\n');
+            for r in -s..=s {
+                let cells: Vec<_> = self.cells()
+                    .filter(|(coords, _, _)| coords.1 == r)
+                    .map(|(coords, op, _)| format!("{} {}", coords, op))
+                    .collect();
+                out += &format!("{}\n", cells.join("  "));
+            }
+        }
+        out
+    }
+
+    /// Returns the grid laid out like `Grid::annotated` without a legend, between rulers that number
+    /// the columns of text that cells lie in, to help count cells along a row or down a column.
+    ///
+    /// Adjacent cells of a row lie two columns apart, and cells of adjacent rows one column apart.
+    pub fn ruled(&self) -> String {
+        let label_width = format!("r={}", 1 - self.size as isize).len();
+        let margin = " ".repeat(label_width + 2);
+        let columns = 4 * self.size - 3;
+        let digit = |n| std::char::from_digit(n as u32 % 10, 10).unwrap();
+        let mut ruler = String::new();
+        if columns > 10 {
+            let tens: String = (0..columns).map(|col| if col % 10 == 0 { digit(col / 10) } else { ' ' }).collect();
+            ruler += &format!("{}{}\n", margin, tens.trim_end());
+        }
+        ruler += &format!("{}{}\n", margin, (0..columns).map(digit).collect::<String>());
+        format!("{}{}{}", ruler, self.annotated(false), ruler)
+    }
+
+    /// Returns an iterator over the coordinates of every cell containing the given `Op`.
+    pub fn find(&self, op: Op) -> impl Iterator<Item = PointAxial> + '_ {
+        self.find_all(move |other| other == op)
+    }
+
+    /// Returns an iterator over the coordinates of every cell whose `Op` matches a predicate.
+    pub fn find_all<'a>(&'a self, mut pred: impl FnMut(Op) -> bool + 'a) -> impl Iterator<Item = PointAxial> + 'a {
+        self.cells().filter_map(move |(coords, op, _)| if pred(op) { Some(coords) } else { None })
+    }
+
+    /// Returns the coordinates of every cell where the given sequence of `Op`s starts
+    /// when read in direction `dir` without wrapping around the edges.
+    pub fn find_sequence(&self, ops: &[Op], dir: Direction) -> Vec<PointAxial> {
+        self.cells()
+            .map(|(coords, _, _)| coords)
+            .filter(|&start| {
+                let mut coords = start;
+                ops.iter().all(|&op| {
+                    let matches = self.contains(coords) && self.get(coords).0 == op;
+                    coords += dir.to_vector();
+                    matches
+                })
+            })
+            .collect()
+    }
+
+    /// Returns every maximal run of adjacent cells along direction `dir` whose `Op`s match a predicate,
+    /// without wrapping around the edges.
+    pub fn runs(&self, dir: Direction, mut pred: impl FnMut(Op) -> bool) -> Vec<Vec<PointAxial>> {
+        let mut runs = Vec::new();
+        for (start, op, _) in self.cells() {
+            let before = start - dir.to_vector();
+            if !pred(op) || self.contains(before) && pred(self.get(before).0) {
+                continue;
+            }
+            let mut run = vec![start];
+            let mut coords = start + dir.to_vector();
+            while self.contains(coords) && pred(self.get(coords).0) {
+                run.push(coords);
+                coords += dir.to_vector();
+            }
+            runs.push(run);
+        }
+        runs
+    }
+
+    /// Parses linear source code like `from_str`, rejecting code that needs a grid with a side length
+    /// over `max_size` with `Error::ProgramTooLarge` before the grid is allocated.
+    pub fn parse_with_max_size(s: &str, max_size: usize) -> Result<Grid, Error> {
+        Grid::parse_with(s, SourceFormat::Linear, Dialect::Standard, max_size, |_| false)
+    }
+
+    /// Parses source code written in a dialect and format, accepting characters that aren't commands
+    /// as `Op::Custom` if `custom` returns true for them.
+    ///
+    /// Code that needs a grid with a side length over `max_size` is rejected with `Error::ProgramTooLarge`
+    /// before the grid is allocated.
+    pub(crate) fn parse_with(
+        s: &str,
+        format: SourceFormat,
+        dialect: Dialect,
+        max_size: usize,
+        custom: impl Fn(char) -> bool,
+    ) -> Result<Grid, Error> {
+        let parse_op = |c| match (Op::from_char(c), Op::from_extension_char(c)) {
+            (Some(op), _) => Ok(op),
+            (None, Some(op)) if dialect == Dialect::Extensions => Ok(op),
+            (None, Some(_)) => Err(Error::ExtensionOp(c)),
+            (None, None) if custom(c) => Ok(Custom(c)),
+            (None, None) => Err(Error::SyntaxError(c)),
+        };
+        let format = match format {
+            SourceFormat::Auto => SourceFormat::detect(s),
+            format => format,
+        };
+        if format != SourceFormat::Linear {
+            let kind = match format {
+                SourceFormat::DoubleSpaced => "double-spaced layout",
+                _ => "padded layout",
+            };
+            let (size, cells) = layout(s, format).map_err(|msg| Error::ParseError(kind, msg))?;
+            if size > max_size {
+                return Err(Error::ProgramTooLarge { size, limit: max_size });
+            }
+            let mut grid = Grid::new(size);
+            for (row, col, c, debug, _) in cells {
+                grid.grid[row][col] = (parse_op(c)?, debug);
+            }
+            return Ok(grid);
+        }
+        // Find the size of the smallest regular hexagon that will contain the code
+        let src_size = s.chars().filter(|&c| !c.is_whitespace() && c != '`').count();
+        let size = (1..).find(|n| 3 * n * (n - 1) + 1 >= src_size).unwrap();
+        if size > max_size {
+            return Err(Error::ProgramTooLarge { size, limit: max_size });
+        }
+        let mut grid = Grid::new(size);
+        // Parse code into commands and write each command into the grid
+        let mut row = 0;
+        let mut col = 0;
+        let mut debug = false;
+        for c in s.chars() {
+            let op = match c {
+                _ if c.is_whitespace() => continue,
+                '`' => { debug = true; continue }
+                _ => parse_op(c)?,
+            };
+            grid.grid[row][col] = (op, debug);
+            debug = false;
+            if col < grid.grid[row].len() - 1 {
+                col += 1;
+            } else {
+                row += 1;
+                col = 0;
+            }
+        }
+        Ok(grid)
+    }
+
     /// Converts a `PointAxial` to its corresponding internal 2D grid index.
-    fn axial_to_index(&self, coords: PointAxial) -> (usize, usize) {
+    pub(crate) fn axial_to_index(&self, coords: PointAxial) -> (usize, usize) {
+        debug_assert!(coords.in_bounds(self.size), "coordinates {} lie outside the grid", coords);
         let PointAxial(q, r) = coords;
         let size = self.size as isize;
         let row = r + size - 1;
@@ -42,8 +290,13 @@ impl Grid {
     }
 }
 
+/// Formats a cell like linear source code, with a backtick before it if it has a debug flag.
+pub(crate) fn cell_source(op: Op, debug: bool) -> String {
+    format!("{}{}", if debug { "`" } else { "" }, op)
+}
+
 /// Enumeration of all commands.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Op {
     /// Does nothing
     Nop,
@@ -84,11 +337,11 @@ pub enum Op {
     Jump,
     /// Changes the direction of the IP
     Redir(Redirect),
-    /// Switches to the previous IP (wrapping from 0 to 5)
+    /// Switches to the previous IP (wrapping from the first to the last, of six or as many as `Builder::ip_count` sets)
     IPPrev,
-    /// Switches to the next IP (wrapping from 5 to 0)
+    /// Switches to the next IP (wrapping from the last to the first)
     IPNext,
-    /// Switches to the IP with the index of the current memory edge mod 6
+    /// Switches to the IP with the index of the current memory edge modulo the number of IPs
     IPSelect,
     /// Moves the MP to the left neighbour
     MPLeft,
@@ -105,69 +358,391 @@ pub enum Op {
     /// Sets the current edge to the value of the right neighbour if the current edge is positive,
     /// and the value of the left neighbour otherwise
     MemCopy,
+    /// Sets the current edge to a random integer from 0 up to but not including its value,
+    /// or a random byte if it isn't positive (extensions dialect only)
+    Random,
+    /// Sets the current edge to the number of ticks executed so far (extensions dialect only)
+    Tick,
+    /// Replaces the current edge, read as the bytes of a variable name, with the bytes of the variable's value,
+    /// or -1 if it isn't set (extensions dialect only)
+    Env,
+    /// Runs an instruction provided by the embedding program, registered with `Builder::custom_op`
+    Custom(char),
 }
 
-impl FromStr for Grid {
-    type Err = Error;
+/// A broad group of commands, used to color or summarize programs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Category {
+    /// No-ops
+    Nop,
+    /// Commands that compute a new value for the current edge
+    Arithmetic,
+    /// Commands that read input or write output
+    Io,
+    /// Commands that move or switch IPs, or end the program
+    Control,
+    /// Commands that move the MP or copy between edges
+    Memory,
+}
 
-    fn from_str(s: &str) -> Result<Self, Error> {
-        // Find the size of the smallest regular hexagon that will contain the code
-        let src_size = s.chars().filter(|&c| !c.is_whitespace() && c != '`').count();
-        let size = (1..).find(|n| 3 * n * (n - 1) + 1 >= src_size).unwrap();
-        let mut grid = Grid::new(size);
-        // Parse code into commands and write each command into the grid
-        let mut row = 0;
-        let mut col = 0;
+impl Category {
+    /// Returns the lowercase name of the category.
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Nop => "nop",
+            Category::Arithmetic => "arithmetic",
+            Category::Io => "io",
+            Category::Control => "control",
+            Category::Memory => "memory",
+        }
+    }
+}
+
+/// The set of commands a program may use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dialect {
+    /// Only the commands of the Hexagony specification
+    Standard,
+    /// The standard commands and nonstandard extensions, such as `Op::Random`
+    Extensions,
+}
+
+impl Dialect {
+    /// Returns the name of the dialect, as used in save states and on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Dialect::Standard => "standard",
+            Dialect::Extensions => "extensions",
+        }
+    }
+
+    /// Returns the dialect with the given name.
+    pub fn from_name(name: &str) -> Option<Dialect> {
+        match name {
+            "standard" => Some(Dialect::Standard),
+            "extensions" => Some(Dialect::Extensions),
+            _ => None,
+        }
+    }
+}
+
+/// The shape that source code is written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceFormat {
+    /// Detects the format from the shape of the code
+    Auto,
+    /// Commands in reading order, ignoring whitespace, which fill the smallest hexagon that fits them
+    Linear,
+    /// A hexagon with a space between cells, as `Grid` is displayed, where blank cells are no-ops
+    Padded,
+    /// A hexagon with three spaces between cells and an empty line between rows, as the Esoteric IDE
+    /// lays out programs, where blank cells are no-ops
+    DoubleSpaced,
+}
+
+impl SourceFormat {
+    /// Returns the format that source code is written in, which is `SourceFormat::Linear` unless
+    /// the code is laid out as a hexagon.
+    pub fn detect(s: &str) -> SourceFormat {
+        [SourceFormat::DoubleSpaced, SourceFormat::Padded].iter()
+            .copied()
+            .find(|&format| layout(s, format).is_ok())
+            .unwrap_or(SourceFormat::Linear)
+    }
+
+    /// Returns the hexagon layout that source code is written in if reading it as `SourceFormat::Linear`
+    /// would put its commands on other cells, because the layout has blank cells before its last command.
+    pub fn misread_as_linear(s: &str) -> Option<SourceFormat> {
+        let format = SourceFormat::detect(s);
+        let (size, cells) = layout(s, format).ok()?;
+        let row_start = |row: usize| (0..row).map(|r| 2 * size - 1 - r.abs_diff(size - 1)).sum::<usize>();
+        let shifted = cells.iter().enumerate().any(|(i, &(row, col, _, _, _))| row_start(row) + col != i);
+        if shifted { Some(format) } else { None }
+    }
+
+    /// Returns the name of the format, as used on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            SourceFormat::Auto => "auto",
+            SourceFormat::Linear => "linear",
+            SourceFormat::Padded => "padded",
+            SourceFormat::DoubleSpaced => "double-spaced",
+        }
+    }
+
+    /// Returns the format with the given name, as used on the command line.
+    pub fn from_name(name: &str) -> Option<SourceFormat> {
+        match name {
+            "auto" => Some(SourceFormat::Auto),
+            "linear" => Some(SourceFormat::Linear),
+            "padded" => Some(SourceFormat::Padded),
+            "double-spaced" => Some(SourceFormat::DoubleSpaced),
+            _ => None,
+        }
+    }
+}
+
+/// The row and column of a cell in a grid, the character in it, its debug flag and its index in the source.
+pub(crate) type LaidOutChar = (usize, usize, char, bool, usize);
+
+/// Returns the side length of the hexagon that source code is laid out as in a format, and where every
+/// character lies in it, or why the code isn't laid out in that format.
+pub(crate) fn layout(s: &str, format: SourceFormat) -> Result<(usize, Vec<LaidOutChar>), String> {
+    // Columns between the cells of a row, and half of that between the first cells of adjacent rows
+    let pitch = match format {
+        SourceFormat::Padded => 2,
+        SourceFormat::DoubleSpaced => 4,
+        _ => return Err(String::from("not a hexagon layout")),
+    };
+    // Each line with the index of its first character in the source
+    let mut next = 0;
+    let lines: Vec<(usize, &str)> = s.split_inclusive('\n')
+        .zip(s.lines())
+        .map(|(raw, line)| {
+            let start = next;
+            next += raw.chars().count();
+            (start, line)
+        })
+        .collect();
+    let is_blank = |&(_, line): &(usize, &str)| line.trim().is_empty();
+    let first = lines.iter().position(|line| !is_blank(line)).ok_or("no code")?;
+    let last = lines.iter().rposition(|line| !is_blank(line)).unwrap();
+    let lines = &lines[first..=last];
+    let rows: Vec<(usize, &str)> = match format {
+        SourceFormat::DoubleSpaced => {
+            if lines.iter().enumerate().any(|(i, line)| is_blank(line) != (i % 2 == 1)) {
+                return Err(String::from("rows must be separated by one empty line"));
+            }
+            lines.iter().step_by(2).copied().collect()
+        }
+        _ => lines.to_vec(),
+    };
+    if rows.len().is_multiple_of(2) {
+        return Err(format!("expected an odd number of rows, found {}", rows.len()));
+    }
+    let size = rows.len().div_ceil(2);
+    // Character columns relative to the first cell of each row
+    let mut chars = Vec::new();
+    for (row, (line_start, line)) in rows.iter().enumerate() {
+        let indent = (pitch / 2 * row.abs_diff(size - 1)) as isize;
         let mut debug = false;
-        for c in s.chars() {
-            let op = match c {
+        for (col, c) in line.chars().enumerate() {
+            match c {
                 _ if c.is_whitespace() => continue,
-                '`' => { debug = true; continue }
-                '.' => Nop,
-                '@' => Terminate,
-                'a'..='z' | 'A'..='Z' => Letter(c as u8),
-                '0'..='9' => Digit(c as u8 - b'0'),
-                ')' => Increment,
-                '(' => Decrement,
-                '+' => Add,
-                '-' => Subtract,
-                '*' => Multiply,
-                ':' => Divide,
-                '%' => Modulo,
-                '~' => Negate,
-                ',' => ReadByte,
-                '?' => ReadInt,
-                ';' => WriteByte,
-                '!' => WriteInt,
-                '$' => Jump,
-                '_' => Redir(Redirect::MirrorHori),
-                '|' => Redir(Redirect::MirrorVert),
-                '/' => Redir(Redirect::MirrorForw),
-                '\\' => Redir(Redirect::MirrorBack),
-                '<' => Redir(Redirect::BranchLeft),
-                '>' => Redir(Redirect::BranchRight),
-                '[' => IPPrev,
-                ']' => IPNext,
-                '#' => IPSelect,
-                '{' => MPLeft,
-                '}' => MPRight,
-                '"' => MPBackLeft,
-                '\'' => MPBackRight,
-                '=' => MPReverse,
-                '^' => MPBranch,
-                '&' => MemCopy,
-                _ => return Err(Error::SyntaxError(c)),
-            };
-            grid.grid[row][col] = (op, debug);
-            debug = false;
-            if col < grid.grid[row].len() - 1 {
-                col += 1;
-            } else {
-                row += 1;
-                col = 0;
+                '`' => debug = true,
+                _ => {
+                    chars.push((row, col as isize - indent, c, debug, line_start + col));
+                    debug = false;
+                }
             }
         }
-        Ok(grid)
+    }
+    // Leading whitespace shared by every row is ignored
+    let start = chars.iter().map(|&(_, col, _, _, _)| col).min().ok_or("no code")?;
+    let mut cells = Vec::with_capacity(chars.len());
+    for (row, col, c, debug, index) in chars {
+        let offset = (col - start) as usize;
+        let len = 2 * size - 1 - row.abs_diff(size - 1);
+        if !offset.is_multiple_of(pitch) {
+            return Err(format!("`{}` on row {} lies between two cells", c, row + 1));
+        }
+        if offset / pitch >= len {
+            return Err(format!("`{}` on row {} lies past the end of the row", c, row + 1));
+        }
+        cells.push((row, offset / pitch, c, debug, index));
+    }
+    Ok((size, cells))
+}
+
+impl Op {
+    /// Returns an iterator over all ops, including every letter and digit.
+    pub fn all() -> impl Iterator<Item = Op> {
+        let letters = (b'A'..=b'Z').chain(b'a'..=b'z').map(Letter);
+        let digits = (0..10).map(Digit);
+        let redirs = Redirect::all().map(Redir);
+        let others = [
+            Nop, Terminate, Increment, Decrement, Add, Subtract, Multiply, Divide, Modulo, Negate,
+            ReadByte, ReadInt, WriteByte, WriteInt, Jump, IPPrev, IPNext, IPSelect,
+            MPLeft, MPRight, MPBackLeft, MPBackRight, MPReverse, MPBranch, MemCopy,
+        ];
+        IntoIterator::into_iter(others).chain(redirs).chain(letters).chain(digits)
+    }
+
+    /// Returns the mirror image of the op across an axis, for a program that runs on the mirror image of
+    /// the original memory.
+    ///
+    /// Redirects are replaced by their mirror images, and the commands that move the MP to the left
+    /// swap places with those that move it to the right. Every other op is kept as it is.
+    pub fn reflect(self, axis: Axis) -> Op {
+        match self {
+            Redir(redir) => Redir(redir.reflect(axis)),
+            MPLeft => MPRight,
+            MPRight => MPLeft,
+            MPBackLeft => MPBackRight,
+            MPBackRight => MPBackLeft,
+            op => op,
+        }
+    }
+
+    /// Returns an iterator over the extension ops, which are only available in `Dialect::Extensions`.
+    pub fn extensions() -> impl Iterator<Item = Op> {
+        IntoIterator::into_iter([Random, Tick, Env])
+    }
+
+    /// Returns the extension op written as the given character, or `None` if it isn't one.
+    pub fn from_extension_char(c: char) -> Option<Op> {
+        match c {
+            'ρ' => Some(Random),
+            'τ' => Some(Tick),
+            'ε' => Some(Env),
+            _ => None,
+        }
+    }
+
+    /// Returns the op written as the given character, or `None` if it isn't a standard command.
+    pub fn from_char(c: char) -> Option<Op> {
+        Some(match c {
+            '.' => Nop,
+            '@' => Terminate,
+            'a'..='z' | 'A'..='Z' => Letter(c as u8),
+            '0'..='9' => Digit(c as u8 - b'0'),
+            ')' => Increment,
+            '(' => Decrement,
+            '+' => Add,
+            '-' => Subtract,
+            '*' => Multiply,
+            ':' => Divide,
+            '%' => Modulo,
+            '~' => Negate,
+            ',' => ReadByte,
+            '?' => ReadInt,
+            ';' => WriteByte,
+            '!' => WriteInt,
+            '$' => Jump,
+            '_' => Redir(Redirect::MirrorHori),
+            '|' => Redir(Redirect::MirrorVert),
+            '/' => Redir(Redirect::MirrorForw),
+            '\\' => Redir(Redirect::MirrorBack),
+            '<' => Redir(Redirect::BranchLeft),
+            '>' => Redir(Redirect::BranchRight),
+            '[' => IPPrev,
+            ']' => IPNext,
+            '#' => IPSelect,
+            '{' => MPLeft,
+            '}' => MPRight,
+            '"' => MPBackLeft,
+            '\'' => MPBackRight,
+            '=' => MPReverse,
+            '^' => MPBranch,
+            '&' => MemCopy,
+            _ => return None,
+        })
+    }
+
+    /// Returns a short name for the op.
+    pub fn name(self) -> &'static str {
+        match self {
+            Nop => "No-op",
+            Terminate => "Terminate",
+            Letter(_) => "Letter",
+            Digit(_) => "Digit",
+            Increment => "Increment",
+            Decrement => "Decrement",
+            Add => "Add",
+            Subtract => "Subtract",
+            Multiply => "Multiply",
+            Divide => "Divide",
+            Modulo => "Modulo",
+            Negate => "Negate",
+            ReadByte => "Read byte",
+            ReadInt => "Read integer",
+            WriteByte => "Write byte",
+            WriteInt => "Write integer",
+            Jump => "Jump",
+            Redir(Redirect::MirrorHori) => "Horizontal mirror",
+            Redir(Redirect::MirrorVert) => "Vertical mirror",
+            Redir(Redirect::MirrorForw) => "Forward mirror",
+            Redir(Redirect::MirrorBack) => "Backward mirror",
+            Redir(Redirect::BranchLeft) => "Left branch",
+            Redir(Redirect::BranchRight) => "Right branch",
+            IPPrev => "Previous IP",
+            IPNext => "Next IP",
+            IPSelect => "Select IP",
+            MPLeft => "MP left",
+            MPRight => "MP right",
+            MPBackLeft => "MP back left",
+            MPBackRight => "MP back right",
+            MPReverse => "MP reverse",
+            MPBranch => "MP branch",
+            MemCopy => "Copy",
+            Random => "Random",
+            Tick => "Tick",
+            Env => "Environment",
+            Custom(_) => "Custom",
+        }
+    }
+
+    /// Returns the category of the op.
+    pub fn category(self) -> Category {
+        match self {
+            Nop => Category::Nop,
+            Letter(_) | Digit(_) | Increment | Decrement | Add | Subtract | Multiply | Divide | Modulo | Negate
+                | Random | Tick => Category::Arithmetic,
+            ReadByte | ReadInt | WriteByte | WriteInt | Env => Category::Io,
+            Terminate | Jump | Redir(_) | IPPrev | IPNext | IPSelect | Custom(_) => Category::Control,
+            MPLeft | MPRight | MPBackLeft | MPBackRight | MPReverse | MPBranch | MemCopy => Category::Memory,
+        }
+    }
+
+    /// Returns a one-sentence description of what the op does.
+    pub fn description(self) -> &'static str {
+        match self {
+            Nop => "Does nothing",
+            Terminate => "Terminates the program",
+            Letter(_) => "Sets the current edge to the letter's character code",
+            Digit(_) => "Multiplies the current edge by 10, then adds the digit",
+            Increment => "Increments the current edge",
+            Decrement => "Decrements the current edge",
+            Add => "Sets the current edge to the sum of the left and right neighbours",
+            Subtract => "Sets the current edge to the left neighbour minus the right neighbour",
+            Multiply => "Sets the current edge to the product of the left and right neighbours",
+            Divide => "Sets the current edge to the left neighbour divided by the right neighbour, rounded down",
+            Modulo => "Sets the current edge to the left neighbour modulo the right neighbour, with the sign of the right",
+            Negate => "Multiplies the current edge by -1",
+            ReadByte => "Reads a byte into the current edge, or -1 at EOF",
+            ReadInt => "Skips input up to a digit or sign, then reads a decimal integer into the current edge (0 at EOF)",
+            WriteByte => "Writes the current edge modulo 256 as a byte",
+            WriteInt => "Writes the current edge in decimal",
+            Jump => "Skips the next cell",
+            Redir(Redirect::MirrorHori) | Redir(Redirect::MirrorVert)
+                | Redir(Redirect::MirrorForw) | Redir(Redirect::MirrorBack) => "Reflects the IP",
+            Redir(Redirect::BranchLeft) | Redir(Redirect::BranchRight) =>
+                "Reflects the IP, or turns it left or right based on the sign of the current edge when it hits the point",
+            IPPrev => "Switches to the previous IP",
+            IPNext => "Switches to the next IP",
+            IPSelect => "Switches to the IP with the index of the current edge modulo the number of IPs",
+            MPLeft => "Moves the MP to the left neighbour",
+            MPRight => "Moves the MP to the right neighbour",
+            MPBackLeft => "Moves the MP backwards to the left",
+            MPBackRight => "Moves the MP backwards to the right",
+            MPReverse => "Reverses the direction of the MP",
+            MPBranch => "Moves the MP to the right neighbour if the current edge is positive, and to the left otherwise",
+            MemCopy => "Copies the right neighbour into the current edge if it is positive, and the left neighbour otherwise",
+            Random => "Sets the current edge to a random integer below its value, or a random byte if it isn't positive",
+            Tick => "Sets the current edge to the number of ticks executed so far",
+            Env => "Replaces the name of a variable in the current edge with its value, or -1 if it isn't set",
+            Custom(_) => "Runs an instruction provided by the embedding program",
+        }
+    }
+}
+
+impl FromStr for Grid {
+    type Err = Error;
+
+    /// Parses linear source code of any size. Use `Grid::parse_with_max_size` to limit the size of
+    /// untrusted code.
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Grid::parse_with(s, SourceFormat::Linear, Dialect::Standard, usize::MAX, |_| false)
     }
 }
 
@@ -179,20 +754,20 @@ impl fmt::Display for Grid {
             for (op, dbg) in line {
                 write!(f, "{}{}", if *dbg { '`' } else { ' ' }, op)?;
             }
-            writeln!(f, "")?;
+            writeln!(f)?;
         }
         Ok(())
     }
 }
 
 
-impl fmt::Display for Op {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", match self {
+impl From<Op> for char {
+    fn from(op: Op) -> char {
+        match op {
             Nop => '.',
             Terminate => '@',
-            Letter(b) => *b as char,
-            Digit(d) => (*d + b'0') as char,
+            Letter(b) => b as char,
+            Digit(d) => (d + b'0') as char,
             Increment => ')',
             Decrement => '(',
             Add => '+',
@@ -222,6 +797,182 @@ impl fmt::Display for Op {
             MPReverse => '=',
             MPBranch => '^',
             MemCopy => '&',
-        })
+            Random => 'ρ',
+            Tick => 'τ',
+            Env => 'ε',
+            Custom(c) => c,
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", char::from(*self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{examples, redirect, redirect_table, wrap};
+
+    /// A grid of side length 3 with letters in its top three rows:
+    ///
+    /// ```text
+    ///     a b .
+    ///    a b . .
+    ///   c a b . .
+    ///    . . . .
+    ///     . . .
+    /// ```
+    fn letters() -> Grid {
+        "ab.ab..cab".parse().unwrap()
+    }
+
+    fn is_letter(op: Op) -> bool {
+        matches!(op, Letter(_))
+    }
+
+    #[test]
+    fn find_returns_matching_cells_in_reading_order() {
+        let grid = letters();
+        let a: Vec<_> = grid.find(Letter(b'a')).collect();
+        assert_eq!(a, [PointAxial(0, -2), PointAxial(-1, -1), PointAxial(-1, 0)]);
+        assert_eq!(grid.find(Terminate).count(), 0);
+        assert_eq!(grid.find(Nop).count(), 12);
+        let letters: Vec<_> = grid.find_all(is_letter).collect();
+        assert_eq!(letters, [
+            PointAxial(0, -2), PointAxial(1, -2), PointAxial(-1, -1), PointAxial(0, -1),
+            PointAxial(-2, 0), PointAxial(-1, 0), PointAxial(0, 0),
+        ]);
+    }
+
+    #[test]
+    fn find_sequence_reads_in_one_direction_without_wrapping() {
+        let grid = letters();
+        let (a, b) = (Letter(b'a'), Letter(b'b'));
+        let starts = [PointAxial(0, -2), PointAxial(-1, -1), PointAxial(-1, 0)];
+        assert_eq!(grid.find_sequence(&[a, b], Direction::East), starts);
+        assert_eq!(grid.find_sequence(&[b, a], Direction::West), [PointAxial(1, -2), PointAxial(0, -1), PointAxial(0, 0)]);
+        assert_eq!(grid.find_sequence(&[a, a], Direction::SouthEast), [PointAxial(-1, -1)]);
+        // The first row has only one cell after its `b`
+        assert_eq!(grid.find_sequence(&[b, Nop, Nop], Direction::East), [PointAxial(0, -1), PointAxial(0, 0)]);
+        assert!(grid.find_sequence(&[a, b, a], Direction::East).is_empty());
+    }
+
+    #[test]
+    fn runs_are_maximal_and_stop_at_the_edges() {
+        let grid = letters();
+        assert_eq!(grid.runs(Direction::East, is_letter), [
+            vec![PointAxial(0, -2), PointAxial(1, -2)],
+            vec![PointAxial(-1, -1), PointAxial(0, -1)],
+            vec![PointAxial(-2, 0), PointAxial(-1, 0), PointAxial(0, 0)],
+        ]);
+        assert_eq!(grid.runs(Direction::SouthEast, is_letter), [
+            vec![PointAxial(0, -2), PointAxial(0, -1), PointAxial(0, 0)],
+            vec![PointAxial(1, -2)],
+            vec![PointAxial(-1, -1), PointAxial(-1, 0)],
+            vec![PointAxial(-2, 0)],
+        ]);
+        let west = grid.runs(Direction::West, is_letter);
+        assert_eq!(west[2], [PointAxial(0, 0), PointAxial(-1, 0), PointAxial(-2, 0)]);
+        assert!(grid.runs(Direction::East, |op| op == Terminate).is_empty());
+    }
+
+    #[test]
+    fn linear_and_layout_keep_debug_flags() {
+        for src in &["`.", "1`2`@", "`H`;`e;..`.", "`?\"`}=)......`.", "..........`."] {
+            let grid: Grid = src.parse().unwrap();
+            let linear = grid.to_linear();
+            let (from_linear, from_layout): (Grid, Grid) = (linear.parse().unwrap(), grid.to_string().parse().unwrap());
+            for reparsed in &[from_linear, from_layout] {
+                assert_eq!(reparsed.size(), grid.size());
+                assert!(grid.cells().eq(reparsed.cells()), "{:?} changed after a round trip", src);
+            }
+            assert_eq!(linear.parse::<Grid>().unwrap().to_linear(), linear);
+        }
+    }
+
+    #[test]
+    fn layouts_are_detected_with_blank_cells() {
+        let padded = " H ;\n;   i\n  `@\n";
+        let double = "  H   ;\n\n;       i\n\n     `@\n";
+        assert_eq!(SourceFormat::detect(padded), SourceFormat::Padded);
+        assert_eq!(SourceFormat::detect(double), SourceFormat::DoubleSpaced);
+        assert_eq!(SourceFormat::detect("H;;.i.`@"), SourceFormat::Linear);
+        for src in &[padded, double] {
+            let grid = Grid::parse_with(src, SourceFormat::Auto, Dialect::Standard, DEFAULT_MAX_SIZE, |_| false).unwrap();
+            assert_eq!(grid.to_linear(), "H;;.i.`@");
+        }
+        assert_eq!(padded.parse::<Grid>().unwrap().to_linear(), "H;;i`@");
+        assert_eq!(SourceFormat::misread_as_linear(padded), Some(SourceFormat::Padded));
+        assert_eq!(SourceFormat::misread_as_linear(double), Some(SourceFormat::DoubleSpaced));
+        // Filled layouts and blank cells after the last command read the same either way
+        assert_eq!(SourceFormat::misread_as_linear(" H ;\n; i .\n `@ ."), None);
+        assert_eq!(SourceFormat::misread_as_linear(" H ;\n; i .\n `@"), None);
+        assert_eq!(SourceFormat::misread_as_linear("H;;.i.`@"), None);
+    }
+
+    #[test]
+    fn embed_copies_commands_and_rejects_collisions() {
+        let mut grid = Grid::new(3);
+        let snippet: Grid = "?!\n`@".parse().unwrap();
+        grid.embed(&snippet, PointAxial(1, 0)).unwrap();
+        assert_eq!(grid.get(PointAxial(1, -1)), (Op::ReadInt, false));
+        assert_eq!(grid.get(PointAxial(2, -1)), (Op::WriteInt, false));
+        assert_eq!(grid.get(PointAxial(0, 0)), (Op::Terminate, true));
+        // Cells that already hold the same command don't collide
+        grid.embed(&snippet, PointAxial(1, 0)).unwrap();
+        assert!(matches!(grid.embed(&snippet, PointAxial(2, 0)), Err(Error::CellCollision(PointAxial(2, -1), Op::WriteInt, Op::ReadInt))));
+        assert!(matches!(grid.embed(&snippet, PointAxial(2, 1)), Err(Error::CellOutsideGrid(PointAxial(3, 0)))));
+        assert_eq!(grid.get(PointAxial(2, 0)), (Op::Nop, false));
+    }
+
+    #[test]
+    fn reflection_mirrors_the_layout() {
+        let grid: Grid = examples::find("primes").unwrap().source().parse().unwrap();
+        for &axis in &[Axis::Horizontal, Axis::Vertical] {
+            let reflected = grid.reflect(axis);
+            for (coords, op, debug) in grid.cells() {
+                assert_eq!(reflected.get(coords.reflect(axis)), (op.reflect(axis), debug));
+            }
+            for (dir, redir, positive, result) in redirect_table() {
+                let reflected = redirect(dir.reflect(axis), redir.reflect(axis), !positive);
+                assert_eq!(reflected, result.reflect(axis), "{} hit moving {} across {:?}", redir, dir, axis);
+            }
+            for (coords, _, _) in grid.cells() {
+                for dir in Direction::all() {
+                    let reflected = wrap(grid.size(), coords.reflect(axis), dir.reflect(axis), false);
+                    assert_eq!(reflected, wrap(grid.size(), coords, dir, true).reflect(axis));
+                }
+            }
+            assert!(grid.cells().eq(grid.reflect(axis).reflect(axis).cells()));
+        }
+    }
+
+    #[test]
+    fn op_metadata_covers_every_command() {
+        let mut names = std::collections::HashSet::new();
+        for op in Op::all() {
+            assert_eq!(Op::from_char(char::from(op)), Some(op));
+            assert!(!op.description().is_empty() && !op.description().ends_with('.'), "{}", op);
+            if let Op::Letter(_) | Op::Digit(_) = op {
+                continue;
+            }
+            assert!(names.insert(op.name()), "{} is used twice", op.name());
+        }
+        assert_eq!(Op::from_char('k').map(Op::name), Some("Letter"));
+        assert_eq!(Op::from_char(' '), None);
+    }
+
+    #[test]
+    fn annotated_grids_label_rows_and_cells() {
+        let grid: Grid = "1`!)@".parse().unwrap();
+        let rows = "r=-1   1`!    q=0..1\n\
+                    r=0   ) @ .   q=-1..1\n\
+                    r=1    . .    q=-1..0\n";
+        assert_eq!(grid.annotated(false), rows);
+        let legend = "\n(0, -1) 1  (1, -1) !\n(-1, 0) )  (0, 0) @  (1, 0) .\n(-1, 1) .  (0, 1) .\n";
+        assert_eq!(grid.annotated(true), format!("{}{}", rows, legend));
     }
 }
@@ -0,0 +1,167 @@
+use std::{env, fs, path::{Path, PathBuf}, process};
+
+use crate::{Error, cases::{TestCase, parse_cases}, report::run_cases};
+
+/// A `cargo test` runner for directories of Hexagony programs, each tested against its expected
+/// input and output files.
+///
+/// For every `NAME.hxg` file in a directory, the test cases are read from `NAME.cases` in the format
+/// of `cases::parse_cases`, or else a single case is made from `NAME.out` and, if it exists, `NAME.in`.
+/// Every test case is reported as a test of its own, named `PATH::CASE`.
+///
+/// Register a test target without the standard harness in `Cargo.toml`:
+///
+/// ```toml
+/// [[test]]
+/// name = "programs"
+/// harness = false
+/// ```
+///
+/// and run the harness from the `main` function of `tests/programs.rs`:
+///
+/// ```no_run
+/// hexagony::harness::Harness::new().dir("tests/programs").run();
+/// ```
+///
+/// Like the standard harness, it runs only the tests whose names contain the first free argument
+/// (or equal it with `--exact`), and lists them instead with `--list`.
+pub struct Harness {
+    dirs: Vec<PathBuf>,
+    max_ticks: u64,
+}
+
+/// A test case of a program found by a `Harness`.
+struct Trial {
+    name: String,
+    src: String,
+    case: TestCase,
+}
+
+impl Default for Harness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Harness {
+    /// Creates a harness without any directories, which stops each program after a million ticks.
+    pub fn new() -> Self {
+        Harness { dirs: Vec::new(), max_ticks: 1_000_000 }
+    }
+
+    /// Adds a directory of programs to test.
+    pub fn dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.dirs.push(path.into());
+        self
+    }
+
+    /// Sets the maximum number of ticks a program may run for in each test case.
+    pub fn max_ticks(mut self, max_ticks: u64) -> Self {
+        self.max_ticks = max_ticks;
+        self
+    }
+
+    /// Runs the tests selected by the command line arguments and exits, with status 101 if any failed.
+    pub fn run(self) -> ! {
+        let (mut filter, mut exact, mut list) = (None, false, false);
+        for arg in env::args().skip(1) {
+            match arg.as_str() {
+                "--exact" => exact = true,
+                "--list" => list = true,
+                // Flags of the standard harness that don't apply, such as `--nocapture`
+                _ if arg.starts_with('-') => (),
+                _ => filter = Some(arg),
+            }
+        }
+        let trials = match self.collect() {
+            Ok(trials) => trials,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                process::exit(101);
+            }
+        };
+        let total = trials.len();
+        let trials: Vec<_> = trials.into_iter().filter(|trial| match &filter {
+            Some(filter) if exact => trial.name == *filter,
+            Some(filter) => trial.name.contains(filter.as_str()),
+            None => true,
+        }).collect();
+        if list {
+            for trial in &trials {
+                println!("{}: test", trial.name);
+            }
+            process::exit(0);
+        }
+        println!("\nrunning {} tests", trials.len());
+        let mut failures = Vec::new();
+        for trial in &trials {
+            let reports = run_cases(&trial.src, std::slice::from_ref(&trial.case), self.max_ticks);
+            match reports[0].failure() {
+                None => println!("test {} ... ok", trial.name),
+                Some(lines) => {
+                    println!("test {} ... FAILED", trial.name);
+                    failures.push((&trial.name, lines));
+                }
+            }
+        }
+        if !failures.is_empty() {
+            println!("\nfailures:\n");
+            for (name, lines) in &failures {
+                println!("---- {} ----", name);
+                for line in lines {
+                    println!("{}", line);
+                }
+                println!();
+            }
+            println!("failures:");
+            for (name, _) in &failures {
+                println!("    {}", name);
+            }
+        }
+        println!(
+            "\ntest result: {}. {} passed; {} failed; 0 ignored; 0 measured; {} filtered out\n",
+            if failures.is_empty() { "ok" } else { "FAILED" },
+            trials.len() - failures.len(),
+            failures.len(),
+            total - trials.len(),
+        );
+        process::exit(if failures.is_empty() { 0 } else { 101 });
+    }
+
+    /// Finds every program in the directories and its test cases, in order of path.
+    fn collect(&self) -> Result<Vec<Trial>, Error> {
+        let mut trials = Vec::new();
+        for dir in &self.dirs {
+            let mut paths = fs::read_dir(dir)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            paths.retain(|path| path.extension().is_some_and(|ext| ext == "hxg"));
+            paths.sort();
+            for path in paths {
+                let src = fs::read_to_string(&path)?;
+                for case in cases_for(&path)? {
+                    let name = format!("{}::{}", path.display(), case.name);
+                    trials.push(Trial { name, src: src.clone(), case });
+                }
+            }
+        }
+        Ok(trials)
+    }
+}
+
+/// Reads the test cases of the program at `path` from the files next to it.
+fn cases_for(path: &Path) -> Result<Vec<TestCase>, Error> {
+    let cases = path.with_extension("cases");
+    if cases.exists() {
+        return parse_cases(&fs::read_to_string(cases)?);
+    }
+    let (input, output) = (path.with_extension("in"), path.with_extension("out"));
+    if !output.exists() {
+        return Err(Error::ParseError("test program", format!("{} has no .cases or .out file", path.display())));
+    }
+    Ok(vec![TestCase {
+        name: "main".to_string(),
+        input: if input.exists() { fs::read(input)? } else { Vec::new() },
+        output: fs::read(output)?,
+    }])
+}
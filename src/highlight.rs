@@ -0,0 +1,67 @@
+use crate::grid::Grid;
+
+/// Colors of each category of command, and the marker for cells with a debug flag.
+const STYLE: &str = "\
+.hexagony { background: #1e1e1e; color: #d4d4d4; padding: 1em; line-height: 1.3; }
+.hexagony .nop { color: #6a6a6a; }
+.hexagony .arithmetic { color: #b5cea8; }
+.hexagony .io { color: #ce9178; }
+.hexagony .control { color: #c586c0; font-weight: bold; }
+.hexagony .memory { color: #4fc1ff; }
+.hexagony .debug { text-decoration: underline wavy #f44747; }
+";
+
+/// Renders a grid as a standalone HTML fragment, laid out as a hexagon like its `Display` form,
+/// with each command colored by its `Category` and cells with a debug flag underlined.
+///
+/// The fragment is a `<style>` element followed by a `<pre class="hexagony">` element, so it can be
+/// pasted into a page or post as it is.
+pub fn to_html(grid: &Grid) -> String {
+    let mut out = format!("<style>\n{}</style>\n<pre class=\"hexagony\">\n", STYLE);
+    let mut row = None;
+    // Cells come in reading order, so each row starts where `r` changes
+    for (coords, op, debug) in grid.cells() {
+        if row != Some(coords.1) {
+            if row.is_some() {
+                out.push('\n');
+            }
+            row = Some(coords.1);
+            out += &" ".repeat(coords.1.unsigned_abs());
+        }
+        let class = match debug {
+            true => format!("{} debug", op.category().name()),
+            false => op.category().name().to_string(),
+        };
+        out += &format!(" <span class=\"{}\">{}</span>", class, escape(char::from(op)));
+    }
+    out += "\n</pre>\n";
+    out
+}
+
+/// Escapes a character for use in HTML text.
+fn escape(c: char) -> String {
+    match c {
+        '<' => "&lt;".to_string(),
+        '>' => "&gt;".to_string(),
+        '&' => "&amp;".to_string(),
+        '"' => "&quot;".to_string(),
+        '\'' => "&#39;".to_string(),
+        _ => c.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_colors_cells_by_category_and_escapes_them() {
+        let html = to_html(&"<`)&@".parse().unwrap());
+        assert!(html.starts_with("<style>\n.hexagony {"));
+        let rows = "<pre class=\"hexagony\">\n  \
+                    <span class=\"control\">&lt;</span> <span class=\"arithmetic debug\">)</span>\n \
+                    <span class=\"memory\">&amp;</span> <span class=\"control\">@</span> <span class=\"nop\">.</span>\n  \
+                    <span class=\"nop\">.</span> <span class=\"nop\">.</span>\n</pre>\n";
+        assert!(html.ends_with(rows), "{}", html);
+    }
+}
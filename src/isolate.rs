@@ -0,0 +1,69 @@
+use std::io;
+
+/// Resource limits applied to an isolated child process.
+///
+/// There's no limit on output here, since `RLIMIT_FSIZE` only covers regular files, not pipes, and would
+/// also cut off error messages and save states. `Builder::max_output` limits what the program writes instead.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    /// CPU time in seconds, after which the child is killed with SIGXCPU
+    pub cpu_secs: Option<u64>,
+    /// Size of the child's address space in bytes, past which allocations fail
+    pub memory_bytes: Option<u64>,
+}
+
+/// How an isolated child process ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The child exited with the given status
+    Exited(i32),
+    /// The child used up its CPU time
+    CpuLimitExceeded,
+    /// The child was killed by the given signal, such as SIGABRT when it runs out of memory
+    Signaled(i32),
+}
+
+/// Forks the process, applying `limits` to the child, which shares STDIN, STDOUT and STDERR
+/// with the parent so its output streams through as it's written.
+///
+/// Returns `None` in the child, which should go on to run the program and exit,
+/// and the outcome in the parent once the child has ended.
+///
+/// Forking copies only the calling thread, so this should be called before starting any threads.
+pub fn fork_isolated(limits: &Limits) -> io::Result<Option<Outcome>> {
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if pid == 0 {
+        let limits = [
+            (libc::RLIMIT_CPU, limits.cpu_secs),
+            (libc::RLIMIT_AS, limits.memory_bytes),
+        ];
+        for (resource, limit) in IntoIterator::into_iter(limits) {
+            if let Some(limit) = limit {
+                // Leave a second of CPU time past the soft limit, so the child gets SIGXCPU instead of SIGKILL
+                let max = if resource == libc::RLIMIT_CPU { limit + 1 } else { limit };
+                let rlimit = libc::rlimit { rlim_cur: limit as libc::rlim_t, rlim_max: max as libc::rlim_t };
+                if unsafe { libc::setrlimit(resource, &rlimit) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        return Ok(None);
+    }
+    let mut status = 0;
+    while unsafe { libc::waitpid(pid, &mut status, 0) } < 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+    Ok(Some(match libc::WIFEXITED(status) {
+        true => Outcome::Exited(libc::WEXITSTATUS(status)),
+        false => match libc::WTERMSIG(status) {
+            libc::SIGXCPU => Outcome::CpuLimitExceeded,
+            signal => Outcome::Signaled(signal),
+        },
+    }))
+}
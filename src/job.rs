@@ -0,0 +1,164 @@
+use std::{fmt::Write, io::Cursor, time::{Duration, Instant}};
+
+use crate::{
+    Builder, CostModel, Dialect, Error, IntEof, ValueType, Wrapping, coords::PointAxial,
+    json::{Json, json_string}, memory::MemoryModel, spec::SpecVersion,
+};
+
+/// Runs a job described as JSON, like the jobs of an online judge or code-running service,
+/// and returns its result as JSON.
+///
+/// A job has the program's `source`, its `input`, a list of command line `flags` such as
+/// `"--dialect=extensions"`, and `limits` on the `ticks`, `output` bytes, `memory` bytes and
+/// wall `time` in seconds:
+///
+/// ```json
+/// {"source": "?!@", "input": "5", "flags": ["--values=byte"], "limits": {"ticks": 1000, "time": 2.5}}
+/// ```
+///
+/// Every field except `source` is optional. The supported flags are `--dialect`, `--wrapping`,
+/// `--values`, `--memory`, `--cost`, `--eof`, `--int-eof`, `--spec-version`, `--ips` and `--seed`,
+/// with values as on the command line.
+///
+/// The result has the program's `output`, the error report in `stderr`, an `exit_status` of 0
+/// if the program terminated and 1 if it failed, and `stats` with the `ticks` and `cost` it ran
+/// for and its wall `time` in seconds:
+///
+/// ```json
+/// {"output": "5", "stderr": "", "exit_status": 0, "error": null, "stats": {"ticks": 3, "cost": 3, "time": 0.000012}}
+/// ```
+///
+/// When the program fails, `error` has the error's `message`, and runtime errors that know where
+/// they happened also have the `tick`, the active `ip` and the `q` and `r` coordinates of the cell:
+///
+/// ```json
+/// {"message": "Division by zero at tick 1, IP 0, cell (1, -1)", "tick": 1, "ip": 0, "q": 1, "r": -1}
+/// ```
+///
+/// Output that isn't valid UTF-8 is converted lossily. A job that isn't valid is rejected with
+/// `Error::ParseError` rather than reported in a result.
+pub fn run_job(job: &str) -> Result<String, Error> {
+    let job = Json::parse(job)?;
+    let invalid = |msg: &str| Error::ParseError("job", msg.to_string());
+    let source = job.get("source").and_then(Json::as_str).ok_or_else(|| invalid("expected a `source` string"))?;
+    let input = match job.get("input") {
+        None | Some(Json::Null) => "",
+        Some(input) => input.as_str().ok_or_else(|| invalid("expected an `input` string"))?,
+    };
+    let mut output = Vec::new();
+    let mut builder = Builder::new(source).input(Cursor::new(input.as_bytes().to_vec())).output(&mut output);
+    match job.get("flags") {
+        None | Some(Json::Null) => (),
+        Some(Json::Array(flags)) => {
+            for flag in flags {
+                builder = apply_flag(builder, flag.as_str().ok_or_else(|| invalid("expected `flags` to be strings"))?)?;
+            }
+        }
+        Some(_) => return Err(invalid("expected a `flags` array")),
+    }
+    // Limits are checked against the largest value each setting can hold, since casts would saturate
+    let limit = |name: &str, max: f64| match job.get("limits").and_then(|limits| limits.get(name)) {
+        None | Some(Json::Null) => Ok(None),
+        Some(value) => value
+            .as_f64()
+            .filter(|n| n.is_finite() && *n >= 0.0 && *n < max)
+            .map(Some)
+            .ok_or_else(|| invalid(&format!("expected `limits.{}` to be a non-negative number in range", name))),
+    };
+    // 2^64 is the first float past `u64::MAX`
+    let u64_end = 18_446_744_073_709_551_616.0;
+    if let Some(ticks) = limit("ticks", u64_end)? {
+        builder = builder.max_ticks(ticks as u64);
+    }
+    if let Some(bytes) = limit("output", u64_end)? {
+        builder = builder.max_output(bytes as u64);
+    }
+    if let Some(bytes) = limit("memory", usize::MAX as f64)? {
+        builder = builder.memory_limit(bytes as usize);
+    }
+    let start = Instant::now();
+    if let Some(secs) = limit("time", f64::MAX)? {
+        let deadline = Duration::try_from_secs_f64(secs).ok().and_then(|time| start.checked_add(time));
+        builder = builder.deadline(deadline.ok_or_else(|| invalid("expected `limits.time` to be in range"))?, 1000);
+    }
+    let (stderr, error, stats) = match builder.build() {
+        Ok(mut hex) => {
+            let result = hex.run();
+            let stats = hex.stats();
+            let (stderr, error) = match &result {
+                Ok(()) => (String::new(), "null".to_string()),
+                Err(e) => (hex.error_report(e), error_json(e)),
+            };
+            (stderr, error, Some(stats))
+        }
+        Err(e) => (format!("Error: {}\n", e), error_json(&e), None),
+    };
+    let time = start.elapsed().as_secs_f64();
+    let mut json = format!(
+        r#"{{"output": {}, "stderr": {}, "exit_status": {}, "error": {}, "stats": {{"#,
+        json_string(&String::from_utf8_lossy(&output)),
+        json_string(&stderr),
+        if stderr.is_empty() { 0 } else { 1 },
+        error,
+    );
+    if let Some(stats) = stats {
+        write!(json, r#""ticks": {}, "cost": {}, "#, stats.ticks, stats.cost).unwrap();
+    }
+    write!(json, r#""time": {:.6}}}}}"#, time).unwrap();
+    Ok(json)
+}
+
+/// Formats an error as the `error` object of a job result.
+fn error_json(error: &Error) -> String {
+    let mut json = format!(r#"{{"message": {}"#, json_string(&error.to_string()));
+    if let Some(location) = error.location() {
+        let PointAxial(q, r) = location.coords;
+        write!(json, r#", "tick": {}, "ip": {}, "q": {}, "r": {}"#, location.tick, location.ip, q, r).unwrap();
+    }
+    json + "}"
+}
+
+/// Configures a builder with a command line flag written as `--name=value`.
+fn apply_flag<'a>(builder: Builder<'a>, flag: &str) -> Result<Builder<'a>, Error> {
+    let invalid = || Error::ParseError("flag", flag.to_string());
+    let (name, value) = flag.strip_prefix("--").and_then(|flag| flag.split_once('=')).ok_or_else(invalid)?;
+    Ok(match (name, value) {
+        ("dialect", "standard") => builder.dialect(Dialect::Standard),
+        ("dialect", "extensions") => builder.dialect(Dialect::Extensions),
+        ("wrapping", "standard") => builder.wrapping(Wrapping::Standard),
+        ("wrapping", "positive") => builder.wrapping(Wrapping::AlwaysPositive),
+        ("wrapping", "torus") => builder.wrapping(Wrapping::Torus),
+        ("values", "unbounded") => builder.value_type(ValueType::Unbounded),
+        ("values", "byte") => builder.value_type(ValueType::Byte),
+        ("memory", model) => builder.memory_model(MemoryModel::from_name(model).ok_or_else(invalid)?),
+        ("cost", "uniform") => builder.cost_model(CostModel::Uniform),
+        ("cost", "bits") => builder.cost_model(CostModel::BitLength),
+        ("eof", value @ ("-1" | "0" | "256")) => builder.byte_eof(value.parse().unwrap()),
+        ("int-eof", "zero") => builder.int_eof(IntEof::Zero),
+        ("int-eof", "minus-one") => builder.int_eof(IntEof::MinusOne),
+        ("int-eof", "unchanged") => builder.int_eof(IntEof::Unchanged),
+        ("spec-version", n) => builder.spec_version(n.parse().ok().and_then(SpecVersion::from_number).ok_or_else(invalid)?),
+        ("ips", n) => match n.parse() {
+            Ok(count @ 1..=256) => builder.ip_count(count),
+            _ => return Err(invalid()),
+        },
+        ("seed", n) => builder.random_seed(n.parse().map_err(|_| invalid())?),
+        _ => return Err(invalid()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_reject_unrepresentable_limits_and_deep_nesting() {
+        for limits in ["{\"time\": 1e300}", "{\"time\": 1e999}", "{\"ticks\": -1}", "{\"output\": 1e20}"] {
+            let job = format!("{{\"source\": \"@\", \"limits\": {}}}", limits);
+            assert!(matches!(run_job(&job), Err(Error::ParseError("job", _))), "{}", limits);
+        }
+        assert!(run_job("{\"source\": \"@\", \"limits\": {\"time\": 2.5}}").unwrap().contains("\"exit_status\": 0"));
+        let nested = format!("{{\"source\": \"@\", \"flags\": {}{}}}", "[".repeat(200_000), "]".repeat(200_000));
+        assert!(matches!(run_job(&nested), Err(Error::ParseError("JSON", _))));
+    }
+}
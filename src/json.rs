@@ -0,0 +1,215 @@
+use crate::Error;
+
+/// A parsed JSON value.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parses a JSON document.
+    pub(crate) fn parse(s: &str) -> Result<Json, Error> {
+        let mut parser = Parser { chars: s.chars().collect(), pos: 0, depth: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.pos == parser.chars.len() {
+            true => Ok(value),
+            false => Err(parser.error("trailing characters")),
+        }
+    }
+
+    /// Returns the value of a key if this is an object that has it.
+    pub(crate) fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Returns the string if this is one.
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the number if this is one.
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+/// The deepest that arrays and objects may be nested, so that parsing can't overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+/// A recursive descent parser over the characters of a JSON document.
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn error(&self, msg: &str) -> Error {
+        Error::ParseError("JSON", format!("{} at character {}", msg, self.pos))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| matches!(c, ' ' | '\t' | '\n' | '\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn next(&mut self) -> Result<char, Error> {
+        let c = *self.chars.get(self.pos).ok_or_else(|| self.error("unexpected end"))?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), Error> {
+        for expected in word.chars() {
+            if self.next()? != expected {
+                return Err(self.error(&format!("expected `{}`", word)));
+            }
+        }
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, Error> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("values nested too deeply"));
+        }
+        self.depth += 1;
+        let value = self.parse_value();
+        self.depth -= 1;
+        value
+    }
+
+    fn parse_value(&mut self) -> Result<Json, Error> {
+        self.skip_whitespace();
+        match self.chars.get(self.pos) {
+            Some('n') => self.expect("null").map(|_| Json::Null),
+            Some('t') => self.expect("true").map(|_| Json::Bool(true)),
+            Some('f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some('"') => self.string().map(Json::String),
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.chars.get(self.pos) == Some(&']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.next()? {
+                        ',' => (),
+                        ']' => return Ok(Json::Array(items)),
+                        _ => return Err(self.error("expected `,` or `]`")),
+                    }
+                }
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut entries = Vec::new();
+                self.skip_whitespace();
+                if self.chars.get(self.pos) == Some(&'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(entries));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.chars.get(self.pos) != Some(&'"') {
+                        return Err(self.error("expected a key"));
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(":")?;
+                    entries.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.next()? {
+                        ',' => (),
+                        '}' => return Ok(Json::Object(entries)),
+                        _ => return Err(self.error("expected `,` or `}`")),
+                    }
+                }
+            }
+            Some(c) if *c == '-' || c.is_ascii_digit() => {
+                let start = self.pos;
+                while self.chars.get(self.pos).is_some_and(|c| matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E')) {
+                    self.pos += 1;
+                }
+                let number: String = self.chars[start..self.pos].iter().collect();
+                number.parse().map(Json::Number).map_err(|_| self.error("invalid number"))
+            }
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.expect("\"")?;
+        let mut s = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Ok(s),
+                '\\' => match self.next()? {
+                    '"' => s.push('"'),
+                    '\\' => s.push('\\'),
+                    '/' => s.push('/'),
+                    'b' => s.push('\u{8}'),
+                    'f' => s.push('\u{c}'),
+                    'n' => s.push('\n'),
+                    'r' => s.push('\r'),
+                    't' => s.push('\t'),
+                    'u' => {
+                        let mut code = self.hex4()?;
+                        // A surrogate pair encodes a character outside the Basic Multilingual Plane
+                        if (0xd800..0xdc00).contains(&code) {
+                            self.expect("\\u")?;
+                            let low = self.hex4()?;
+                            code = 0x10000 + ((code - 0xd800) << 10) + low.wrapping_sub(0xdc00);
+                        }
+                        s.push(char::from_u32(code).ok_or_else(|| self.error("invalid escape"))?);
+                    }
+                    _ => return Err(self.error("invalid escape")),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, Error> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.next()?.to_digit(16).ok_or_else(|| self.error("invalid escape"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+}
+
+/// Returns a string as a quoted JSON string.
+pub(crate) fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json += "\\\"",
+            '\\' => json += "\\\\",
+            '\n' => json += "\\n",
+            '\r' => json += "\\r",
+            c if (c as u32) < 0x20 => json += &format!("\\u{:04x}", c as u32),
+            c => json.push(c),
+        }
+    }
+    json + "\""
+}
@@ -0,0 +1,91 @@
+use crate::cases::{TestCase, parse_cases};
+
+/// A practice task with hidden test cases.
+pub struct Kata {
+    pub name: &'static str,
+    pub description: &'static str,
+    cases: &'static str,
+}
+
+/// Every bundled kata, from easiest to hardest.
+const KATAS: &[Kata] = &[
+    Kata {
+        name: "hello",
+        description: "Print `Hello, World!` without a trailing newline.",
+        cases: r#"
+[no input]
+output = "Hello, World!"
+"#,
+    },
+    Kata {
+        name: "cat",
+        description: "Print the input unchanged, stopping at the end of the input.",
+        cases: r#"
+[empty]
+[one line]
+input = "Hello\n"
+output = "Hello\n"
+[several lines]
+input = "line 1\nline 2\n\nline 4"
+output = "line 1\nline 2\n\nline 4"
+[binary]
+input = "\x00\x01\x7f\xfe"
+output = "\x00\x01\x7f\xfe"
+"#,
+    },
+    Kata {
+        name: "fibonacci",
+        description: "Read a number n and print the nth Fibonacci number, where F(0) = 0 and F(1) = 1.",
+        cases: r#"
+[zero]
+input = "0"
+output = "0"
+[one]
+input = "1"
+output = "1"
+[two]
+input = "2"
+output = "1"
+[ten]
+input = "10"
+output = "55"
+[large]
+input = "90"
+output = "2880067194370816120"
+"#,
+    },
+];
+
+impl Kata {
+    /// Returns the hidden test cases of this kata.
+    pub fn cases(&self) -> Vec<TestCase> {
+        parse_cases(self.cases).expect("bundled test cases are valid")
+    }
+}
+
+/// Returns every bundled kata.
+pub fn katas() -> &'static [Kata] {
+    KATAS
+}
+
+/// Returns the bundled kata with the given name.
+pub fn find(name: &str) -> Option<&'static Kata> {
+    KATAS.iter().find(|kata| kata.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples;
+
+    #[test]
+    fn katas_check_solutions_against_hidden_cases() {
+        for name in &["hello", "cat"] {
+            let solution = examples::find(name).unwrap().source();
+            assert!(find(name).unwrap().cases().iter().all(|case| case.check(solution, 100_000)), "kata {}", name);
+        }
+        assert!(!find("hello").unwrap().cases().iter().any(|case| case.check("@", 100_000)));
+        assert!(katas().iter().all(|kata| !kata.cases().is_empty()));
+        assert!(find("nope").is_none());
+    }
+}
@@ -1,24 +1,152 @@
-use std::{fmt, io::{self, Read, Write}, iter::Peekable};
-use rug::{Assign, Integer};
+use std::{fmt, io, sync::{Arc, atomic::{AtomicBool, Ordering}}, thread, time::{Duration, Instant}};
+use rug::Integer;
 
-use coords::PointAxial;
-use direction::{Direction, redirect};
-use grid::{Grid, Op};
+use coords::{PointAxial, PointCube};
+use counter::Counter;
+use explain::{Snapshot, explain};
+use fingerprint::Fnv;
 use memory::Memory;
+pub use analysis::{DEFAULT_MAX_STATES, Termination, check_termination};
+pub use builder::Builder;
+pub use direction::{Axis, Direction, Redirect, redirect, redirect_sources, redirect_table};
+pub use grid::{Category, DEFAULT_MAX_SIZE, Dialect, Grid, Op, SourceFormat};
+pub use runtime::{IntEof, Runtime, ValueType, binary_stdout};
+pub use stats::{IPStats, Stats};
 
-mod coords;
+mod analysis;
+pub mod annotate;
+pub mod bench;
+mod builder;
+pub mod cases;
+pub mod cast;
+pub mod compile;
+pub mod coords;
+mod counter;
+pub mod custom;
+pub mod debugger;
+pub mod diff;
 mod direction;
+#[cfg(unix)]
+pub mod editor;
+pub mod examples;
+pub mod expand;
+pub mod expect;
+mod explain;
+mod fingerprint;
+pub mod generate;
+#[cfg(unix)]
+pub mod isolate;
+pub mod job;
 mod grid;
-mod memory;
+pub mod harness;
+pub mod highlight;
+mod json;
+pub mod kata;
+pub mod memory;
+pub mod minimize;
+pub mod mirror;
+pub mod observer;
+pub mod pipe;
+pub mod profile;
+pub mod quine;
+pub mod record;
+pub mod repack;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod report;
+mod runtime;
+pub mod signal;
+pub mod spec;
+pub mod state;
+mod stats;
+pub mod superopt;
+pub mod testing;
+pub mod trace;
+pub mod trim;
 
 /// Returns a `String` representation of an empty `Grid` with the given side length.
+///
+/// See `Template` for other fill characters, a `@` and coordinate rulers.
 pub fn source_template(size: usize) -> String {
-    match size {
-        0 => String::new(),
-        _ => Grid::new(size).to_string(),
+    Template::new(size).to_string()
+}
+
+/// An empty grid to start writing a program from, formatted with `Display`.
+///
+/// ```
+/// use hexagony::{Template, coords::PointAxial};
+///
+/// let template = Template::new(2).fill('_').terminate_at(PointAxial(1, 0));
+/// assert_eq!(template.to_string(), "  _ _\n _ _ @\n  _ _\n");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Template {
+    size: usize,
+    fill: char,
+    terminate: Option<PointAxial>,
+    rulers: bool,
+}
+
+impl Template {
+    /// Creates a new `Template` of the given side length, filled with `.` and without rulers.
+    pub fn new(size: usize) -> Template {
+        Template { size, fill: '.', terminate: None, rulers: false }
+    }
+
+    /// Sets the character of the empty cells.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fill` is whitespace, which the template couldn't be parsed back from.
+    pub fn fill(mut self, fill: char) -> Self {
+        assert!(!fill.is_whitespace(), "templates can't be filled with whitespace");
+        self.fill = fill;
+        self
+    }
+
+    /// Places a `@` at the given grid coordinates.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `coords` lies outside the grid.
+    pub fn terminate_at(mut self, coords: PointAxial) -> Self {
+        assert!(coords.in_bounds(self.size), "coordinates {} lie outside the grid", coords);
+        self.terminate = Some(coords);
+        self
+    }
+
+    /// Sets whether to label each row with its `r` coordinate and range of `q` coordinates and number the
+    /// columns of text above and below, like `Grid::ruled`, which makes the template a reference rather
+    /// than source code.
+    pub fn rulers(mut self, rulers: bool) -> Self {
+        self.rulers = rulers;
+        self
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.size == 0 {
+            return Ok(());
+        }
+        let mut grid = Grid::new(self.size);
+        if let Some(coords) = self.terminate {
+            grid.set(coords, (Op::Terminate, false));
+        }
+        let text = if self.rulers { grid.ruled() } else { grid.to_string() };
+        let fill = self.fill.to_string();
+        for line in text.lines() {
+            // Leave the `q=` ruler alone, since its range is written with dots
+            let (cells, ruler) = line.split_at(line.find("   q=").unwrap_or(line.len()));
+            writeln!(f, "{}{}", cells.replace('.', &fill), ruler)?;
+        }
+        Ok(())
     }
 }
 
+/// Number of ticks between checks of the flag set by `Builder::dump_when`.
+const DUMP_CHECK_INTERVAL: u64 = 1024;
+
 /// Parses and runs a string slice of Hexagony source code.
 ///
 /// If the `debug_level` is 1, debug info will be printed when an instruction
@@ -32,192 +160,647 @@ pub fn run(src: &str, debug_level: u8) -> Result<(), Error> {
 /// A Hexagony interpreter.
 ///
 /// Stores all state-related information needed to run a Hexagony program.
-struct Hexagony {
+pub struct Hexagony<'a> {
     grid: Grid,
-    mem: Memory,
-    ips: [IP; 6],
+    rt: Runtime<'a>,
+    ips: Vec<IP>,
+    ip_stats: Vec<stats::IPCounters>,
+    /// Steps each IP can take in its direction before it might leave the grid, or 0 if unknown
+    ip_room: Vec<usize>,
     ip_idx: usize,
-    tick: Integer,
+    tick: Counter,
     debug_level: u8,
-    input: Peekable<io::Bytes<io::Stdin>>,
+    explain: bool,
+    tick_range: (u64, Option<u64>),
+    max_ticks: Option<u64>,
+    cost_model: CostModel,
+    handlers: Option<Vec<Vec<runtime::Handler>>>,
+    cost: Counter,
+    deadline: Option<(Instant, u64)>,
+    input_log: Option<Box<dyn io::Write + 'a>>,
+    checkpoints: Option<state::Checkpoints>,
+    dump_flag: Option<&'a AtomicBool>,
+    abort: Option<AbortHandle>,
+    op_hash: Option<Fnv>,
+    trace: Option<Box<dyn io::Write + 'a>>,
+    output_log: Option<Box<dyn io::Write + 'a>>,
+    delay: Option<Duration>,
+    follow_ip: Option<usize>,
+    followed_away: bool,
+    dialect: Dialect,
+    wrapping: Wrapping,
+    observers: Vec<Box<dyn observer::Observer + 'a>>,
+    watch_memory: bool,
+}
+
+/// A handle that stops a running interpreter from another thread, registered with `Builder::abort_handle`.
+///
+/// The interpreter checks the handle at the start of every tick and stops with `Error::Aborted`,
+/// leaving its state available through methods like `Hexagony::save_state`.
+#[derive(Clone, Debug, Default)]
+pub struct AbortHandle(Arc<AtomicBool>);
+
+impl AbortHandle {
+    /// Creates a new `AbortHandle` that hasn't been triggered.
+    pub fn new() -> AbortHandle {
+        AbortHandle::default()
+    }
+
+    /// Stops every interpreter using this handle at its next tick.
+    pub fn abort(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether `abort` has been called.
+    pub fn is_aborted(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
 }
 
 /// An instruction pointer (IP).
 ///
 /// Each IP stores its location on the grid and its current direction.
-struct IP {
-    coords: PointAxial,
-    dir: Direction,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IP {
+    pub coords: PointAxial,
+    pub dir: Direction,
 }
 
-impl Hexagony {
+impl Hexagony<'_> {
     /// Creates a new Hexagony interpreter with the given source code and debug level.
-    fn new(src: &str, debug_level: u8) -> Result<Self, Error> {
-        let grid: Grid = src.parse()?;
-        let size = grid.size() as isize;
-        Ok(Hexagony {
-            grid,
-            mem: Memory::new(),
-            ips: [
-                IP { coords: PointAxial(0, -size + 1), dir: Direction::East },
-                IP { coords: PointAxial(size - 1, -size + 1), dir: Direction::SouthEast },
-                IP { coords: PointAxial(size - 1, 0), dir: Direction::SouthWest },
-                IP { coords: PointAxial(0, size - 1), dir: Direction::West },
-                IP { coords: PointAxial(-size + 1, size - 1), dir: Direction::NorthWest },
-                IP { coords: PointAxial(-size + 1, 0), dir: Direction::NorthEast },
-            ],
-            ip_idx: 0,
-            tick: Integer::new(),
-            debug_level,
-            input: std::io::stdin().bytes().peekable(),
-        })
+    ///
+    /// Use a `Builder` for more configuration options.
+    pub fn new(src: &str, debug_level: u8) -> Result<Self, Error> {
+        Builder::new(src).debug_level(debug_level).build()
     }
 
     /// Runs the interpreter.
     ///
     /// Returns `Ok` if it hit a terminate instruction and `Err` if a runtime error occurred.
-    fn run(&mut self) -> Result<(), Error> {
-        loop {
-            let (op, dbg) = self.grid.get(self.ips[self.ip_idx].coords);
-            let dbg_tick = self.debug_level > 1 && dbg || self.debug_level > 0;
-            if dbg_tick {
-                eprintln!("\nTick {}:", self.tick);
-                eprintln!("IPs (! indicates active IP): ");
-                for (i, ip) in self.ips.iter().enumerate() {
-                    eprintln!("{} {}: {}, {}", if self.ip_idx == i { '!' } else { ' ' }, i, ip.coords, ip.dir);
+    pub fn run(&mut self) -> Result<(), Error> {
+        while !self.step_or_flush()? {}
+        Ok(())
+    }
+
+    /// Runs the interpreter until it terminates or `stop` is set.
+    ///
+    /// Returns `Ok(true)` if it hit a terminate instruction, `Ok(false)` if it was stopped
+    /// and `Err` if a runtime error occurred.
+    pub fn run_until(&mut self, stop: &AtomicBool) -> Result<bool, Error> {
+        while !stop.load(Ordering::Relaxed) {
+            if self.step_or_flush()? {
+                return Ok(true);
+            }
+        }
+        self.rt.flush()?;
+        Ok(false)
+    }
+
+    /// Executes a single tick, flushing the output written so far if it fails,
+    /// since callers often exit right after reporting the error.
+    fn step_or_flush(&mut self) -> Result<bool, Error> {
+        // The runtime error is more useful than any error flushing
+        self.step().inspect_err(|_| {
+            self.rt.flush().ok();
+        })
+    }
+
+    /// Executes a single tick.
+    ///
+    /// Returns `Ok(true)` if the current IP hit a terminate instruction, `Ok(false)` if the program
+    /// can keep running and `Err` if a runtime error occurred.
+    pub fn step(&mut self) -> Result<bool, Error> {
+        if self.max_ticks.is_some_and(|max| self.cost.at_least(max)) {
+            return Err(Error::TickLimitExceeded);
+        }
+        if self.abort.as_ref().is_some_and(AbortHandle::is_aborted) {
+            return Err(Error::Aborted);
+        }
+        if let Some((deadline, every)) = &self.deadline {
+            if self.tick.is_divisible(*every) && Instant::now() >= *deadline {
+                return Err(Error::DeadlineExceeded(self.tick.to_integer()));
+            }
+        }
+        if let Some(flag) = self.dump_flag {
+            if self.tick.is_divisible(DUMP_CHECK_INTERVAL) && flag.load(Ordering::Relaxed) {
+                flag.store(false, Ordering::Relaxed);
+                eprint!("{}", self.dump());
+            }
+        }
+        let coords = self.ips[self.ip_idx].coords;
+        let (op, dbg) = self.grid.get(coords);
+        self.ip_stats[self.ip_idx].visited.insert(self.grid.axial_to_index(coords));
+        if let Some(hash) = &mut self.op_hash {
+            hash.write(&(char::from(op) as u32).to_le_bytes());
+            hash.write(&[self.ip_idx as u8]);
+            hash.write(&(coords.0 as i64).to_le_bytes());
+            hash.write(&(coords.1 as i64).to_le_bytes());
+        }
+        let (start, end) = self.tick_range;
+        let in_range = self.tick.at_least(start) && end.is_none_or(|end| !self.tick.at_least(end));
+        let shows_ticks = in_range && (self.debug_level > 0 || self.explain);
+        if self.followed_away && self.follow_ip == Some(self.ip_idx) {
+            if shows_ticks {
+                eprintln!("-- IP {} takes control at tick {} --", self.ip_idx, self.tick);
+            }
+            self.followed_away = false;
+        }
+        let in_range = in_range && self.follow_ip.is_none_or(|idx| idx == self.ip_idx);
+        let dbg_tick = in_range && (self.debug_level > 1 || self.debug_level > 0 && dbg);
+        let before = if self.explain && in_range {
+            Some(Snapshot {
+                left: self.rt.mem.get_left().clone(),
+                current: self.rt.mem.get().clone(),
+                right: self.rt.mem.get_right().clone(),
+                dir: self.ips[self.ip_idx].dir,
+            })
+        } else {
+            None
+        };
+        if dbg_tick {
+            eprintln!("\nTick {}:", self.tick);
+            eprintln!("IPs (! indicates active IP): ");
+            for (i, (ip, stats)) in self.ips.iter().zip(&self.ip_stats).enumerate() {
+                eprintln!(
+                    "{} {}: {}, {} ({} ticks, {} cells visited)",
+                    if self.ip_idx == i { '!' } else { ' ' }, i, ip.coords, ip.dir, stats.ticks, stats.visited.len(),
+                );
+            }
+            eprintln!("Command: {}", op);
+        }
+        for observer in &mut self.observers {
+            observer.on_tick(self.tick.get(), self.ip_idx, self.ips[self.ip_idx], op)?;
+        }
+        let watched = match self.watch_memory {
+            true => Some((self.rt.mem.position(), self.rt.mem.get().clone())),
+            false => None,
+        };
+        let cost = self.cost_model.cost(op, &self.rt.mem);
+        let mut next_idx = self.ip_idx;
+        let dir = self.ips[self.ip_idx].dir;
+        match op {
+            Op::Terminate => {
+                if let Some(before) = before {
+                    let desc = explain(op, &before, self.rt.mem.get(), before.dir, next_idx);
+                    eprintln!("Tick {}: IP {} at {}: {}", self.tick, self.ip_idx, coords, desc);
                 }
-                eprintln!("Command: {}", op);
-            }
-            let mut next_idx = self.ip_idx;
-            match op {
-                Op::Nop => (),
-                Op::Terminate => {
-                    if dbg_tick {
-                        eprintln!("Memory: {}", self.mem);
-                    }
-                    return Ok(())
+                if dbg_tick {
+                    eprintln!("Memory: {}", self.rt.mem);
                 }
-                Op::Letter(b) => self.mem.get_mut().assign(b),
-                Op::Digit(d) => {
-                    let val = self.mem.get_mut();
-                    *val *= 10;
-                    *val += d;
+                if let Some(w) = &mut self.trace {
+                    let dir = self.ips[self.ip_idx].dir;
+                    trace::write_tick(w, self.ip_idx, coords, dir, op, self.rt.mem.get().to_u64_wrapping())?;
+                    trace::write_end(w)?;
                 }
-                Op::Increment => *self.mem.get_mut() += 1,
-                Op::Decrement => *self.mem.get_mut() -= 1,
-                Op::Add => self.mem.set((self.mem.get_left() + self.mem.get_right()).into()),
-                Op::Subtract => self.mem.set((self.mem.get_left() - self.mem.get_right()).into()),
-                Op::Multiply => self.mem.set((self.mem.get_left() * self.mem.get_right()).into()),
-                Op::Divide => {
-                    if *self.mem.get_right() == 0 { return Err(Error::ZeroDivisionError) }
-                    self.mem.set((self.mem.get_left() / self.mem.get_right()).into());
-                },
-                Op::Modulo => self.mem.set({
-                    let (left, right) = (self.mem.get_left(), self.mem.get_right());
-                    if *right == 0 { return Err(Error::ZeroDivisionError) }
-                    let (_, rem) = left.div_rem_ref(right).into();
-                    if rem != 0 && (*left < 0) != (*right < 0) { rem + right } else { rem }
-                }),
-                Op::Negate => *self.mem.get_mut() *= -1,
-                Op::ReadByte => self.mem.set(match self.input.next() {
-                    Some(b) => Integer::from(b?),
-                    None => Integer::from(-1),
-                }),
-                Op::ReadInt => {
-                    let val = self.mem.get_mut();
-                    val.assign(0);
-                    let mut sign = 1;
-                    while let Some(b) = self.input.next() {
-                        match b? {
-                            b'+' => break,
-                            b'-' => {
-                                sign = -1;
-                                break;
-                            }
-                            d @ b'0'..=b'9' => {
-                                *val *= 10;
-                                *val += d - b'0';
-                                break;
-                            }
-                            _ => (),
-                        }
-                    }
-                    while let Some(Ok(d @ b'0'..=b'9')) = self.input.peek() {
-                        *val *= 10;
-                        *val += d - b'0';
-                        self.input.next();
+                self.rt.flush()?;
+                return Ok(true);
+            }
+            Op::Jump => self.advance_ip(),
+            Op::Redir(redir) => {
+                let ip = &mut self.ips[self.ip_idx];
+                let new_dir = redirect(ip.dir, redir, self.rt.positive());
+                if new_dir != ip.dir {
+                    ip.dir = new_dir;
+                    self.ip_room[self.ip_idx] = 0;
+                }
+            }
+            Op::IPPrev => next_idx = (self.ip_idx + self.ips.len() - 1) % self.ips.len(), // Add the count to avoid underflow
+            Op::IPNext => next_idx = (self.ip_idx + 1) % self.ips.len(),
+            Op::IPSelect => next_idx = self.rt.mem.get().mod_u(self.ips.len() as u32) as usize,
+            Op::Tick => {
+                self.tick.assign_to(self.rt.mem.get_mut());
+                self.rt.wrap_current();
+                self.rt.check_memory()?;
+            }
+            _ => match &self.handlers {
+                Some(rows) => {
+                    let (row, col) = self.grid.axial_to_index(coords);
+                    self.rt.execute_with(op, rows[row][col])
+                }
+                None => self.rt.execute(op),
+            }
+            .map_err(|e| e.located(Location { tick: self.tick.to_integer(), ip: self.ip_idx, coords }))?,
+        }
+        if let Some((position, old)) = watched {
+            // Ops that move the MP don't write memory, so only check for changes to the same cell
+            if position == self.rt.mem.position() && old != *self.rt.mem.get() {
+                for observer in &mut self.observers {
+                    if observer.watches_memory() {
+                        observer.on_memory_write(self.tick.get(), &position, &old, self.rt.mem.get())?;
                     }
-                    *val *= sign;
                 }
-                Op::WriteByte => io::stdout().write_all(&[self.mem.get().mod_u(256) as u8])?,
-                Op::WriteInt => print!("{}", self.mem.get()),
-                Op::Jump => self.advance_ip(),
-                Op::Redir(redir) => {
-                    let ip = &mut self.ips[self.ip_idx];
-                    ip.dir = redirect(ip.dir, redir, *self.mem.get() > 0);
+            }
+        }
+        if let Op::WriteByte | Op::WriteInt = op {
+            for observer in &mut self.observers {
+                observer.on_output(self.tick.get(), self.rt.last_output())?;
+            }
+        }
+        if let (Op::ReadByte | Op::ReadInt, Some(log)) = (op, &mut self.input_log) {
+            let (bytes, eof) = self.rt.last_input();
+            record::log_input(log, self.tick.get(), bytes, eof)?;
+        }
+        if let (Op::WriteByte | Op::WriteInt, Some(log)) = (op, &mut self.output_log) {
+            record::log_output(log, self.tick.get(), self.ip_idx, coords, op, self.rt.last_output())?;
+        }
+        if let Some(w) = &mut self.trace {
+            trace::write_tick(w, self.ip_idx, coords, dir, op, self.rt.mem.get().to_u64_wrapping())?;
+            if let Op::ReadByte | Op::ReadInt = op {
+                let (bytes, eof) = self.rt.last_input();
+                trace::write_input(w, bytes, eof)?;
+            }
+        }
+        if let Some(before) = before {
+            let desc = explain(op, &before, self.rt.mem.get(), self.ips[self.ip_idx].dir, next_idx);
+            eprintln!("Tick {}: IP {} at {}: {}", self.tick, self.ip_idx, coords, desc);
+        }
+        if dbg_tick {
+            eprintln!("New direction: {}", self.ips[self.ip_idx].dir);
+            eprintln!("Memory:\n{}", self.rt.mem);
+        }
+        self.advance_ip();
+        self.ip_stats[self.ip_idx].ticks += 1;
+        if self.follow_ip == Some(self.ip_idx) && next_idx != self.ip_idx {
+            if shows_ticks {
+                eprintln!("-- IP {} hands control to IP {} at tick {} --", self.ip_idx, next_idx, self.tick);
+            }
+            self.followed_away = true;
+        }
+        self.ip_idx = next_idx;
+        self.tick.add(1);
+        self.cost.add(cost);
+        if self.checkpoints.as_ref().is_some_and(|c| self.tick.is_divisible(c.every)) {
+            let state = self.save_state()?;
+            self.checkpoints.as_mut().unwrap().save(&state)?;
+        }
+        if let Some(delay) = self.delay {
+            // Show output as it's written instead of when a line ends
+            self.rt.flush()?;
+            thread::sleep(delay);
+        }
+        Ok(false)
+    }
+
+    /// Returns a snapshot of the execution statistics collected so far.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            ticks: self.tick.to_integer(),
+            cost: self.cost.to_integer(),
+            ips: self.ips.iter().zip(&self.ip_stats).map(|(ip, stats)| (stats.to_stats(&self.grid), ip.coords, ip.dir)).collect(),
+        }
+    }
+
+    /// Returns a description of the current tick, every IP and the memory, for reporting where
+    /// a program is without stopping it.
+    pub fn dump(&self) -> String {
+        let mut dump = format!("Tick {}\nIPs (! indicates active IP):\n", self.tick);
+        for (i, ip) in self.ips.iter().enumerate() {
+            let (op, _) = self.grid.get(ip.coords);
+            let active = if i == self.ip_idx { '!' } else { ' ' };
+            dump += &format!("{} {}: {}, {} (next command `{}`)\n", active, i, ip.coords, ip.dir, op);
+        }
+        dump + &format!("Memory: {}\n", self.rt.mem.summary())
+    }
+
+    /// Returns a report of where a runtime error returned by `step` or `run` happened: the tick,
+    /// the active IP and the op it was executing, the cells around it and the memory around the MP.
+    pub fn error_report(&self, error: &Error) -> String {
+        let ip = self.ips[self.ip_idx];
+        let (op, _) = self.grid.get(ip.coords);
+        // Errors that carry their location already name the tick, IP and cell
+        let mut report = match error.location() {
+            Some(_) => format!("Error: {}\n  moving {}, executing `{}`\n\n", error, ip.dir, op),
+            None => format!(
+                "Error: {}\n  at tick {}, IP {} at {} moving {}, executing `{}`\n\n",
+                error, self.tick, self.ip_idx, ip.coords, ip.dir, op,
+            ),
+        };
+        // Lay out the cells within two steps, two columns apart with rows offset by one cell
+        for dr in -2..=2 {
+            let mut line = vec![' '; 19];
+            for dq in -2..=2 {
+                let coords = ip.coords + PointAxial(dq, dr);
+                if coords.distance(ip.coords) > 2 || !self.grid.contains(coords) {
+                    continue;
+                }
+                let col = (9 + 2 * (2 * dq + dr)) as usize;
+                line[col] = char::from(self.grid.get(coords).0);
+                if dq == 0 && dr == 0 {
+                    line[col - 1] = '[';
+                    line[col + 1] = ']';
                 }
-                Op::IPPrev => next_idx = (self.ip_idx + 5) % 6, // +5 (= -1 mod 6) to avoid underflow
-                Op::IPNext => next_idx = (self.ip_idx + 1) % 6,
-                Op::IPSelect => next_idx = self.mem.get().mod_u(6) as usize,
-                Op::MPLeft => self.mem.move_left(),
-                Op::MPRight => self.mem.move_right(),
-                Op::MPBackLeft => { self.mem.reverse(); self.mem.move_right(); self.mem.reverse(); }
-                Op::MPBackRight => { self.mem.reverse(); self.mem.move_left(); self.mem.reverse(); }
-                Op::MPReverse => self.mem.reverse(),
-                Op::MPBranch => if *self.mem.get() > 0 { self.mem.move_right() } else { self.mem.move_left() }
-                Op::MemCopy => self.mem.set(if *self.mem.get() > 0 { self.mem.get_right().clone() } else { self.mem.get_left().clone() }),
             }
-            if dbg_tick {
-                eprintln!("New direction: {}", self.ips[self.ip_idx].dir);
-                eprintln!("Memory:\n{}", self.mem);
+            let line: String = line.into_iter().collect();
+            let line = line.trim_end();
+            if !line.is_empty() {
+                report += &format!("  {}\n", line);
             }
-            self.advance_ip();
-            self.ip_idx = next_idx;
-            self.tick += 1
         }
+        report + &format!("\n  Memory: {}\n", self.rt.mem.summary())
+    }
+
+    /// Returns a hash of every op executed so far, the output written so far and the current
+    /// state of the program, or `None` if fingerprinting wasn't enabled with `Builder::fingerprint`.
+    ///
+    /// The hash is the same on every platform, so two runs with the same fingerprint behaved
+    /// identically, even across interpreter versions.
+    ///
+    /// Fails if the state can't be saved, as with `save_state`.
+    pub fn fingerprint(&self) -> Result<Option<u64>, Error> {
+        let (ops, output) = match (self.op_hash, self.rt.output_hash()) {
+            (Some(ops), Some(output)) => (ops, output),
+            _ => return Ok(None),
+        };
+        let mut hash = Fnv::new();
+        hash.write(&ops.finish().to_le_bytes());
+        hash.write(&output.to_le_bytes());
+        hash.write(self.save_state()?.to_string().as_bytes());
+        Ok(Some(hash.finish()))
+    }
+
+    /// Returns a snapshot of the program that can be resumed with `Builder::resume`.
+    ///
+    /// Fails if memory that was spilled to disk with `Builder::spill_memory` can't be read back.
+    pub fn save_state(&self) -> Result<state::SaveState, Error> {
+        Ok(state::SaveState {
+            grid: self.grid.clone(),
+            mem: self.rt.mem.snapshot()?,
+            ips: self.ips.clone(),
+            ip_idx: self.ip_idx,
+            tick: self.tick.to_integer(),
+            input_position: self.rt.input_position(),
+            dialect: self.dialect,
+            wrapping: self.wrapping,
+            value_type: self.rt.value_type(),
+        })
+    }
+
+    /// Returns the grid of the program.
+    pub fn grid(&self) -> &Grid {
+        &self.grid
+    }
+
+    /// Returns the IPs in index order.
+    pub fn ips(&self) -> &[IP] {
+        &self.ips
+    }
+
+    /// Returns the index of the currently active IP.
+    pub fn current_ip(&self) -> usize {
+        self.ip_idx
+    }
+
+    /// Moves the IP with the given index to the given grid coordinates and points it in direction `dir`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `idx` is not a valid IP index or `coords` lies outside the grid.
+    pub fn set_ip(&mut self, idx: usize, coords: PointAxial, dir: Direction) {
+        assert!(self.grid.contains(coords), "IP coordinates {} lie outside the grid", coords);
+        self.ips[idx] = IP { coords, dir };
+        self.ip_room[idx] = 0;
     }
 
     /// Moves the current IP to the next grid space in its current direction.
+    ///
+    /// Moves that can't reach the edge skip the wrapping rules, and the number of them left is
+    /// recomputed whenever the IP wraps or changes direction.
     fn advance_ip(&mut self) {
-        if self.grid.size() == 1 {
-            return;
-        }
         let ip = &mut self.ips[self.ip_idx];
-        // Use post-move cube coords to check for wrapping
-        ip.coords += ip.dir.to_vector();
-        let PointAxial(x, z) = ip.coords;
-        let y = -x - z;
-        let size = self.grid.size();
-        let (x_big, y_big, z_big) = (x.abs() as usize >= size, y.abs() as usize >= size, z.abs() as usize >= size);
-        // Return early if (x, y, z) are in-bounds
-        if !(x_big || y_big || z_big) {
+        let room = &mut self.ip_room[self.ip_idx];
+        if *room > 0 {
+            ip.coords += ip.dir.to_vector();
+            *room -= 1;
             return;
         }
-        // Use pre-move axial coords to compute wrapped coords
-        ip.coords -= ip.dir.to_vector();
-        let PointAxial(q, r) = ip.coords;
-        ip.coords = match (x_big, y_big, z_big, *self.mem.get() > 0) {
-            // Impossible to be all in range or out of range here
-            (false, false, false, _) | (true, true, true, _) => unreachable!(),
-            // If two values are in range, wrap around an edge
-            (false, false, true, _) => PointAxial(q + r, -r),
-            (false, true, false, _) => PointAxial(-r, -q),
-            (true, false, false, _) => PointAxial(-q, q + r),
-            // If one value is in range, branch out of a corner
-            // There are two paths that lead to each corner
-            (false, true, true, false) | (true, false, true, true) => PointAxial(q + r, -r),
-            (true, false, true, false) | (true, true, false, true) => PointAxial(-q, q + r),
-            (true, true, false, false) | (false, true, true, true) => PointAxial(-r, -q),
+        let positive = self.rt.positive();
+        ip.coords = self.wrapping.wrap(self.grid.size(), ip.coords, ip.dir, positive);
+        *room = ip.coords.steps_to_edge(ip.dir, self.grid.size());
+    }
+}
+
+/// Returns the IPs of a program run with the default six, in their starting corners for a grid of the given size.
+/// Other numbers of IPs are placed by `perimeter_ips`.
+///
+/// IP 0 starts in the top-left corner moving east, and each following IP starts
+/// one corner further clockwise, moving along the next edge clockwise.
+pub fn initial_ips(size: usize) -> [IP; 6] {
+    let size = size as isize;
+    [
+        IP { coords: PointAxial(0, -size + 1), dir: Direction::East },
+        IP { coords: PointAxial(size - 1, -size + 1), dir: Direction::SouthEast },
+        IP { coords: PointAxial(size - 1, 0), dir: Direction::SouthWest },
+        IP { coords: PointAxial(0, size - 1), dir: Direction::West },
+        IP { coords: PointAxial(-size + 1, size - 1), dir: Direction::NorthWest },
+        IP { coords: PointAxial(-size + 1, 0), dir: Direction::NorthEast },
+    ]
+}
+
+/// Returns the coordinates of the grid space one step from `coords` in direction `dir`.
+///
+/// Leaving the grid through an edge wraps around to the opposite edge, and leaving through a corner
+/// branches based on whether the current memory edge is `positive`.
+pub fn wrap(size: usize, coords: PointAxial, dir: Direction, positive: bool) -> PointAxial {
+    if size == 1 {
+        return coords;
+    }
+    // Use post-move cube coords to check for wrapping
+    let moved = coords + dir.to_vector();
+    if moved.in_bounds(size) {
+        return moved;
+    }
+    let PointCube(x, y, z) = moved.into();
+    let (x_big, y_big, z_big) = (x.unsigned_abs() >= size, y.unsigned_abs() >= size, z.unsigned_abs() >= size);
+    // Use pre-move axial coords to compute wrapped coords
+    let PointAxial(q, r) = coords;
+    match (x_big, y_big, z_big, positive) {
+        // Impossible to be all in range or out of range here
+        (false, false, false, _) | (true, true, true, _) => unreachable!(),
+        // If two values are in range, wrap around an edge
+        (false, false, true, _) => PointAxial(q + r, -r),
+        (false, true, false, _) => PointAxial(-r, -q),
+        (true, false, false, _) => PointAxial(-q, q + r),
+        // If one value is in range, branch out of a corner
+        // There are two paths that lead to each corner
+        (false, true, true, false) | (true, false, true, true) => PointAxial(q + r, -r),
+        (true, false, true, false) | (true, true, false, true) => PointAxial(-q, q + r),
+        (true, true, false, false) | (false, true, true, true) => PointAxial(-r, -q),
+    }
+}
+
+/// Returns `count` IPs spread evenly around the edge of a grid of the given size, each moving
+/// clockwise along its edge.
+///
+/// IP 0 starts in the top-left corner moving east, so six IPs are placed like `initial_ips`.
+/// IPs share cells when there are more of them than cells around the edge.
+pub fn perimeter_ips(size: usize, count: usize) -> Vec<IP> {
+    let corners = initial_ips(size);
+    let side = size - 1;
+    (0..count).map(|i| {
+        if side == 0 {
+            return corners[i % 6];
+        }
+        let pos = i * 6 * side / count;
+        let corner = corners[pos / side];
+        let coords = corner.coords + corner.dir.to_vector() * (pos % side) as isize;
+        IP { coords, dir: corner.dir }
+    }).collect()
+}
+
+/// A rule for where an IP goes when it leaves the grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wrapping {
+    /// Wrap around to the opposite edge, branching at corners based on the current memory edge, like `wrap`
+    Standard,
+    /// Wrap like `Standard`, but leave every corner as if the current memory edge were positive
+    AlwaysPositive,
+    /// Wrap around to the other end of the line the IP is moving along, so that it never changes lines
+    Torus,
+}
+
+impl Wrapping {
+    /// Returns the name of the rule, as used in save states and on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Wrapping::Standard => "standard",
+            Wrapping::AlwaysPositive => "positive",
+            Wrapping::Torus => "torus",
+        }
+    }
+
+    /// Returns the rule with the given name.
+    pub fn from_name(name: &str) -> Option<Wrapping> {
+        match name {
+            "standard" => Some(Wrapping::Standard),
+            "positive" => Some(Wrapping::AlwaysPositive),
+            "torus" => Some(Wrapping::Torus),
+            _ => None,
         }
     }
+
+    /// Returns the coordinates of the grid space one step from `coords` in direction `dir` under this rule.
+    pub fn wrap(self, size: usize, coords: PointAxial, dir: Direction, positive: bool) -> PointAxial {
+        match self {
+            Wrapping::Standard => wrap(size, coords, dir, positive),
+            Wrapping::AlwaysPositive => wrap(size, coords, dir, true),
+            Wrapping::Torus => {
+                let moved = coords + dir.to_vector();
+                if moved.in_bounds(size) {
+                    return moved;
+                }
+                // Walk back to the first cell of the line
+                let mut start = coords;
+                while (start - dir.to_vector()).in_bounds(size) {
+                    start -= dir.to_vector();
+                }
+                start
+            }
+        }
+    }
+}
+
+/// How the interpreter finds the code that executes each command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dispatch {
+    /// Match on the command every tick
+    Match,
+    /// Look up a function resolved for each cell when the interpreter is built
+    Table,
+}
+
+/// Returns the handler of every cell of a grid, laid out like the grid's rows.
+fn handler_table(grid: &Grid) -> Vec<Vec<runtime::Handler>> {
+    let mut rows = vec![Vec::new(); 2 * grid.size() - 1];
+    for (coords, op, _) in grid.cells() {
+        rows[grid.axial_to_index(coords).0].push(runtime::handler(op));
+    }
+    rows
+}
+
+/// A rule for how many ticks of the `Builder::max_ticks` budget each instruction costs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CostModel {
+    /// Every instruction costs one tick
+    Uniform,
+    /// Instructions that compute with or print memory edges cost an extra tick for every 64 bits
+    /// of their operands, so enormous numbers can't dodge the budget
+    BitLength,
+}
+
+impl CostModel {
+    /// Returns the number of ticks `op` costs when executed with the given memory, before it runs.
+    pub fn cost(self, op: Op, mem: &dyn memory::Memory) -> u64 {
+        if self == CostModel::Uniform {
+            return 1;
+        }
+        let bits = match op {
+            Op::Add | Op::Subtract | Op::Multiply | Op::Divide | Op::Modulo => {
+                mem.get_left().significant_bits() + mem.get_right().significant_bits()
+            }
+            Op::Digit(_) | Op::Increment | Op::Decrement | Op::Negate | Op::WriteInt => mem.get().significant_bits(),
+            Op::MemCopy => mem.get_left().significant_bits().max(mem.get_right().significant_bits()),
+            _ => 0,
+        };
+        1 + u64::from(bits / 64)
+    }
+}
+
+/// Where a runtime error happened: the tick, the active IP and the cell it was executing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub tick: Integer,
+    pub ip: usize,
+    pub coords: PointAxial,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at tick {}, IP {}, cell {}", self.tick, self.ip, self.coords)
+    }
 }
 
 /// Error type returned by functions in this crate.
 #[derive(Debug)]
 pub enum Error {
     SyntaxError(char),
+    ExtensionOp(char),
+    ProgramTooLarge { size: usize, limit: usize },
     IOError(io::Error),
-    ZeroDivisionError,
+    ZeroDivisionError(Option<Location>),
+    TickLimitExceeded,
+    DeadlineExceeded(Integer),
+    OutputLimitExceeded,
+    OutputClosed,
+    Aborted,
+    MemoryLimitExceeded(usize),
+    TestCaseError(usize, String),
+    ParseError(&'static str, String),
+    ExpectFailed(String),
+    TraceMismatch(String),
+    IncompatibleSettings(String),
+    ScriptError(String),
+    ScriptStopped(String),
+    CellOutsideGrid(PointAxial),
+    CellCollision(PointAxial, Op, Op),
+    Unmirrorable(PointAxial, String),
+}
+
+impl Error {
+    /// Returns where a runtime error happened, if it was raised while a program was running.
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            Error::ZeroDivisionError(location) => location.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Attaches a location to a runtime error that doesn't have one yet.
+    fn located(self, location: Location) -> Self {
+        match self {
+            Error::ZeroDivisionError(None) => Error::ZeroDivisionError(Some(location)),
+            e => e,
+        }
+    }
 }
 
 impl From<io::Error> for Error {
@@ -232,8 +815,286 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::SyntaxError(c) => write!(f, "Unrecognized character in source code: {}", c),
+            Error::ExtensionOp(c) => write!(f, "`{}` is an extension command, which requires the extensions dialect", c),
+            Error::ProgramTooLarge { size, limit } => {
+                write!(f, "Program needs a grid of side length {}, which is over the limit of {}", size, limit)
+            }
             Error::IOError(e) => write!(f, "{}", e),
-            Error::ZeroDivisionError => write!(f, "Division by zero"),
+            Error::ZeroDivisionError(None) => write!(f, "Division by zero"),
+            Error::ZeroDivisionError(Some(location)) => write!(f, "Division by zero {}", location),
+            Error::TickLimitExceeded => write!(f, "Tick limit exceeded"),
+            Error::DeadlineExceeded(tick) => write!(f, "Deadline exceeded at tick {}", tick),
+            Error::OutputLimitExceeded => write!(f, "Output limit exceeded"),
+            Error::Aborted => write!(f, "Aborted"),
+            Error::OutputClosed => write!(f, "Output was closed before the program ended"),
+            Error::MemoryLimitExceeded(limit) => write!(f, "Memory limit of {} bytes exceeded", limit),
+            Error::TestCaseError(line, msg) => write!(f, "Invalid test case on line {}: {}", line, msg),
+            Error::ParseError(kind, s) => write!(f, "Invalid {}: {}", kind, s),
+            Error::ExpectFailed(msg) => write!(f, "Expectation failed: {}", msg),
+            Error::TraceMismatch(msg) => write!(f, "Trace mismatch: {}", msg),
+            Error::IncompatibleSettings(msg) => write!(f, "Incompatible settings: {}", msg),
+            Error::ScriptError(msg) => write!(f, "Script error: {}", msg),
+            Error::ScriptStopped(msg) => write!(f, "Stopped by script: {}", msg),
+            Error::CellOutsideGrid(coords) => write!(f, "Cell {} lies outside the grid", coords),
+            Error::CellCollision(coords, old, new) => {
+                write!(f, "Cell {} already holds `{}`, so `{}` can't be placed there", coords, old, new)
+            }
+            Error::Unmirrorable(coords, reason) => write!(f, "Can't mirror the program at cell {}: {}", coords, reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_moves_within_grid() {
+        assert_eq!(wrap(3, PointAxial(0, 0), Direction::East, false), PointAxial(1, 0));
+        assert_eq!(wrap(3, PointAxial(0, 0), Direction::NorthWest, true), PointAxial(0, -1));
+    }
+
+    #[test]
+    fn wrap_single_cell_grid() {
+        for dir in Direction::all() {
+            assert_eq!(wrap(1, PointAxial(0, 0), dir, false), PointAxial(0, 0));
+        }
+    }
+
+    #[test]
+    fn wrap_around_edges() {
+        // Off the end of the first row onto the start of the middle row
+        assert_eq!(wrap(3, PointAxial(2, -2), Direction::East, false), PointAxial(-2, 0));
+        // Off the bottom edge onto the top edge
+        assert_eq!(wrap(3, PointAxial(-1, 2), Direction::SouthEast, false), PointAxial(1, -2));
+        // Edges ignore the sign of the memory edge
+        assert_eq!(wrap(3, PointAxial(-1, 2), Direction::SouthEast, true), PointAxial(1, -2));
+    }
+
+    #[test]
+    fn wrap_branches_at_corners() {
+        assert_eq!(wrap(3, PointAxial(2, 0), Direction::East, false), PointAxial(0, -2));
+        assert_eq!(wrap(3, PointAxial(2, 0), Direction::East, true), PointAxial(-2, 2));
+    }
+
+    #[test]
+    fn wrap_stays_in_bounds() {
+        for size in 1..7 {
+            for coords in coords::rows(size) {
+                for dir in Direction::all() {
+                    for &positive in &[false, true] {
+                        assert!(wrap(size, coords, dir, positive).in_bounds(size));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn custom_ip_starts_and_followed_ips_are_checked() {
+        let centre = |size: usize| vec![initial_ips(size)[0], IP { coords: PointAxial(0, 0), dir: Direction::East }];
+        let run = |builder: Builder| {
+            let mut output = Vec::new();
+            builder.output(&mut output).build()?.run()?;
+            Ok::<_, Error>(output)
+        };
+        // `]` hands over to IP 1, which prints from the centre instead of leaving its corner
+        assert_eq!(run(Builder::new("]..!@")).unwrap(), b"");
+        assert_eq!(run(Builder::new("]..!@").ip_starts(centre)).unwrap(), b"0");
+        assert_eq!(Builder::new("]..!@").ip_starts(centre).compile().unwrap()(b"").unwrap(), b"0");
+        let incompatible = |builder: Builder| matches!(run(builder), Err(Error::IncompatibleSettings(_)));
+        assert!(incompatible(Builder::new("@").ip_starts(|_| Vec::new())));
+        assert!(incompatible(Builder::new("@").ip_starts(|size| vec![IP { coords: PointAxial(size as isize, 0), dir: Direction::East }])));
+        assert!(incompatible(Builder::new("@").ip_starts(centre).record_trace(io::sink())));
+        assert!(incompatible(Builder::new("@").follow_ip(6)));
+        assert!(incompatible(Builder::new("@").ip_starts(centre).follow_ip(2)));
+        assert!(run(Builder::new("@").ip_count(8).follow_ip(7)).is_ok());
+    }
+
+    #[test]
+    fn torus_wrapping_stays_on_line() {
+        // Off the end of the first row back onto its start
+        assert_eq!(Wrapping::Torus.wrap(3, PointAxial(2, -2), Direction::East, false), PointAxial(0, -2));
+        // Off the bottom of a column back onto its top
+        assert_eq!(Wrapping::Torus.wrap(3, PointAxial(-1, 2), Direction::SouthEast, true), PointAxial(-1, -1));
+    }
+
+    #[test]
+    fn abort_handle_stops_at_next_tick() {
+        let handle = AbortHandle::new();
+        let mut hex = Builder::new(".").output(Vec::new()).abort_handle(handle.clone()).build().unwrap();
+        assert!(!hex.step().unwrap());
+        handle.abort();
+        assert!(matches!(hex.step(), Err(Error::Aborted)));
+        assert_eq!(*hex.save_state().unwrap().tick(), 1);
+    }
+
+    #[test]
+    fn division_by_zero_reports_its_location() {
+        let mut hex = Builder::new("1:@").output(Vec::new()).build().unwrap();
+        let error = hex.run().unwrap_err();
+        let location = Location { tick: Integer::from(1), ip: 0, coords: PointAxial(1, -1) };
+        assert_eq!(error.location(), Some(&location));
+        assert_eq!(error.to_string(), "Division by zero at tick 1, IP 0, cell (1, -1)");
+        let result = job::run_job(r#"{"source": "1:@"}"#).unwrap();
+        assert!(result.contains(r#""tick": 1, "ip": 0, "q": 1, "r": -1}"#));
+        // Extension commands outside of ASCII are laid out whole
+        let mut hex = Builder::new("ρ:").dialect(Dialect::Extensions).output(Vec::new()).build().unwrap();
+        let error = hex.run().unwrap_err();
+        assert!(hex.error_report(&error).contains("ρ  [:]\n"), "{}", hex.error_report(&error));
+    }
+
+    #[test]
+    fn states_and_traces_keep_extension_settings() {
+        let builder = |output| {
+            Builder::new("ρ!τ....!)!@")
+                .dialect(Dialect::Extensions)
+                .wrapping(Wrapping::AlwaysPositive)
+                .value_type(ValueType::Byte)
+                .output(output)
+        };
+        let mut trace = Vec::new();
+        builder(Vec::new()).record_trace(&mut trace).build().unwrap().run().unwrap();
+        assert_eq!(trace::replay(&trace).unwrap(), 7);
+        let mut hex = builder(Vec::new()).max_ticks(3).build().unwrap();
+        assert!(matches!(hex.run(), Err(Error::TickLimitExceeded)));
+        let state = hex.save_state().unwrap().to_string();
+        assert!(state.contains("dialect extensions\nwrapping positive\nvalues byte\n"));
+        let mut output = Vec::new();
+        Builder::resume(state.parse().unwrap()).output(&mut output).build().unwrap().run().unwrap();
+        assert_eq!(output, b"23");
+    }
+
+    #[test]
+    fn size_caps_apply_to_every_builder() {
+        let too_large = |result: Result<_, Error>| matches!(result, Err(Error::ProgramTooLarge { size: 3, limit: 2 }));
+        let src = "1234567890";
+        assert_eq!(src.parse::<Grid>().unwrap().size(), 3);
+        assert!(too_large(Grid::parse_with_max_size(src, 2).map(|_| ())));
+        assert!(too_large(Builder::new(src).max_size(2).build().map(|_| ())));
+        assert!(too_large(Builder::from_grid(Grid::new(3)).max_size(2).build().map(|_| ())));
+        let state = Builder::new(src).input(&b""[..]).build().unwrap().save_state().unwrap();
+        assert!(too_large(Builder::resume(state).max_size(2).build().map(|_| ())));
+    }
+
+    #[test]
+    fn checkpoints_keep_the_latest_states() {
+        let dir = std::env::temp_dir().join(format!("hexagony-checkpoints-{}", std::process::id()));
+        let src = examples::find("primes").unwrap().source();
+        let mut expected = Vec::new();
+        let hex = Builder::new(src).input(&b"97"[..]).output(&mut expected).checkpoints(100, &dir, 2);
+        hex.build().unwrap().run().unwrap();
+        let mut ticks: Vec<u64> = std::fs::read_dir(&dir).unwrap()
+            .map(|entry| {
+                let name = entry.unwrap().file_name().into_string().unwrap();
+                name.strip_prefix("tick-").and_then(|name| name.strip_suffix(".state")).unwrap().parse().unwrap()
+            })
+            .collect();
+        ticks.sort_unstable();
+        assert_eq!(ticks.len(), 2);
+        assert_eq!(ticks[1] - ticks[0], 100);
+        let state = std::fs::read_to_string(dir.join(format!("tick-{}.state", ticks[1]))).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let mut output = Vec::new();
+        Builder::resume(state.parse().unwrap()).input(&b"97"[..]).output(&mut output).build().unwrap().run().unwrap();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn output_logs_name_the_tick_and_cell_of_each_write() {
+        let mut log = Vec::new();
+        Builder::new("1!)!@").output(io::sink()).log_output(&mut log).build().unwrap().run().unwrap();
+        let expected = "1: IP 0 at (1, -1) `!` wrote 31 \"1\"\n3: IP 0 at (0, 0) `!` wrote 32 \"2\"\n";
+        assert_eq!(String::from_utf8(log).unwrap(), expected);
+    }
+
+    #[test]
+    fn delays_sleep_after_every_tick() {
+        let start = Instant::now();
+        let mut output = Vec::new();
+        Builder::new("1!)!@").output(&mut output).delay(Duration::from_millis(25)).build().unwrap().run().unwrap();
+        // Four ticks before the one that terminates
+        assert!(start.elapsed() >= Duration::from_millis(100), "{:?}", start.elapsed());
+        assert_eq!(output, b"12");
+    }
+
+    #[test]
+    fn env_looks_up_variables_packed_into_the_edge() {
+        let run = |src: &str| {
+            let mut output = Vec::new();
+            let hex = Builder::new(src).dialect(Dialect::Extensions).env_var("H", "AB").output(&mut output);
+            hex.build().unwrap().run().unwrap();
+            output
+        };
+        // "AB" as base-256 digits
+        assert_eq!(run("Hε!@"), b"16706");
+        assert_eq!(run("Jε!@"), b"-1");
+    }
+
+    #[test]
+    fn deadlines_stop_long_runs_on_a_checked_tick() {
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let result = Builder::new(")").output(io::sink()).deadline(deadline, 1000).build().unwrap().run();
+        match result {
+            Err(Error::DeadlineExceeded(tick)) => assert!(tick > 0 && tick.is_divisible_u(1000), "{}", tick),
+            result => panic!("expected the deadline to pass, got {:?}", result),
         }
+        assert!(Instant::now() >= deadline);
+        let hex = Builder::new("1!@").output(io::sink()).deadline(Instant::now() + Duration::from_secs(60), 1);
+        hex.build().unwrap().run().unwrap();
+    }
+
+    #[test]
+    fn bit_length_costs_charge_for_large_operands() {
+        let mem = testing::memory(Integer::from(1) << 640, 5, Integer::from(1) << 100);
+        let mem = memory::AnyMemory::Hex(mem);
+        assert_eq!(CostModel::BitLength.cost(Op::Increment, &mem), 11);
+        assert_eq!(CostModel::BitLength.cost(Op::Multiply, &mem), 2);
+        assert_eq!(CostModel::BitLength.cost(Op::MPLeft, &mem), 1);
+        assert_eq!(CostModel::Uniform.cost(Op::Increment, &mem), 1);
+        let state = format!(
+            "hexagony-state 1\ntick 0\ninput 0\nactive 0\nip 0 0 -1 E\nmp 0 0 E ccw\nedge 0 0 E {}\ngrid\n)!@",
+            Integer::from(1) << 640,
+        );
+        let run = |cost_model, max_ticks| {
+            let builder = Builder::resume(state.parse().unwrap()).output(io::sink()).cost_model(cost_model).max_ticks(max_ticks);
+            let mut hex = builder.build().unwrap();
+            hex.run().map(|()| hex.stats().cost)
+        };
+        // `)` and `!` cost 11 ticks each, and `@` ends the program before it's charged
+        assert_eq!(run(CostModel::Uniform, 20).unwrap(), 2);
+        assert_eq!(run(CostModel::BitLength, 30).unwrap(), 22);
+        assert!(matches!(run(CostModel::BitLength, 20), Err(Error::TickLimitExceeded)));
+    }
+
+    #[test]
+    fn slurped_input_is_read_when_building() {
+        struct FailingReader;
+        impl io::Read for FailingReader {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("input failed"))
+            }
+        }
+        let run = |slurp| {
+            let mut output = Vec::new();
+            Builder::new("?,;!@").input(&b"12 x"[..]).output(&mut output).slurp_input(slurp).build().unwrap().run().unwrap();
+            output
+        };
+        assert_eq!(run(true), run(false));
+        let failing = || io::Read::chain(&b"5"[..], FailingReader);
+        assert!(Builder::new("?!@").input(failing()).build().is_ok());
+        assert!(matches!(Builder::new("?!@").input(failing()).slurp_input(true).build(), Err(Error::IOError(_))));
+    }
+
+    #[test]
+    fn templates_take_a_fill_a_terminate_cell_and_rulers() {
+        assert_eq!(source_template(2), "  . .\n . . .\n  . .\n");
+        let at = PointAxial(-1, 2);
+        let grid: Grid = Template::new(3).terminate_at(at).to_string().parse().unwrap();
+        assert_eq!(grid.size(), 3);
+        assert!(grid.cells().all(|(coords, op, _)| op == if coords == at { Op::Terminate } else { Op::Nop }));
+        let ruled = Template::new(2).fill('_').terminate_at(PointAxial(0, 1)).rulers(true).to_string();
+        assert_eq!(ruled, "      01234\nr=-1   _ _    q=0..1\nr=0   _ _ _   q=-1..1\nr=1    _ @    q=-1..0\n      01234\n");
+        assert_eq!(Template::new(0).rulers(true).to_string(), "");
     }
 }
@@ -1,21 +1,550 @@
 use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
 use std::path::Path;
-use clap::clap_app;
-use hexagony;
+use std::process;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use clap::{ArgMatches, clap_app};
+use hexagony::{Axis, Builder, CostModel, Dialect, Dispatch, Direction, Error, IntEof, Op, SourceFormat, Termination, ValueType, Wrapping, cases::TestCase, coords::PointAxial, memory::MemoryModel, report::Format, spec::SpecVersion, trace::Target, debugger::{Debugger, PromptInput}, generate::Pattern, quine::Whitespace};
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// A global allocator that keeps track of the current and peak number of bytes on the heap.
+#[cfg(feature = "alloc-stats")]
+struct CountingAlloc;
+
+#[cfg(feature = "alloc-stats")]
+static HEAP_USED: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+#[cfg(feature = "alloc-stats")]
+static HEAP_PEAK: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[cfg(feature = "alloc-stats")]
+unsafe impl std::alloc::GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        let ptr = std::alloc::System.alloc(layout);
+        if !ptr.is_null() {
+            use std::sync::atomic::Ordering::Relaxed;
+            let used = HEAP_USED.fetch_add(layout.size(), Relaxed) + layout.size();
+            HEAP_PEAK.fetch_max(used, Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        std::alloc::System.dealloc(ptr, layout);
+        HEAP_USED.fetch_sub(layout.size(), std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+#[cfg(feature = "alloc-stats")]
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+/// Alignment of the blocks allocated for GMP, which is also the size of the header before each block.
+#[cfg(feature = "alloc-stats")]
+const GMP_ALIGN: usize = 16;
+
+/// Allocates a block for GMP through the global allocator, so big integers are counted too.
+///
+/// The size of each block is kept in a header before it, since GMP doesn't always pass it back when
+/// freeing the block.
+#[cfg(feature = "alloc-stats")]
+extern "C" fn gmp_alloc(size: usize) -> *mut std::ffi::c_void {
+    let layout = std::alloc::Layout::from_size_align(GMP_ALIGN + size, GMP_ALIGN).unwrap();
+    unsafe {
+        let base = std::alloc::alloc(layout);
+        if base.is_null() {
+            std::alloc::handle_alloc_error(layout);
+        }
+        (base as *mut usize).write(size);
+        base.add(GMP_ALIGN).cast()
+    }
+}
+
+/// Resizes a block allocated by `gmp_alloc`.
+#[cfg(feature = "alloc-stats")]
+extern "C" fn gmp_realloc(ptr: *mut std::ffi::c_void, _old_size: usize, size: usize) -> *mut std::ffi::c_void {
+    unsafe {
+        let base = (ptr as *mut u8).sub(GMP_ALIGN);
+        let layout = std::alloc::Layout::from_size_align_unchecked(GMP_ALIGN + (base as *const usize).read(), GMP_ALIGN);
+        let base = std::alloc::realloc(base, layout, GMP_ALIGN + size);
+        if base.is_null() {
+            std::alloc::handle_alloc_error(std::alloc::Layout::from_size_align_unchecked(GMP_ALIGN + size, GMP_ALIGN));
+        }
+        (base as *mut usize).write(size);
+        base.add(GMP_ALIGN).cast()
+    }
+}
+
+/// Frees a block allocated by `gmp_alloc`.
+#[cfg(feature = "alloc-stats")]
+extern "C" fn gmp_free(ptr: *mut std::ffi::c_void, _size: usize) {
+    unsafe {
+        let base = (ptr as *mut u8).sub(GMP_ALIGN);
+        let layout = std::alloc::Layout::from_size_align_unchecked(GMP_ALIGN + (base as *const usize).read(), GMP_ALIGN);
+        std::alloc::dealloc(base, layout);
+    }
+}
+
+/// Returns the most bytes that were on the heap at once, if the allocator keeps count.
+fn peak_heap() -> Option<usize> {
+    #[cfg(feature = "alloc-stats")]
+    return Some(HEAP_PEAK.load(std::sync::atomic::Ordering::Relaxed));
+    #[cfg(not(feature = "alloc-stats"))]
+    None
+}
+
+/// Like `print!`, but writes through a locked STDOUT and returns errors, such as the reader of a
+/// pipe going away, instead of panicking.
+macro_rules! out {
+    ($($arg:tt)*) => { write!(io::stdout().lock(), $($arg)*) };
+}
+
+/// Like `println!`, but returns errors as `out!` does.
+macro_rules! outln {
+    ($($arg:tt)*) => { writeln!(io::stdout().lock(), $($arg)*) };
+}
+
+/// Returns whether an error means that whatever reads the output went away, like `head` does.
+fn is_broken_pipe(e: &(dyn std::error::Error + 'static)) -> bool {
+    match (e.downcast_ref::<io::Error>(), e.downcast_ref::<Error>()) {
+        (Some(e), _) | (_, Some(Error::IOError(e))) => e.kind() == io::ErrorKind::BrokenPipe,
+        (_, Some(Error::OutputClosed)) => true,
+        _ => false,
+    }
+}
+
+/// Returns the largest side length allowed by `--max-size`.
+fn max_size(matches: &ArgMatches) -> Result<usize, Box<dyn std::error::Error>> {
+    Ok(matches.value_of("max_size").map(str::parse).transpose()?.unwrap_or(hexagony::DEFAULT_MAX_SIZE))
+}
+
+/// Fails like the parser does if a grid with side length `size` would be over `--max-size`.
+fn check_size(matches: &ArgMatches, size: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let limit = max_size(matches)?;
+    match size > limit {
+        true => Err(Error::ProgramTooLarge { size, limit }.into()),
+        false => Ok(()),
+    }
+}
+
+/// Parses the program in the file named by the argument `arg`, enforcing `--max-size`.
+fn read_grid(matches: &ArgMatches, arg: &str) -> Result<hexagony::Grid, Box<dyn std::error::Error>> {
+    let src = fs::read_to_string(Path::new(matches.value_of(arg).unwrap()))?;
+    Ok(hexagony::Grid::parse_with_max_size(&src, max_size(matches)?)?)
+}
+
+/// Applies the `--max-size`, `--wrapping` and `--ips` options of a subcommand that analyzes or
+/// transforms a program.
+fn layout<'a>(matches: &ArgMatches, builder: Builder<'a>) -> Result<Builder<'a>, Box<dyn std::error::Error>> {
+    let mut builder = builder.max_size(max_size(matches)?).wrapping(match matches.value_of("wrapping") {
+        Some("positive") => Wrapping::AlwaysPositive,
+        Some("torus") => Wrapping::Torus,
+        _ => Wrapping::Standard,
+    });
+    if let Some(n) = matches.value_of("ips") {
+        let count = n.parse()?;
+        if !(1..=256).contains(&count) {
+            return Err("The number of IPs must be from 1 to 256".into());
+        }
+        builder = builder.ip_count(count);
+    }
+    Ok(builder)
+}
+
+/// Reads the source code of the program in the file named by the argument `arg`, checking that it
+/// fits within `--max-size` before anything else parses it.
+fn read_program(matches: &ArgMatches, arg: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let src = fs::read_to_string(Path::new(matches.value_of(arg).unwrap()))?;
+    hexagony::Grid::parse_with_max_size(&src, max_size(matches)?)?;
+    Ok(src)
+}
+
+fn main() {
+    if let Err(e) = run() {
+        if is_broken_pipe(&*e) {
+            // The status a shell reports for a process killed by SIGPIPE
+            process::exit(141);
+        }
+        eprintln!("Error: {:?}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    // Before any big integer is allocated, since GMP must free every block with the functions that allocated it
+    #[cfg(feature = "alloc-stats")]
+    unsafe {
+        gmp_mpfr_sys::gmp::set_memory_functions(Some(gmp_alloc), Some(gmp_realloc), Some(gmp_free));
+    }
     let matches = clap_app!(hexagony =>
         (version: "0.1.0")
+        (@setting SubcommandsNegateReqs)
         (@group mode +required =>
             (@arg grid: -g [N] "Prints an empty hex grid of side-length N")
             (@arg FILE: "Path to a source file to run")
+            (@arg load_state: --("load-state") [FILE] "Resumes a program from a file written by --save-state")
         )
-        (@arg debug: -d "Activates debug annotations in front of the source code")
+        (@arg fill: --fill [CHAR] requires[grid] "Fills the grid printed by -g with CHAR instead of `.`")
+        (@arg terminate_at: --("terminate-at") +takes_value +allow_hyphen_values number_of_values(2)
+            value_names(&["Q", "R"]) requires[grid] "Places a `@` at cell (Q, R) of the grid printed by -g")
+        (@arg rulers: --rulers requires[grid]
+            "Labels each row of the grid printed by -g with its r coordinate and range of q coordinates, \
+             and numbers the columns of text above and below")
+        (@arg debug: -d "Prints diagnostic information for ticks that execute a cell annotated with a backtick (`) in \
+            the source code. Earlier versions printed it after every tick, which now needs -D")
         (@arg diag: -D "Prints diagnostic information after every program tick")
+        (@arg stats: -s --stats "Prints execution statistics for each IP after the program ends")
+        (@arg explain: -e --explain "Prints a plain-English explanation of every executed instruction")
+        (@arg from_tick: --("from-tick") [N] "Only prints debug info and explanations starting at tick N")
+        (@arg to_tick: --("to-tick") [N] "Only prints debug info and explanations before tick N")
+        (@arg follow_ip: --("follow-ip") [N]
+            "Only prints debug info and explanations for ticks where IP N is active")
+        (@arg max_ticks: --("max-ticks") [N] "Aborts the program after N ticks")
+        (@arg isolate: --isolate
+            "Runs the program in a child process with the --isolate-* limits enforced by the OS (Unix only)")
+        (@arg isolate_cpu: --("isolate-cpu") [SECS] requires[isolate] "Kills the isolated program after SECS seconds of CPU time")
+        (@arg isolate_memory: --("isolate-memory") [BYTES] requires[isolate]
+            "Limits the address space of the isolated program to BYTES bytes")
+        (@arg max_size: --("max-size") [N] +global
+            "Rejects programs and grids with a side length over N, for running and every subcommand (default: 500)")
+        (@arg cost: --cost [MODEL] possible_values(&["uniform", "bits"])
+            "How many ticks of --max-ticks each instruction costs (default: uniform); bits charges a tick per 64 bits of operands")
+        (@arg dispatch: --dispatch [MODE] possible_values(&["match", "table"])
+            "How the interpreter finds the code for each command (default: match); both give the same results")
+        (@arg max_memory: --("max-memory") [BYTES] "Aborts the program once its memory uses about BYTES bytes")
+        (@arg warn_memory: --("warn-memory") [BYTES] "Prints a warning whenever the program's memory grows past about BYTES bytes")
+        (@arg time_limit: --("time-limit") [SECS] "Aborts the program once it has run for SECS seconds")
+        (@arg max_output: --("max-output") [BYTES] "Aborts the program once it writes more than BYTES bytes")
+        (@arg delay: --delay [MS] "Waits MS milliseconds after every tick, to watch a program run in slow motion")
+        (@arg utf8: -u --utf8 "Reads and writes Unicode code points encoded as UTF-8 instead of bytes")
+        (@arg echo_input: --("echo-input") "Echoes the input consumed by each `,` and `?` to STDERR")
+        (@arg slurp: --slurp conflicts_with[interactive]
+            "Reads all of STDIN before the program starts, unless it's a terminal, and serves `,` and `?` from memory")
+        (@arg output: -o --output [FILE] conflicts_with[tee] "Writes the program's output to FILE instead of STDOUT")
+        (@arg tee: --tee [FILE] "Writes the program's output to FILE as well as STDOUT")
+        (@arg output_fd: --("output-fd") [N] conflicts_with[output tee]
+            "Writes the program's output to the already open file descriptor N instead of STDOUT, keeping STDOUT and STDERR for the interpreter (Unix only)")
+        (@arg interactive: -i --interactive "Runs the program in an interactive debugger")
+        (@arg debug_on_error: --("debug-on-error")
+            "Opens the interactive debugger to inspect the program if it stops with a runtime error")
+        (@arg eof: --eof [VALUE] +allow_hyphen_values possible_values(&["-1", "0", "256"])
+            "Value that `,` reads at EOF (default: -1)")
+        (@arg int_eof: --("int-eof") [MODE] possible_values(&["zero", "minus-one", "unchanged"])
+            "What `?` does at EOF before finding a number (default: zero)")
+        (@arg spec_version: --("spec-version") [N]
+            "Revision of the Hexagony spec to run the program under (default: the latest, 1)")
+        (@arg dialect: --dialect [NAME] possible_values(&["standard", "extensions"])
+            "Set of commands the program may use (default: standard); see the ops subcommand for the extensions")
+        (@arg source_format: --format [FORMAT] possible_values(&["auto", "linear", "padded", "double-spaced"])
+            "Shape the source code is written in (default: linear); auto detects it from the code, padded is \
+             a hexagon as the fmt subcommand prints it, and double-spaced one with three spaces between cells \
+             and empty lines between rows")
+        (@arg wrapping: --wrapping [RULE] possible_values(&["standard", "positive", "torus"])
+            "Where IPs go when they leave the grid (default: standard); positive leaves every corner as if the \
+             current edge were positive, and torus wraps to the other end of the same line")
+        (@arg memory: --memory [MODEL] possible_values(&["hex", "tape", "stack"])
+            "Layout of memory (default: hex); tape is a line of cells, and stack keeps the current cell on top \
+             of a stack, pushing with `}` and popping with `{`")
+        (@arg spill_memory: --("spill-memory") [N]
+            "Keeps only about N memory edges in RAM, spilling the least recently used ones to a temporary file")
+        (@arg values: --values [TYPE] possible_values(&["unbounded", "byte"])
+            "Range of values memory edges can hold (default: unbounded); byte wraps arithmetic around modulo 256")
+        (@arg ips: --ips [N] "Runs the program with N IPs spread evenly around the edge of the grid (default: 6)")
+        (@arg seed: --seed [N] "Seeds the random numbers of the extension command `ρ`, to make runs reproducible")
+        (@arg env: --env [VAR] ... number_of_values(1)
+            "Sets a variable NAME=VALUE for the extension command `ε` to look up (can be repeated)")
+        (@arg allow_env: --("allow-env") "Lets the extension command `ε` look up the environment variables of the process")
+        (@arg record_input: --("record-input") [FILE] "Records every byte the program reads to FILE")
+        (@arg replay_input: --("replay-input") [FILE] conflicts_with[arg interactive]
+            "Reads the program's input from a file written by --record-input instead of STDIN")
+        (@arg log_output: --("log-output") [FILE]
+            "Logs every byte the program writes to FILE, with the tick, IP and cell that wrote it")
+        (@arg save_state: --("save-state") [FILE]
+            "Saves the state of the program to FILE when it ends, including when it fails or hits --max-ticks")
+        (@arg profile: --profile "Prints the wall time spent on the 20 slowest cells after the program ends")
+        (@arg profile_folded: --("profile-folded") [FILE]
+            "Writes the wall time spent on every cell to FILE in the folded stack format of flamegraph tools")
+        (@arg annotate_json: --("annotate-json") [FILE] requires[FILE]
+            "Writes the coordinates, source index, category and execution count of every cell to FILE as JSON when the program ends")
+        (@arg script: --script [FILE]
+            "Runs the `on tick`, `on write` and `on output` handlers of the script in FILE as the program runs \
+             (needs the scripting feature)")
+        (@arg trace: --trace [FILE] conflicts_with[load_state]
+            "Records every tick and all consumed input to FILE, for checking with the replay subcommand")
+        (@arg fingerprint: --fingerprint
+            "Prints a hash of the executed ops, the output and the final state after the program ends")
+        (@arg dump_on_interrupt: --("dump-on-interrupt")
+            "On Ctrl-C, prints where the program was and its memory before exiting (and saves its state with --save-state), \
+             even while it waits for input; with -i, stops a step or continue command instead")
+        (@arg checkpoint_every: --("checkpoint-every") [N] "Saves the state of the program every N ticks")
+        (@arg checkpoint_dir: --("checkpoint-dir") [DIR] requires[checkpoint_every]
+            "Directory to save checkpoints in (default: the current directory)")
+        (@arg checkpoint_keep: --("checkpoint-keep") [K] requires[checkpoint_every]
+            "Number of most recent checkpoints to keep (default: 5)")
+        (@arg arg: --arg [STRING] ... number_of_values(1)
+            "Adds STRING and a newline to the program's input before STDIN (can be repeated)")
+        (@subcommand backtrack =>
+            (about: "Explains how an IP got to a tick or cell in a file written by --trace")
+            (@arg tick: --tick [N] conflicts_with[cell] "Tick to start from (default: the last tick)")
+            (@arg cell: --cell +takes_value +allow_hyphen_values number_of_values(2) value_names(&["Q", "R"])
+                "Starts from the last tick where an IP was at cell (Q, R)")
+            (@arg max_hops: --("max-hops") [N] "Maximum number of steps to show (default: 20)")
+            (@arg TRACE: +required "Path to a trace file")
+        )
+        (@subcommand bench =>
+            (about: "Runs a program repeatedly with the same input and reports how fast it runs")
+            (@arg iterations: --iterations [N] "Number of timed runs (default: 10)")
+            (@arg input: --input [INPUT] "Path to a file to use as input for every run (default: no input)")
+            (@arg max_ticks: --("max-ticks") [N] "Aborts a run after N ticks")
+            (@arg compare_dispatch: --("compare-dispatch") conflicts_with[compare_spill]
+                "Also benchmarks with --dispatch table and compares the two")
+            (@arg compare_spill: --("compare-spill") [N]
+                "Also benchmarks with memory spilled to disk beyond N edges, as with --spill-memory, and compares the two")
+            (@arg FILE: +required "Path to a source file to benchmark")
+        )
+        (@subcommand cast =>
+            (about: "Records a run of a program as an asciinema cast, reading its input from STDIN")
+            (@arg max_ticks: --("max-ticks") [N] "Maximum number of ticks to record (default: 10000)")
+            (@arg frame_ms: --("frame-ms") [MS] "Time between frames in the cast (default: 100)")
+            (@arg FILE: +required "Path to a source file to run")
+            (@arg CAST: +required "Path to write the cast to")
+        )
+        (@subcommand check =>
+            (about: "Statically analyzes a program without running it")
+            (@arg termination: --termination "Classifies whether the program terminates (exits with 1 if it loops, 2 if unknown)")
+            (@arg max_states: --("max-states") [N] "Maximum number of states to explore")
+            (@arg wrapping: --wrapping [RULE] possible_values(&["standard", "positive", "torus"])
+                "Where IPs go when they leave the grid (default: standard), as for running a program")
+            (@arg ips: --ips [N] "Number of IPs, spread evenly around the edge of the grid (default: 6)")
+            (@arg FILE: +required "Path to a source file to check")
+        )
+        (@subcommand diff =>
+            (about: "Compares two programs cell by cell, aligning them on the center of the hexagon (exits with 1 if they differ)")
+            (@arg A: +required "Path to the first source file")
+            (@arg B: +required "Path to the second source file")
+        )
+        (@subcommand compile =>
+            (about: "Compiles a program into the source code of a Rust function")
+            (@arg name: --name [NAME] "Name of the generated function (default: run)")
+            (@arg wrapping: --wrapping [RULE] possible_values(&["standard", "positive", "torus"])
+                "Where IPs go when they leave the grid (default: standard), as for running a program")
+            (@arg ips: --ips [N] "Number of IPs, spread evenly around the edge of the grid (default: 6)")
+            (@arg FILE: +required "Path to a source file to compile")
+        )
+        (@subcommand edit =>
+            (about: "Opens a terminal editor that lays out a program as a hexagon while you type (Unix only)")
+            (@arg FILE: +required "Path to the source file to edit, which is created when saved")
+        )
+        (@subcommand expand =>
+            (about: "Pads a program to a larger hexagon, adding mirrors to keep its wrapping paths intact")
+            (@arg size: --size <N> "Side length of the expanded hexagon")
+            (@arg wrapping: --wrapping [RULE] possible_values(&["standard", "positive", "torus"])
+                "Where IPs go when they leave the grid (default: standard), as for running a program")
+            (@arg ips: --ips [N] "Number of IPs, spread evenly around the edge of the grid (default: 6)")
+            (@arg FILE: +required "Path to a source file to expand")
+        )
+        (@subcommand fmt =>
+            (about: "Lays out a program as a hexagon, or converts it to compact source code on one line, keeping debug flags on their cells")
+            (@arg linear: --linear "Prints the program on one line, without trailing no-ops that don't change its size")
+            (@arg rulers: --rulers conflicts_with[linear]
+                "Labels each row with its r coordinate and range of q coordinates, and numbers the columns of text \
+                 above and below")
+            (@arg FILE: +required "Path to a source file to format")
+        )
+        (@subcommand gen_text =>
+            (name: "gen-text")
+            (about: "Generates a program that prints the given text")
+            (@arg TEXT: +required "Text for the program to print")
+        )
+        (@subcommand grid =>
+            (about: "Prints a program's grid with the axial coordinates of its rows")
+            (@arg legend: -l --legend "Also lists the coordinates of every cell")
+            (@arg FILE: +required "Path to a source file to print")
+        )
+        (@subcommand highlight =>
+            (about: "Renders a program as syntax-highlighted HTML, coloring commands by category")
+            (@arg output: -o --output [OUT] "Path to write the HTML to (default: STDOUT)")
+            (@arg FILE: +required "Path to a source file to render")
+        )
+        (@subcommand example =>
+            (about: "Runs a bundled example program, reading its input from STDIN, or lists the examples if no name is given")
+            (@arg list: --list conflicts_with[NAME] "Lists the bundled examples")
+            (@arg print: --print "Prints the example's source code instead of running it")
+            (@arg NAME: "Name of the example")
+        )
+        (@subcommand job =>
+            (about: "Runs a job read from STDIN as JSON and prints the result as JSON, for code-running services")
+        )
+        (@subcommand kata =>
+            (about: "Tests a program against a practice task, or lists the tasks if no name is given")
+            (@arg max_ticks: --("max-ticks") [N] "Maximum number of ticks per test case (default: 100000)")
+            (@arg format: --format [FORMAT] possible_value[text junit tap] "Format of the results (default: text)")
+            (@arg NAME: "Name of the task")
+            (@arg FILE: "Path to a source file that solves the task")
+        )
+        (@subcommand minimize =>
+            (about: "Shrinks a program while it still passes a set of test cases")
+            (@arg max_ticks: --("max-ticks") [N] "Maximum number of ticks per test case (default: 10000)")
+            (@arg FILE: +required "Path to a source file to minimize")
+            (@arg CASES: +required "Path to a test case file")
+        )
+        (@subcommand mirror =>
+            (about: "Reflects a program across an axis through its center, rewriting mirrors, branches and MP moves \
+                     into their mirror images, and prints where its IPs start. The result behaves like the original \
+                     when run from those IPs, and programs whose IPs can reach a sign-dependent turn, `-`, `:`, `%`, \
+                     `&` or `^` are refused")
+            (@arg axis: --axis [AXIS] possible_values(&["horizontal", "vertical"]) "Axis to reflect across (default: vertical)")
+            (@arg linear: --linear "Prints the reflected program on one line")
+            (@arg wrapping: --wrapping [RULE] possible_values(&["standard", "positive", "torus"])
+                "Where IPs go when they leave the grid (default: standard), as for running a program")
+            (@arg ips: --ips [N] "Number of IPs, spread evenly around the edge of the grid (default: 6)")
+            (@arg FILE: +required "Path to a source file to reflect")
+        )
+        (@subcommand new =>
+            (about: "Creates a directory with a program, a sample input file and test cases to start from")
+            (@arg size: --size [N] "Side length of the program's grid (default: 3)")
+            (@arg NAME: +required "Name of the program and its directory")
+        )
+        (@subcommand scaffold =>
+            (about: "Prints a skeleton program with the mirrors, branches and `@` of a classic structure in place, \
+                     and lists the cells left for each part of the program")
+            (@arg size: --size [N] "Side length of the program's grid (default: 5)")
+            (@arg pattern: --pattern [PATTERN] possible_values(&["loop", "two-ip", "io-loop"])
+                "Structure to lay out (default: loop): a loop while the current edge is positive, two IPs taking \
+                 turns, or a loop over the bytes of the input")
+        )
+        (@subcommand test =>
+            (about: "Runs a program with a set of test cases and reports which ones pass")
+            (@arg max_ticks: --("max-ticks") [N] "Maximum number of ticks per test case (default: 100000)")
+            (@arg format: --format [FORMAT] possible_value[text junit tap] "Format of the results (default: text)")
+            (@arg FILE: +required "Path to a source file to test")
+            (@arg CASES: +required "Path to a test case file")
+        )
+        (@subcommand trim =>
+            (about: "Removes the no-ops after the last command of a program, shrinking it to the smallest hexagon that fits")
+            (@arg linear: --linear "Prints the trimmed program on one line")
+            (@arg wrapping: --wrapping [RULE] possible_values(&["standard", "positive", "torus"])
+                "Where IPs go when they leave the grid (default: standard), as for running a program")
+            (@arg ips: --ips [N] "Number of IPs, spread evenly around the edge of the grid (default: 6)")
+            (@arg FILE: +required "Path to a source file to trim")
+        )
+        (@subcommand verify_quine =>
+            (name: "verify-quine")
+            (about: "Checks whether a program prints its own source code when run without input")
+            (@arg whitespace: --whitespace [MODE] possible_value[exact trailing ignore]
+                "How to normalize whitespace before comparing (default: exact)")
+            (@arg max_ticks: --("max-ticks") [N] "Aborts the program after N ticks")
+            (@arg FILE: +required "Path to a source file to check")
+        )
+        (@subcommand ops =>
+            (about: "Prints a reference table of all commands, or details about one command")
+            (@arg CHAR: "Command to describe")
+        )
+        (@subcommand pipe =>
+            (about: "Runs two programs concurrently, connecting the output of A to the input of B")
+            (@arg back_channel: --("back-channel") "Also connects the output of B to the input of A")
+            (@arg max_ticks: --("max-ticks") [N] "Aborts either program once it has run for N ticks")
+            (@arg A: +required "Path to the source file of the program reading from STDIN")
+            (@arg B: +required "Path to the source file of the program writing to STDOUT")
+        )
+        (@subcommand repack =>
+            (about: "Rearranges the no-ops of a program to fit it in a smaller hexagon")
+            (@arg max_ticks: --("max-ticks") [N] "Maximum number of ticks per test case (default: 10000)")
+            (@arg budget: --budget [N] "Maximum number of layouts to test (default: 100000)")
+            (@arg FILE: +required "Path to a source file to repack")
+            (@arg CASES: +required "Path to a test case file")
+        )
+        (@subcommand replay =>
+            (about: "Re-runs a program from a file written by --trace, checking that every tick matches")
+            (@arg TRACE: +required "Path to a trace file")
+        )
+        (@subcommand superopt =>
+            (about: "Searches for the shortest programs that pass a set of test cases")
+            (@arg size: --size [N] "Maximum side length of the programs to search (default: 2)")
+            (@arg ops: --ops [OPS] "Characters to build programs from (default: all commands except letters)")
+            (@arg max_ticks: --("max-ticks") [N] "Maximum number of ticks per test case (default: 1000)")
+            (@arg CASES: +required "Path to a test case file")
+        )
     ).get_matches();
+    match matches.subcommand() {
+        ("backtrack", Some(m)) => return backtrack(m),
+        ("bench", Some(m)) => return bench(m),
+        ("cast", Some(m)) => return cast(m),
+        ("check", Some(m)) => return check(m),
+        ("compile", Some(m)) => {
+            let src = read_program(m, "FILE")?;
+            out!("{}", layout(m, Builder::new(&src))?.to_rust(m.value_of("name").unwrap_or("run"))?)?;
+            return Ok(());
+        }
+        ("diff", Some(m)) => return diff(m),
+        ("edit", Some(m)) => return edit(m),
+        ("expand", Some(m)) => return expand(m),
+        ("fmt", Some(m)) => {
+            let grid = read_grid(m, "FILE")?;
+            match (m.is_present("linear"), m.is_present("rulers")) {
+                (true, _) => outln!("{}", grid.to_linear())?,
+                (false, true) => out!("{}", grid.ruled())?,
+                (false, false) => out!("{}", grid)?,
+            }
+            return Ok(());
+        }
+        ("gen-text", Some(m)) => {
+            out!("{}", hexagony::generate::text_printer(m.value_of("TEXT").unwrap().as_bytes()))?;
+            return Ok(());
+        }
+        ("grid", Some(m)) => {
+            out!("{}", read_grid(m, "FILE")?.annotated(m.is_present("legend")))?;
+            return Ok(());
+        }
+        ("example", Some(m)) => return example(m),
+        ("highlight", Some(m)) => {
+            let html = hexagony::highlight::to_html(&read_grid(m, "FILE")?);
+            match m.value_of("output") {
+                Some(path) => fs::write(path, html)?,
+                None => out!("{}", html)?,
+            }
+            return Ok(());
+        }
+        ("job", Some(_)) => {
+            let mut job = String::new();
+            io::stdin().read_to_string(&mut job)?;
+            outln!("{}", hexagony::job::run_job(&job)?)?;
+            return Ok(());
+        }
+        ("kata", Some(m)) => return kata(m),
+        ("minimize", Some(m)) => return minimize(m),
+        ("mirror", Some(m)) => return mirror(m),
+        ("new", Some(m)) => return new(m),
+        ("ops", Some(m)) => return ops(m),
+        ("pipe", Some(m)) => return pipe(m),
+        ("repack", Some(m)) => return repack(m),
+        ("replay", Some(m)) => return replay(m),
+        ("scaffold", Some(m)) => return scaffold(m),
+        ("superopt", Some(m)) => return superopt(m),
+        ("test", Some(m)) => return test(m),
+        ("trim", Some(m)) => return trim(m),
+        ("verify-quine", Some(m)) => return verify_quine(m),
+        _ => (),
+    }
     // Check for grid argument
     if let Some(s) = matches.value_of("grid") {
-        print!("{}", hexagony::source_template(s.parse()?));
+        let size = s.parse()?;
+        check_size(&matches, size)?;
+        let mut template = hexagony::Template::new(size).rulers(matches.is_present("rulers"));
+        if let Some(fill) = matches.value_of("fill") {
+            let mut chars = fill.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if !c.is_whitespace() => template = template.fill(c),
+                _ => return Err("The fill must be a single character other than whitespace".into()),
+            }
+        }
+        if let Some(mut values) = matches.values_of("terminate_at") {
+            let coords = PointAxial(values.next().unwrap().parse()?, values.next().unwrap().parse()?);
+            if !coords.in_bounds(size) {
+                return Err(format!("Cell {} lies outside a grid of side length {}", coords, size).into());
+            }
+            template = template.terminate_at(coords);
+        }
+        out!("{}", template)?;
         return Ok(());
     }
     // Choose highest debug level that has a flag set
@@ -24,9 +553,731 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         (true, false) => 1,
         (false, false) => 0,
     };
-    if let Some(s) = matches.value_of("FILE") {
-        let src = fs::read_to_string(Path::new(s))?;
-        hexagony::run(&src, debug_level)?;
+    let builder = match (matches.value_of("FILE"), matches.value_of("load_state")) {
+        (Some(path), _) => {
+            let src = fs::read_to_string(Path::new(path))?;
+            if let (None, Some(format)) = (matches.value_of("source_format"), SourceFormat::misread_as_linear(&src)) {
+                eprintln!("Warning: the source is laid out as a {} hexagon with blank cells, which linear source skips; \
+                           pass --format {} to keep them", format.name(), format.name());
+            }
+            Builder::new(&src)
+        }
+        (None, Some(path)) => Builder::resume(fs::read_to_string(Path::new(path))?.parse()?),
+        (None, None) => return Ok(()),
+    };
+    let source_format = matches.value_of("source_format").and_then(SourceFormat::from_name).unwrap_or(SourceFormat::Linear);
+    let from_tick = matches.value_of("from_tick").map(str::parse).transpose()?.unwrap_or(0);
+    let to_tick = matches.value_of("to_tick").map(str::parse).transpose()?;
+    let mut builder = builder
+        .debug_level(debug_level)
+        .explain(matches.is_present("explain"))
+        .tick_range(from_tick, to_tick)
+        .utf8(matches.is_present("utf8"))
+        .echo_input(matches.is_present("echo_input"))
+        .fingerprint(matches.is_present("fingerprint"))
+        .dialect(match matches.value_of("dialect") {
+            Some("extensions") => Dialect::Extensions,
+            _ => Dialect::Standard,
+        })
+        .source_format(source_format)
+        .wrapping(match matches.value_of("wrapping") {
+            Some("positive") => Wrapping::AlwaysPositive,
+            Some("torus") => Wrapping::Torus,
+            _ => Wrapping::Standard,
+        })
+        .value_type(match matches.value_of("values") {
+            Some("byte") => ValueType::Byte,
+            _ => ValueType::Unbounded,
+        })
+        .memory_model(matches.value_of("memory").and_then(MemoryModel::from_name).unwrap_or(MemoryModel::Hex));
+    if let Some(value) = matches.value_of("eof") {
+        builder = builder.byte_eof(value.parse()?);
+    }
+    if let Some(mode) = matches.value_of("int_eof") {
+        builder = builder.int_eof(match mode {
+            "minus-one" => IntEof::MinusOne,
+            "unchanged" => IntEof::Unchanged,
+            _ => IntEof::Zero,
+        });
+    }
+    let mut ip_count = SpecVersion::LATEST.capabilities().ips;
+    if let Some(n) = matches.value_of("spec_version") {
+        let version = n.parse().ok().and_then(SpecVersion::from_number).ok_or_else(|| format!("Unknown spec version: {}", n))?;
+        ip_count = version.capabilities().ips;
+        builder = builder.spec_version(version);
+    }
+    if let Some(n) = matches.value_of("seed") {
+        builder = builder.random_seed(n.parse()?);
+    }
+    for var in matches.values_of("env").into_iter().flatten() {
+        let (name, value) = var.split_once('=').ok_or_else(|| format!("Expected NAME=VALUE: {}", var))?;
+        builder = builder.env_var(name, value);
+    }
+    builder = builder.process_env(matches.is_present("allow_env"));
+    if let Some(n) = matches.value_of("max_ticks") {
+        builder = builder.max_ticks(n.parse()?);
+    }
+    if let Some(n) = matches.value_of("spill_memory") {
+        builder = builder.spill_memory(n.parse()?);
+    }
+    if matches.value_of("dispatch") == Some("table") {
+        builder = builder.dispatch(Dispatch::Table);
+    }
+    if matches.value_of("cost") == Some("bits") {
+        builder = builder.cost_model(CostModel::BitLength);
+    }
+    if let Some(n) = matches.value_of("max_size") {
+        builder = builder.max_size(n.parse()?);
+    }
+    if let Some(bytes) = matches.value_of("max_memory") {
+        builder = builder.memory_limit(bytes.parse()?);
+    }
+    if let Some(bytes) = matches.value_of("warn_memory") {
+        builder = builder.soft_memory_limit(bytes.parse()?, |used| eprintln!("Warning: memory uses about {} bytes", used));
+    }
+    if let Some(secs) = matches.value_of("time_limit") {
+        builder = builder.deadline(Instant::now() + Duration::from_secs_f64(secs.parse()?), 1000);
+    }
+    if let Some(n) = matches.value_of("ips") {
+        let count = n.parse()?;
+        if !(1..=256).contains(&count) {
+            return Err("The number of IPs must be from 1 to 256".into());
+        }
+        ip_count = count;
+        builder = builder.ip_count(count);
+    }
+    if let Some(n) = matches.value_of("follow_ip") {
+        let idx = n.parse()?;
+        if idx >= ip_count {
+            return Err(format!("The IP to follow must be from 0 to {}", ip_count - 1).into());
+        }
+        builder = builder.follow_ip(idx);
+    }
+    if let Some(ms) = matches.value_of("delay") {
+        builder = builder.delay(Duration::from_millis(ms.parse()?));
+    }
+    if let Some(n) = matches.value_of("max_output") {
+        builder = builder.max_output(n.parse()?);
+    }
+    if let Some(n) = matches.value_of("checkpoint_every") {
+        let every = n.parse()?;
+        let keep = matches.value_of("checkpoint_keep").map(str::parse).transpose()?.unwrap_or(5);
+        if every == 0 || keep == 0 {
+            return Err("The checkpoint interval and count must be positive".into());
+        }
+        builder = builder.checkpoints(every, matches.value_of("checkpoint_dir").unwrap_or("."), keep);
+    }
+    // The debugger reads its commands from STDIN, so the program's input must be requested separately
+    let stdin: Box<dyn Read> = match (matches.is_present("interactive"), matches.is_present("dump_on_interrupt")) {
+        (true, _) => Box::new(PromptInput::default()),
+        (false, true) => Box::new(hexagony::signal::Interruptible(io::stdin())),
+        (false, false) => Box::new(io::stdin()),
+    };
+    let args = matches.values_of("arg").into_iter().flatten();
+    let prelude: Vec<u8> = args.flat_map(|arg| arg.bytes().chain(Some(b'\n'))).collect();
+    builder = builder.input(io::Cursor::new(prelude).chain(stdin));
+    if matches.is_present("slurp") && !io::stdin().is_terminal() {
+        builder = builder.slurp_input(true);
+    }
+    if let Some(path) = matches.value_of("replay_input") {
+        let input = hexagony::record::replay_input(&fs::read_to_string(Path::new(path))?)?;
+        builder = builder.input(io::Cursor::new(input));
+    }
+    if let Some(path) = matches.value_of("record_input") {
+        builder = builder.record_input(io::BufWriter::new(fs::File::create(path)?));
+    }
+    if let Some(path) = matches.value_of("log_output") {
+        builder = builder.log_output(io::BufWriter::new(fs::File::create(path)?));
+    }
+    if let Some(path) = matches.value_of("trace") {
+        builder = builder.record_trace(io::BufWriter::new(fs::File::create(path)?));
+    }
+    if let Some(path) = matches.value_of("output") {
+        builder = builder.output(io::BufWriter::new(fs::File::create(path)?));
+    }
+    if let Some(path) = matches.value_of("tee") {
+        builder = builder.output(Tee(hexagony::binary_stdout(), io::BufWriter::new(fs::File::create(path)?)));
+    }
+    if let Some(n) = matches.value_of("output_fd") {
+        builder = builder.output(io::BufWriter::new(output_fd(n.parse()?)?));
+    }
+    #[cfg(unix)]
+    {
+        builder = builder.dump_when(hexagony::signal::catch_dump_signal());
+    }
+    #[cfg(unix)]
+    {
+        if matches.is_present("isolate") {
+            isolate(&matches)?;
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        if matches.is_present("isolate") {
+            return Err("--isolate is only supported on Unix".into());
+        }
+    }
+    let counts = hexagony::annotate::CellCounts::default();
+    if matches.is_present("annotate_json") {
+        builder = builder.observer(counts.clone());
+    }
+    let profiler = hexagony::profile::Profiler::default();
+    if matches.is_present("profile") || matches.is_present("profile_folded") {
+        builder = builder.observer(profiler.clone());
+    }
+    if let Some(path) = matches.value_of("script") {
+        #[cfg(feature = "scripting")]
+        {
+            builder = builder.observer(hexagony::script::Script::new(&fs::read_to_string(path)?)?);
+        }
+        #[cfg(not(feature = "scripting"))]
+        {
+            let _ = path;
+            return Err("--script needs hexagony to be built with the scripting feature".into());
+        }
+    }
+    let mut hex = builder.build()?;
+    let mut interrupted = false;
+    let result = if matches.is_present("interactive") {
+        match matches.is_present("dump_on_interrupt") {
+            true => Debugger::new(&mut hex).interrupt_when(hexagony::signal::catch_interrupt()).run(),
+            false => Debugger::new(&mut hex).run(),
+        }
+    } else if matches.is_present("dump_on_interrupt") {
+        let flag = hexagony::signal::catch_interrupt();
+        match hex.run_until(flag) {
+            // Ctrl-C while waiting for input fails the read instead
+            Err(_) if flag.load(Ordering::Relaxed) => {
+                interrupted = true;
+                Ok(())
+            }
+            result => result.map(|terminated| interrupted = !terminated),
+        }
+    } else {
+        hex.run()
+    };
+    // Whatever reads the output stopped early, like `head` does, so there's nothing left to flush
+    let output_closed = matches!(result, Err(Error::OutputClosed));
+    if interrupted {
+        eprint!("\nInterrupted\n{}", hex.dump());
+    }
+    if let Some(path) = matches.value_of("save_state") {
+        fs::write(path, hex.save_state()?.to_string())?;
+    }
+    profiler.finish();
+    if let Some(path) = matches.value_of("profile_folded") {
+        fs::write(path, profiler.folded())?;
+    }
+    if matches.is_present("profile") {
+        if !output_closed {
+            io::stdout().flush()?;
+        }
+        eprint!("\n{}", profiler.report(20));
+    }
+    if let Some(path) = matches.value_of("annotate_json") {
+        let src = fs::read_to_string(Path::new(matches.value_of("FILE").unwrap()))?;
+        fs::write(path, hexagony::annotate::to_json(&src, source_format, hex.grid(), Some(&counts)))?;
+    }
+    if matches.is_present("stats") {
+        if !output_closed {
+            io::stdout().flush()?;
+        }
+        eprint!("\n{}", hex.stats());
+        if let Some(peak) = peak_heap() {
+            eprintln!("Peak heap: {} bytes", peak);
+        }
+    }
+    if let Some(hash) = hex.fingerprint()? {
+        if !output_closed {
+            io::stdout().flush()?;
+        }
+        eprintln!("\nFingerprint: {:016x}", hash);
+    }
+    if interrupted {
+        process::exit(130);
+    }
+    if output_closed {
+        // The status a shell reports for a process killed by SIGPIPE
+        process::exit(141);
+    }
+    if let Err(e) = &result {
+        io::stdout().flush()?;
+        eprint!("\n{}", hex.error_report(e));
+        if matches.is_present("debug_on_error") {
+            Debugger::new(&mut hex).post_mortem()?;
+        }
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Opens a duplicate of an open file descriptor that the program's output is written to.
+#[cfg(unix)]
+fn output_fd(fd: i32) -> Result<fs::File, Box<dyn std::error::Error>> {
+    use std::os::unix::io::FromRawFd;
+    // Closing the duplicate when the program ends leaves the original descriptor open for its owner
+    let dup = if fd < 0 { -1 } else { unsafe { libc::dup(fd) } };
+    if dup < 0 {
+        return Err(format!("File descriptor {} is not open", fd).into());
+    }
+    Ok(unsafe { fs::File::from_raw_fd(dup) })
+}
+
+#[cfg(not(unix))]
+fn output_fd(_fd: i32) -> Result<fs::File, Box<dyn std::error::Error>> {
+    Err("--output-fd is only supported on Unix".into())
+}
+
+/// Forks a child process with OS-enforced limits to run the program, and exits with its status
+/// once it ends.
+///
+/// Returns in the child, which goes on to run the program.
+#[cfg(unix)]
+fn isolate(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    use hexagony::isolate::{Limits, Outcome};
+
+    let limits = Limits {
+        cpu_secs: matches.value_of("isolate_cpu").map(str::parse).transpose()?,
+        memory_bytes: matches.value_of("isolate_memory").map(str::parse).transpose()?,
+    };
+    let code = match hexagony::isolate::fork_isolated(&limits)? {
+        None => return Ok(()),
+        Some(Outcome::Exited(code)) => code,
+        Some(Outcome::CpuLimitExceeded) => {
+            eprintln!("\nError: CPU time limit exceeded");
+            1
+        }
+        Some(Outcome::Signaled(signal)) => {
+            eprintln!("\nError: Program was killed by signal {}", signal);
+            128 + signal
+        }
+    };
+    process::exit(code)
+}
+
+/// A writer that writes everything to two writers.
+struct Tee<A, B>(A, B);
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.1.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()?;
+        self.1.flush()
+    }
+}
+
+/// Runs the `backtrack` subcommand.
+fn backtrack(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let trace = fs::read(Path::new(matches.value_of("TRACE").unwrap()))?;
+    let target = match (matches.value_of("tick"), matches.values_of("cell")) {
+        (Some(tick), _) => Target::Tick(tick.parse()?),
+        (None, Some(mut cell)) => Target::Cell(PointAxial(cell.next().unwrap().parse()?, cell.next().unwrap().parse()?)),
+        (None, None) => Target::Last,
+    };
+    let max_hops = matches.value_of("max_hops").map(str::parse).transpose()?.unwrap_or(20);
+    let (ip, hops) = hexagony::trace::backtrack(&trace, target, max_hops)?;
+    outln!("Path of IP {}, most recent first:", ip)?;
+    for hop in hops {
+        outln!("  {}", hop)?;
+    }
+    Ok(())
+}
+
+/// Runs the `bench` subcommand.
+fn bench(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let src = read_program(matches, "FILE")?;
+    let input = matches.value_of("input").map(fs::read).transpose()?.unwrap_or_default();
+    let iterations = matches.value_of("iterations").map(str::parse).transpose()?.unwrap_or(10);
+    if iterations == 0 {
+        return Err("The number of iterations must be positive".into());
+    }
+    let max_ticks: Option<u64> = matches.value_of("max_ticks").map(str::parse).transpose()?;
+    let limit = |builder: Builder<'static>| match max_ticks {
+        Some(max) => builder.max_ticks(max),
+        None => builder,
+    };
+    let base = hexagony::bench::bench(&src, &input, iterations, limit)?;
+    out!("{}", base)?;
+    if let Some(n) = matches.value_of("compare_spill") {
+        let hot_edges = n.parse()?;
+        let spilled = hexagony::bench::bench(&src, &input, iterations, |builder| limit(builder).spill_memory(hot_edges))?;
+        outln!("\nWith --spill-memory {}:\n{}", hot_edges, spilled)?;
+        outln!("Spilled runs take {:.2}x as long", spilled.median().as_secs_f64() / base.median().as_secs_f64())?;
+    }
+    if matches.is_present("compare_dispatch") {
+        let table = hexagony::bench::bench(&src, &input, iterations, |builder| limit(builder).dispatch(Dispatch::Table))?;
+        outln!("\nWith --dispatch table:\n{}", table)?;
+        outln!("Table dispatch takes {:.2}x as long", table.median().as_secs_f64() / base.median().as_secs_f64())?;
+    }
+    Ok(())
+}
+
+/// Runs the `cast` subcommand.
+fn cast(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let src = read_program(matches, "FILE")?;
+    let max_ticks = matches.value_of("max_ticks").map(str::parse).transpose()?.unwrap_or(10000);
+    let frame_ms: f64 = matches.value_of("frame_ms").map(str::parse).transpose()?.unwrap_or(100.0);
+    let mut input = Vec::new();
+    io::stdin().read_to_end(&mut input)?;
+    let mut cast = io::BufWriter::new(fs::File::create(matches.value_of("CAST").unwrap())?);
+    hexagony::cast::record_cast(&src, &input, max_ticks, frame_ms / 1000.0, &mut cast)?;
+    Ok(cast.flush()?)
+}
+
+/// Runs the `check` subcommand.
+///
+/// Termination is currently the only check, so it also runs when `--termination` is omitted.
+fn check(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let src = read_program(matches, "FILE")?;
+    let max_states = match matches.value_of("max_states") {
+        Some(s) => s.parse()?,
+        None => hexagony::DEFAULT_MAX_STATES,
+    };
+    let result = layout(matches, Builder::new(&src))?.check_termination(max_states)?;
+    outln!("Termination: {}", result)?;
+    match result {
+        Termination::Terminates => Ok(()),
+        Termination::Loops => process::exit(1),
+        Termination::Unknown => process::exit(2),
+    }
+}
+
+/// Runs the `diff` subcommand.
+fn diff(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let a = read_grid(matches, "A")?;
+    let b = read_grid(matches, "B")?;
+    if a.size() != b.size() {
+        outln!("Side length: {} -> {} (cells outside the smaller grid count as `.`)", a.size(), b.size())?;
+    }
+    let diffs = hexagony::diff::diff(&a, &b);
+    for diff in &diffs {
+        outln!("{}", diff)?;
+    }
+    match diffs.len() {
+        0 if a.size() == b.size() => Ok(()),
+        0 => process::exit(1),
+        n => {
+            outln!("{} {} differ", n, if n == 1 { "cell" } else { "cells" })?;
+            process::exit(1)
+        }
+    }
+}
+
+/// Runs the `edit` subcommand.
+fn edit(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(unix)]
+    {
+        hexagony::editor::Editor::open(matches.value_of("FILE").unwrap())?.run()?;
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = matches;
+        Err("The editor is only supported on Unix".into())
+    }
+}
+
+/// Runs the `expand` subcommand.
+///
+/// Paths that couldn't be preserved are reported as warnings.
+fn expand(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let src = read_program(matches, "FILE")?;
+    let size = matches.value_of("size").unwrap().parse()?;
+    check_size(matches, size)?;
+    if size < src.parse::<hexagony::Grid>()?.size() {
+        return Err("The expanded hexagon must be at least as large as the program".into());
+    }
+    let (grid, broken) = layout(matches, Builder::new(&src))?.expand(size)?;
+    out!("{}", grid)?;
+    for path in broken {
+        eprintln!("Warning: {}", path);
+    }
+    Ok(())
+}
+
+/// Runs the `example` subcommand.
+fn example(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let name = match matches.value_of("NAME") {
+        Some(name) => name,
+        None => {
+            for example in hexagony::examples::examples() {
+                outln!("{:<16}{}", example.name, example.description)?;
+            }
+            return Ok(());
+        }
+    };
+    let example = hexagony::examples::find(name).ok_or_else(|| format!("Unknown example: {}", name))?;
+    if matches.is_present("print") {
+        out!("{}", example.source())?;
+        return Ok(());
+    }
+    Ok(hexagony::run(example.source(), 0)?)
+}
+
+/// Runs the `kata` subcommand.
+///
+/// Without a source file, prints the description of the task instead of testing anything.
+fn kata(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let name = match matches.value_of("NAME") {
+        Some(name) => name,
+        None => {
+            for kata in hexagony::kata::katas() {
+                outln!("{:<12}{}", kata.name, kata.description)?;
+            }
+            return Ok(());
+        }
+    };
+    let kata = hexagony::kata::find(name).ok_or_else(|| format!("Unknown kata: {}", name))?;
+    let src = match matches.value_of("FILE") {
+        Some(_) => read_program(matches, "FILE")?,
+        None => {
+            outln!("{}", kata.description)?;
+            return Ok(());
+        }
+    };
+    let max_ticks = matches.value_of("max_ticks").map(str::parse).transpose()?.unwrap_or(100_000);
+    run_tests(matches, kata.name, &src, &kata.cases(), max_ticks)
+}
+
+/// Runs the `mirror` subcommand.
+fn mirror(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let src = read_program(matches, "FILE")?;
+    let axis = match matches.value_of("axis") {
+        Some("horizontal") => Axis::Horizontal,
+        _ => Axis::Vertical,
+    };
+    let mirrored = layout(matches, Builder::new(&src))?.mirror(axis)?;
+    match matches.is_present("linear") {
+        true => outln!("{}", mirrored.grid.to_linear())?,
+        false => out!("{}", mirrored.grid)?,
+    }
+    for (i, ip) in mirrored.starts.iter().enumerate() {
+        eprintln!("IP {} starts at {} moving {}", i, ip.coords, ip.dir);
+    }
+    Ok(())
+}
+
+/// Runs the `scaffold` subcommand.
+fn scaffold(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let pattern = match matches.value_of("pattern") {
+        Some("two-ip") => Pattern::TwoIp,
+        Some("io-loop") => Pattern::IoLoop,
+        _ => Pattern::Loop,
+    };
+    let size = matches.value_of("size").map(str::parse).transpose()?.unwrap_or(5);
+    check_size(matches, size)?;
+    if size < pattern.min_size() {
+        return Err(format!("This pattern needs a side length of at least {}", pattern.min_size()).into());
+    }
+    let scaffold = hexagony::generate::scaffold(size, pattern);
+    out!("{}", scaffold.grid)?;
+    for (description, cells) in scaffold.parts {
+        let cells: Vec<_> = cells.iter().map(ToString::to_string).collect();
+        eprintln!("{}: {}", description, if cells.is_empty() { String::from("no cells") } else { cells.join(" ") });
+    }
+    Ok(())
+}
+
+/// Runs the `minimize` subcommand.
+fn minimize(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let src = read_program(matches, "FILE")?;
+    let cases = hexagony::cases::parse_cases(&fs::read_to_string(Path::new(matches.value_of("CASES").unwrap()))?)?;
+    let max_ticks = matches.value_of("max_ticks").map(str::parse).transpose()?.unwrap_or(10000);
+    match hexagony::minimize::minimize(&src, &cases, max_ticks) {
+        Some(min) => outln!("{}", min)?,
+        None => return Err("The program does not pass the test cases".into()),
+    }
+    Ok(())
+}
+
+/// Runs the `new` subcommand.
+///
+/// The program only terminates, so its test case passes until the expected output is filled in.
+fn new(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let name = matches.value_of("NAME").unwrap();
+    let size: usize = matches.value_of("size").map(str::parse).transpose()?.unwrap_or(3);
+    if size == 0 {
+        return Err("The side length must be positive".into());
+    }
+    let dir = Path::new(name);
+    if dir.exists() {
+        return Err(format!("{} already exists", dir.display()).into());
+    }
+    let name = dir.file_name().ok_or("The name must end with a file name")?.to_string_lossy();
+    let corner = size as isize - 1;
+    let program = hexagony::Template::new(size).terminate_at(PointAxial(corner, -corner));
+    let input = "Hello, World!\n";
+    let cases = format!(
+        "# Test cases for {0}.hxg, run with `hexagony test {0}.hxg {0}.cases`\n\
+         # or by a `hexagony::harness::Harness` given this directory\n\
+         \n\
+         [sample]\n\
+         input = {1:?}\n\
+         output = \"\"\n",
+        name, input,
+    );
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(format!("{}.hxg", name)), program.to_string())?;
+    fs::write(dir.join(format!("{}.in", name)), input)?;
+    fs::write(dir.join(format!("{}.cases", name)), cases)?;
+    outln!("Created {} with {}.hxg, {}.in and {}.cases", dir.display(), name, name, name)?;
+    Ok(())
+}
+
+/// Runs the `ops` subcommand.
+///
+/// Letters and digits are summarized as one row each in the table.
+fn ops(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let s = match matches.value_of("CHAR") {
+        Some(s) => s,
+        None => {
+            for op in Op::all() {
+                let c = match op {
+                    Op::Letter(b'A') => "A-Z a-z".to_string(),
+                    Op::Digit(0) => "0-9".to_string(),
+                    Op::Letter(_) | Op::Digit(_) => continue,
+                    _ => op.to_string(),
+                };
+                outln!("{:<9}{:<19}{}", c, op.name(), op.description())?;
+            }
+            outln!("\nExtensions (--dialect extensions):")?;
+            for op in Op::extensions() {
+                outln!("{:<9}{:<19}{}", op.to_string(), op.name(), op.description())?;
+            }
+            return Ok(());
+        }
+    };
+    let mut chars = s.chars();
+    let op = match (chars.next(), chars.next()) {
+        (Some(c), None) => Op::from_char(c)
+            .or_else(|| Op::from_extension_char(c))
+            .ok_or_else(|| format!("`{}` is not a command", c))?,
+        _ => return Err("Expected a single character".into()),
+    };
+    outln!("`{}`: {}", op, op.name())?;
+    outln!("{}.", op.description())?;
+    match op {
+        Op::Letter(b) => outln!("The current edge is set to {}.", b)?,
+        Op::Random | Op::Tick | Op::Env => outln!("This is an extension command, which requires --dialect extensions.")?,
+        Op::Digit(d) => outln!("The current edge becomes 10 times its value plus {}.", d)?,
+        Op::Redir(redir) => {
+            outln!("\nMoving    Non-positive  Positive")?;
+            for dir in Direction::all() {
+                let (neg, pos) = (hexagony::redirect(dir, redir, false), hexagony::redirect(dir, redir, true));
+                outln!("{:<10}{:<14}{}", dir.to_string(), neg.to_string(), pos)?;
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Runs the `pipe` subcommand.
+fn pipe(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let a = read_program(matches, "A")?;
+    let b = read_program(matches, "B")?;
+    let max_ticks = matches.value_of("max_ticks").map(str::parse).transpose()?;
+    Ok(hexagony::pipe::run_pipeline(&a, &b, matches.is_present("back_channel"), max_ticks)?)
+}
+
+/// Runs the `repack` subcommand.
+fn repack(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let src = read_program(matches, "FILE")?;
+    let cases = hexagony::cases::parse_cases(&fs::read_to_string(Path::new(matches.value_of("CASES").unwrap()))?)?;
+    let max_ticks = matches.value_of("max_ticks").map(str::parse).transpose()?.unwrap_or(10000);
+    let budget = matches.value_of("budget").map(str::parse).transpose()?.unwrap_or(100_000);
+    match hexagony::repack::repack(&src, &cases, max_ticks, budget) {
+        Some(packed) => outln!("{}", packed)?,
+        None => return Err("The program does not pass the test cases".into()),
+    }
+    Ok(())
+}
+
+/// Runs the `replay` subcommand.
+fn replay(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let trace = fs::read(Path::new(matches.value_of("TRACE").unwrap()))?;
+    let ticks = hexagony::trace::replay(&trace)?;
+    outln!("Replayed {} ticks, all matching the trace", ticks)?;
+    Ok(())
+}
+
+/// Runs the `superopt` subcommand.
+fn superopt(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let cases = hexagony::cases::parse_cases(&fs::read_to_string(Path::new(matches.value_of("CASES").unwrap()))?)?;
+    let size = matches.value_of("size").map(str::parse).transpose()?.unwrap_or(2);
+    let max_ticks = matches.value_of("max_ticks").map(str::parse).transpose()?.unwrap_or(1000);
+    let mut ops: Vec<char> = matches.value_of("ops").unwrap_or(".@)(+-*:%~,?;!$_|/\\<>[]#{}\"'=^&0123456789").chars().collect();
+    ops.sort_unstable();
+    ops.dedup();
+    let found = hexagony::superopt::superoptimize(&cases, &ops, size, max_ticks);
+    if found.is_empty() {
+        outln!("No programs found")?;
+    }
+    for program in found {
+        outln!("{}", program)?;
+    }
+    Ok(())
+}
+
+/// Runs the `test` subcommand.
+fn test(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let path = matches.value_of("FILE").unwrap();
+    let src = read_program(matches, "FILE")?;
+    let cases = hexagony::cases::parse_cases(&fs::read_to_string(Path::new(matches.value_of("CASES").unwrap()))?)?;
+    let max_ticks = matches.value_of("max_ticks").map(str::parse).transpose()?.unwrap_or(100_000);
+    run_tests(matches, path, &src, &cases, max_ticks)
+}
+
+/// Runs a program with test cases and reports the results in the format chosen with `--format`,
+/// exiting with status 1 if any of them failed.
+fn run_tests(matches: &ArgMatches, suite: &str, src: &str, cases: &[TestCase], max_ticks: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let format = matches.value_of("format").and_then(Format::from_name).unwrap_or(Format::Text);
+    let reports = hexagony::report::run_cases(src, cases, max_ticks);
+    out!("{}", format.render(suite, &reports))?;
+    if !reports.iter().all(|report| report.passed()) {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Runs the `trim` subcommand.
+fn trim(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let src = read_program(matches, "FILE")?;
+    let size = src.parse::<hexagony::Grid>()?.size();
+    let trimmed = layout(matches, Builder::new(&src))?.trim()?;
+    match matches.is_present("linear") {
+        true => outln!("{}", trimmed.grid.to_linear())?,
+        false => out!("{}", trimmed.grid)?,
+    }
+    eprintln!("Side length: {} -> {}", size, trimmed.grid.size());
+    if !trimmed.reached.is_empty() {
+        let cells: Vec<_> = trimmed.reached.iter().map(ToString::to_string).collect();
+        eprintln!("Warning: IPs can reach {} removed cells, so the program may have relied on them: {}",
+            cells.len(), cells.join(", "));
+    }
+    Ok(())
+}
+
+/// Runs the `verify-quine` subcommand.
+fn verify_quine(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let src = read_program(matches, "FILE")?;
+    let whitespace = match matches.value_of("whitespace") {
+        Some("trailing") => Whitespace::Trailing,
+        Some("ignore") => Whitespace::Ignore,
+        _ => Whitespace::Exact,
+    };
+    let max_ticks = matches.value_of("max_ticks").map(str::parse).transpose()?;
+    match hexagony::quine::verify_quine(&src, whitespace, max_ticks)? {
+        None => outln!("The program is a quine")?,
+        Some(divergence) => {
+            outln!("{}", divergence)?;
+            process::exit(1);
+        }
     }
     Ok(())
 }
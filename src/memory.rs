@@ -1,9 +1,12 @@
-use std::collections::HashMap;
-use std::fmt;
-use rug::Integer;
+use std::collections::{HashMap, HashSet};
+use std::{env, fmt, fs::{self, File}, io::{self, Read, Seek, SeekFrom, Write}, mem, path::PathBuf, process, rc::Rc};
+use std::time::{SystemTime, UNIX_EPOCH};
+use rug::{Integer, integer::Order};
+
+use crate::Error;
 
 /// One of three edges of the hex used for indexing.
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 enum Dir {
     NE,
     E,
@@ -13,107 +16,1099 @@ enum Dir {
 /// Orientation of a memory pointer relative to its hex.
 #[derive(Clone, Copy)]
 enum Rot {
-    CW,
-    CCW,
+    Cw,
+    Ccw,
+}
+
+/// The coordinates and `Dir` of a memory edge.
+type Edge = (isize, isize, Dir);
+
+/// The key of a memory edge in a map.
+///
+/// Edges whose `q` and `r` coordinates lie within `-BIAS..BIAS` have them offset by `Index::BIAS`
+/// and packed into 31 bits each above the two bits of their `Dir`, so they hash and compare as a
+/// single word. Edges further out, which take at least a billion MP moves to reach, are keyed by
+/// their coordinates instead.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Index {
+    Packed(u64),
+    Wide(isize, isize, Dir),
+}
+
+impl Index {
+    /// The offset that makes the smallest packed coordinate 0.
+    const BIAS: isize = 1 << 30;
+
+    /// Returns the key of an edge.
+    fn new((q, r, d): Edge) -> Index {
+        let range = -Index::BIAS..Index::BIAS;
+        match range.contains(&q) && range.contains(&r) {
+            true => Index::Packed(((q + Index::BIAS) as u64) << 33 | ((r + Index::BIAS) as u64) << 2 | d as u64),
+            false => Index::Wide(q, r, d),
+        }
+    }
+
+    /// Returns the edge this is the key of.
+    fn unpack(self) -> Edge {
+        let packed = match self {
+            Index::Packed(packed) => packed,
+            Index::Wide(q, r, d) => return (q, r, d),
+        };
+        let q = (packed >> 33) as isize - Index::BIAS;
+        let r = (packed >> 2 & 0x7fff_ffff) as isize - Index::BIAS;
+        let d = match packed & 3 {
+            0 => Dir::NE,
+            1 => Dir::E,
+            _ => Dir::SE,
+        };
+        (q, r, d)
+    }
+}
+
+/// The memory that a program's commands read and write through a memory pointer (MP).
+///
+/// Every model has a current cell and a left and right neighbour of it, so the instruction
+/// surface stays the same, but where the neighbours are and how the MP moves depends on the model.
+pub trait Memory: fmt::Display {
+    /// Returns the model this memory implements.
+    fn model(&self) -> MemoryModel;
+
+    /// Returns a reference to the value in the current cell.
+    fn get(&self) -> &Integer;
+
+    /// Returns a reference to the value in the left neighbour.
+    fn get_left(&self) -> &Integer;
+
+    /// Returns a reference to the value in the right neighbour.
+    fn get_right(&self) -> &Integer;
+
+    /// Sets the current cell to the given value.
+    fn set(&mut self, value: Integer);
+
+    /// Returns a mutable reference to the value in the current cell.
+    fn get_mut(&mut self) -> &mut Integer;
+
+    /// Calls `f` with a mutable reference to the value in the current cell and references to the values
+    /// in the left and right neighbours, so the current cell can be computed in place from them.
+    fn update(&mut self, f: &mut dyn FnMut(&mut Integer, &Integer, &Integer));
+
+    /// Moves the MP to the left neighbour.
+    fn move_left(&mut self);
+
+    /// Moves the MP to the right neighbour.
+    fn move_right(&mut self);
+
+    /// Reverses the direction of the MP.
+    fn reverse(&mut self);
+
+    /// Returns approximately how many bytes the stored cells use, including their values.
+    ///
+    /// This counts every cell unless `track_usage` was called.
+    fn bytes_used(&self) -> usize;
+
+    /// Starts keeping a running count of the bytes used, so that `bytes_used` is cheap to call
+    /// after every op. Until then, changing cells does no accounting.
+    fn track_usage(&mut self);
+
+    /// Returns a short description of the position of the MP, such as `(0, 0, E)`.
+    fn position(&self) -> String;
+
+    /// Returns a one-line description of the MP, its neighbours and how much memory is in use.
+    fn summary(&self) -> String;
+
+    /// Writes the MP and every nonzero cell as lines of a save state.
+    fn write_state(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+
+    /// Restores the MP or a cell from the words of a line written by `write_state`.
+    ///
+    /// Returns `None` if the line isn't valid.
+    fn read_state_line(&mut self, words: &[&str]) -> Option<()>;
+
+    /// Returns and clears the first error that storing cells outside of RAM ran into since the last
+    /// call, which leaves the cells that couldn't be read back where they were.
+    fn take_error(&mut self) -> Option<io::Error> {
+        None
+    }
+}
+
+/// The memory of a running program, in any of the built-in models.
+///
+/// Calls are dispatched by matching on the model, so the standard hex model is called directly
+/// rather than through a trait object.
+#[derive(Clone)]
+pub enum AnyMemory {
+    Hex(HexMemory),
+    Tape(Tape),
+    Stack(Stack),
+    Spill(SpillMemory),
+}
+
+/// Calls a method on the memory of whichever model an `AnyMemory` holds.
+macro_rules! dispatch {
+    ($self:expr, $mem:ident => $call:expr) => {
+        match $self {
+            AnyMemory::Hex($mem) => $call,
+            AnyMemory::Tape($mem) => $call,
+            AnyMemory::Stack($mem) => $call,
+            AnyMemory::Spill($mem) => $call,
+        }
+    };
+}
+
+impl AnyMemory {
+    /// Returns a copy of this memory with every cell in RAM, as save states keep it.
+    pub fn snapshot(&self) -> Result<AnyMemory, Error> {
+        match self {
+            AnyMemory::Spill(spill) => Ok(AnyMemory::Hex(spill.to_hex()?)),
+            mem => Ok(mem.clone()),
+        }
+    }
+}
+
+impl Memory for AnyMemory {
+    fn model(&self) -> MemoryModel {
+        dispatch!(self, mem => mem.model())
+    }
+
+    fn get(&self) -> &Integer {
+        dispatch!(self, mem => mem.get())
+    }
+
+    fn get_left(&self) -> &Integer {
+        dispatch!(self, mem => mem.get_left())
+    }
+
+    fn get_right(&self) -> &Integer {
+        dispatch!(self, mem => mem.get_right())
+    }
+
+    fn set(&mut self, value: Integer) {
+        dispatch!(self, mem => mem.set(value))
+    }
+
+    fn get_mut(&mut self) -> &mut Integer {
+        dispatch!(self, mem => mem.get_mut())
+    }
+
+    fn update(&mut self, f: &mut dyn FnMut(&mut Integer, &Integer, &Integer)) {
+        dispatch!(self, mem => mem.update(f))
+    }
+
+    fn move_left(&mut self) {
+        dispatch!(self, mem => mem.move_left())
+    }
+
+    fn move_right(&mut self) {
+        dispatch!(self, mem => mem.move_right())
+    }
+
+    fn reverse(&mut self) {
+        dispatch!(self, mem => mem.reverse())
+    }
+
+    fn bytes_used(&self) -> usize {
+        dispatch!(self, mem => mem.bytes_used())
+    }
+
+    fn track_usage(&mut self) {
+        dispatch!(self, mem => mem.track_usage())
+    }
+
+    fn position(&self) -> String {
+        dispatch!(self, mem => mem.position())
+    }
+
+    fn summary(&self) -> String {
+        dispatch!(self, mem => mem.summary())
+    }
+
+    fn write_state(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        dispatch!(self, mem => mem.write_state(f))
+    }
+
+    fn read_state_line(&mut self, words: &[&str]) -> Option<()> {
+        dispatch!(self, mem => mem.read_state_line(words))
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        dispatch!(self, mem => mem.take_error())
+    }
+}
+
+impl fmt::Display for AnyMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        dispatch!(self, mem => mem.fmt(f))
+    }
+}
+
+/// Approximate bookkeeping of the bytes used by the cells of a memory.
+///
+/// A cell handed out by `get_mut` may still change size, so its old size stays pending until
+/// the cell is next set or the MP moves. Nothing is counted until `track` is called.
+#[derive(Clone, Copy, Default)]
+struct Usage {
+    tracked: bool,
+    bytes: usize,
+    pending: Option<usize>,
+}
+
+impl Usage {
+    /// Starts counting from `bytes`, the total used by every cell.
+    fn track(&mut self, bytes: usize) {
+        *self = Usage { tracked: true, bytes, pending: None };
+    }
+
+    /// Accounts for a cell of `old` bytes being replaced by one of `new` bytes.
+    fn replace(&mut self, old: usize, new: usize) {
+        if self.tracked {
+            self.bytes = self.bytes - old + new;
+        }
+    }
+
+    /// Accounts for the pending cell, which now uses `current` bytes.
+    fn settle(&mut self, current: usize) {
+        if let Some(old) = self.pending.take() {
+            self.replace(old, current);
+        }
+    }
+
+    /// Returns the total, given that the pending cell now uses `current` bytes.
+    fn total(&self, current: usize) -> usize {
+        match self.pending {
+            Some(old) => self.bytes - old + current,
+            None => self.bytes,
+        }
+    }
+}
+
+/// Returns the approximate number of bytes used by a stored cell of type `T` holding `value`,
+/// or 0 if the cell isn't stored.
+fn cell_bytes<T>(value: Option<&Integer>) -> usize {
+    value.map_or(0, |v| mem::size_of::<T>() + v.capacity() / 8)
 }
 
-/// Tuple of values used as the index of a memory edge.
-type Index = (isize, isize, Dir);
+/// The layout of memory used by a program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryModel {
+    /// The standard hexagonal grid of edges
+    Hex,
+    /// An infinite line of cells, whose neighbours are the cells on either side
+    Tape,
+    /// A stack whose top is the current cell and whose neighbours are the two values below it
+    Stack,
+}
+
+impl MemoryModel {
+    /// Returns the name of this model used in save states and on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            MemoryModel::Hex => "hex",
+            MemoryModel::Tape => "tape",
+            MemoryModel::Stack => "stack",
+        }
+    }
+
+    /// Returns the model with the given name.
+    pub fn from_name(name: &str) -> Option<MemoryModel> {
+        match name {
+            "hex" => Some(MemoryModel::Hex),
+            "tape" => Some(MemoryModel::Tape),
+            "stack" => Some(MemoryModel::Stack),
+            _ => None,
+        }
+    }
+
+    /// Creates an empty memory of this model.
+    pub fn create(self) -> AnyMemory {
+        match self {
+            MemoryModel::Hex => AnyMemory::Hex(HexMemory::new()),
+            MemoryModel::Tape => AnyMemory::Tape(Tape::new()),
+            MemoryModel::Stack => AnyMemory::Stack(Stack::new()),
+        }
+    }
+}
 
 /// A pointy-topped hexagonal grid that stores an integer in each edge.
 ///
 /// Edges are indexed by the axial coordinates of the westward adjacent hexagon,
 /// and a direction (NE, E, SE) to identify a specific edge of the hexagon.
-pub struct Memory {
+#[derive(Clone)]
+pub struct HexMemory {
     mem: HashMap<Index, Integer>,
-    mp: Index,
+    mp: Edge,
+    /// The key of `mp`, kept so that the current edge can be looked up without packing it
+    key: Index,
     rot: Rot,
     default: Integer,
+    usage: Usage,
 }
 
-impl Memory {
-    /// Creates an empty `Memory` instance.
-    pub fn new() -> Memory {
-        Memory {
+impl HexMemory {
+    /// Creates an empty `HexMemory` instance.
+    pub fn new() -> HexMemory {
+        HexMemory {
             mem: HashMap::new(),
             mp: (0, 0, Dir::E),
-            rot: Rot::CCW,
+            key: Index::new((0, 0, Dir::E)),
+            rot: Rot::Ccw,
             default: Integer::new(),
+            usage: Usage::default(),
+        }
+    }
+
+    /// Returns the number of bytes used by the current edge, or 0 while usage isn't tracked.
+    fn current_bytes(&self) -> usize {
+        match self.usage.tracked {
+            true => cell_bytes::<(Index, Integer)>(self.mem.get(&self.key)),
+            false => 0,
         }
     }
 
-    /// Returns the index of the left neighbour edge.
-    fn left_index(&self) -> (Index, Rot) {
+    /// Returns the number of bytes used by every edge.
+    fn all_bytes(&self) -> usize {
+        self.mem.values().map(|v| cell_bytes::<(Index, Integer)>(Some(v))).sum()
+    }
+
+    /// Returns the left neighbour edge and the rotation of the MP there.
+    fn left_edge(&self) -> (Edge, Rot) {
         let (q, r, e) = self.mp;
         match (e, self.rot) {
-            (Dir::NE, Rot::CCW) => ((q, r - 1, Dir::SE), Rot::CW),
-            (Dir::NE, Rot::CW) => ((q + 1, r - 1, Dir::SE), Rot::CCW),
-            (Dir::E, Rot::CCW) => ((q, r, Dir::NE), Rot::CCW),
-            (Dir::E, Rot::CW) => ((q, r + 1, Dir::NE), Rot::CW),
-            (Dir::SE, Rot::CCW) => ((q, r, Dir::E), Rot::CCW),
-            (Dir::SE, Rot::CW) => ((q - 1, r + 1, Dir::E), Rot::CW),
+            (Dir::NE, Rot::Ccw) => ((q, r - 1, Dir::SE), Rot::Cw),
+            (Dir::NE, Rot::Cw) => ((q + 1, r - 1, Dir::SE), Rot::Ccw),
+            (Dir::E, Rot::Ccw) => ((q, r, Dir::NE), Rot::Ccw),
+            (Dir::E, Rot::Cw) => ((q, r + 1, Dir::NE), Rot::Cw),
+            (Dir::SE, Rot::Ccw) => ((q, r, Dir::E), Rot::Ccw),
+            (Dir::SE, Rot::Cw) => ((q - 1, r + 1, Dir::E), Rot::Cw),
         }
     }
 
-    /// Returns the index of the right neighbour edge.
-    fn right_index(&self) -> (Index, Rot) {
+    /// Returns the right neighbour edge and the rotation of the MP there.
+    fn right_edge(&self) -> (Edge, Rot) {
         let (q, r, e) = self.mp;
         match (e, self.rot) {
-            (Dir::NE, Rot::CCW) => ((q, r - 1, Dir::E), Rot::CCW),
-            (Dir::NE, Rot::CW) => ((q, r, Dir::E), Rot::CW),
-            (Dir::E, Rot::CCW) => ((q + 1, r - 1, Dir::SE), Rot::CCW),
-            (Dir::E, Rot::CW) => ((q, r, Dir::SE), Rot::CW),
-            (Dir::SE, Rot::CCW) => ((q, r + 1, Dir::NE), Rot::CW),
-            (Dir::SE, Rot::CW) => ((q - 1, r + 1, Dir::NE), Rot::CCW),
+            (Dir::NE, Rot::Ccw) => ((q, r - 1, Dir::E), Rot::Ccw),
+            (Dir::NE, Rot::Cw) => ((q, r, Dir::E), Rot::Cw),
+            (Dir::E, Rot::Ccw) => ((q + 1, r - 1, Dir::SE), Rot::Ccw),
+            (Dir::E, Rot::Cw) => ((q, r, Dir::SE), Rot::Cw),
+            (Dir::SE, Rot::Ccw) => ((q, r + 1, Dir::NE), Rot::Cw),
+            (Dir::SE, Rot::Cw) => ((q - 1, r + 1, Dir::NE), Rot::Ccw),
         }
     }
+}
 
-    /// Returns a reference to the value in the left neighbour.
-    pub fn get_left(&self) -> &Integer {
-        self.mem.get(&self.left_index().0).unwrap_or(&self.default)
+impl HexMemory {
+    /// Moves the MP to an edge, keeping its rotation.
+    fn move_to(&mut self, mp: Edge) {
+        self.mp = mp;
+        self.key = Index::new(mp);
     }
+}
 
-    /// Returns a reference to the value in the right neighbour.
-    pub fn get_right(&self) -> &Integer {
-        self.mem.get(&self.right_index().0).unwrap_or(&self.default)
+impl Default for HexMemory {
+    fn default() -> HexMemory {
+        HexMemory::new()
     }
+}
 
-    /// Returns a reference to the value in the current memory edge.
-    pub fn get(&self) -> &Integer {
-        self.mem.get(&self.mp).unwrap_or(&self.default)
+impl Memory for HexMemory {
+    fn model(&self) -> MemoryModel {
+        MemoryModel::Hex
     }
 
-    /// Sets the current memory edge to the given value.
-    pub fn set(&mut self, value: Integer) {
-        self.mem.insert(self.mp, value);
+    fn get_left(&self) -> &Integer {
+        self.mem.get(&Index::new(self.left_edge().0)).unwrap_or(&self.default)
     }
 
-    /// Returns a mutable reference to the value in the current memory edge.
-    pub fn get_mut(&mut self) -> &mut Integer {
-        self.mem.entry(self.mp).or_default()
+    fn get_right(&self) -> &Integer {
+        self.mem.get(&Index::new(self.right_edge().0)).unwrap_or(&self.default)
     }
 
-    /// Moves the MP to the left neighbour.
-    pub fn move_left(&mut self) {
-        let (mp, rot) = self.left_index();
-        self.mp = mp;
+    fn get(&self) -> &Integer {
+        self.mem.get(&self.key).unwrap_or(&self.default)
+    }
+
+    fn set(&mut self, value: Integer) {
+        self.usage.settle(self.current_bytes());
+        let old = self.current_bytes();
+        self.mem.insert(self.key, value);
+        self.usage.replace(old, self.current_bytes());
+    }
+
+    fn get_mut(&mut self) -> &mut Integer {
+        self.usage.settle(self.current_bytes());
+        self.usage.pending = Some(self.current_bytes());
+        self.mem.entry(self.key).or_default()
+    }
+
+    fn update(&mut self, f: &mut dyn FnMut(&mut Integer, &Integer, &Integer)) {
+        self.usage.settle(self.current_bytes());
+        let old = self.current_bytes();
+        // The neighbours are always other edges, so the current one can be taken out while they're borrowed
+        let mut value = self.mem.remove(&self.key).unwrap_or_default();
+        f(&mut value, self.get_left(), self.get_right());
+        self.mem.insert(self.key, value);
+        self.usage.replace(old, self.current_bytes());
+    }
+
+    fn move_left(&mut self) {
+        self.usage.settle(self.current_bytes());
+        let (mp, rot) = self.left_edge();
+        self.move_to(mp);
         self.rot = rot;
     }
 
-    /// Moves the MP to the right neighbour.
-    pub fn move_right(&mut self) {
-        let (mp, rot) = self.right_index();
-        self.mp = mp;
+    fn move_right(&mut self) {
+        self.usage.settle(self.current_bytes());
+        let (mp, rot) = self.right_edge();
+        self.move_to(mp);
         self.rot = rot;
     }
 
-    /// Reverses the direction of the MP.
-    pub fn reverse(&mut self) {
+    fn reverse(&mut self) {
         self.rot = match self.rot {
-            Rot::CW => Rot::CCW,
-            Rot::CCW => Rot::CW,
+            Rot::Cw => Rot::Ccw,
+            Rot::Ccw => Rot::Cw,
         };
     }
+
+    fn bytes_used(&self) -> usize {
+        match self.usage.tracked {
+            true => self.usage.total(self.current_bytes()),
+            false => self.all_bytes(),
+        }
+    }
+
+    fn track_usage(&mut self) {
+        if !self.usage.tracked {
+            self.usage.track(self.all_bytes());
+        }
+    }
+
+    fn position(&self) -> String {
+        let (q, r, d) = self.mp;
+        format!("({}, {}, {})", q, r, d)
+    }
+
+    fn summary(&self) -> String {
+        let (q, r, d) = self.mp;
+        let nonzero = self.mem.values().filter(|v| **v != 0).count();
+        format!(
+            "MP at ({}, {}, {}) = {} (left {}, right {}), {} nonzero edges",
+            q, r, d, self.get(), self.get_left(), self.get_right(), nonzero,
+        )
+    }
+
+    fn write_state(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (q, r, d) = self.mp;
+        let rot = match self.rot {
+            Rot::Cw => "cw",
+            Rot::Ccw => "ccw",
+        };
+        writeln!(f, "mp {} {} {} {}", q, r, d, rot)?;
+        let mut edges: Vec<_> = self.mem.iter().filter(|(_, v)| **v != 0).collect();
+        edges.sort_unstable_by_key(|&(index, _)| {
+            let (q, r, d) = index.unpack();
+            (r, q, d)
+        });
+        for (index, v) in edges {
+            let (q, r, d) = index.unpack();
+            writeln!(f, "edge {} {} {} {}", q, r, d, v)?;
+        }
+        Ok(())
+    }
+
+    fn read_state_line(&mut self, words: &[&str]) -> Option<()> {
+        let dir = |s: &str| match s {
+            "NE" => Some(Dir::NE),
+            "E" => Some(Dir::E),
+            "SE" => Some(Dir::SE),
+            _ => None,
+        };
+        match words {
+            ["mp", q, r, d, rot] => {
+                self.usage.settle(self.current_bytes());
+                let (q, r): (isize, isize) = (q.parse().ok()?, r.parse().ok()?);
+                // The neighbours of the MP have to be addressable too
+                let inner = isize::MIN + 1..isize::MAX;
+                if !inner.contains(&q) || !inner.contains(&r) {
+                    return None;
+                }
+                self.move_to((q, r, dir(d)?));
+                self.rot = match *rot {
+                    "cw" => Rot::Cw,
+                    "ccw" => Rot::Ccw,
+                    _ => return None,
+                };
+            }
+            ["edge", q, r, d, v] => {
+                let index = Index::new((q.parse().ok()?, r.parse().ok()?, dir(d)?));
+                let old = cell_bytes::<(Index, Integer)>(self.mem.get(&index));
+                self.mem.insert(index, v.parse().ok()?);
+                self.usage.replace(old, cell_bytes::<(Index, Integer)>(self.mem.get(&index)));
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+/// An infinite line of cells indexed by integers.
+///
+/// The MP faces forwards or backwards along the line. Its left neighbour is the cell behind it
+/// and its right neighbour is the cell ahead of it.
+#[derive(Clone)]
+pub struct Tape {
+    cells: HashMap<isize, Integer>,
+    mp: isize,
+    forwards: bool,
+    default: Integer,
+    usage: Usage,
+}
+
+impl Tape {
+    /// Creates an empty `Tape` instance.
+    pub fn new() -> Tape {
+        Tape {
+            cells: HashMap::new(),
+            mp: 0,
+            forwards: true,
+            default: Integer::new(),
+            usage: Usage::default(),
+        }
+    }
+
+    /// Returns the number of bytes used by the current cell, or 0 while usage isn't tracked.
+    fn current_bytes(&self) -> usize {
+        match self.usage.tracked {
+            true => cell_bytes::<(isize, Integer)>(self.cells.get(&self.mp)),
+            false => 0,
+        }
+    }
+
+    /// Returns the number of bytes used by every cell.
+    fn all_bytes(&self) -> usize {
+        self.cells.values().map(|v| cell_bytes::<(isize, Integer)>(Some(v))).sum()
+    }
+
+    /// Returns the offset of the cell ahead of the MP.
+    fn ahead(&self) -> isize {
+        if self.forwards { 1 } else { -1 }
+    }
+}
+
+impl Default for Tape {
+    fn default() -> Tape {
+        Tape::new()
+    }
+}
+
+impl Memory for Tape {
+    fn model(&self) -> MemoryModel {
+        MemoryModel::Tape
+    }
+
+    fn get(&self) -> &Integer {
+        self.cells.get(&self.mp).unwrap_or(&self.default)
+    }
+
+    fn get_left(&self) -> &Integer {
+        self.cells.get(&(self.mp - self.ahead())).unwrap_or(&self.default)
+    }
+
+    fn get_right(&self) -> &Integer {
+        self.cells.get(&(self.mp + self.ahead())).unwrap_or(&self.default)
+    }
+
+    fn set(&mut self, value: Integer) {
+        self.usage.settle(self.current_bytes());
+        let old = self.current_bytes();
+        self.cells.insert(self.mp, value);
+        self.usage.replace(old, self.current_bytes());
+    }
+
+    fn get_mut(&mut self) -> &mut Integer {
+        self.usage.settle(self.current_bytes());
+        self.usage.pending = Some(self.current_bytes());
+        self.cells.entry(self.mp).or_default()
+    }
+
+    fn update(&mut self, f: &mut dyn FnMut(&mut Integer, &Integer, &Integer)) {
+        self.usage.settle(self.current_bytes());
+        let old = self.current_bytes();
+        let mut value = self.cells.remove(&self.mp).unwrap_or_default();
+        f(&mut value, self.get_left(), self.get_right());
+        self.cells.insert(self.mp, value);
+        self.usage.replace(old, self.current_bytes());
+    }
+
+    fn move_left(&mut self) {
+        self.usage.settle(self.current_bytes());
+        self.mp -= self.ahead();
+    }
+
+    fn move_right(&mut self) {
+        self.usage.settle(self.current_bytes());
+        self.mp += self.ahead();
+    }
+
+    fn reverse(&mut self) {
+        self.forwards = !self.forwards;
+    }
+
+    fn bytes_used(&self) -> usize {
+        match self.usage.tracked {
+            true => self.usage.total(self.current_bytes()),
+            false => self.all_bytes(),
+        }
+    }
+
+    fn track_usage(&mut self) {
+        if !self.usage.tracked {
+            self.usage.track(self.all_bytes());
+        }
+    }
+
+    fn position(&self) -> String {
+        format!("cell {}", self.mp)
+    }
+
+    fn summary(&self) -> String {
+        let nonzero = self.cells.values().filter(|v| **v != 0).count();
+        format!(
+            "MP at cell {} = {} (left {}, right {}), {} nonzero cells",
+            self.mp, self.get(), self.get_left(), self.get_right(), nonzero,
+        )
+    }
+
+    fn write_state(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "mp {} {}", self.mp, if self.forwards { "forwards" } else { "backwards" })?;
+        let mut cells: Vec<_> = self.cells.iter().filter(|(_, v)| **v != 0).collect();
+        cells.sort_unstable_by_key(|&(&i, _)| i);
+        for (i, v) in cells {
+            writeln!(f, "cell {} {}", i, v)?;
+        }
+        Ok(())
+    }
+
+    fn read_state_line(&mut self, words: &[&str]) -> Option<()> {
+        match words {
+            ["mp", i, dir] => {
+                self.usage.settle(self.current_bytes());
+                self.mp = i.parse().ok()?;
+                self.forwards = match *dir {
+                    "forwards" => true,
+                    "backwards" => false,
+                    _ => return None,
+                };
+            }
+            ["cell", i, v] => {
+                let i = i.parse().ok()?;
+                let old = cell_bytes::<(isize, Integer)>(self.cells.get(&i));
+                self.cells.insert(i, v.parse().ok()?);
+                self.usage.replace(old, cell_bytes::<(isize, Integer)>(self.cells.get(&i)));
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+/// A stack of cells whose top is the current cell, with zeros below the bottom.
+///
+/// The left and right neighbours are the second and third values from the top, swapped while
+/// the MP is reversed. Moving right pushes a 0 and moving left pops the top.
+#[derive(Clone)]
+pub struct Stack {
+    values: Vec<Integer>,
+    reversed: bool,
+    default: Integer,
+    usage: Usage,
+}
+
+impl Stack {
+    /// Creates an empty `Stack` instance.
+    pub fn new() -> Stack {
+        Stack {
+            values: Vec::new(),
+            reversed: false,
+            default: Integer::new(),
+            usage: Usage::default(),
+        }
+    }
+
+    /// Returns the number of bytes used by the top of the stack, or 0 while usage isn't tracked.
+    fn current_bytes(&self) -> usize {
+        match self.usage.tracked {
+            true => cell_bytes::<Integer>(self.values.last()),
+            false => 0,
+        }
+    }
+
+    /// Returns the number of bytes used by every value on the stack.
+    fn all_bytes(&self) -> usize {
+        self.values.iter().map(|v| cell_bytes::<Integer>(Some(v))).sum()
+    }
+
+    /// Returns a reference to the value `depth` values below the top.
+    fn below(&self, depth: usize) -> &Integer {
+        match self.values.len().checked_sub(depth + 1) {
+            Some(i) => &self.values[i],
+            None => &self.default,
+        }
+    }
+}
+
+impl Default for Stack {
+    fn default() -> Stack {
+        Stack::new()
+    }
+}
+
+impl Memory for Stack {
+    fn model(&self) -> MemoryModel {
+        MemoryModel::Stack
+    }
+
+    fn get(&self) -> &Integer {
+        self.below(0)
+    }
+
+    fn get_left(&self) -> &Integer {
+        self.below(if self.reversed { 2 } else { 1 })
+    }
+
+    fn get_right(&self) -> &Integer {
+        self.below(if self.reversed { 1 } else { 2 })
+    }
+
+    fn set(&mut self, value: Integer) {
+        *self.get_mut() = value;
+    }
+
+    fn get_mut(&mut self) -> &mut Integer {
+        self.usage.settle(self.current_bytes());
+        self.usage.pending = Some(self.current_bytes());
+        if self.values.is_empty() {
+            self.values.push(Integer::new());
+        }
+        self.values.last_mut().unwrap()
+    }
+
+    fn update(&mut self, f: &mut dyn FnMut(&mut Integer, &Integer, &Integer)) {
+        self.get_mut();
+        let default = &self.default;
+        let (top, rest) = self.values.split_last_mut().unwrap();
+        let below = |depth: usize| rest.len().checked_sub(depth).map_or(default, |i| &rest[i]);
+        match self.reversed {
+            true => f(top, below(2), below(1)),
+            false => f(top, below(1), below(2)),
+        }
+    }
+
+    fn move_left(&mut self) {
+        self.usage.settle(self.current_bytes());
+        self.usage.replace(self.current_bytes(), 0);
+        self.values.pop();
+    }
+
+    fn move_right(&mut self) {
+        self.usage.settle(self.current_bytes());
+        self.values.push(Integer::new());
+        self.usage.replace(0, self.current_bytes());
+    }
+
+    fn reverse(&mut self) {
+        self.reversed = !self.reversed;
+    }
+
+    fn bytes_used(&self) -> usize {
+        match self.usage.tracked {
+            true => self.usage.total(self.current_bytes()),
+            false => self.all_bytes(),
+        }
+    }
+
+    fn track_usage(&mut self) {
+        if !self.usage.tracked {
+            self.usage.track(self.all_bytes());
+        }
+    }
+
+    fn position(&self) -> String {
+        format!("depth {}", self.values.len().max(1))
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "top = {} (left {}, right {}), {} values on the stack",
+            self.get(), self.get_left(), self.get_right(), self.values.len(),
+        )
+    }
+
+    fn write_state(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "mp {}", if self.reversed { "reversed" } else { "normal" })?;
+        for v in &self.values {
+            writeln!(f, "push {}", v)?;
+        }
+        Ok(())
+    }
+
+    fn read_state_line(&mut self, words: &[&str]) -> Option<()> {
+        match words {
+            ["mp", "normal"] => self.reversed = false,
+            ["mp", "reversed"] => self.reversed = true,
+            ["push", v] => {
+                self.usage.settle(self.current_bytes());
+                self.values.push(v.parse().ok()?);
+                self.usage.replace(0, self.current_bytes());
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+/// A hexagonal memory that keeps only the most recently used edges in RAM and spills the rest
+/// to a temporary file, so programs that touch huge amounts of memory can finish.
+///
+/// The current edge and its neighbours are always kept in RAM. Once more than `hot_limit` edges are,
+/// the least recently used half of them are written to the file and read back when the MP reaches them.
+/// An edge that is spilled again goes back to the same place in the file if it still fits there, so
+/// the file only grows with the number of distinct edges spilled and the size of their values. It's
+/// deleted when the memory is dropped (or right away on Unix).
+///
+/// Errors reading or writing the file are reported by `Memory::take_error` after the move that
+/// caused them, and save states are snapshots with every edge read back into RAM.
+pub struct SpillMemory {
+    hot: HexMemory,
+    hot_limit: usize,
+    last_used: HashMap<Index, u64>,
+    clock: u64,
+    /// The length of the value of every edge in the file
+    cold: HashMap<Index, usize>,
+    /// The offset and capacity of the place in the file of every edge that was ever spilled
+    slots: HashMap<Index, (u64, usize)>,
+    file: Rc<SpillFile>,
+    error: Option<io::Error>,
+}
+
+/// The file that edges are spilled to, which copies of a `SpillMemory` share.
+struct SpillFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl SpillMemory {
+    /// Creates an empty `SpillMemory` that keeps about `hot_limit` edges in RAM, spilling to a new
+    /// file in the temporary directory.
+    pub fn new(hot_limit: usize) -> io::Result<SpillMemory> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos());
+        let path = env::temp_dir().join(format!("hexagony-spill-{}-{}", process::id(), nanos));
+        let file = fs::OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        // An open file stays usable after it's unlinked, so it can't be left behind if the process exits early
+        #[cfg(unix)]
+        fs::remove_file(&path)?;
+        Ok(SpillMemory {
+            hot: HexMemory::new(),
+            hot_limit: hot_limit.max(3),
+            last_used: HashMap::new(),
+            clock: 0,
+            cold: HashMap::new(),
+            slots: HashMap::new(),
+            file: Rc::new(SpillFile { file, path }),
+            error: None,
+        })
+    }
+
+    /// Returns the current edge and its neighbours, which must stay in RAM.
+    fn protected(&self) -> [Index; 3] {
+        [self.hot.key, Index::new(self.hot.left_edge().0), Index::new(self.hot.right_edge().0)]
+    }
+
+    /// Reads the current edge and its neighbours back into RAM if they were spilled,
+    /// then spills edges if too many are in RAM, keeping the first error for `take_error`.
+    fn fault_in(&mut self) {
+        if let Err(e) = self.try_fault_in() {
+            self.error.get_or_insert(e);
+        }
+    }
+
+    fn try_fault_in(&mut self) -> io::Result<()> {
+        self.hot.usage.settle(self.hot.current_bytes());
+        self.clock += 1;
+        for index in self.protected() {
+            if let Some(&len) = self.cold.get(&index) {
+                let value = self.read_cold(self.slots[&index].0, len)?;
+                self.cold.remove(&index);
+                let bytes = cell_bytes::<(Index, Integer)>(Some(&value));
+                self.hot.mem.insert(index, value);
+                self.hot.usage.replace(0, bytes);
+            }
+            self.last_used.insert(index, self.clock);
+        }
+        if self.hot.mem.len() > self.hot_limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Moves the least recently used half of the edges in RAM to the file.
+    fn spill(&mut self) -> io::Result<()> {
+        let protected: HashSet<_> = IntoIterator::into_iter(self.protected()).collect();
+        let mut victims: Vec<_> = self.hot.mem.keys().filter(|index| !protected.contains(index)).copied().collect();
+        victims.sort_unstable_by_key(|index| self.last_used.get(index).copied().unwrap_or(0));
+        victims.truncate(self.hot.mem.len() - self.hot_limit / 2);
+        // Places in the file may only be reused while no copy of the memory can still be reading them
+        let reuse = Rc::strong_count(&self.file) == 1;
+        let end = (&self.file.file).seek(SeekFrom::End(0))?;
+        let mut appended = Vec::new();
+        for index in victims {
+            // Zero is the default, so it doesn't need to be stored
+            if self.hot.mem[&index] != 0 {
+                let value = &self.hot.mem[&index];
+                let mut buf = vec![(*value < 0) as u8];
+                buf.extend(value.to_digits::<u8>(Order::Lsf));
+                match self.slots.get(&index) {
+                    Some(&(offset, capacity)) if reuse && buf.len() <= capacity => {
+                        (&self.file.file).seek(SeekFrom::Start(offset))?;
+                        (&self.file.file).write_all(&buf)?;
+                    }
+                    _ => {
+                        self.slots.insert(index, (end + appended.len() as u64, buf.len()));
+                        appended.extend_from_slice(&buf);
+                    }
+                }
+                self.cold.insert(index, buf.len());
+            }
+            let value = self.hot.mem.remove(&index).unwrap();
+            self.last_used.remove(&index);
+            self.hot.usage.replace(cell_bytes::<(Index, Integer)>(Some(&value)), 0);
+        }
+        (&self.file.file).seek(SeekFrom::Start(end))?;
+        (&self.file.file).write_all(&appended)
+    }
+
+    /// Returns the length of the file that edges are spilled to.
+    #[cfg(test)]
+    pub(crate) fn file_len(&self) -> u64 {
+        self.file.file.metadata().map_or(0, |m| m.len())
+    }
+
+    /// Reads a value written by `spill`.
+    fn read_cold(&self, offset: u64, len: usize) -> io::Result<Integer> {
+        let mut buf = vec![0; len];
+        (&self.file.file).seek(SeekFrom::Start(offset))?;
+        (&self.file.file).read_exact(&mut buf)?;
+        let value = Integer::from_digits(&buf[1..], Order::Lsf);
+        Ok(if buf[0] == 1 { -value } else { value })
+    }
+
+    /// Returns a copy of the memory with every edge in RAM.
+    fn to_hex(&self) -> io::Result<HexMemory> {
+        let mut hex = self.hot.clone();
+        for (&index, &len) in &self.cold {
+            let value = self.read_cold(self.slots[&index].0, len)?;
+            hex.usage.replace(0, cell_bytes::<(Index, Integer)>(Some(&value)));
+            hex.mem.insert(index, value);
+        }
+        Ok(hex)
+    }
+}
+
+impl Memory for SpillMemory {
+    fn model(&self) -> MemoryModel {
+        MemoryModel::Hex
+    }
+
+    fn get(&self) -> &Integer {
+        self.hot.get()
+    }
+
+    fn get_left(&self) -> &Integer {
+        self.hot.get_left()
+    }
+
+    fn get_right(&self) -> &Integer {
+        self.hot.get_right()
+    }
+
+    fn set(&mut self, value: Integer) {
+        self.hot.set(value);
+    }
+
+    fn get_mut(&mut self) -> &mut Integer {
+        self.hot.get_mut()
+    }
+
+    fn update(&mut self, f: &mut dyn FnMut(&mut Integer, &Integer, &Integer)) {
+        self.hot.update(f)
+    }
+
+    fn move_left(&mut self) {
+        self.hot.move_left();
+        self.fault_in();
+    }
+
+    fn move_right(&mut self) {
+        self.hot.move_right();
+        self.fault_in();
+    }
+
+    fn reverse(&mut self) {
+        self.hot.reverse();
+        self.fault_in();
+    }
+
+    fn bytes_used(&self) -> usize {
+        self.hot.bytes_used()
+    }
+
+    fn track_usage(&mut self) {
+        self.hot.track_usage();
+    }
+
+    fn position(&self) -> String {
+        self.hot.position()
+    }
+
+    fn summary(&self) -> String {
+        format!("{}, {} edges spilled to disk", self.hot.summary(), self.cold.len())
+    }
+
+    fn write_state(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.to_hex().map_err(|_| fmt::Error)?.write_state(f)
+    }
+
+    fn read_state_line(&mut self, words: &[&str]) -> Option<()> {
+        self.hot.read_state_line(words)?;
+        self.try_fault_in().ok()
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
+
+impl Clone for SpillMemory {
+    /// Copies the memory, sharing the file that its spilled edges are in.
+    fn clone(&self) -> Self {
+        SpillMemory {
+            hot: self.hot.clone(),
+            hot_limit: self.hot_limit,
+            last_used: self.last_used.clone(),
+            clock: self.clock,
+            cold: self.cold.clone(),
+            slots: self.slots.clone(),
+            file: Rc::clone(&self.file),
+            error: None,
+        }
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
 }
 
 impl fmt::Display for Dir {
@@ -126,11 +1121,118 @@ impl fmt::Display for Dir {
     }
 }
 
-impl fmt::Display for Memory {
+impl fmt::Display for HexMemory {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for ((q, r, d), v) in &self.mem {
+        for (index, v) in &self.mem {
+            let (q, r, d) = index.unpack();
             writeln!(f, "({}, {}, {}): {}", q, r, d, v)?;
         }
         Ok(())
     }
 }
+
+impl fmt::Display for Tape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, v) in &self.cells {
+            writeln!(f, "{}: {}", i, v)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Stack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for v in self.values.iter().rev() {
+            writeln!(f, "{}", v)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SpillMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.hot)?;
+        for (&index, &len) in &self.cold {
+            let (q, r, d) = index.unpack();
+            match self.read_cold(self.slots[&index].0, len) {
+                Ok(value) => writeln!(f, "({}, {}, {}): {}", q, r, d, value)?,
+                Err(e) => writeln!(f, "({}, {}, {}): unreadable ({})", q, r, d, e)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Builder, examples};
+
+    #[test]
+    fn memory_usage_is_only_counted_under_a_limit() {
+        let mut counted = HexMemory::new();
+        counted.track_usage();
+        let mut uncounted = HexMemory::new();
+        for i in 0..60u32 {
+            for mem in [&mut counted, &mut uncounted].iter_mut() {
+                mem.set(Integer::from(i) << (i * 10));
+                *mem.get_mut() *= 3;
+                mem.move_right();
+                if i % 7 == 0 {
+                    mem.move_left();
+                }
+            }
+            if i == 30 {
+                uncounted.track_usage();
+            }
+            assert_eq!(counted.bytes_used(), uncounted.bytes_used());
+        }
+        let result = Builder::new("){}").output(io::sink()).memory_limit(1000).build().unwrap().run();
+        assert!(matches!(result, Err(Error::MemoryLimitExceeded(1000))));
+    }
+
+    #[test]
+    fn hex_memory_reaches_edges_past_the_packed_range() {
+        let mut mem = HexMemory::new();
+        mem.read_state_line(&["mp", "0", "-1073741820", "E", "ccw"]).unwrap();
+        mem.read_state_line(&["edge", "0", "-1073741830", "E", "7"]).unwrap();
+        // Like running `{}`, which moves one edge further north each time
+        for _ in 0..10 {
+            mem.move_left();
+            mem.move_right();
+        }
+        assert_eq!(mem.position(), "(0, -1073741830, E)");
+        assert_eq!(*mem.get(), 7);
+    }
+
+    #[test]
+    fn spilled_memory_reuses_its_file_and_rejects_other_models() {
+        let mut mem = SpillMemory::new(3).unwrap();
+        let mut file_len = 0;
+        for round in 0..20 {
+            // Moving right six times walks around a hexagon of edges, more than fit in RAM
+            for _ in 0..6 {
+                mem.set(Integer::from(round + 1000));
+                mem.move_right();
+            }
+            assert!(mem.take_error().is_none());
+            let len = mem.file_len();
+            assert!(round < 2 || len == file_len, "the spill file grew from {} to {} bytes", file_len, len);
+            file_len = len;
+        }
+        assert!(file_len > 0);
+        let sorted = |s: String| { let mut lines: Vec<_> = s.lines().map(String::from).collect(); lines.sort(); lines };
+        assert_eq!(sorted(AnyMemory::Spill(mem.clone()).snapshot().unwrap().to_string()), sorted(mem.to_string()));
+        let spill = |builder: Builder<'static>| builder.output(io::sink()).spill_memory(10).build().map(|_| ());
+        assert!(matches!(spill(Builder::new("@").memory_model(MemoryModel::Tape)), Err(Error::IncompatibleSettings(_))));
+        let state = Builder::new("@").output(io::sink()).build().unwrap().save_state().unwrap();
+        assert!(matches!(spill(Builder::resume(state)), Err(Error::IncompatibleSettings(_))));
+        let primes = examples::find("primes").unwrap().source();
+        for (input, expected) in &[("97", "1"), ("91", "0")] {
+            let mut output = Vec::new();
+            let hex = Builder::new(primes).input(input.as_bytes()).output(&mut output).spill_memory(3);
+            hex.build().unwrap().run().unwrap();
+            assert_eq!(output, expected.as_bytes());
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use crate::cases::TestCase;
+
+/// Greedily shrinks a program while it still passes every test case within `max_ticks` ticks.
+///
+/// Tries truncating the program to fit smaller hexagons, removing single ops and replacing
+/// single ops with no-ops, repeating until none of these changes succeed. Debug flags
+/// and whitespace are discarded.
+///
+/// Returns the minimized source code, or `None` if the original program fails a test case.
+pub fn minimize(src: &str, cases: &[TestCase], max_ticks: u64) -> Option<String> {
+    let passes = |ops: &[char]| {
+        let src: String = ops.iter().collect();
+        cases.iter().all(|case| case.check(&src, max_ticks))
+    };
+    let mut ops: Vec<char> = src.chars().filter(|&c| !c.is_whitespace() && c != '`').collect();
+    if !passes(&ops) {
+        return None;
+    }
+    loop {
+        let mut improved = false;
+        // Truncate to the capacity of each smaller hexagon
+        let mut capacities = (1..).map(|n| 3 * n * (n - 1) + 1).take_while(|&len| len < ops.len());
+        if let Some(len) = capacities.find(|&len| passes(&ops[..len])) {
+            ops.truncate(len);
+            improved = true;
+        }
+        // Remove single ops
+        let mut i = 0;
+        while i < ops.len() {
+            let mut candidate = ops.clone();
+            candidate.remove(i);
+            if passes(&candidate) {
+                ops = candidate;
+                improved = true;
+            } else {
+                i += 1;
+            }
+        }
+        // Replace single ops with no-ops
+        for i in 0..ops.len() {
+            if ops[i] == '.' {
+                continue;
+            }
+            let mut candidate = ops.clone();
+            candidate[i] = '.';
+            if passes(&candidate) {
+                ops = candidate;
+                improved = true;
+            }
+        }
+        if !improved {
+            return Some(ops.into_iter().collect());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minimizing_keeps_a_passing_program() {
+        let cases = [TestCase { name: String::new(), input: Vec::new(), output: b"1".to_vec() }];
+        assert_eq!(minimize(" 1 !\n`@ ; .\n . .", &cases, 100).as_deref(), Some("1!@"));
+        assert_eq!(minimize("1!@", &cases, 100).as_deref(), Some("1!@"));
+        assert_eq!(minimize("2!@", &cases, 100), None);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{Builder, Error, IP, Wrapping, analysis::reachable_states, compile::step, direction::Axis, grid::{Grid, Op}};
+
+/// A program reflected by `mirror`, with the IPs to run it from.
+pub struct Mirrored {
+    pub grid: Grid,
+    /// Mirror images of the original program's IPs, in the same order, to run the program from with
+    /// `Builder::ip_starts`
+    pub starts: Vec<IP>,
+}
+
+/// Reflects a program across an axis through the center of its grid, so that it behaves like the original when its
+/// IPs start from the mirror images of the original ones.
+///
+/// The reflected program works on the mirror image of the original memory, so the commands that move the MP to the
+/// left and right swap places along with the mirrors and branches, as described by `Op::reflect`. Branches and
+/// corners still turn right when the current edge is positive, which is the other way in the mirror image, and
+/// `-`, `:`, `%`, `&` and `^` treat the left and right neighbours differently, so a program is refused with
+/// `Error::Unmirrorable` if an IP can reach any of them, assuming every memory-dependent choice can go either way.
+/// Use `Builder::mirror` to follow another wrapping rule or other IPs.
+pub fn mirror(src: &str, axis: Axis) -> Result<Mirrored, Error> {
+    Builder::new(src).mirror(axis)
+}
+
+/// Reflects a program, as described by `mirror`, whose IPs follow the given wrapping rule from `starts`.
+pub(crate) fn mirror_grid(grid: &Grid, wrapping: Wrapping, starts: &[IP], axis: Axis) -> Result<Mirrored, Error> {
+    let reflected = grid.reflect(axis);
+    for (_, coords, dir) in reachable_states(grid, wrapping, starts) {
+        let op = grid.get(coords).0;
+        if let Op::Subtract | Op::Divide | Op::Modulo | Op::MPBranch | Op::MemCopy | Op::Custom(_) = op {
+            let reason = match op {
+                Op::Custom(_) => format!("custom commands like `{}` can't be reflected", op),
+                _ => format!("`{}` treats its left and right neighbours differently", op),
+            };
+            return Err(Error::Unmirrorable(coords, reason));
+        }
+        for &positive in &[false, true] {
+            let (next, next_dir) = step(grid, wrapping, (coords, dir), positive);
+            if step(&reflected, wrapping, (coords.reflect(axis), dir.reflect(axis)), positive)
+                != (next.reflect(axis), next_dir.reflect(axis))
+            {
+                let reason = format!("an IP moving {} turns on the sign of the current edge", dir);
+                return Err(Error::Unmirrorable(coords, reason));
+            }
+        }
+    }
+    let starts = starts.iter().map(|ip| IP { coords: ip.coords.reflect(axis), dir: ip.dir.reflect(axis) }).collect();
+    Ok(Mirrored { grid: reflected, starts })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{coords::PointAxial, examples};
+
+    /// Runs a program with the given input, from the given IPs or the usual ones.
+    fn run(src: &str, input: &str, starts: Option<Vec<IP>>) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut builder = Builder::new(src).input(Cursor::new(input.to_string())).output(&mut output).max_ticks(10_000);
+        if let Some(starts) = starts {
+            builder = builder.ip_starts(move |_| starts.clone());
+        }
+        builder.build().unwrap().run().unwrap();
+        output
+    }
+
+    #[test]
+    fn mirrored_programs_behave_like_the_originals() {
+        let programs = ["H;e;l;l;o;@", "A;}B;{;\"';@", "5}9{*)\"'!@", "\"5'}7{+!@", "1]!@.;.", "]!;@.."];
+        for &axis in &[Axis::Horizontal, Axis::Vertical] {
+            for src in &programs {
+                let mirrored = mirror(src, axis).unwrap();
+                assert_eq!(run(&mirrored.grid.to_string(), "", Some(mirrored.starts)), run(src, "", None), "{} across {:?}", src, axis);
+            }
+            // The second IP starts from the mirror image of its usual corner
+            let mirrored = mirror("]!;@..", axis).unwrap();
+            assert_ne!(run(&mirrored.grid.to_string(), "", None), b"00");
+            for example in examples::examples() {
+                match mirror(example.source(), axis) {
+                    Ok(mirrored) => {
+                        let output = run(&mirrored.grid.to_string(), example.sample_input, Some(mirrored.starts));
+                        assert_eq!(output, example.sample_output.as_bytes(), "{} across {:?}", example.name, axis);
+                    }
+                    Err(e) => assert!(matches!(e, Error::Unmirrorable(..)), "{}", e),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn programs_that_turn_on_memory_or_read_neighbours_in_order_are_refused() {
+        let refusal = |src: &str| match mirror(src, Axis::Vertical) {
+            Err(Error::Unmirrorable(coords, reason)) => (coords, reason),
+            _ => panic!("{} was mirrored", src),
+        };
+        let truth_machine = examples::find("truth-machine").unwrap().source();
+        assert_eq!(refusal(truth_machine), (PointAxial(0, 0), String::from("an IP moving E turns on the sign of the current edge")));
+        let primes = examples::find("primes").unwrap().source();
+        assert_eq!(refusal(primes), (PointAxial(-4, 0), String::from("`&` treats its left and right neighbours differently")));
+        for &op in &["-", ":", "%", "^"] {
+            let reason = format!("`{}` treats its left and right neighbours differently", op);
+            assert_eq!(refusal(&format!("12{}!@", op)), (PointAxial(-1, 0), reason));
+        }
+        // Unreachable cells don't matter
+        assert!(mirror("1!@-", Axis::Vertical).is_ok());
+    }
+}
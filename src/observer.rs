@@ -0,0 +1,98 @@
+use rug::Integer;
+
+use crate::{Error, IP, Op};
+
+/// Callbacks for watching a running program, registered with `Builder::observer`.
+///
+/// Every method does nothing by default, so an observer only implements the events it needs.
+/// Returning an error stops the program with it, which lets an observer act as a breakpoint.
+///
+/// ```
+/// use hexagony::{Builder, observer::Observer};
+/// use rug::Integer;
+///
+/// /// Records every value over 1000 written to memory.
+/// struct Large(Vec<Integer>);
+///
+/// impl Observer for Large {
+///     fn on_memory_write(&mut self, _: &Integer, _: &str, _: &Integer, new: &Integer) -> Result<(), hexagony::Error> {
+///         if *new > 1000 {
+///             self.0.push(new.clone());
+///         }
+///         Ok(())
+///     }
+/// }
+///
+/// let mut large = Large(Vec::new());
+/// Builder::new("99999@").output(Vec::new()).observer(&mut large).build()?.run()?;
+/// assert_eq!(large.0, [9999, 99999]);
+/// # Ok::<(), hexagony::Error>(())
+/// ```
+pub trait Observer {
+    /// Called before each tick, with the index and position of the active IP and the op it's on.
+    fn on_tick(&mut self, _tick: &Integer, _ip_idx: usize, _ip: IP, _op: Op) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called after a tick changes the value of the current memory cell, which `position` describes
+    /// like `Memory::position`.
+    fn on_memory_write(&mut self, _tick: &Integer, _position: &str, _old: &Integer, _new: &Integer) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called after a tick writes bytes to the output.
+    fn on_output(&mut self, _tick: &Integer, _bytes: &[u8]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Whether `on_memory_write` should be called. The interpreter has to copy the current cell
+    /// before every tick to detect writes, so observers that ignore them can return false to skip it.
+    fn watches_memory(&self) -> bool {
+        true
+    }
+}
+
+impl<O: Observer + ?Sized> Observer for &mut O {
+    fn on_tick(&mut self, tick: &Integer, ip_idx: usize, ip: IP, op: Op) -> Result<(), Error> {
+        (**self).on_tick(tick, ip_idx, ip, op)
+    }
+
+    fn on_memory_write(&mut self, tick: &Integer, position: &str, old: &Integer, new: &Integer) -> Result<(), Error> {
+        (**self).on_memory_write(tick, position, old, new)
+    }
+
+    fn on_output(&mut self, tick: &Integer, bytes: &[u8]) -> Result<(), Error> {
+        (**self).on_output(tick, bytes)
+    }
+
+    fn watches_memory(&self) -> bool {
+        (**self).watches_memory()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::{Builder, annotate, coords::PointAxial};
+
+    #[test]
+    fn memory_writes_reach_observers_that_watch_them() {
+        struct Writes(Vec<Integer>);
+
+        impl Observer for Writes {
+            fn on_memory_write(&mut self, _: &Integer, _: &str, _: &Integer, new: &Integer) -> Result<(), Error> {
+                self.0.push(new.clone());
+                Ok(())
+            }
+        }
+
+        let mut writes = Writes(Vec::new());
+        let counts = annotate::CellCounts::default();
+        let hex = Builder::new("1!)!@").output(io::sink()).observer(counts.clone()).observer(&mut writes);
+        hex.build().unwrap().run().unwrap();
+        assert_eq!(writes.0, [1, 2]);
+        assert_eq!(counts.get(PointAxial(1, -1)), 1);
+    }
+}
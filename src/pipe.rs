@@ -0,0 +1,144 @@
+use std::{collections::VecDeque, io::{self, Read, Write}, sync::{Arc, Condvar, Mutex}, thread};
+
+use crate::{Builder, Error, binary_stdout, grid::Grid};
+
+/// The pipes between two programs and whose turn it is to execute a tick.
+///
+/// Pipes and programs are indexed by 0 for the first program and 1 for the second,
+/// and each pipe is named after the program that writes to it.
+struct Shared {
+    pipes: [VecDeque<u8>; 2],
+    turn: usize,
+    waiting: [bool; 2],
+    done: [bool; 2],
+}
+
+/// The state shared by both threads of a pipeline.
+type Handle = Arc<(Mutex<Shared>, Condvar)>;
+
+/// The end of a pipe that a program reads from.
+struct PipeReader {
+    shared: Handle,
+    me: usize,
+}
+
+/// The end of a pipe that a program writes to, which also copies the bytes to `tee` if it's set.
+struct PipeWriter {
+    shared: Handle,
+    me: usize,
+    tee: Option<Box<dyn Write>>,
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (lock, cvar) = &*self.shared;
+        let mut s = lock.lock().unwrap();
+        let (me, other) = (self.me, 1 - self.me);
+        loop {
+            if s.pipes[other].is_empty() {
+                // Reading from a finished program, or from one that is itself waiting for us, would never end
+                if s.done[other] || s.waiting[other] && s.pipes[me].is_empty() {
+                    s.waiting[me] = false;
+                    return Ok(0);
+                }
+                s.waiting[me] = true;
+                s.turn = other;
+                cvar.notify_all();
+            } else if s.turn == me || s.done[other] {
+                let n = buf.len().min(s.pipes[other].len());
+                for (dst, src) in buf.iter_mut().zip(s.pipes[other].drain(..n)) {
+                    *dst = src;
+                }
+                return Ok(n);
+            }
+            s = cvar.wait(s).unwrap();
+        }
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(tee) = &mut self.tee {
+            tee.write_all(buf)?;
+        }
+        let (lock, cvar) = &*self.shared;
+        let mut s = lock.lock().unwrap();
+        s.pipes[self.me].extend(buf);
+        // The reader can run again, so it gets its fair share of ticks from here on
+        s.waiting[1 - self.me] = false;
+        cvar.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.tee {
+            Some(tee) => tee.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Runs a program one tick per turn until it terminates, then wakes the other program.
+fn run_turns(mut builder: Builder<'_>, shared: &Handle, me: usize, max_ticks: Option<u64>) -> Result<(), Error> {
+    if let Some(max) = max_ticks {
+        builder = builder.max_ticks(max);
+    }
+    let result = builder.build().and_then(|mut hex| {
+        let (lock, cvar) = &**shared;
+        loop {
+            {
+                let mut s = lock.lock().unwrap();
+                while s.turn != me && !s.done[1 - me] && !s.waiting[1 - me] {
+                    s = cvar.wait(s).unwrap();
+                }
+            }
+            let done = hex.step()?;
+            let mut s = lock.lock().unwrap();
+            s.turn = 1 - me;
+            cvar.notify_all();
+            if done {
+                return Ok(());
+            }
+        }
+    });
+    let (lock, cvar) = &**shared;
+    lock.lock().unwrap().done[me] = true;
+    cvar.notify_all();
+    result
+}
+
+/// Runs two programs concurrently, with the output of `a` connected to the input of `b`.
+///
+/// `a` reads from STDIN and `b` writes to STDOUT. With a `back_channel`, the output of `b`
+/// is also connected to the input of `a`.
+///
+/// The programs take turns executing one tick each while both can run. A program waiting for
+/// input from the other lets it run until it writes something, and reads EOF once the other
+/// program ends or when both are waiting for each other.
+///
+/// Both programs run to completion, and the error of `a` is returned before the error of `b`.
+pub fn run_pipeline(a: &str, b: &str, back_channel: bool, max_ticks: Option<u64>) -> Result<(), Error> {
+    let (grid_a, grid_b): (Grid, Grid) = (a.parse()?, b.parse()?);
+    let shared: Handle = Arc::new((
+        Mutex::new(Shared { pipes: Default::default(), turn: 0, waiting: [false; 2], done: [false; 2] }),
+        Condvar::new(),
+    ));
+    let thread_a = {
+        let shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            let mut builder = Builder::from_grid(grid_a).output(PipeWriter { shared: Arc::clone(&shared), me: 0, tee: None });
+            if back_channel {
+                builder = builder.input(PipeReader { shared: Arc::clone(&shared), me: 0 });
+            }
+            run_turns(builder, &shared, 0, max_ticks)
+        })
+    };
+    let builder = Builder::from_grid(grid_b).input(PipeReader { shared: Arc::clone(&shared), me: 1 });
+    let builder = match back_channel {
+        true => builder.output(PipeWriter { shared: Arc::clone(&shared), me: 1, tee: Some(binary_stdout()) }),
+        false => builder,
+    };
+    let result_b = run_turns(builder, &shared, 1, max_ticks);
+    thread_a.join().unwrap()?;
+    result_b
+}
@@ -0,0 +1,99 @@
+use std::{cell::RefCell, collections::HashMap, fmt::Write, rc::Rc, time::{Duration, Instant}};
+use rug::Integer;
+
+use crate::{Error, IP, Op, coords::PointAxial, observer::Observer};
+
+/// Time spent on one cell by one IP.
+#[derive(Clone, Copy, Default)]
+struct CellTime {
+    op: Option<Op>,
+    count: u64,
+    time: Duration,
+}
+
+/// The timings collected so far, and the cell whose tick is still running.
+#[derive(Default)]
+struct Timings {
+    cells: HashMap<(usize, PointAxial), CellTime>,
+    running: Option<((usize, PointAxial), Instant)>,
+}
+
+/// An observer that measures the wall time of every tick and attributes it to the cell that was
+/// executed, which can still be read while the interpreter owns a clone of it.
+///
+/// The time of a tick runs from its start to the start of the next one, so it includes the time
+/// the interpreter spends between ticks. Call `finish` after the program ends to count its last tick.
+#[derive(Clone, Default)]
+pub struct Profiler(Rc<RefCell<Timings>>);
+
+impl Observer for Profiler {
+    fn on_tick(&mut self, _tick: &Integer, ip_idx: usize, ip: IP, op: Op) -> Result<(), Error> {
+        let now = Instant::now();
+        let mut timings = self.0.borrow_mut();
+        if let Some((key, start)) = timings.running.take() {
+            timings.cells.entry(key).or_default().time += now - start;
+        }
+        let cell = timings.cells.entry((ip_idx, ip.coords)).or_default();
+        cell.op = Some(op);
+        cell.count += 1;
+        timings.running = Some(((ip_idx, ip.coords), now));
+        Ok(())
+    }
+
+    fn watches_memory(&self) -> bool {
+        false
+    }
+}
+
+impl Profiler {
+    /// Stops timing the last tick.
+    pub fn finish(&self) {
+        let mut timings = self.0.borrow_mut();
+        if let Some((key, start)) = timings.running.take() {
+            timings.cells.entry(key).or_default().time += start.elapsed();
+        }
+    }
+
+    /// Returns the cells sorted from slowest to fastest in total, with their IP, op,
+    /// execution count and total time.
+    fn sorted(&self) -> Vec<((usize, PointAxial), CellTime)> {
+        let mut cells: Vec<_> = self.0.borrow().cells.iter().map(|(&key, &cell)| (key, cell)).collect();
+        cells.sort_by_key(|&((ip, PointAxial(q, r)), cell)| (std::cmp::Reverse(cell.time), ip, r, q));
+        cells
+    }
+
+    /// Returns a table of the `limit` slowest cells, with the share of the total time, the number
+    /// of executions and the mean time per execution of each.
+    pub fn report(&self, limit: usize) -> String {
+        let cells = self.sorted();
+        let total: Duration = cells.iter().map(|(_, cell)| cell.time).sum();
+        let mut out = format!("Profile: {:?} over {} cells\n", total, cells.len());
+        out += "  IP  Cell         Op     Time          Share  Count       Mean\n";
+        for &((ip, coords), cell) in cells.iter().take(limit) {
+            let share = 100.0 * cell.time.as_secs_f64() / total.as_secs_f64().max(f64::MIN_POSITIVE);
+            writeln!(
+                out, "  {:<3} {:<12} {:<6} {:<13} {:>5.1}% {:<11} {:?}",
+                ip,
+                coords.to_string(),
+                format!("`{}`", cell.op.map_or('?', char::from)),
+                format!("{:?}", cell.time),
+                share,
+                cell.count,
+                Duration::from_secs_f64(cell.time.as_secs_f64() / cell.count.max(1) as f64),
+            ).unwrap();
+        }
+        out
+    }
+
+    /// Returns the timings in the folded stack format of flamegraph tools, with a frame for the IP
+    /// and one for the cell, and the time of each cell in nanoseconds.
+    pub fn folded(&self) -> String {
+        let mut out = String::new();
+        for ((ip, coords), cell) in self.sorted() {
+            // Op names don't contain `;`, which separates frames
+            let op = cell.op.map_or("Unknown", Op::name);
+            writeln!(out, "IP {};{} {} {}", ip, coords, op, cell.time.as_nanos()).unwrap();
+        }
+        out
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt;
+
+use crate::{Builder, Error};
+
+/// How whitespace is normalized before comparing a program's output to its source code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Whitespace {
+    /// Compare byte-for-byte
+    Exact,
+    /// Ignore whitespace at the end of each line and trailing blank lines
+    Trailing,
+    /// Ignore all whitespace
+    Ignore,
+}
+
+/// The first position at which a program's output differs from its source code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// Byte offset into the normalized source code and output
+    pub position: usize,
+    /// The byte of source code at the position, or `None` if the source code ended
+    pub expected: Option<u8>,
+    /// The byte of output at the position, or `None` if the output ended
+    pub found: Option<u8>,
+}
+
+/// Runs a program with no input and checks whether its output equals its own source code
+/// after normalizing whitespace in both.
+///
+/// Returns the first `Divergence`, or `None` if the program is a quine.
+pub fn verify_quine(src: &str, whitespace: Whitespace, max_ticks: Option<u64>) -> Result<Option<Divergence>, Error> {
+    let mut output = Vec::new();
+    let mut builder = Builder::new(src).input(&[][..]).output(&mut output);
+    if let Some(max_ticks) = max_ticks {
+        builder = builder.max_ticks(max_ticks);
+    }
+    builder.build()?.run()?;
+    let (expected, found) = (normalize(src.as_bytes(), whitespace), normalize(&output, whitespace));
+    let position = expected.iter().zip(&found).take_while(|(a, b)| a == b).count();
+    if position == expected.len() && position == found.len() {
+        return Ok(None);
+    }
+    Ok(Some(Divergence {
+        position,
+        expected: expected.get(position).copied(),
+        found: found.get(position).copied(),
+    }))
+}
+
+/// Normalizes the whitespace in some text.
+fn normalize(text: &[u8], whitespace: Whitespace) -> Vec<u8> {
+    match whitespace {
+        Whitespace::Exact => text.to_vec(),
+        Whitespace::Trailing => {
+            let mut out = Vec::new();
+            for line in text.split(|&b| b == b'\n') {
+                let end = line.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(0, |i| i + 1);
+                out.extend_from_slice(&line[..end]);
+                out.push(b'\n');
+            }
+            while out.last() == Some(&b'\n') {
+                out.pop();
+            }
+            out
+        }
+        Whitespace::Ignore => text.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect(),
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |b: Option<u8>| match b {
+            Some(b) => format!("{:?}", b as char),
+            None => "end of text".to_string(),
+        };
+        write!(f, "Output diverges from source at byte {}: expected {}, found {}",
+            self.position, show(self.expected), show(self.found))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A quine that stores the rest of its code as the digits of one number, which it decodes and prints
+    /// after printing those digits.
+    const QUINE: &str = include_str!("../tests/programs/quine.hxg");
+
+    #[test]
+    fn quines_have_no_divergence() {
+        assert_eq!(verify_quine(QUINE, Whitespace::Exact, Some(100_000)).unwrap(), None);
+    }
+
+    #[test]
+    fn near_misses_report_where_the_output_diverges() {
+        let divergence = verify_quine("H;@", Whitespace::Exact, None).unwrap().unwrap();
+        assert_eq!(divergence, Divergence { position: 1, expected: Some(b';'), found: None });
+        assert_eq!(divergence.to_string(), "Output diverges from source at byte 1: expected ';', found end of text");
+        let divergence = verify_quine("H;;@", Whitespace::Exact, None).unwrap().unwrap();
+        assert_eq!(divergence, Divergence { position: 1, expected: Some(b';'), found: Some(b'H') });
+    }
+
+    #[test]
+    fn whitespace_is_normalized_by_mode() {
+        let trailing = format!("{}  \n\n", QUINE);
+        let divergence = verify_quine(&trailing, Whitespace::Exact, Some(100_000)).unwrap();
+        assert_eq!(divergence, Some(Divergence { position: QUINE.len(), expected: Some(b' '), found: None }));
+        assert_eq!(verify_quine(&trailing, Whitespace::Trailing, Some(100_000)).unwrap(), None);
+        assert_eq!(verify_quine(&trailing, Whitespace::Ignore, Some(100_000)).unwrap(), None);
+
+        let spaced = QUINE.replace('\\', " \\");
+        let position = QUINE.find('\\').unwrap();
+        let divergence = verify_quine(&spaced, Whitespace::Trailing, Some(100_000)).unwrap();
+        assert_eq!(divergence, Some(Divergence { position, expected: Some(b' '), found: Some(b'\\') }));
+        assert_eq!(verify_quine(&spaced, Whitespace::Ignore, Some(100_000)).unwrap(), None);
+    }
+}
@@ -0,0 +1,68 @@
+use std::io::{self, Write};
+use rug::Integer;
+
+use crate::{Error, coords::PointAxial, grid::Op};
+
+/// Writes a line to an input log with the bytes consumed by a `,` or `?` at the given tick,
+/// in the form `TICK: HEX HEX ...`, followed by `EOF` if it reached the end of the input.
+pub(crate) fn log_input(log: &mut dyn Write, tick: &Integer, bytes: &[u8], eof: bool) -> io::Result<()> {
+    write!(log, "{}:", tick)?;
+    for b in bytes {
+        write!(log, " {:02x}", b)?;
+    }
+    if eof {
+        write!(log, " EOF")?;
+    }
+    writeln!(log)
+}
+
+/// Writes a line to an output log with the bytes written by the op at the given tick and cell,
+/// in the form `TICK: IP I at (Q, R) `OP` wrote HEX HEX ... "TEXT"`.
+pub(crate) fn log_output(log: &mut dyn Write, tick: &Integer, ip_idx: usize, coords: PointAxial, op: Op, bytes: &[u8]) -> io::Result<()> {
+    write!(log, "{}: IP {} at {} `{}` wrote", tick, ip_idx, coords, op)?;
+    for b in bytes {
+        write!(log, " {:02x}", b)?;
+    }
+    writeln!(log, " {:?}", String::from_utf8_lossy(bytes))
+}
+
+/// Parses an input log written by `Builder::record_input` and returns all of the recorded input in order.
+///
+/// Tick numbers are ignored, and blank lines and lines starting with `#` are skipped.
+pub fn replay_input(log: &str) -> Result<Vec<u8>, Error> {
+    let mut input = Vec::new();
+    for line in log.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let invalid = || Error::ParseError("input log line", line.to_string());
+        let (tick, bytes) = line.split_once(':').ok_or_else(invalid)?;
+        tick.parse::<Integer>().map_err(|_| invalid())?;
+        for word in bytes.split_whitespace() {
+            match word {
+                "EOF" => (),
+                _ => input.push(u8::from_str_radix(word, 16).map_err(|_| invalid())?),
+            }
+        }
+    }
+    Ok(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Builder;
+
+    #[test]
+    fn recorded_input_replays_the_same_run() {
+        let run = |input: &[u8]| {
+            let (mut output, mut log) = (Vec::new(), Vec::new());
+            Builder::new("?,;!@").input(input).output(&mut output).record_input(&mut log).build().unwrap().run().unwrap();
+            (output, String::from_utf8(log).unwrap())
+        };
+        let (output, log) = run(b"12x");
+        assert_eq!(log, "0: 31 32\n1: 78\n");
+        assert_eq!(run(b"7").1, "0: 37\n1: EOF\n");
+        let input = replay_input(&format!("# a comment\n{}", log)).unwrap();
+        assert_eq!(input, b"12x");
+        assert_eq!(run(&input).0, output);
+        assert!(replay_input("0 31").is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use std::{cell::Cell, collections::{HashSet, VecDeque}};
+
+use crate::cases::TestCase;
+
+/// Maximum number of candidate layouts kept between search steps.
+const BEAM_WIDTH: usize = 8;
+
+/// Searches for an equivalent arrangement of a program that fits in a smaller hexagon by deleting
+/// and moving no-ops, keeping every other op in order.
+///
+/// Candidates are checked against every test case with a limit of `max_ticks` ticks, and at most
+/// `budget` candidates are checked in total. Debug flags and whitespace are discarded.
+///
+/// Returns the source code of the smallest arrangement found, which is the original program if
+/// it could not be shrunk, or `None` if the original program fails a test case.
+pub fn repack(src: &str, cases: &[TestCase], max_ticks: u64, budget: usize) -> Option<String> {
+    repack_with(src, budget, |src| cases.iter().all(|case| case.check(src, max_ticks)))
+}
+
+/// Repacks a program, as described by `repack`, keeping the arrangements that `check` accepts.
+fn repack_with(src: &str, budget: usize, check: impl Fn(&str) -> bool) -> Option<String> {
+    let checks = Cell::new(0);
+    let passes = |ops: &[char]| {
+        checks.set(checks.get() + 1);
+        check(&ops.iter().collect::<String>())
+    };
+    let mut ops: Vec<char> = src.chars().filter(|&c| !c.is_whitespace() && c != '`').collect();
+    if !passes(&ops) {
+        return None;
+    }
+    loop {
+        let size = size_of(ops.len());
+        if size <= 1 {
+            break;
+        }
+        let capacity = 3 * (size - 1) * (size - 2) + 1;
+        if ops.iter().filter(|&&c| c != '.').count() > capacity {
+            break;
+        }
+        // Shrink while still passing by deleting or moving one no-op at a time
+        let mut seen = HashSet::new();
+        let mut advance = |beam: &[Vec<char>], expand: fn(&[char]) -> Vec<Vec<char>>| -> Vec<Vec<char>> {
+            beam.iter()
+                .flat_map(|c| expand(c))
+                .filter(|c| seen.insert(c.clone()))
+                .take_while(|_| checks.get() < budget)
+                .filter(|c| passes(c))
+                .take(BEAM_WIDTH)
+                .collect()
+        };
+        let mut beam = vec![ops.clone()];
+        while beam.iter().all(|c| c.len() > capacity) {
+            let mut next = advance(&beam, deletions);
+            if next.is_empty() {
+                next = advance(&beam, moves);
+            }
+            if next.is_empty() {
+                break;
+            }
+            beam = next;
+        }
+        if let Some(fit) = beam.iter().find(|c| c.len() <= capacity) {
+            ops = fit.clone();
+            continue;
+        }
+        // Otherwise squeeze the closest layouts into the smaller hexagon and move no-ops until one passes
+        let mut queue: VecDeque<_> = beam.iter().map(|c| squeeze(c, capacity)).collect();
+        let found = loop {
+            let candidate = match queue.pop_front() {
+                Some(c) if checks.get() < budget => c,
+                _ => break None,
+            };
+            if passes(&candidate) {
+                break Some(candidate);
+            }
+            for layout in moves(&candidate) {
+                if queue.len() + checks.get() < budget && seen.insert(layout.clone()) {
+                    queue.push_back(layout);
+                }
+            }
+        };
+        match found {
+            Some(fit) => ops = fit,
+            None => break,
+        }
+    }
+    Some(ops.into_iter().collect())
+}
+
+/// Returns the side length of the smallest hexagon that holds the given number of ops.
+fn size_of(len: usize) -> usize {
+    (1..).find(|n| 3 * n * (n - 1) + 1 >= len).unwrap()
+}
+
+/// Deletes no-ops from the longest runs of no-ops until a layout fits within `capacity` cells.
+fn squeeze(ops: &[char], capacity: usize) -> Vec<char> {
+    let mut ops = ops.to_vec();
+    while ops.len() > capacity {
+        // Find the start of the last longest run
+        let (mut best, mut best_len, mut i) = (0, 0, 0);
+        while i < ops.len() {
+            let len = ops[i..].iter().take_while(|&&c| c == '.').count();
+            if len > 0 && len >= best_len {
+                best = i;
+                best_len = len;
+            }
+            i += len.max(1);
+        }
+        ops.remove(best);
+    }
+    ops
+}
+
+/// Returns every layout obtained by deleting one no-op, skipping deletions that
+/// would be identical to another deletion from the same run of no-ops.
+fn deletions(ops: &[char]) -> Vec<Vec<char>> {
+    (0..ops.len())
+        .filter(|&i| ops[i] == '.' && (i == 0 || ops[i - 1] != '.'))
+        .map(|i| [&ops[..i], &ops[i + 1..]].concat())
+        .collect()
+}
+
+/// Returns every layout obtained by moving one no-op to a different position,
+/// which shifts the code between the two positions by one cell.
+fn moves(ops: &[char]) -> Vec<Vec<char>> {
+    let mut layouts = Vec::new();
+    for deleted in deletions(ops) {
+        for j in 0..=deleted.len() {
+            let mut layout = deleted.clone();
+            layout.insert(j, '.');
+            if layout != ops {
+                layouts.push(layout);
+            }
+        }
+    }
+    layouts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repacking_fits_programs_in_smaller_hexagons_within_the_budget() {
+        let cases = [TestCase { name: String::new(), input: b"7".to_vec(), output: b"8".to_vec() }];
+        let src = "?)!....@";
+        let repacked = repack(src, &cases, 100, 1000).unwrap();
+        assert!(repacked.len() <= 7 && cases[0].check(&repacked, 100), "{}", repacked);
+        assert_eq!(repack("?)!@", &cases, 100, 1000).as_deref(), Some("?)!@"));
+        assert_eq!(repack("?(!@", &cases, 100, 1000), None);
+        for budget in 1..20 {
+            let checks = Cell::new(0);
+            repack_with(src, budget, |src| {
+                checks.set(checks.get() + 1);
+                cases[0].check(src, 100)
+            });
+            assert!(checks.get() <= budget, "{} checks with a budget of {}", checks.get(), budget);
+        }
+        // The first check counts too, so a budget of 1 keeps the program as it is
+        assert_eq!(repack(src, &cases, 100, 1).as_deref(), Some(src));
+    }
+}
@@ -0,0 +1,178 @@
+use std::{fmt::Write, time::{Duration, Instant}};
+
+use crate::{Error, cases::TestCase};
+
+/// The result of running a program with one test case.
+pub struct CaseReport<'c> {
+    pub case: &'c TestCase,
+    /// Output of the program, or the error it failed with
+    pub result: Result<Vec<u8>, Error>,
+    /// Wall time the program took to run
+    pub time: Duration,
+}
+
+impl CaseReport<'_> {
+    /// Returns whether the program terminated and produced the expected output.
+    pub fn passed(&self) -> bool {
+        matches!(&self.result, Ok(output) if *output == self.case.output)
+    }
+
+    /// Describes why the test case failed, one line per detail, or returns `None` if it passed.
+    pub fn failure(&self) -> Option<Vec<String>> {
+        let mut lines = vec![format!("input:    {:?}", String::from_utf8_lossy(&self.case.input))];
+        match &self.result {
+            Ok(output) if *output == self.case.output => return None,
+            Ok(output) => {
+                lines.push(format!("expected: {:?}", String::from_utf8_lossy(&self.case.output)));
+                lines.push(format!("got:      {:?}", String::from_utf8_lossy(output)));
+                let diverge = output.iter().zip(&self.case.output).take_while(|(a, b)| a == b).count();
+                lines.push(format!("first difference at byte {}", diverge));
+            }
+            Err(e) => lines.push(format!("error:    {}", e)),
+        }
+        Some(lines)
+    }
+}
+
+/// Runs a program with every test case for at most `max_ticks` ticks each.
+pub fn run_cases<'c>(src: &str, cases: &'c [TestCase], max_ticks: u64) -> Vec<CaseReport<'c>> {
+    cases.iter().map(|case| {
+        let start = Instant::now();
+        let result = case.run(src, max_ticks);
+        CaseReport { case, result, time: start.elapsed() }
+    }).collect()
+}
+
+/// A format for the results of a test run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `PASS` or `FAIL` and details for each test case, then a count of the passed cases
+    Text,
+    /// A JUnit XML report with one `testsuite` holding a `testcase` per test case
+    JUnit,
+    /// A Test Anything Protocol (version 13) stream
+    Tap,
+}
+
+impl Format {
+    /// Returns the format with the given name: `text`, `junit` or `tap`.
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "text" => Some(Format::Text),
+            "junit" => Some(Format::JUnit),
+            "tap" => Some(Format::Tap),
+            _ => None,
+        }
+    }
+
+    /// Formats the results of running the test suite named `suite`.
+    pub fn render(self, suite: &str, reports: &[CaseReport]) -> String {
+        let mut out = String::new();
+        match self {
+            Format::Text => {
+                for report in reports {
+                    match report.failure() {
+                        None => writeln!(out, "PASS {}", report.case.name).unwrap(),
+                        Some(lines) => {
+                            writeln!(out, "FAIL {}", report.case.name).unwrap();
+                            for line in lines {
+                                writeln!(out, "  {}", line).unwrap();
+                            }
+                        }
+                    }
+                }
+                let passed = reports.iter().filter(|report| report.passed()).count();
+                writeln!(out, "{}/{} passed", passed, reports.len()).unwrap();
+            }
+            Format::JUnit => {
+                let failures = reports.iter().filter(|report| !report.passed()).count();
+                let time: Duration = reports.iter().map(|report| report.time).sum();
+                writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+                writeln!(
+                    out, r#"<testsuite name="{}" tests="{}" failures="{}" time="{:.3}">"#,
+                    xml_escape(suite), reports.len(), failures, time.as_secs_f64(),
+                ).unwrap();
+                for report in reports {
+                    write!(
+                        out, r#"  <testcase classname="{}" name="{}" time="{:.3}""#,
+                        xml_escape(suite), xml_escape(&report.case.name), report.time.as_secs_f64(),
+                    ).unwrap();
+                    match report.failure() {
+                        None => writeln!(out, "/>").unwrap(),
+                        Some(lines) => {
+                            let message = match report.result {
+                                Ok(_) => "wrong output",
+                                Err(_) => "runtime error",
+                            };
+                            writeln!(out, ">\n    <failure message=\"{}\">{}</failure>", message, xml_escape(&lines.join("\n"))).unwrap();
+                            writeln!(out, "  </testcase>").unwrap();
+                        }
+                    }
+                }
+                writeln!(out, "</testsuite>").unwrap();
+            }
+            Format::Tap => {
+                writeln!(out, "TAP version 13\n1..{}", reports.len()).unwrap();
+                for (i, report) in reports.iter().enumerate() {
+                    // `#` starts a directive in a TAP description
+                    let name = report.case.name.replace('#', "\\#");
+                    match report.failure() {
+                        None => writeln!(out, "ok {} - {}", i + 1, name).unwrap(),
+                        Some(lines) => {
+                            writeln!(out, "not ok {} - {}", i + 1, name).unwrap();
+                            for line in lines {
+                                writeln!(out, "# {}", line).unwrap();
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Escapes text for use in XML attributes and elements.
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '&' => escaped += "&amp;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&apos;",
+            // Control characters other than whitespace aren't allowed in XML 1.0
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => escaped += &format!("\\u{{{:x}}}", c as u32),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cases;
+
+    #[test]
+    fn test_reports_render_as_text_junit_and_tap() {
+        let cases = "[adds #1]\ninput = \"41\"\noutput = \"42\"\n[<wrong>]\ninput = \"1\"\noutput = \"3\"";
+        let cases = cases::parse_cases(cases).unwrap();
+        let mut reports = run_cases("?)!@", &cases, 100);
+        assert!(reports[0].passed() && !reports[1].passed());
+        reports[0].time = Duration::from_millis(1500);
+        reports[1].time = Duration::from_millis(250);
+        let text = Format::Text.render("add", &reports);
+        let expected = "PASS adds #1\nFAIL <wrong>\n  input:    \"1\"\n  expected: \"3\"\n  got:      \"2\"\n  first difference at byte 0\n\
+                        1/2 passed\n";
+        assert_eq!(text, expected);
+        let tap = Format::Tap.render("add", &reports);
+        assert!(tap.starts_with("TAP version 13\n1..2\nok 1 - adds \\#1\nnot ok 2 - <wrong>\n# input:    \"1\"\n"), "{}", tap);
+        let junit = Format::JUnit.render("add", &reports);
+        assert!(junit.contains(r#"<testsuite name="add" tests="2" failures="1" time="1.750">"#), "{}", junit);
+        assert!(junit.contains(r#"<testcase classname="add" name="adds #1" time="1.500"/>"#), "{}", junit);
+        let failure = "name=\"&lt;wrong&gt;\" time=\"0.250\">\n    <failure message=\"wrong output\">input:    &quot;1&quot;\n";
+        assert!(junit.contains(failure), "{}", junit);
+    }
+}
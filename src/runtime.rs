@@ -0,0 +1,769 @@
+use std::{collections::HashMap, io::{self, BufReader, Read, Write}, iter::Peekable, time::{SystemTime, UNIX_EPOCH}};
+use rug::{Assign, Integer, integer::Order, rand::RandState};
+
+use crate::{Error, custom::{Context, CustomOp}, fingerprint::Fnv, grid::Op, memory::{AnyMemory, Memory, MemoryModel}};
+
+/// Returns a writer to STDOUT that writes bytes unchanged, including bytes that aren't valid UTF-8.
+///
+/// The writer flushes after every newline, like `io::stdout()`.
+pub fn binary_stdout() -> Box<dyn Write> {
+    #[cfg(windows)]
+    {
+        use std::{fs::File, mem::ManuallyDrop, os::windows::io::{AsRawHandle, FromRawHandle}};
+
+        /// A console handle written through `File`, which the standard library's `Stdout`
+        /// would reject non-UTF-8 bytes for.
+        struct Console(ManuallyDrop<File>);
+
+        impl Write for Console {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.0.flush()
+            }
+        }
+
+        // The handle stays owned by `Stdout`, so it must never be closed by `File`
+        let file = unsafe { File::from_raw_handle(io::stdout().as_raw_handle()) };
+        Box::new(io::LineWriter::new(Console(ManuallyDrop::new(file))))
+    }
+    #[cfg(not(windows))]
+    {
+        Box::new(io::stdout())
+    }
+}
+
+/// Converts an error writing output into an `Error`, distinguishing a reader that went away.
+fn output_error(e: io::Error) -> Error {
+    match e.kind() {
+        io::ErrorKind::BrokenPipe => Error::OutputClosed,
+        _ => Error::IOError(e),
+    }
+}
+
+/// What `?` sets the current memory edge to when it reaches EOF before finding a number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntEof {
+    /// Set the edge to 0
+    Zero,
+    /// Set the edge to -1, like `,`
+    MinusOne,
+    /// Leave the edge unchanged
+    Unchanged,
+}
+
+/// The range of values a memory edge can hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    /// Arbitrarily large integers
+    Unbounded,
+    /// Bytes from 0 to 255, with arithmetic wrapping around
+    Byte,
+}
+
+impl ValueType {
+    /// Returns the name of the type, as used in save states and on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            ValueType::Unbounded => "unbounded",
+            ValueType::Byte => "byte",
+        }
+    }
+
+    /// Returns the type with the given name.
+    pub fn from_name(name: &str) -> Option<ValueType> {
+        match name {
+            "unbounded" => Some(ValueType::Unbounded),
+            "byte" => Some(ValueType::Byte),
+            _ => None,
+        }
+    }
+
+    /// Returns `value` reduced to the range of this type, or `None` if it's already in range.
+    pub fn wrap(self, value: &Integer) -> Option<Integer> {
+        match self {
+            ValueType::Byte if *value < 0 || *value > 255 => Some(Integer::from(value.mod_u(256))),
+            _ => None,
+        }
+    }
+}
+
+/// Returns a random number generator for `Op::Random` seeded with `seed`.
+fn seeded_rand(seed: u64) -> RandState<'static> {
+    let mut rand = RandState::new();
+    rand.seed(&Integer::from(seed));
+    rand
+}
+
+/// A function that applies the effect of an op on memory and I/O, given the op for its payload.
+pub(crate) type Handler = fn(&mut Runtime<'_>, Op) -> Result<(), Error>;
+
+/// Returns a handler that applies the effect of `op`, which is specialized to the op for the
+/// simple commands that most ticks execute and falls back to matching on the op otherwise.
+///
+/// The specialized handlers are the same functions that `Runtime::apply` calls for those ops.
+pub(crate) fn handler(op: Op) -> Handler {
+    match op {
+        Op::Nop | Op::Redir(_) | Op::Jump => nop,
+        Op::Letter(_) => letter,
+        Op::Digit(_) => digit,
+        Op::Increment => increment,
+        Op::Decrement => decrement,
+        Op::Add => add,
+        Op::Subtract => subtract,
+        Op::Multiply => multiply,
+        Op::Negate => negate,
+        Op::MPLeft => mp_left,
+        Op::MPRight => mp_right,
+        Op::MPReverse => mp_reverse,
+        _ => |rt, op| rt.apply(op),
+    }
+}
+
+fn nop(_: &mut Runtime<'_>, _: Op) -> Result<(), Error> {
+    Ok(())
+}
+
+fn letter(rt: &mut Runtime<'_>, op: Op) -> Result<(), Error> {
+    if let Op::Letter(b) = op {
+        rt.mem.get_mut().assign(b);
+    }
+    Ok(())
+}
+
+fn digit(rt: &mut Runtime<'_>, op: Op) -> Result<(), Error> {
+    if let Op::Digit(d) = op {
+        let val = rt.mem.get_mut();
+        *val *= 10;
+        *val += d;
+    }
+    Ok(())
+}
+
+fn increment(rt: &mut Runtime<'_>, _: Op) -> Result<(), Error> {
+    *rt.mem.get_mut() += 1;
+    Ok(())
+}
+
+fn decrement(rt: &mut Runtime<'_>, _: Op) -> Result<(), Error> {
+    *rt.mem.get_mut() -= 1;
+    Ok(())
+}
+
+fn add(rt: &mut Runtime<'_>, _: Op) -> Result<(), Error> {
+    rt.mem.update(&mut |value, left, right| value.assign(left + right));
+    Ok(())
+}
+
+fn subtract(rt: &mut Runtime<'_>, _: Op) -> Result<(), Error> {
+    rt.mem.update(&mut |value, left, right| value.assign(left - right));
+    Ok(())
+}
+
+fn multiply(rt: &mut Runtime<'_>, _: Op) -> Result<(), Error> {
+    rt.mem.update(&mut |value, left, right| value.assign(left * right));
+    Ok(())
+}
+
+fn negate(rt: &mut Runtime<'_>, _: Op) -> Result<(), Error> {
+    *rt.mem.get_mut() *= -1;
+    Ok(())
+}
+
+fn mp_left(rt: &mut Runtime<'_>, _: Op) -> Result<(), Error> {
+    rt.move_mp(|mem| mem.move_left())
+}
+
+fn mp_right(rt: &mut Runtime<'_>, _: Op) -> Result<(), Error> {
+    rt.move_mp(|mem| mem.move_right())
+}
+
+fn mp_reverse(rt: &mut Runtime<'_>, _: Op) -> Result<(), Error> {
+    rt.move_mp(|mem| mem.reverse())
+}
+
+/// A number of bytes of memory, and a callback for when memory grows past it.
+pub(crate) type SoftLimit<'a> = (usize, Box<dyn FnMut(usize) + 'a>);
+
+/// The memory and I/O of a running Hexagony program.
+///
+/// A `Runtime` executes every op that doesn't affect control flow, leaving
+/// the IPs to the interpreter or to ahead-of-time compiled code.
+pub struct Runtime<'a> {
+    pub(crate) mem: AnyMemory,
+    input: Peekable<io::Bytes<BufReader<Box<dyn Read + 'a>>>>,
+    output: Box<dyn Write + 'a>,
+    utf8: bool,
+    byte_eof: i64,
+    int_eof: IntEof,
+    value_type: ValueType,
+    memory_limit: Option<usize>,
+    soft_memory_limit: Option<SoftLimit<'a>>,
+    over_soft_limit: bool,
+    max_output: Option<u64>,
+    written: u64,
+    echo_input: bool,
+    consumed: Vec<u8>,
+    consumed_eof: bool,
+    produced: Vec<u8>,
+    bytes_read: u64,
+    output_hash: Option<Fnv>,
+    pub(crate) custom_ops: HashMap<char, Box<dyn CustomOp + 'a>>,
+    seed: u64,
+    rand: RandState<'static>,
+    env: HashMap<Vec<u8>, Vec<u8>>,
+    process_env: bool,
+}
+
+impl<'a> Runtime<'a> {
+    /// Creates a new `Runtime` with empty memory that reads from `input` and writes to `output`.
+    pub fn new(input: impl Read + 'a, output: impl Write + 'a) -> Runtime<'a> {
+        Runtime::from_boxed(Box::new(input), Box::new(output))
+    }
+
+    /// Creates a new `Runtime` from boxed I/O streams.
+    pub(crate) fn from_boxed(input: Box<dyn Read + 'a>, output: Box<dyn Write + 'a>) -> Runtime<'a> {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        Runtime {
+            mem: MemoryModel::Hex.create(),
+            input: BufReader::new(input).bytes().peekable(),
+            output,
+            utf8: false,
+            byte_eof: -1,
+            int_eof: IntEof::Zero,
+            value_type: ValueType::Unbounded,
+            memory_limit: None,
+            soft_memory_limit: None,
+            over_soft_limit: false,
+            max_output: None,
+            written: 0,
+            echo_input: false,
+            consumed: Vec::new(),
+            consumed_eof: false,
+            produced: Vec::new(),
+            bytes_read: 0,
+            output_hash: None,
+            custom_ops: HashMap::new(),
+            seed,
+            rand: seeded_rand(seed),
+            env: HashMap::new(),
+            process_env: false,
+        }
+    }
+
+    /// Sets whether `,` and `;` read and write Unicode code points encoded as UTF-8 instead of bytes.
+    pub fn set_utf8(&mut self, utf8: bool) {
+        self.utf8 = utf8;
+    }
+
+    /// Replaces memory with an empty memory of the given model, which is `MemoryModel::Hex` by default.
+    pub fn set_memory_model(&mut self, model: MemoryModel) {
+        self.mem = model.create();
+    }
+
+    /// Sets the range of values memory edges can hold, which is `ValueType::Unbounded` by default.
+    ///
+    /// Every op that writes an edge reduces the result to the range, so `ValueType::Byte` makes
+    /// arithmetic wrap around and sets the edge to 255 where it would be -1.
+    pub fn set_value_type(&mut self, value_type: ValueType) {
+        self.value_type = value_type;
+    }
+
+    /// Returns the range of values memory edges can hold.
+    pub fn value_type(&self) -> ValueType {
+        self.value_type
+    }
+
+    /// Sets the approximate number of bytes memory may use before the program is aborted with
+    /// `Error::MemoryLimitExceeded`.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    /// Sets a callback that is called with the approximate number of bytes memory uses whenever
+    /// that rises above `limit`, without stopping the program.
+    pub fn set_soft_memory_limit(&mut self, limit: usize, callback: impl FnMut(usize) + 'a) {
+        self.soft_memory_limit = Some((limit, Box::new(callback)));
+    }
+
+    /// Returns approximately how many bytes memory uses.
+    pub fn memory_used(&self) -> usize {
+        self.mem.bytes_used()
+    }
+
+    /// Enforces the memory limits after an op that may have grown memory.
+    pub(crate) fn check_memory(&mut self) -> Result<(), Error> {
+        if self.memory_limit.is_none() && self.soft_memory_limit.is_none() {
+            return Ok(());
+        }
+        // Memory only keeps count of its size once a limit needs it
+        self.mem.track_usage();
+        let used = self.mem.bytes_used();
+        if let Some((limit, callback)) = &mut self.soft_memory_limit {
+            if used > *limit && !self.over_soft_limit {
+                callback(used);
+            }
+            self.over_soft_limit = used > *limit;
+        }
+        match self.memory_limit {
+            Some(limit) if used > limit => Err(Error::MemoryLimitExceeded(limit)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reduces the current memory edge to the range of the value type.
+    ///
+    /// Returns before looking up the edge when values are unbounded, since every op calls this.
+    #[inline]
+    pub(crate) fn wrap_current(&mut self) {
+        if self.value_type == ValueType::Unbounded {
+            return;
+        }
+        let value = self.mem.get();
+        if *value < 0 || *value > 255 {
+            let byte = value.mod_u(256);
+            self.mem.get_mut().assign(byte);
+        }
+    }
+
+    /// Seeds the random numbers generated by `Op::Random`, which are seeded from the clock by default.
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rand = seeded_rand(seed);
+    }
+
+    /// Returns the seed of the random numbers generated by `Op::Random`.
+    pub fn random_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Sets a variable that `Op::Env` can look up.
+    pub fn set_env_var(&mut self, name: &str, value: &str) {
+        self.env.insert(name.as_bytes().to_vec(), value.as_bytes().to_vec());
+    }
+
+    /// Sets whether `Op::Env` looks up the environment variables of the process when a variable
+    /// wasn't set with `set_env_var`.
+    pub fn set_process_env(&mut self, process_env: bool) {
+        self.process_env = process_env;
+    }
+
+    /// Returns whether the current memory edge is positive.
+    pub fn positive(&self) -> bool {
+        *self.mem.get() > 0
+    }
+
+    /// Executes a run of `count` digit commands at once, whose digits read as the decimal number `digits`,
+    /// which has the same effect as executing each of them with `execute`.
+    ///
+    /// Runs longer than 19 digits start with zeros, since `digits` is a `u64`.
+    pub fn execute_digits(&mut self, count: u32, digits: u64) -> Result<(), Error> {
+        let val = self.mem.get_mut();
+        match 10u64.checked_pow(count) {
+            Some(scale) => *val *= scale,
+            None => *val *= Integer::from(Integer::u_pow_u(10, count)),
+        }
+        *val += digits;
+        self.wrap_current();
+        self.check_memory()
+    }
+
+    /// Executes a run of MP moves at once, each of which is `{`, `}`, `"`, `'` or `=`, which has the
+    /// same effect as executing each of them with `execute`. Other ops are ignored.
+    pub fn execute_moves(&mut self, moves: &[Op]) -> Result<(), Error> {
+        self.move_mp(|mem| {
+            for op in moves {
+                match op {
+                    Op::MPLeft => mem.move_left(),
+                    Op::MPRight => mem.move_right(),
+                    Op::MPBackLeft => { mem.reverse(); mem.move_right(); mem.reverse(); }
+                    Op::MPBackRight => { mem.reverse(); mem.move_left(); mem.reverse(); }
+                    Op::MPReverse => mem.reverse(),
+                    _ => (),
+                }
+            }
+        })?;
+        self.check_memory()
+    }
+
+    /// Executes a run of `count` `;` commands at once, which has the same effect as executing each
+    /// of them with `execute`.
+    pub fn execute_writes(&mut self, count: u64) -> Result<(), Error> {
+        self.produced.clear();
+        let mut buf = [0; 4];
+        let bytes: &[u8] = match self.utf8 {
+            true => {
+                let c = std::char::from_u32(self.mem.get().mod_u(0x110000)).unwrap_or(char::REPLACEMENT_CHARACTER);
+                c.encode_utf8(&mut buf).as_bytes()
+            }
+            false => {
+                buf[0] = self.mem.get().mod_u(256) as u8;
+                &buf[..1]
+            }
+        };
+        let bytes = bytes.to_vec();
+        // Write as many copies as the output limit allows, so the one that exceeds it fails as it would alone
+        let fits = self.max_output.map_or(count, |max| (max.saturating_sub(self.written) / bytes.len() as u64).min(count));
+        self.write(&bytes.repeat(fits as usize))?;
+        if fits < count {
+            self.write(&bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the index of the IP selected by `#` among `ip_count` IPs, which is the current memory edge
+    /// mod `ip_count`.
+    pub fn selected_ip(&self, ip_count: usize) -> usize {
+        self.mem.get().mod_u(ip_count as u32) as usize
+    }
+
+    /// Sets the value that `,` reads at EOF, which is -1 by default.
+    pub fn set_byte_eof(&mut self, value: i64) {
+        self.byte_eof = value;
+    }
+
+    /// Sets what `?` does when it reaches EOF before finding a number, which is `IntEof::Zero` by default.
+    pub fn set_int_eof(&mut self, int_eof: IntEof) {
+        self.int_eof = int_eof;
+    }
+
+    /// Sets the maximum number of bytes the program may write before failing with `Error::OutputLimitExceeded`.
+    pub fn set_max_output(&mut self, max_output: Option<u64>) {
+        self.max_output = max_output;
+    }
+
+    /// Sets whether the input consumed by each `,` and `?` is echoed to STDERR.
+    pub fn set_echo_input(&mut self, echo_input: bool) {
+        self.echo_input = echo_input;
+    }
+
+    /// Flushes the output stream.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.output.flush().map_err(output_error)
+    }
+
+    /// Executes an op's effect on memory and I/O.
+    ///
+    /// Ops that only affect control flow do nothing.
+    pub fn execute(&mut self, op: Op) -> Result<(), Error> {
+        self.execute_with(op, Runtime::apply)
+    }
+
+    /// Executes an op like `execute`, with its effect applied by `handler`, such as one from `handler`.
+    #[inline]
+    pub(crate) fn execute_with(&mut self, op: Op, handler: impl FnOnce(&mut Self, Op) -> Result<(), Error>) -> Result<(), Error> {
+        if let Op::WriteByte | Op::WriteInt = op {
+            self.produced.clear();
+        }
+        if !matches!(op, Op::ReadByte | Op::ReadInt) {
+            return self.execute_op(op, handler);
+        }
+        self.consumed.clear();
+        self.consumed_eof = false;
+        let result = self.execute_op(op, handler);
+        if self.echo_input {
+            let eof = if self.consumed_eof { " EOF" } else { "" };
+            eprintln!("[{} read {:?}{}]", op, String::from_utf8_lossy(&self.consumed), eof);
+        }
+        result
+    }
+
+    /// Returns the bytes consumed by the most recently executed `,` or `?`,
+    /// and whether it reached EOF.
+    pub fn last_input(&self) -> (&[u8], bool) {
+        (&self.consumed, self.consumed_eof)
+    }
+
+    /// Returns the bytes written by the most recently executed `;` or `!`.
+    pub fn last_output(&self) -> &[u8] {
+        &self.produced
+    }
+
+    /// Starts hashing everything written from now on, for `Hexagony::fingerprint`.
+    pub(crate) fn hash_output(&mut self) {
+        self.output_hash = Some(Fnv::new());
+    }
+
+    /// Returns the hash of the output written since `hash_output` was called.
+    pub(crate) fn output_hash(&self) -> Option<u64> {
+        self.output_hash.as_ref().map(Fnv::finish)
+    }
+
+    /// Returns the number of bytes of input read so far.
+    pub fn input_position(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Discards bytes of input until `position` bytes have been read or the input ends.
+    pub(crate) fn skip_input(&mut self, position: u64) -> Result<(), Error> {
+        while self.bytes_read < position && self.next_byte()?.is_some() {}
+        Ok(())
+    }
+
+    /// Executes an op with a handler without echoing its input.
+    #[inline]
+    fn execute_op(&mut self, op: Op, handler: impl FnOnce(&mut Self, Op) -> Result<(), Error>) -> Result<(), Error> {
+        handler(self, op)?;
+        self.wrap_current();
+        self.check_memory()
+    }
+
+    /// Applies an op's effect on memory and I/O.
+    fn apply(&mut self, op: Op) -> Result<(), Error> {
+        match op {
+            // `Tick` is executed by the interpreter, which counts the ticks
+            Op::Nop | Op::Terminate | Op::Jump | Op::Redir(_) | Op::IPPrev | Op::IPNext | Op::IPSelect | Op::Tick => (),
+            Op::Letter(_) => letter(self, op)?,
+            Op::Digit(_) => digit(self, op)?,
+            Op::Increment => increment(self, op)?,
+            Op::Decrement => decrement(self, op)?,
+            Op::Add => add(self, op)?,
+            Op::Subtract => subtract(self, op)?,
+            Op::Multiply => multiply(self, op)?,
+            Op::Divide => {
+                if *self.mem.get_right() == 0 { return Err(Error::ZeroDivisionError(None)) }
+                self.mem.update(&mut |value, left, right| value.assign(left / right));
+            },
+            Op::Modulo => {
+                if *self.mem.get_right() == 0 { return Err(Error::ZeroDivisionError(None)) }
+                self.mem.update(&mut |value, left, right| {
+                    value.assign(left % right);
+                    if *value != 0 && (*left < 0) != (*right < 0) {
+                        *value += right;
+                    }
+                });
+            }
+            Op::Negate => negate(self, op)?,
+            Op::ReadByte => {
+                let val = match self.next_byte()? {
+                    Some(b) if self.utf8 => Integer::from(self.read_code_point(b)?),
+                    Some(b) => Integer::from(b),
+                    None => Integer::from(self.byte_eof),
+                };
+                self.mem.set(val);
+            }
+            Op::ReadInt => {
+                let mut val = Integer::new();
+                let mut sign = 1;
+                let mut found = false;
+                while let Some(b) = self.next_byte()? {
+                    found = true;
+                    match b {
+                        b'+' => break,
+                        b'-' => {
+                            sign = -1;
+                            break;
+                        }
+                        d @ b'0'..=b'9' => {
+                            val += d - b'0';
+                            break;
+                        }
+                        _ => found = false,
+                    }
+                }
+                if !found {
+                    match self.int_eof {
+                        IntEof::Zero => self.mem.set(val),
+                        IntEof::MinusOne => self.mem.set(Integer::from(-1)),
+                        IntEof::Unchanged => (),
+                    }
+                    return Ok(());
+                }
+                while let Some(Ok(d @ b'0'..=b'9')) = self.input.peek() {
+                    val *= 10;
+                    val += *d - b'0';
+                    self.next_byte()?;
+                }
+                val *= sign;
+                self.mem.set(val);
+            }
+            Op::WriteByte if self.utf8 => {
+                let c = std::char::from_u32(self.mem.get().mod_u(0x110000)).unwrap_or(char::REPLACEMENT_CHARACTER);
+                self.write(c.encode_utf8(&mut [0; 4]).as_bytes())?;
+            }
+            Op::WriteByte => self.write(&[self.mem.get().mod_u(256) as u8])?,
+            Op::WriteInt => self.write_int()?,
+            Op::MPLeft => mp_left(self, op)?,
+            Op::MPRight => mp_right(self, op)?,
+            Op::MPBackLeft => self.move_mp(|mem| { mem.reverse(); mem.move_right(); mem.reverse(); })?,
+            Op::MPBackRight => self.move_mp(|mem| { mem.reverse(); mem.move_left(); mem.reverse(); })?,
+            Op::MPReverse => mp_reverse(self, op)?,
+            Op::MPBranch => self.move_mp(|mem| if *mem.get() > 0 { mem.move_right() } else { mem.move_left() })?,
+            Op::MemCopy => self.mem.update(&mut |value, left, right| {
+                let source = if *value > 0 { right } else { left };
+                value.assign(source);
+            }),
+            Op::Random => {
+                let bound = self.mem.get_mut();
+                if *bound <= 0 {
+                    bound.assign(256);
+                }
+                bound.random_below_mut(&mut self.rand);
+            }
+            Op::Env => {
+                // Names and values are packed into a single edge as base-256 digits, most significant first,
+                // so a letter command gives a one-character name
+                let name = match *self.mem.get() > 0 {
+                    true => self.mem.get().to_digits::<u8>(Order::Msf),
+                    false => Vec::new(),
+                };
+                let value = self.env.get(&name).cloned().or_else(|| match self.process_env {
+                    true => String::from_utf8(name).ok().and_then(|name| std::env::var(name).ok()).map(String::into_bytes),
+                    false => None,
+                });
+                self.mem.set(match value {
+                    Some(bytes) => Integer::from_digits(&bytes, Order::Msf),
+                    None => Integer::from(-1),
+                });
+            }
+            Op::Custom(c) => {
+                // Take the op out while it runs, since it gets mutable access to the runtime
+                let mut custom = self.custom_ops.remove(&c).ok_or(Error::SyntaxError(c))?;
+                let result = custom.execute(&mut Context::new(self));
+                self.custom_ops.insert(c, custom);
+                result?
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the MP with `f`, failing if memory spilled to disk couldn't be read back or written.
+    fn move_mp(&mut self, f: impl FnOnce(&mut AnyMemory)) -> Result<(), Error> {
+        f(&mut self.mem);
+        self.mem.take_error().map_or(Ok(()), |e| Err(e.into()))
+    }
+
+    /// Writes the current memory edge in decimal, formatting values that fit in an `i64` without allocating.
+    fn write_int(&mut self) -> Result<(), Error> {
+        let n = match self.mem.get().to_i64() {
+            Some(n) => n,
+            None => {
+                let digits = self.mem.get().to_string();
+                return self.write(digits.as_bytes());
+            }
+        };
+        // Fill the buffer from the end, since the digits come out least significant first
+        let mut buf = [0; 20];
+        let mut start = buf.len();
+        let mut rest = n.unsigned_abs();
+        loop {
+            start -= 1;
+            buf[start] = b'0' + (rest % 10) as u8;
+            rest /= 10;
+            if rest == 0 {
+                break;
+            }
+        }
+        if n < 0 {
+            start -= 1;
+            buf[start] = b'-';
+        }
+        self.write(&buf[start..])
+    }
+
+    /// Writes bytes to the output stream, enforcing the output limit.
+    pub(crate) fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.written += bytes.len() as u64;
+        if self.max_output.is_some_and(|max| self.written > max) {
+            return Err(Error::OutputLimitExceeded);
+        }
+        if let Some(hash) = &mut self.output_hash {
+            hash.write(bytes);
+        }
+        self.produced.extend_from_slice(bytes);
+        self.output.write_all(bytes).map_err(output_error)
+    }
+
+    /// Reads the next byte of input, or `None` at EOF.
+    pub(crate) fn next_byte(&mut self) -> Result<Option<u8>, Error> {
+        let b = self.input.next().transpose()?;
+        self.bytes_read += b.is_some() as u64;
+        self.consumed.extend(b);
+        self.consumed_eof |= b.is_none();
+        Ok(b)
+    }
+
+    /// Reads the rest of a UTF-8 sequence that starts with the given byte and returns its code point.
+    ///
+    /// Invalid, truncated or overlong sequences decode to U+FFFD.
+    fn read_code_point(&mut self, first: u8) -> Result<u32, Error> {
+        // The smallest code point that needs a sequence of this length, since shorter ones must use a shorter sequence
+        let (len, min, mut cp) = match first {
+            0x00..=0x7F => return Ok(first as u32),
+            0xC0..=0xDF => (2, 0x80, first as u32 & 0x1F),
+            0xE0..=0xEF => (3, 0x800, first as u32 & 0x0F),
+            0xF0..=0xF7 => (4, 0x10000, first as u32 & 0x07),
+            _ => return Ok(char::REPLACEMENT_CHARACTER as u32),
+        };
+        for _ in 1..len {
+            match self.input.peek() {
+                Some(Ok(b)) if b & 0xC0 == 0x80 => cp = cp << 6 | (*b & 0x3F) as u32,
+                _ => return Ok(char::REPLACEMENT_CHARACTER as u32),
+            }
+            self.next_byte()?;
+        }
+        if cp < min {
+            return Ok(char::REPLACEMENT_CHARACTER as u32);
+        }
+        Ok(std::char::from_u32(cp).map_or(char::REPLACEMENT_CHARACTER as u32, |c| c as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_input_decodes_code_points_and_replaces_invalid_sequences() {
+        let read = |input: &[u8]| {
+            let mut rt = Runtime::new(input, io::sink());
+            rt.set_utf8(true);
+            let mut code_points = Vec::new();
+            loop {
+                rt.execute(Op::ReadByte).unwrap();
+                match rt.mem.get().to_i64().unwrap() {
+                    -1 => return code_points,
+                    cp => code_points.push(cp),
+                }
+            }
+        };
+        let replacement = char::REPLACEMENT_CHARACTER as i64;
+        assert_eq!(read("aé€😀".as_bytes()), [0x61, 0xE9, 0x20AC, 0x1F600]);
+        // Truncated sequences keep the byte that cut them short
+        assert_eq!(read(b"\xE2\x82a"), [replacement, 0x61]);
+        assert_eq!(read(b"\xF0\x9F\x98"), [replacement]);
+        // Overlong encodings of `)`, `/` and U+FFFF
+        assert_eq!(read(b"\xC0\xA9\xE0\x80\xAF\xF0\x8F\xBF\xBF"), [replacement; 3]);
+        assert_eq!(read(b"\xC2\x80\xE0\xA0\x80\xF0\x90\x80\x80"), [0x80, 0x800, 0x10000]);
+        // A surrogate and a code point past U+10FFFF
+        assert_eq!(read(b"\xED\xA0\x80\xF4\x90\x80\x80"), [replacement; 2]);
+        assert_eq!(read(b"\xF4\x8F\xBF\xBF\xFF"), [0x10FFFF, replacement]);
+    }
+
+    #[test]
+    fn random_values_reach_past_64_bits() {
+        let mut rt = Runtime::new(io::empty(), io::sink());
+        rt.set_random_seed(1);
+        let bound = Integer::from(Integer::u_pow_u(2, 100));
+        rt.mem.set(bound.clone());
+        rt.execute(Op::Random).unwrap();
+        assert!(*rt.mem.get() < bound && rt.mem.get().significant_bits() > 64);
+    }
+
+    #[test]
+    fn integer_output_matches_the_decimal_form() {
+        let huge: Integer = Integer::from(Integer::u_pow_u(10, 30)) + 7;
+        let values = [Integer::from(0), Integer::from(-1), Integer::from(9), Integer::from(-10), Integer::from(i64::MAX),
+                      Integer::from(i64::MIN), Integer::from(i64::MIN) - 1, huge.clone(), -huge];
+        for value in &values {
+            let mut output = Vec::new();
+            let mut rt = Runtime::new(io::empty(), &mut output);
+            rt.mem.set(value.clone());
+            rt.execute(Op::WriteInt).unwrap();
+            drop(rt);
+            assert_eq!(output, value.to_string().into_bytes());
+        }
+    }
+}
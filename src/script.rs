@@ -0,0 +1,556 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use rug::Integer;
+
+use crate::{Error, IP, Op, observer::Observer};
+
+/// An observer that runs the handlers of a small script, for ad-hoc analyses without recompiling.
+///
+/// A script is a list of handlers of the form `on EVENT [when CONDITION] { STATEMENTS }`, where the
+/// event is `tick`, `write` or `output`. Each event makes some variables available to its handlers:
+///
+/// - `tick`: `tick`, `ip` (the index of the active IP), `q`, `r`, `dir` and `op`
+/// - `write`: `tick`, `position` (as `Memory::position` describes it), `old` and `new`
+/// - `output`: `tick`, `text` (the bytes written, as a string) and `len`
+///
+/// The statements are `log EXPR, ...`, which writes the values on a line of the log, `set NAME = EXPR`,
+/// which sets a variable that keeps its value between events and starts at 0, and `stop [EXPR]`,
+/// which stops the program. Expressions are made of integers, strings, `true`, `false`, variables,
+/// `+ - * / %`, comparisons, `! && ||` and parentheses, and `#` starts a comment.
+///
+/// ```
+/// use hexagony::{Builder, script::Script};
+///
+/// let source = r#"
+///     on write when position == "(0, 0, E)" && new > 1000 {
+///         log "edge exceeded 1000 at tick", tick, "with", new
+///         set count = count + 1
+///     }
+/// "#;
+/// let mut script = Script::with_log(source, Vec::new())?;
+/// Builder::new("99999@").output(Vec::new()).observer(&mut script).build()?.run()?;
+/// assert_eq!(script.log(), b"edge exceeded 1000 at tick 3 with 9999\nedge exceeded 1000 at tick 4 with 99999\n");
+/// assert_eq!(script.var("count").unwrap().to_string(), "2");
+/// # Ok::<(), hexagony::Error>(())
+/// ```
+pub struct Script<W: Write = io::Stderr> {
+    handlers: Vec<Handler>,
+    vars: HashMap<String, Value>,
+    log: W,
+}
+
+impl Script {
+    /// Parses a script that logs to STDERR.
+    pub fn new(source: &str) -> Result<Self, Error> {
+        Script::with_log(source, io::stderr())
+    }
+}
+
+impl<W: Write> Script<W> {
+    /// Parses a script that logs to the given writer.
+    pub fn with_log(source: &str, log: W) -> Result<Self, Error> {
+        let mut parser = Parser { tokens: tokenize(source)?, pos: 0 };
+        let mut handlers = Vec::new();
+        while parser.pos < parser.tokens.len() {
+            handlers.push(parser.handler()?);
+        }
+        let mut vars = HashMap::new();
+        for handler in &handlers {
+            for statement in &handler.body {
+                if let Statement::Set(name, _) = statement {
+                    vars.insert(name.clone(), Value::Int(Integer::new()));
+                }
+            }
+        }
+        for handler in &handlers {
+            let known = |name: &str| handler.event.vars().contains(&name) || vars.contains_key(name);
+            let exprs = handler.condition.iter().chain(handler.body.iter().flat_map(Statement::exprs));
+            for expr in exprs {
+                if let Some(name) = expr.unknown_var(&known) {
+                    return Err(Error::ParseError("script", format!("unknown variable `{}` in an on {} handler", name, handler.event)));
+                }
+            }
+        }
+        Ok(Script { handlers, vars, log })
+    }
+
+    /// Returns the current value of a variable set by the script.
+    pub fn var(&self, name: &str) -> Option<&Value> {
+        self.vars.get(name)
+    }
+
+    /// Returns the writer the script logs to.
+    pub fn log(&self) -> &W {
+        &self.log
+    }
+
+    /// Runs the handlers of an event in order, looking up its variables with `event_var`.
+    fn fire(&mut self, event: Event, event_var: &dyn Fn(&str) -> Option<Value>) -> Result<(), Error> {
+        let Script { handlers, vars, log } = self;
+        for handler in handlers.iter().filter(|h| h.event == event) {
+            let lookup = |name: &str| event_var(name).or_else(|| vars.get(name).cloned());
+            if let Some(condition) = &handler.condition {
+                if !condition.eval(&lookup)?.truth()? {
+                    continue;
+                }
+            }
+            for statement in &handler.body {
+                let lookup = |name: &str| event_var(name).or_else(|| vars.get(name).cloned());
+                match statement {
+                    Statement::Log(exprs) => {
+                        let values = exprs.iter().map(|e| e.eval(&lookup).map(|v| v.to_string())).collect::<Result<Vec<_>, _>>()?;
+                        writeln!(log, "{}", values.join(" "))?;
+                    }
+                    Statement::Set(name, expr) => {
+                        let value = expr.eval(&lookup)?;
+                        vars.insert(name.clone(), value);
+                    }
+                    Statement::Stop(expr) => {
+                        let msg = match expr {
+                            Some(expr) => expr.eval(&lookup)?.to_string(),
+                            None => format!("on {} handler", event),
+                        };
+                        return Err(Error::ScriptStopped(msg));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Observer for Script<W> {
+    fn on_tick(&mut self, tick: &Integer, ip_idx: usize, ip: IP, op: Op) -> Result<(), Error> {
+        self.fire(Event::Tick, &|name| Some(match name {
+            "tick" => Value::Int(tick.clone()),
+            "ip" => Value::Int(ip_idx.into()),
+            "q" => Value::Int(ip.coords.0.into()),
+            "r" => Value::Int(ip.coords.1.into()),
+            "dir" => Value::Str(ip.dir.to_string()),
+            "op" => Value::Str(op.to_string()),
+            _ => return None,
+        }))
+    }
+
+    fn on_memory_write(&mut self, tick: &Integer, position: &str, old: &Integer, new: &Integer) -> Result<(), Error> {
+        self.fire(Event::Write, &|name| Some(match name {
+            "tick" => Value::Int(tick.clone()),
+            "position" => Value::Str(position.to_string()),
+            "old" => Value::Int(old.clone()),
+            "new" => Value::Int(new.clone()),
+            _ => return None,
+        }))
+    }
+
+    fn on_output(&mut self, tick: &Integer, bytes: &[u8]) -> Result<(), Error> {
+        self.fire(Event::Output, &|name| Some(match name {
+            "tick" => Value::Int(tick.clone()),
+            "text" => Value::Str(String::from_utf8_lossy(bytes).into_owned()),
+            "len" => Value::Int(bytes.len().into()),
+            _ => return None,
+        }))
+    }
+
+    fn watches_memory(&self) -> bool {
+        self.handlers.iter().any(|h| h.event == Event::Write)
+    }
+}
+
+/// A value in a script.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Int(Integer),
+    Str(String),
+    Bool(bool),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "integer",
+            Value::Str(_) => "string",
+            Value::Bool(_) => "boolean",
+        }
+    }
+
+    fn truth(&self) -> Result<bool, Error> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            v => Err(Error::ScriptError(format!("expected a boolean, found {} {}", v.type_name(), v))),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+/// The events a script can handle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Event {
+    Tick,
+    Write,
+    Output,
+}
+
+impl Event {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tick" => Some(Event::Tick),
+            "write" => Some(Event::Write),
+            "output" => Some(Event::Output),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Event::Tick => "tick",
+            Event::Write => "write",
+            Event::Output => "output",
+        }
+    }
+
+    /// Returns the names of the variables the event makes available.
+    fn vars(self) -> &'static [&'static str] {
+        match self {
+            Event::Tick => &["tick", "ip", "q", "r", "dir", "op"],
+            Event::Write => &["tick", "position", "old", "new"],
+            Event::Output => &["tick", "text", "len"],
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+struct Handler {
+    event: Event,
+    condition: Option<Expr>,
+    body: Vec<Statement>,
+}
+
+enum Statement {
+    Log(Vec<Expr>),
+    Set(String, Expr),
+    Stop(Option<Expr>),
+}
+
+impl Statement {
+    fn exprs(&self) -> Vec<&Expr> {
+        match self {
+            Statement::Log(exprs) => exprs.iter().collect(),
+            Statement::Set(_, expr) => vec![expr],
+            Statement::Stop(expr) => expr.iter().collect(),
+        }
+    }
+}
+
+enum Expr {
+    Value(Value),
+    Var(String),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Returns the first variable in the expression that `known` doesn't accept.
+    fn unknown_var<'e>(&'e self, known: &dyn Fn(&str) -> bool) -> Option<&'e str> {
+        match self {
+            Expr::Value(_) => None,
+            Expr::Var(name) => Some(name.as_str()).filter(|name| !known(name)),
+            Expr::Not(e) | Expr::Negate(e) => e.unknown_var(known),
+            Expr::Binary(_, a, b) => a.unknown_var(known).or_else(|| b.unknown_var(known)),
+        }
+    }
+
+    fn eval(&self, lookup: &dyn Fn(&str) -> Option<Value>) -> Result<Value, Error> {
+        match self {
+            Expr::Value(v) => Ok(v.clone()),
+            Expr::Var(name) => lookup(name).ok_or_else(|| Error::ScriptError(format!("unknown variable `{}`", name))),
+            Expr::Not(e) => Ok(Value::Bool(!e.eval(lookup)?.truth()?)),
+            Expr::Negate(e) => match e.eval(lookup)? {
+                Value::Int(n) => Ok(Value::Int(-n)),
+                v => Err(Error::ScriptError(format!("cannot negate {} {}", v.type_name(), v))),
+            },
+            Expr::Binary("&&", a, b) => Ok(Value::Bool(a.eval(lookup)?.truth()? && b.eval(lookup)?.truth()?)),
+            Expr::Binary("||", a, b) => Ok(Value::Bool(a.eval(lookup)?.truth()? || b.eval(lookup)?.truth()?)),
+            Expr::Binary(op, a, b) => binary(op, a.eval(lookup)?, b.eval(lookup)?),
+        }
+    }
+}
+
+/// Applies an arithmetic or comparison operator, dividing with the same rounding as Hexagony.
+fn binary(op: &str, a: Value, b: Value) -> Result<Value, Error> {
+    let order = match (&a, &b) {
+        (Value::Int(x), Value::Int(y)) => x.cmp(y),
+        (Value::Str(x), Value::Str(y)) => x.cmp(y),
+        (Value::Bool(x), Value::Bool(y)) if matches!(op, "==" | "!=") => x.cmp(y),
+        _ => Ordering::Equal,
+    };
+    let same_type = std::mem::discriminant(&a) == std::mem::discriminant(&b);
+    Ok(match (op, a, b) {
+        ("==", ..) if same_type => Value::Bool(order == Ordering::Equal),
+        ("!=", ..) if same_type => Value::Bool(order != Ordering::Equal),
+        ("<", Value::Int(_), Value::Int(_)) | ("<", Value::Str(_), Value::Str(_)) => Value::Bool(order == Ordering::Less),
+        ("<=", Value::Int(_), Value::Int(_)) | ("<=", Value::Str(_), Value::Str(_)) => Value::Bool(order != Ordering::Greater),
+        (">", Value::Int(_), Value::Int(_)) | (">", Value::Str(_), Value::Str(_)) => Value::Bool(order == Ordering::Greater),
+        (">=", Value::Int(_), Value::Int(_)) | (">=", Value::Str(_), Value::Str(_)) => Value::Bool(order != Ordering::Less),
+        ("+", Value::Str(x), Value::Str(y)) => Value::Str(x + &y),
+        ("+", Value::Int(x), Value::Int(y)) => Value::Int(x + y),
+        ("-", Value::Int(x), Value::Int(y)) => Value::Int(x - y),
+        ("*", Value::Int(x), Value::Int(y)) => Value::Int(x * y),
+        ("/" | "%", Value::Int(_), Value::Int(y)) if y == 0 => return Err(Error::ScriptError("division by zero".to_string())),
+        ("/", Value::Int(x), Value::Int(y)) => Value::Int(x.div_rem_floor(y).0),
+        ("%", Value::Int(x), Value::Int(y)) => Value::Int(x.div_rem_floor(y).1),
+        (op, a, b) => {
+            return Err(Error::ScriptError(format!("cannot apply `{}` to {} {} and {} {}", op, a.type_name(), a, b.type_name(), b)));
+        }
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Int(Integer),
+    Str(String),
+    Name(String),
+    Symbol(&'static str),
+}
+
+/// Symbols in the order they're matched, so that two-character ones win over their prefixes.
+const SYMBOLS: [&str; 20] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "!", "(", ")", "{", "}", ",", "=",
+];
+
+const KEYWORDS: [&str; 7] = ["on", "when", "log", "set", "stop", "true", "false"];
+
+/// Splits a script into tokens, each with the line it's on.
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, Error> {
+    let error = |msg: String, line: usize| Error::ParseError("script", format!("{} on line {}", msg, line));
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    let mut line = 1;
+    while let Some(&(i, c)) = chars.peek() {
+        if c == '\n' {
+            line += 1;
+            chars.next();
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+        } else if c.is_ascii_digit() {
+            let mut digits = String::new();
+            while let Some((_, d)) = chars.next_if(|(_, d)| d.is_ascii_digit()) {
+                digits.push(d);
+            }
+            tokens.push((Token::Int(digits.parse().unwrap()), line));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some((_, d)) = chars.next_if(|(_, d)| d.is_alphanumeric() || *d == '_') {
+                name.push(d);
+            }
+            tokens.push((Token::Name(name), line));
+        } else if c == '"' {
+            let start = line;
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, 'n')) => s.push('\n'),
+                        Some((_, c @ ('"' | '\\'))) => s.push(c),
+                        _ => return Err(error("invalid escape in string".to_string(), line)),
+                    },
+                    Some((_, c)) => {
+                        line += (c == '\n') as usize;
+                        s.push(c);
+                    }
+                    None => return Err(error("unterminated string".to_string(), start)),
+                }
+            }
+            tokens.push((Token::Str(s), start));
+        } else {
+            let symbol = SYMBOLS.iter().find(|s| source[i..].starts_with(*s))
+                .ok_or_else(|| error(format!("unexpected character `{}`", c), line))?;
+            chars.nth(symbol.len() - 1);
+            tokens.push((Token::Symbol(symbol), line));
+        }
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser over the tokens of a script.
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, msg: &str) -> Error {
+        match self.tokens.get(self.pos) {
+            Some((_, line)) => Error::ParseError("script", format!("{} on line {}", msg, line)),
+            None => Error::ParseError("script", format!("{} at the end", msg)),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol)
+    }
+
+    fn is_name(&self, name: &str) -> bool {
+        matches!(self.peek(), Some(Token::Name(n)) if n == name)
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), Error> {
+        if !self.is_symbol(symbol) {
+            return Err(self.error(&format!("expected `{}`", symbol)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<String, Error> {
+        match self.peek() {
+            Some(Token::Name(n)) if !KEYWORDS.contains(&n.as_str()) => {
+                let n = n.clone();
+                self.pos += 1;
+                Ok(n)
+            }
+            _ => Err(self.error("expected a name")),
+        }
+    }
+
+    fn handler(&mut self) -> Result<Handler, Error> {
+        if !self.is_name("on") {
+            return Err(self.error("expected `on`"));
+        }
+        self.pos += 1;
+        let event = match self.peek() {
+            Some(Token::Name(n)) => Event::from_name(n),
+            _ => None,
+        }.ok_or_else(|| self.error("expected `tick`, `write` or `output`"))?;
+        self.pos += 1;
+        let condition = match self.is_name("when") {
+            true => {
+                self.pos += 1;
+                Some(self.expr()?)
+            }
+            false => None,
+        };
+        self.expect_symbol("{")?;
+        let mut body = Vec::new();
+        while !self.is_symbol("}") {
+            body.push(self.statement()?);
+        }
+        self.pos += 1;
+        Ok(Handler { event, condition, body })
+    }
+
+    fn statement(&mut self) -> Result<Statement, Error> {
+        if self.is_name("log") {
+            self.pos += 1;
+            let mut exprs = vec![self.expr()?];
+            while self.is_symbol(",") {
+                self.pos += 1;
+                exprs.push(self.expr()?);
+            }
+            Ok(Statement::Log(exprs))
+        } else if self.is_name("set") {
+            self.pos += 1;
+            let name = self.name()?;
+            if [Event::Tick, Event::Write, Event::Output].iter().any(|e| e.vars().contains(&name.as_str())) {
+                return Err(self.error(&format!("`{}` is the name of an event variable", name)));
+            }
+            self.expect_symbol("=")?;
+            Ok(Statement::Set(name, self.expr()?))
+        } else if self.is_name("stop") {
+            self.pos += 1;
+            let ends = self.is_symbol("}") || ["log", "set", "stop"].iter().any(|k| self.is_name(k));
+            Ok(Statement::Stop(if ends { None } else { Some(self.expr()?) }))
+        } else {
+            Err(self.error("expected `log`, `set`, `stop` or `}`"))
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, Error> {
+        self.binary(0)
+    }
+
+    /// Parses operators of the given precedence level and above, which associate to the left.
+    fn binary(&mut self, level: usize) -> Result<Expr, Error> {
+        const LEVELS: [&[&str]; 5] = [&["||"], &["&&"], &["==", "!=", "<", "<=", ">", ">="], &["+", "-"], &["*", "/", "%"]];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut expr = self.binary(level + 1)?;
+        while let Some(&op) = LEVELS[level].iter().find(|op| self.is_symbol(op)) {
+            self.pos += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.binary(level + 1)?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, Error> {
+        let token = self.peek().cloned().ok_or_else(|| self.error("expected an expression"))?;
+        self.pos += 1;
+        Ok(match token {
+            Token::Symbol("!") => Expr::Not(Box::new(self.unary()?)),
+            Token::Symbol("-") => Expr::Negate(Box::new(self.unary()?)),
+            Token::Symbol("(") => {
+                let expr = self.expr()?;
+                self.expect_symbol(")")?;
+                expr
+            }
+            Token::Int(n) => Expr::Value(Value::Int(n)),
+            Token::Str(s) => Expr::Value(Value::Str(s)),
+            Token::Name(n) if n == "true" => Expr::Value(Value::Bool(true)),
+            Token::Name(n) if n == "false" => Expr::Value(Value::Bool(false)),
+            Token::Name(n) if !KEYWORDS.contains(&n.as_str()) => Expr::Var(n),
+            _ => {
+                self.pos -= 1;
+                return Err(self.error("expected an expression"));
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Builder;
+
+    #[test]
+    fn scripts_watch_ticks_and_output_and_can_stop() {
+        let source = "on output { set total = total + len } on tick when op == \"@\" { log \"end at\", q, r, dir } \
+                      on output when total >= 3 { stop \"three bytes\" }";
+        let mut script = Script::with_log(source, Vec::new()).unwrap();
+        let mut output = Vec::new();
+        let result = Builder::new("1!2!3!@").output(&mut output).observer(&mut script).build().unwrap().run();
+        assert!(matches!(result, Err(Error::ScriptStopped(msg)) if msg == "three bytes"));
+        assert_eq!(script.var("total"), Some(&Value::Int(Integer::from(3))));
+        let mut script = Script::with_log(source.replace(">= 3", "> 9").as_str(), Vec::new()).unwrap();
+        Builder::new("1!2!3!@").output(io::sink()).observer(&mut script).build().unwrap().run().unwrap();
+        assert_eq!(script.log(), b"end at 0 1 E\n");
+        assert!(Script::new("on write { log nope }").is_err());
+        assert!(Script::new("on tick { set op = 1 }").is_err());
+        let mut script = Script::with_log("on tick { log tick / 0 }", io::sink()).unwrap();
+        assert!(matches!(Builder::new("@").observer(&mut script).build().unwrap().run(), Err(Error::ScriptError(_))));
+    }
+}
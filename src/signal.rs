@@ -0,0 +1,83 @@
+use std::{io::{self, Read}, sync::atomic::{AtomicBool, Ordering}};
+
+/// Set by the Ctrl-C handler installed with `catch_interrupt`.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Set by the SIGUSR1 handler installed with `catch_dump_signal`.
+#[cfg(unix)]
+static DUMP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs a handler for Ctrl-C that sets the returned flag instead of ending the process.
+///
+/// The flag can be passed to `Hexagony::run_until` so an interrupted program stops cleanly.
+/// On Unix, Ctrl-C also interrupts a blocked read, which an `Interruptible` reader turns into an error.
+pub fn catch_interrupt() -> &'static AtomicBool {
+    #[cfg(unix)]
+    {
+        extern "C" fn handler(_: libc::c_int) {
+            INTERRUPTED.store(true, Ordering::Relaxed);
+        }
+        // Without SA_RESTART, so that reads waiting for input fail with EINTR
+        install(libc::SIGINT, handler, 0);
+    }
+    #[cfg(windows)]
+    {
+        extern "system" fn handler(_: u32) -> i32 {
+            INTERRUPTED.store(true, Ordering::Relaxed);
+            // Handled, so the process isn't ended
+            1
+        }
+        extern "system" {
+            fn SetConsoleCtrlHandler(handler: Option<extern "system" fn(u32) -> i32>, add: i32) -> i32;
+        }
+        unsafe {
+            SetConsoleCtrlHandler(Some(handler), 1);
+        }
+    }
+    &INTERRUPTED
+}
+
+/// Installs a handler for SIGUSR1 that sets the returned flag instead of ending the process.
+///
+/// The flag can be passed to `Builder::dump_when` so that `kill -USR1` prints the state of a
+/// running program without stopping it.
+#[cfg(unix)]
+pub fn catch_dump_signal() -> &'static AtomicBool {
+    extern "C" fn handler(_: libc::c_int) {
+        DUMP_REQUESTED.store(true, Ordering::Relaxed);
+    }
+    // The program keeps running after a dump, so reads waiting for input carry on
+    install(libc::SIGUSR1, handler, libc::SA_RESTART);
+    &DUMP_REQUESTED
+}
+
+/// Installs `handler` for `signal` with `sigaction` and the given flags.
+#[cfg(unix)]
+fn install(signal: libc::c_int, handler: extern "C" fn(libc::c_int), flags: libc::c_int) {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as libc::sighandler_t;
+        action.sa_flags = flags;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, std::ptr::null_mut());
+    }
+}
+
+/// A reader that fails once Ctrl-C has been caught by `catch_interrupt`, instead of retrying a read
+/// that the signal interrupted, so a program waiting for input can still be stopped.
+pub struct Interruptible<R>(pub R);
+
+impl<R: Read> Read for Interruptible<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.0.read(buf) {
+                // Callers retry reads that fail with `Interrupted`, so report another kind of error
+                Err(e) if e.kind() == io::ErrorKind::Interrupted && INTERRUPTED.load(Ordering::Relaxed) => {
+                    return Err(io::Error::other("interrupted by Ctrl-C"));
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                result => return result,
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt;
+
+use crate::IntEof;
+
+/// A revision of the Hexagony specification.
+///
+/// Changes to the meaning of programs get a new revision, and older revisions keep their behavior,
+/// so a program can pin the revision it was written for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SpecVersion {
+    /// The language as described on the esolangs wiki and implemented by the reference interpreter
+    V1,
+}
+
+/// The behavior of a revision of the specification, which the interpreter consults when parsing
+/// and running programs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Number of IPs a program starts with
+    pub ips: usize,
+    /// Value that `,` reads at EOF
+    pub byte_eof: i64,
+    /// What `?` does when it reaches EOF before finding a number
+    pub int_eof: IntEof,
+}
+
+/// Every revision from oldest to newest, along with its capabilities.
+const REVISIONS: &[(SpecVersion, Capabilities)] = &[
+    (SpecVersion::V1, Capabilities { ips: 6, byte_eof: -1, int_eof: IntEof::Zero }),
+];
+
+impl SpecVersion {
+    /// The newest revision, which programs are run with by default.
+    pub const LATEST: SpecVersion = SpecVersion::V1;
+
+    /// Returns an iterator over every revision from oldest to newest.
+    pub fn all() -> impl Iterator<Item = SpecVersion> {
+        REVISIONS.iter().map(|&(version, _)| version)
+    }
+
+    /// Returns the revision with the given number, or `None` if there isn't one.
+    pub fn from_number(n: u32) -> Option<SpecVersion> {
+        SpecVersion::all().find(|version| version.number() == n)
+    }
+
+    /// Returns the number of this revision, starting from 1.
+    pub fn number(self) -> u32 {
+        match self {
+            SpecVersion::V1 => 1,
+        }
+    }
+
+    /// Returns the capabilities of this revision.
+    pub fn capabilities(self) -> Capabilities {
+        REVISIONS.iter().find(|&&(version, _)| version == self).unwrap().1
+    }
+}
+
+impl Default for SpecVersion {
+    fn default() -> SpecVersion {
+        SpecVersion::LATEST
+    }
+}
+
+impl fmt::Display for SpecVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.number())
+    }
+}
@@ -0,0 +1,192 @@
+use std::{collections::VecDeque, fmt, fs, path::PathBuf, str::FromStr};
+use rug::Integer;
+
+use crate::{Error, IP, ValueType, Wrapping, coords::PointAxial, grid::{Dialect, Grid, SourceFormat}, memory::{AnyMemory, Memory, MemoryModel}};
+
+/// A snapshot of a running program that can be written to a file and resumed later with
+/// `Builder::resume`.
+///
+/// The snapshot holds the grid, memory, IPs, tick count and the number of bytes of input the
+/// program has read, along with the settings that change what the program does: the dialect,
+/// wrapping rule, value type and memory model. Output, statistics and other interpreter settings
+/// aren't saved.
+#[derive(Clone)]
+pub struct SaveState {
+    pub(crate) grid: Grid,
+    pub(crate) mem: AnyMemory,
+    pub(crate) ips: Vec<IP>,
+    pub(crate) ip_idx: usize,
+    pub(crate) tick: Integer,
+    pub(crate) input_position: u64,
+    pub(crate) dialect: Dialect,
+    pub(crate) wrapping: Wrapping,
+    pub(crate) value_type: ValueType,
+}
+
+/// First line of every save state, to reject other files and future formats.
+const HEADER: &str = "hexagony-state 1";
+
+impl SaveState {
+    /// Returns the tick the program was on when the state was saved.
+    pub fn tick(&self) -> &Integer {
+        &self.tick
+    }
+
+    /// Returns the number of bytes of input the program had read when the state was saved.
+    pub fn input_position(&self) -> u64 {
+        self.input_position
+    }
+}
+
+impl fmt::Display for SaveState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "tick {}", self.tick)?;
+        writeln!(f, "input {}", self.input_position)?;
+        writeln!(f, "active {}", self.ip_idx)?;
+        for (i, ip) in self.ips.iter().enumerate() {
+            writeln!(f, "ip {} {} {} {}", i, ip.coords.0, ip.coords.1, ip.dir)?;
+        }
+        // Settings are only written when they differ from the defaults
+        if self.dialect != Dialect::Standard {
+            writeln!(f, "dialect {}", self.dialect.name())?;
+        }
+        if self.wrapping != Wrapping::Standard {
+            writeln!(f, "wrapping {}", self.wrapping.name())?;
+        }
+        if self.value_type != ValueType::Unbounded {
+            writeln!(f, "values {}", self.value_type.name())?;
+        }
+        if self.mem.model() != MemoryModel::Hex {
+            writeln!(f, "memory {}", self.mem.model().name())?;
+        }
+        self.mem.write_state(f)?;
+        writeln!(f, "grid")?;
+        write!(f, "{}", self.grid)
+    }
+}
+
+impl FromStr for SaveState {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut lines = s.lines();
+        if lines.next().map(str::trim_end) != Some(HEADER) {
+            return Err(Error::ParseError("save state", "missing header".to_string()));
+        }
+        let mut mem = MemoryModel::Hex.create();
+        let mut ips = Vec::new();
+        let (mut ip_idx, mut tick, mut input_position) = (None, None, None);
+        let mut grid = None;
+        let (mut dialect, mut wrapping, mut value_type) = (Dialect::Standard, Wrapping::Standard, ValueType::Unbounded);
+        while let Some(line) = lines.next() {
+            let invalid = || Error::ParseError("save state", line.to_string());
+            let words: Vec<_> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => (),
+                ["tick", n] => tick = Some(n.parse::<Integer>().ok().filter(|n| *n >= 0).ok_or_else(invalid)?),
+                ["input", n] => input_position = Some(n.parse().map_err(|_| invalid())?),
+                ["active", i] => ip_idx = Some(i.parse().map_err(|_| invalid())?),
+                ["ip", i, q, r, dir] => {
+                    let i: usize = i.parse().ok().filter(|&i| i < 256).ok_or_else(invalid)?;
+                    if ips.len() <= i {
+                        ips.resize(i + 1, None);
+                    }
+                    let coords = PointAxial(q.parse().map_err(|_| invalid())?, r.parse().map_err(|_| invalid())?);
+                    ips[i] = Some(IP { coords, dir: dir.parse()? });
+                }
+                ["dialect", name] => dialect = Dialect::from_name(name).ok_or_else(invalid)?,
+                ["wrapping", name] => wrapping = Wrapping::from_name(name).ok_or_else(invalid)?,
+                ["values", name] => value_type = ValueType::from_name(name).ok_or_else(invalid)?,
+                ["memory", name] => mem = MemoryModel::from_name(name).ok_or_else(invalid)?.create(),
+                ["grid"] => {
+                    // The grid takes up the rest of the file, written out in full
+                    let text = lines.by_ref().collect::<Vec<_>>().join("\n");
+                    grid = Some(Grid::parse_with(&text, SourceFormat::Linear, dialect, usize::MAX, |_| false)?);
+                }
+                _ => mem.read_state_line(&words).ok_or_else(invalid)?,
+            }
+        }
+        let missing = |what: &str| Error::ParseError("save state", format!("missing {}", what));
+        let grid = grid.ok_or_else(|| missing("grid"))?;
+        let ips = ips.into_iter().map(|ip| ip.ok_or_else(|| missing("IP"))).collect::<Result<Vec<_>, _>>()?;
+        if let Some(ip) = ips.iter().find(|ip| !grid.contains(ip.coords)) {
+            return Err(Error::ParseError("save state", format!("IP at {} lies outside the grid", ip.coords)));
+        }
+        let ip_idx = ip_idx.ok_or_else(|| missing("active IP"))?;
+        if ip_idx >= ips.len() {
+            return Err(Error::ParseError("save state", format!("active IP {} doesn't exist", ip_idx)));
+        }
+        Ok(SaveState {
+            grid,
+            mem,
+            ips,
+            ip_idx,
+            tick: tick.ok_or_else(|| missing("tick"))?,
+            input_position: input_position.ok_or_else(|| missing("input position"))?,
+            dialect,
+            wrapping,
+            value_type,
+        })
+    }
+}
+
+/// Saves the state of a running program every few ticks, keeping only the most recent files.
+pub(crate) struct Checkpoints {
+    pub(crate) every: u64,
+    dir: PathBuf,
+    keep: usize,
+    saved: VecDeque<PathBuf>,
+}
+
+impl Checkpoints {
+    /// Creates a new `Checkpoints` that saves every `every` ticks and keeps at most `keep` files in `dir`.
+    pub(crate) fn new(every: u64, dir: PathBuf, keep: usize) -> Checkpoints {
+        Checkpoints { every, dir, keep, saved: VecDeque::new() }
+    }
+
+    /// Writes a checkpoint named after its tick, deleting the oldest checkpoints past the limit.
+    pub(crate) fn save(&mut self, state: &SaveState) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("tick-{}.state", state.tick));
+        fs::write(&path, state.to_string())?;
+        self.saved.push_back(path);
+        while self.saved.len() > self.keep {
+            fs::remove_file(self.saved.pop_front().unwrap())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Builder, examples};
+
+    #[test]
+    fn save_state_rejects_out_of_range_ips_and_ticks() {
+        let state = "hexagony-state 1\ntick 0\ninput 0\nactive 0\nip 0 9223372036854775807 9223372036854775807 E\ngrid\n.";
+        assert!(state.parse::<SaveState>().is_err());
+        let state = |tick| format!("hexagony-state 1\ntick {}\ninput 0\nactive 0\nip 0 0 0 E\ngrid\n.", tick);
+        assert!(matches!(state("-5").parse::<SaveState>(), Err(Error::ParseError("save state", line)) if line == "tick -5"));
+        assert_eq!(*state("5").parse::<SaveState>().unwrap().tick(), 5);
+    }
+
+    #[test]
+    fn resumed_programs_finish_like_uninterrupted_ones() {
+        let src = examples::find("primes").unwrap().source();
+        let mut expected = Vec::new();
+        Builder::new(src).input(&b"97"[..]).output(&mut expected).build().unwrap().run().unwrap();
+        for &stop in &[1, 2, 10, 100, 1000] {
+            let mut output = Vec::new();
+            let mut hex = Builder::new(src).input(&b"97"[..]).output(&mut output).max_ticks(stop).build().unwrap();
+            assert!(matches!(hex.run(), Err(Error::TickLimitExceeded)));
+            let state: SaveState = hex.save_state().unwrap().to_string().parse().unwrap();
+            drop(hex);
+            assert_eq!(*state.tick(), stop);
+            let hex = Builder::resume(state).input(&b"97"[..]).output(&mut output);
+            hex.build().unwrap().run().unwrap();
+            assert_eq!(output, expected, "stopped after {} ticks", stop);
+        }
+    }
+}
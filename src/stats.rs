@@ -0,0 +1,124 @@
+use std::{collections::HashSet, fmt};
+use rug::Integer;
+
+use crate::{coords::PointAxial, direction::Direction, grid::Grid};
+
+/// Execution statistics collected for a single IP.
+#[derive(Clone, Default)]
+pub struct IPStats {
+    /// Number of ticks during which the IP was active
+    pub ticks: u64,
+    /// Grid cells executed by the IP
+    pub visited: HashSet<PointAxial>,
+}
+
+/// A snapshot of the execution statistics of a Hexagony program.
+pub struct Stats {
+    /// Total number of ticks executed
+    pub ticks: Integer,
+    /// Total number of ticks charged by the cost model, which is `ticks` under `CostModel::Uniform`
+    pub cost: Integer,
+    /// Statistics, position and direction of each IP
+    pub ips: Vec<(IPStats, PointAxial, Direction)>,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Ticks: {}", self.ticks)?;
+        if self.cost != self.ticks {
+            writeln!(f, "Cost: {} ticks", self.cost)?;
+        }
+        for (i, (stats, coords, dir)) in self.ips.iter().enumerate() {
+            writeln!(f, "IP {}: {} ticks active, ended at {} facing {}", i, stats.ticks, coords, dir)?;
+            let mut visited: Vec<_> = stats.visited.iter().collect();
+            // Sort cells in row-major order
+            visited.sort_by_key(|&&PointAxial(q, r)| (r, q));
+            write!(f, "  {} cells visited:", visited.len())?;
+            for cell in visited {
+                write!(f, " {}", cell)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Statistics that an interpreter collects for a single IP while it runs, turned into `IPStats` on request.
+#[derive(Clone)]
+pub(crate) struct IPCounters {
+    pub(crate) ticks: u64,
+    pub(crate) visited: CellSet,
+}
+
+impl IPCounters {
+    /// Creates counters for an IP on a grid of the given side length.
+    pub(crate) fn new(size: usize) -> IPCounters {
+        IPCounters { ticks: 0, visited: CellSet::new(size) }
+    }
+
+    /// Returns the statistics collected so far, for the grid the IP runs on.
+    pub(crate) fn to_stats(&self, grid: &Grid) -> IPStats {
+        let visited = grid.cells()
+            .map(|(coords, _, _)| coords)
+            .filter(|&coords| self.visited.contains(grid.axial_to_index(coords)))
+            .collect();
+        IPStats { ticks: self.ticks, visited }
+    }
+}
+
+/// A set of cells of a grid, with one bit for each row and column index of `Grid::axial_to_index`,
+/// which is cheap enough to update on every tick.
+#[derive(Clone)]
+pub(crate) struct CellSet {
+    bits: Vec<u64>,
+    /// Number of columns of the widest row
+    width: usize,
+}
+
+impl CellSet {
+    /// Creates an empty set for a grid of the given side length.
+    pub(crate) fn new(size: usize) -> CellSet {
+        let width = (2 * size).saturating_sub(1);
+        CellSet { bits: vec![0; (width * width).div_ceil(64)], width }
+    }
+
+    /// Adds the cell with the given row and column index.
+    pub(crate) fn insert(&mut self, (row, col): (usize, usize)) {
+        let bit = row * self.width + col;
+        self.bits[bit / 64] |= 1 << (bit % 64);
+    }
+
+    /// Returns whether the set holds the cell with the given row and column index.
+    pub(crate) fn contains(&self, (row, col): (usize, usize)) -> bool {
+        let bit = row * self.width + col;
+        self.bits[bit / 64] & 1 << (bit % 64) != 0
+    }
+
+    /// Returns the number of cells in the set.
+    pub(crate) fn len(&self) -> usize {
+        self.bits.iter().map(|word| word.count_ones() as usize).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::{Builder, coords::PointAxial};
+
+    #[test]
+    fn stats_list_the_cells_each_ip_visited() {
+        let mut hex = Builder::new("1!@").output(io::sink()).build().unwrap();
+        hex.run().unwrap();
+        let stats = hex.stats();
+        let (first, _, _) = &stats.ips[0];
+        assert_eq!(first.ticks, 2);
+        let mut visited: Vec<_> = first.visited.iter().copied().collect();
+        visited.sort_by_key(|&PointAxial(q, r)| (r, q));
+        assert_eq!(visited, [PointAxial(0, -1), PointAxial(1, -1), PointAxial(-1, 0)]);
+        assert!(stats.ips[1..].iter().all(|(stats, _, _)| stats.ticks == 0 && stats.visited.is_empty()));
+        let mut hex = Builder::new("@").build().unwrap();
+        hex.run().unwrap();
+        assert_eq!(hex.stats().ips[0].0.visited.len(), 1);
+    }
+}
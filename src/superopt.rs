@@ -0,0 +1,79 @@
+use std::thread;
+
+use crate::cases::TestCase;
+
+/// Searches all programs up to the given hexagon size that consist of the given ops,
+/// and returns the shortest ones that pass every test case within `max_ticks` ticks.
+///
+/// Programs ending in a no-op are skipped since they are equivalent to shorter programs.
+/// The search is split across all available CPU cores.
+pub fn superoptimize(cases: &[TestCase], ops: &[char], max_size: usize, max_ticks: u64) -> Vec<String> {
+    let max_len = if max_size == 0 { 0 } else { 3 * max_size * (max_size - 1) + 1 };
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    for len in 1..=max_len {
+        let mut found: Vec<String> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads)
+                .map(|t| scope.spawn(move || search(cases, ops, len, max_ticks, t, threads)))
+                .collect();
+            handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+        });
+        if !found.is_empty() {
+            found.sort();
+            return found;
+        }
+    }
+    Vec::new()
+}
+
+/// Returns all passing programs of the given length whose first op has an index in `ops`
+/// congruent to `offset` modulo `stride`.
+fn search(cases: &[TestCase], ops: &[char], len: usize, max_ticks: u64, offset: usize, stride: usize) -> Vec<String> {
+    let mut found = Vec::new();
+    let mut digits = vec![0; len];
+    for first in (offset..ops.len()).step_by(stride) {
+        digits.iter_mut().for_each(|d| *d = 0);
+        digits[0] = first;
+        loop {
+            let program: String = digits.iter().map(|&d| ops[d]).collect();
+            if !program.ends_with('.')
+                && program.contains('@')
+                && cases.iter().all(|case| case.check(&program, max_ticks))
+            {
+                found.push(program);
+            }
+            if !increment(&mut digits[1..], ops.len()) {
+                break;
+            }
+        }
+    }
+    found
+}
+
+/// Advances a little-endian odometer of digits in the given base.
+///
+/// Returns `false` once every digit has wrapped around to zero.
+fn increment(digits: &mut [usize], base: usize) -> bool {
+    for d in digits.iter_mut().rev() {
+        *d += 1;
+        if *d < base {
+            return true;
+        }
+        *d = 0;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn superoptimizing_finds_the_shortest_passing_programs() {
+        let case = |input: &str, output: &str| TestCase { name: String::new(), input: input.into(), output: output.into() };
+        assert_eq!(superoptimize(&[case("", "1")], &['.', '1', '!', '@'], 2, 100), ["1!@"]);
+        let cat = [case("a", "a"), case("b", "b")];
+        assert_eq!(superoptimize(&cat, &['.', ',', ';', '@'], 2, 100), [",;@"]);
+        // Nothing passes with these ops
+        assert!(superoptimize(&[case("", "2")], &['.', '!', '@'], 2, 100).is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+use std::io::Cursor;
+use rug::Integer;
+
+use crate::{Builder, coords::PointAxial, grid::{Grid, Op}, memory::{HexMemory, Memory}};
+
+/// Maximum number of ticks a program may run for in `run_program` and `assert_program!`.
+pub const MAX_TICKS: u64 = 1_000_000;
+
+/// Asserts that a program prints the expected output when run with the given input, which may be
+/// string or byte slices, and no input if it's left out.
+///
+/// ```
+/// use hexagony::assert_program;
+///
+/// assert_program!("?)!@", "41" => "42");
+/// assert_program!("H;i;@" => b"Hi");
+/// ```
+///
+/// The program fails the assertion if it doesn't terminate within `testing::MAX_TICKS` ticks.
+#[macro_export]
+macro_rules! assert_program {
+    ($src:expr, $input:expr => $output:expr) => {
+        $crate::testing::assert_output($src, AsRef::<[u8]>::as_ref(&$input), AsRef::<[u8]>::as_ref(&$output))
+    };
+    ($src:expr => $output:expr) => {
+        $crate::testing::assert_output($src, b"", AsRef::<[u8]>::as_ref(&$output))
+    };
+}
+
+/// Runs a program with the given input and returns its output, panicking if it fails
+/// or doesn't terminate within `MAX_TICKS` ticks.
+pub fn run_program(src: &str, input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let result = Builder::new(src)
+        .input(Cursor::new(input.to_vec()))
+        .output(&mut output)
+        .max_ticks(MAX_TICKS)
+        .build()
+        .and_then(|mut hex| hex.run());
+    if let Err(e) = result {
+        panic!("program failed: {}\n  output so far: {:?}", e, String::from_utf8_lossy(&output));
+    }
+    output
+}
+
+/// Panics unless a program prints `expected` when run with `input`, as `assert_program!` does.
+#[track_caller]
+pub fn assert_output(src: &str, input: &[u8], expected: &[u8]) {
+    let output = run_program(src, input);
+    if output != expected {
+        panic!(
+            "program printed the wrong output\n  input:    {:?}\n  expected: {:?}\n  got:      {:?}",
+            String::from_utf8_lossy(input), String::from_utf8_lossy(expected), String::from_utf8_lossy(&output),
+        );
+    }
+}
+
+/// Creates a grid of the given side length that is empty except for the given ops,
+/// each written as its character at a pair of axial coordinates.
+///
+/// # Panics
+///
+/// Panics if a character isn't a standard command or a cell is outside the grid.
+pub fn grid(size: usize, cells: &[((isize, isize), char)]) -> Grid {
+    let mut grid = Grid::new(size);
+    for &((q, r), c) in cells {
+        let op = Op::from_char(c).unwrap_or_else(|| panic!("`{}` is not a command", c));
+        assert!(grid.contains(PointAxial(q, r)), "({}, {}) is outside a grid of size {}", q, r, size);
+        grid.set(PointAxial(q, r), (op, false));
+    }
+    grid
+}
+
+/// Creates hexagonal memory whose current edge, left neighbour and right neighbour hold the given values,
+/// which is the setup the arithmetic commands need.
+pub fn memory(current: impl Into<Integer>, left: impl Into<Integer>, right: impl Into<Integer>) -> HexMemory {
+    let mut mem = HexMemory::new();
+    mem.set(current.into());
+    // `{` is undone by `"` and `}` by `'`
+    mem.move_left();
+    mem.set(left.into());
+    mem.reverse();
+    mem.move_right();
+    mem.reverse();
+    mem.move_right();
+    mem.set(right.into());
+    mem.reverse();
+    mem.move_left();
+    mem.reverse();
+    mem
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory;
+
+    #[test]
+    fn testing_fixtures() {
+        assert_program!("?)!@", "41" => "42");
+        let mem = memory::AnyMemory::Hex(memory(1, 2, 3));
+        assert_eq!((mem.get(), mem.get_left(), mem.get_right()), (&Integer::from(1), &Integer::from(2), &Integer::from(3)));
+        assert_eq!(grid(2, &[((0, 0), '@')]).get(PointAxial(0, 0)).0, Op::Terminate);
+    }
+}
@@ -0,0 +1,400 @@
+use std::{fmt, io::{self, Cursor, Write}, str};
+use rug::Integer;
+
+use crate::{
+    Builder, Direction, Error, IntEof, Redirect, ValueType, Wrapping,
+    coords::PointAxial,
+    direction::{redirect, redirect_sources},
+    grid::{Dialect, Grid, Op, SourceFormat},
+    memory::{Memory, MemoryModel},
+};
+
+/// First bytes of every trace, followed by a format version.
+const MAGIC: &[u8; 5] = b"HXGT\x02";
+
+/// The settings a trace was recorded with that affect how the program runs.
+#[derive(Clone, Copy)]
+pub(crate) struct Settings {
+    pub(crate) utf8: bool,
+    pub(crate) byte_eof: i64,
+    pub(crate) int_eof: IntEof,
+    pub(crate) dialect: Dialect,
+    pub(crate) wrapping: Wrapping,
+    pub(crate) ip_count: usize,
+    pub(crate) value_type: ValueType,
+    pub(crate) memory_model: MemoryModel,
+    pub(crate) random_seed: u64,
+}
+
+/// Writes the header of a trace: the settings the program runs with and its grid.
+pub(crate) fn write_header(w: &mut dyn Write, grid: &Grid, settings: &Settings) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&[settings.utf8 as u8])?;
+    w.write_all(&settings.byte_eof.to_le_bytes())?;
+    w.write_all(&[match settings.int_eof {
+        IntEof::Zero => 0,
+        IntEof::MinusOne => 1,
+        IntEof::Unchanged => 2,
+    }])?;
+    for name in [settings.dialect.name(), settings.wrapping.name(), settings.value_type.name(), settings.memory_model.name()] {
+        w.write_all(&[name.len() as u8])?;
+        w.write_all(name.as_bytes())?;
+    }
+    w.write_all(&(settings.ip_count as u32).to_le_bytes())?;
+    w.write_all(&settings.random_seed.to_le_bytes())?;
+    let src = grid.to_string();
+    w.write_all(&(src.len() as u32).to_le_bytes())?;
+    w.write_all(src.as_bytes())
+}
+
+/// Writes a record of an executed tick: the active IP, where it was, the op it executed and
+/// the low 64 bits of the current memory edge afterwards.
+pub(crate) fn write_tick(w: &mut dyn Write, ip_idx: usize, coords: PointAxial, dir: Direction, op: Op, value: u64) -> io::Result<()> {
+    w.write_all(b"T")?;
+    w.write_all(&[ip_idx as u8])?;
+    w.write_all(&(coords.0 as i64).to_le_bytes())?;
+    w.write_all(&(coords.1 as i64).to_le_bytes())?;
+    w.write_all(&[dir.index() as u8])?;
+    w.write_all(&(char::from(op) as u32).to_le_bytes())?;
+    w.write_all(&value.to_le_bytes())
+}
+
+/// Writes a record of the input consumed by the previous tick.
+pub(crate) fn write_input(w: &mut dyn Write, bytes: &[u8], eof: bool) -> io::Result<()> {
+    w.write_all(b"I")?;
+    w.write_all(&[eof as u8])?;
+    w.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    w.write_all(bytes)
+}
+
+/// Writes a record that the previous tick terminated the program.
+pub(crate) fn write_end(w: &mut dyn Write) -> io::Result<()> {
+    w.write_all(b"E")
+}
+
+/// One record of a trace.
+enum Record {
+    Tick { ip_idx: usize, coords: PointAxial, dir: Direction, op: char, value: u64 },
+    Input { bytes: Vec<u8>, eof: bool },
+    End,
+}
+
+/// Reads the fields of a trace in order.
+struct Reader<'t> {
+    trace: &'t [u8],
+    pos: usize,
+}
+
+impl<'t> Reader<'t> {
+    /// Returns the next `n` bytes, or fails if the trace is truncated.
+    fn take(&mut self, n: usize) -> Result<&'t [u8], Error> {
+        let bytes = self.trace.get(self.pos..self.pos + n)
+            .ok_or_else(|| Error::ParseError("trace", format!("truncated at byte {}", self.pos)))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    /// Reads a byte.
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Reads a little-endian `u32`.
+    fn u32(&mut self) -> Result<u32, Error> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    /// Reads a little-endian `u64`.
+    fn u64(&mut self) -> Result<u64, Error> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Reads a name written with a length byte in front of it.
+    fn name(&mut self) -> Result<&'t str, Error> {
+        let len = self.u8()? as usize;
+        str::from_utf8(self.take(len)?).map_err(|_| Error::ParseError("trace", "setting isn't UTF-8".to_string()))
+    }
+
+    /// Returns the next record, or `None` at the end of the trace.
+    fn record(&mut self) -> Result<Option<Record>, Error> {
+        if self.pos == self.trace.len() {
+            return Ok(None);
+        }
+        let record = match self.u8()? {
+            b'T' => Record::Tick {
+                ip_idx: self.u8()? as usize,
+                coords: PointAxial(self.u64()? as i64 as isize, self.u64()? as i64 as isize),
+                dir: Direction::from_index(self.u8()? as usize),
+                op: std::char::from_u32(self.u32()?).unwrap_or(char::REPLACEMENT_CHARACTER),
+                value: self.u64()?,
+            },
+            b'I' => {
+                let eof = self.u8()? != 0;
+                let len = self.u32()? as usize;
+                Record::Input { bytes: self.take(len)?.to_vec(), eof }
+            }
+            b'E' => Record::End,
+            tag => return Err(Error::ParseError("trace", format!("unknown record {:?} at byte {}", tag as char, self.pos - 1))),
+        };
+        Ok(Some(record))
+    }
+}
+
+/// Parses the header and records of a trace.
+fn parse(trace: &[u8]) -> Result<(Grid, Settings, Vec<Record>), Error> {
+    let mut reader = Reader { trace, pos: 0 };
+    if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(Error::ParseError("trace", "missing header".to_string()));
+    }
+    let unknown = |name: &str| Error::ParseError("trace", format!("unknown setting {:?}", name));
+    let utf8 = reader.u8()? != 0;
+    let byte_eof = reader.u64()? as i64;
+    let int_eof = match reader.u8()? {
+        0 => IntEof::Zero,
+        1 => IntEof::MinusOne,
+        _ => IntEof::Unchanged,
+    };
+    let name = reader.name()?;
+    let dialect = Dialect::from_name(name).ok_or_else(|| unknown(name))?;
+    let name = reader.name()?;
+    let wrapping = Wrapping::from_name(name).ok_or_else(|| unknown(name))?;
+    let name = reader.name()?;
+    let value_type = ValueType::from_name(name).ok_or_else(|| unknown(name))?;
+    let name = reader.name()?;
+    let memory_model = MemoryModel::from_name(name).ok_or_else(|| unknown(name))?;
+    let ip_count = reader.u32()? as usize;
+    if !(1..=256).contains(&ip_count) {
+        return Err(Error::ParseError("trace", format!("invalid IP count {}", ip_count)));
+    }
+    let random_seed = reader.u64()?;
+    let settings = Settings { utf8, byte_eof, int_eof, dialect, wrapping, ip_count, value_type, memory_model, random_seed };
+    let len = reader.u32()? as usize;
+    let src = str::from_utf8(reader.take(len)?).map_err(|_| Error::ParseError("trace", "grid isn't UTF-8".to_string()))?;
+    let grid = Grid::parse_with(src, SourceFormat::Linear, dialect, usize::MAX, |_| false)?;
+    let mut records = Vec::new();
+    while let Some(record) = reader.record()? {
+        records.push(record);
+    }
+    Ok((grid, settings, records))
+}
+
+/// Re-executes a program from a trace written by `Builder::record_trace`, feeding it the recorded
+/// input and checking that every tick matches the trace.
+///
+/// Returns the number of ticks replayed, or `Error::TraceMismatch` at the first tick that differs.
+/// A trace that ends without the program terminating, like one of a program that was interrupted
+/// or failed, is replayed up to its last tick.
+pub fn replay(trace: &[u8]) -> Result<u64, Error> {
+    let (grid, settings, records) = parse(trace)?;
+    let input: Vec<u8> = records.iter().flat_map(|record| match record {
+        Record::Input { bytes, .. } => bytes.as_slice(),
+        _ => &[],
+    }).copied().collect();
+    let mut hex = Builder::from_grid(grid)
+        .input(Cursor::new(input))
+        .output(io::sink())
+        .utf8(settings.utf8)
+        .byte_eof(settings.byte_eof)
+        .int_eof(settings.int_eof)
+        .wrapping(settings.wrapping)
+        .ip_count(settings.ip_count)
+        .value_type(settings.value_type)
+        .memory_model(settings.memory_model)
+        .random_seed(settings.random_seed)
+        .build()?;
+    let mut ticks = 0;
+    let mut tick = hex.tick.to_integer();
+    let mut terminated = false;
+    for record in records {
+        match record {
+            Record::Tick { .. } if terminated => return Err(mismatch(&tick, "the program terminated but the trace continues")),
+            Record::Tick { ip_idx, coords, dir, op, value } => {
+                let ip = hex.ips[hex.ip_idx];
+                let actual = char::from(hex.grid.get(ip.coords).0);
+                if (hex.ip_idx, ip.coords, ip.dir, actual) != (ip_idx, coords, dir, op) {
+                    return Err(mismatch(&hex.tick.to_integer(), &format!(
+                        "expected IP {} at {} moving {} to execute `{}`, but IP {} at {} moving {} executes `{}`",
+                        ip_idx, coords, dir, op, hex.ip_idx, ip.coords, ip.dir, actual,
+                    )));
+                }
+                tick = hex.tick.to_integer();
+                terminated = hex.step()?;
+                let actual = hex.rt.mem.get().to_u64_wrapping();
+                if actual != value {
+                    return Err(mismatch(&tick, &format!(
+                        "expected the memory edge to end with {:#x}, but it ends with {:#x}", value, actual,
+                    )));
+                }
+                ticks += 1;
+            }
+            Record::Input { bytes, eof } => {
+                let (actual, actual_eof) = hex.rt.last_input();
+                if (actual, actual_eof) != (bytes.as_slice(), eof) {
+                    return Err(mismatch(&tick, &format!("expected to read {:?}, but read {:?}", bytes, actual)));
+                }
+            }
+            Record::End if !terminated => return Err(mismatch(&tick, "the trace ends with termination but the program continues")),
+            Record::End => (),
+        }
+    }
+    Ok(ticks)
+}
+
+/// Returns a mismatch error for the given tick.
+fn mismatch(tick: &Integer, msg: &str) -> Error {
+    Error::TraceMismatch(format!("tick {}: {}", tick, msg))
+}
+
+/// The tick to start from when tracing a path backwards with `backtrack`.
+#[derive(Clone, Copy, Debug)]
+pub enum Target {
+    /// The last tick in the trace
+    Last,
+    /// The tick with the given number
+    Tick(u64),
+    /// The last tick on which the active IP was at the given cell
+    Cell(PointAxial),
+}
+
+/// One step of the path that led an IP to a cell, as found by `backtrack`.
+#[derive(Clone, Debug)]
+pub enum Hop {
+    /// The IP moved in a straight line between two ticks
+    Straight { ticks: (u64, u64), from: PointAxial, to: PointAxial, dir: Direction },
+    /// A mirror or branch changed the IP's direction, which it would also have done
+    /// for the other incoming directions in `also_from`
+    Redirect { tick: u64, coords: PointAxial, redirect: Redirect, from: Direction, to: Direction, also_from: Vec<Direction> },
+    /// The IP left the grid and wrapped around to the other side
+    Wrap { tick: u64, from: PointAxial, to: PointAxial, dir: Direction },
+    /// The IP skipped a cell with `$`
+    Jump { tick: u64, from: PointAxial, to: PointAxial },
+    /// The IP got control back from another IP
+    Switch { tick: u64, from_ip: usize },
+    /// The IP's first tick
+    Start { tick: u64, coords: PointAxial, dir: Direction },
+}
+
+/// Traces the path of the IP that was active at the target tick backwards through a trace
+/// written by `Builder::record_trace`, summarizing it as the redirects, wraps and switches
+/// between IPs that led it there, most recent first.
+///
+/// At most `max_hops` hops are returned. Returns the index of the IP and its path, or
+/// `Error::ParseError` if the trace doesn't contain the target.
+pub fn backtrack(trace: &[u8], target: Target, max_hops: usize) -> Result<(usize, Vec<Hop>), Error> {
+    let (_, _, records) = parse(trace)?;
+    let ticks: Vec<_> = records.into_iter().filter_map(|record| match record {
+        Record::Tick { ip_idx, coords, dir, op, .. } => Some((ip_idx, coords, dir, op)),
+        _ => None,
+    }).collect();
+    let missing = || Error::ParseError("trace", format!("no tick matches {:?}", target));
+    let target = match target {
+        Target::Last => ticks.len().checked_sub(1),
+        Target::Tick(tick) => Some(tick as usize).filter(|&t| t < ticks.len()),
+        Target::Cell(cell) => ticks.iter().rposition(|&(_, coords, _, _)| coords == cell),
+    }.ok_or_else(missing)?;
+    let ip = ticks[target].0;
+    let own: Vec<usize> = (0..=target).filter(|&t| ticks[t].0 == ip).collect();
+    let mut hops = Vec::new();
+    let mut run_end = target;
+    for i in (1..own.len()).rev() {
+        if hops.len() >= max_hops {
+            hops.truncate(max_hops);
+            return Ok((ip, hops));
+        }
+        let (prev, cur) = (own[i - 1], own[i]);
+        let ((_, p_coords, p_dir, p_op), (_, c_coords, c_dir, _)) = (ticks[prev], ticks[cur]);
+        let mut events = Vec::new();
+        if cur != prev + 1 {
+            events.push(Hop::Switch { tick: cur as u64, from_ip: ticks[cur - 1].0 });
+        }
+        if p_op == '$' {
+            events.push(Hop::Jump { tick: prev as u64, from: p_coords, to: c_coords });
+        } else if c_coords != p_coords + c_dir.to_vector() {
+            events.push(Hop::Wrap { tick: prev as u64, from: p_coords, to: c_coords, dir: c_dir });
+        }
+        if p_dir != c_dir {
+            if let Some(Op::Redir(redir)) = Op::from_char(p_op) {
+                // Only one sign of the memory edge can have produced the new direction
+                let positive = redirect(p_dir, redir, true) == c_dir;
+                let also_from = redirect_sources(c_dir, redir, positive).into_iter().filter(|&d| d != p_dir).collect();
+                events.push(Hop::Redirect { tick: prev as u64, coords: p_coords, redirect: redir, from: p_dir, to: c_dir, also_from });
+            }
+        }
+        if !events.is_empty() {
+            if cur != run_end {
+                let (from, to) = (c_coords, ticks[run_end].1);
+                hops.push(Hop::Straight { ticks: (cur as u64, run_end as u64), from, to, dir: c_dir });
+            }
+            hops.extend(events);
+            run_end = prev;
+        }
+    }
+    let (_, coords, dir, _) = ticks[own[0]];
+    if own[0] != run_end {
+        hops.push(Hop::Straight { ticks: (own[0] as u64, run_end as u64), from: coords, to: ticks[run_end].1, dir });
+    }
+    hops.push(Hop::Start { tick: own[0] as u64, coords, dir });
+    hops.truncate(max_hops);
+    Ok((ip, hops))
+}
+
+impl fmt::Display for Hop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hop::Straight { ticks: (start, end), from, to, dir } =>
+                write!(f, "ticks {}-{}: moved {} from {} to {}", start, end, dir, from, to),
+            Hop::Redirect { tick, coords, redirect, from, to, also_from } => {
+                write!(f, "tick {}: `{}` at {} turned the IP from {} to {}", tick, redirect, coords, from, to)?;
+                if !also_from.is_empty() {
+                    let dirs: Vec<_> = also_from.iter().map(Direction::to_string).collect();
+                    write!(f, " (as it would have when moving {})", dirs.join(" or "))?;
+                }
+                Ok(())
+            }
+            Hop::Wrap { tick, from, to, dir } => write!(f, "tick {}: wrapped from {} to {} moving {}", tick, from, to, dir),
+            Hop::Jump { tick, from, to } => write!(f, "tick {}: `$` at {} skipped to {}", tick, from, to),
+            Hop::Switch { tick, from_ip } => write!(f, "tick {}: got control from IP {}", tick, from_ip),
+            Hop::Start { tick, coords, dir } => write!(f, "tick {}: started at {} moving {}", tick, coords, dir),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples;
+
+    #[test]
+    fn traces_replay_and_catch_changed_runs() {
+        let mut trace = Vec::new();
+        Builder::new("?)!@").input(&b"41"[..]).output(io::sink()).record_trace(&mut trace).build().unwrap().run().unwrap();
+        assert_eq!(replay(&trace).unwrap(), 4);
+        // Feed the replay different input than the recorded run read
+        let pos = trace.windows(2).rposition(|w| w == b"41").unwrap();
+        trace[pos] = b'5';
+        assert!(matches!(replay(&trace), Err(Error::TraceMismatch(msg)) if msg.starts_with("tick 0: ")));
+        assert!(replay(&trace[..pos]).is_err());
+    }
+
+    #[test]
+    fn backtracking_finds_the_redirects_and_wraps_to_a_cell() {
+        let mut trace = Vec::new();
+        let cat = examples::find("cat").unwrap().source();
+        Builder::new(cat).input(&b"a"[..]).output(io::sink()).record_trace(&mut trace).build().unwrap().run().unwrap();
+        let (ip, hops) = backtrack(&trace, Target::Last, 20).unwrap();
+        assert_eq!((ip, hops.len()), (0, 10));
+        assert!(matches!(hops[0], Hop::Wrap { tick: 9, from: PointAxial(0, -1), to: PointAxial(-1, 1), dir: Direction::NorthWest }));
+        assert!(matches!(hops[1], Hop::Straight { ticks: (7, 9), from: PointAxial(0, 1), to: PointAxial(0, -1), .. }));
+        assert!(matches!(
+            hops[8],
+            Hop::Redirect { tick: 0, redirect: Redirect::MirrorBack, from: Direction::East, to: Direction::SouthWest, .. }
+        ));
+        assert!(matches!(hops[9], Hop::Start { tick: 0, coords: PointAxial(0, -1), dir: Direction::East }));
+        assert_eq!(backtrack(&trace, Target::Cell(PointAxial(1, 0)), 3).unwrap().1.len(), 3);
+        assert!(matches!(backtrack(&trace, Target::Tick(100), 10), Err(Error::ParseError("trace", _))));
+    }
+}
@@ -0,0 +1,68 @@
+use crate::{Builder, Error, IP, Wrapping, analysis::reachable_cells, coords::PointAxial, grid::{Grid, Op, cell_source}};
+
+/// A program with its trailing no-ops removed by `trim`.
+pub struct Trimmed {
+    pub grid: Grid,
+    /// Removed cells of the original program that an IP can reach, in row-major order
+    pub reached: Vec<PointAxial>,
+}
+
+/// Removes the no-ops after the last command of a program, laying it out in the smallest hexagon
+/// that fits the rest. No-ops with a debug flag count as commands.
+///
+/// The commands keep their order, but move to other coordinates if the hexagon gets smaller.
+/// If it does, removed cells that an IP can reach in the original program, assuming every memory-dependent choice
+/// can go either way, are returned in `Trimmed::reached`, since the program may rely on them to route its IPs.
+/// Use `Builder::trim` to follow another wrapping rule or other IPs.
+pub fn trim(src: &str) -> Result<Trimmed, Error> {
+    Builder::new(src).trim()
+}
+
+/// Trims a program, as described by `trim`, whose IPs follow the given wrapping rule from `starts`.
+pub(crate) fn trim_grid(grid: Grid, wrapping: Wrapping, starts: &[IP]) -> Result<Trimmed, Error> {
+    let cells: Vec<_> = grid.cells().collect();
+    let len = cells.iter().rposition(|&(_, op, debug)| op != Op::Nop || debug).map_or(0, |i| i + 1);
+    let code: String = cells[..len].iter()
+        .map(|&(_, op, debug)| cell_source(op, debug))
+        .collect();
+    let trimmed: Grid = code.parse()?;
+    // A hexagon of the same size pads the program with the same no-ops, so nothing moves
+    if trimmed.size() == grid.size() {
+        return Ok(Trimmed { grid, reached: Vec::new() });
+    }
+    let reachable = reachable_cells(&grid, wrapping, starts);
+    let reached = cells[len..].iter()
+        .map(|&(coords, _, _)| coords)
+        .filter(|coords| reachable.contains(coords))
+        .collect();
+    Ok(Trimmed { grid: trimmed, reached })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trimming_keeps_same_size_grids_and_shrinks_the_rest() {
+        // The trailing no-op is padding of a hexagon of the same size, so nothing changes
+        let trimmed = trim("1!@.").unwrap();
+        assert_eq!(trimmed.grid.to_string(), "  1 !\n @ . .\n  . .\n");
+        assert!(trimmed.reached.is_empty());
+        // Commands move to other cells of the smaller hexagon, such as `@` from the end of the top row
+        let trimmed = trim(&format!("1!@{}", ".".repeat(16))).unwrap();
+        assert_eq!(trimmed.grid.size(), 2);
+        assert_eq!(trimmed.grid.find(Op::Terminate).collect::<Vec<_>>(), [PointAxial(-1, 0)]);
+        assert!(trimmed.reached.is_empty());
+    }
+
+    #[test]
+    fn trimming_reports_removed_cells_that_ips_reach() {
+        // Without a `@`, the IP runs through the removed rows below the top one
+        let trimmed = trim(&format!(")!{}", ".".repeat(17))).unwrap();
+        assert_eq!(trimmed.grid.to_linear(), ")!");
+        assert_eq!(trimmed.reached, [
+            PointAxial(2, -2), PointAxial(-2, 0), PointAxial(-1, 0), PointAxial(0, 0), PointAxial(1, 0), PointAxial(2, 0),
+            PointAxial(-2, 2), PointAxial(-1, 2), PointAxial(0, 2),
+        ]);
+    }
+}
@@ -0,0 +1,225 @@
+use std::process::{Command, Stdio};
+
+#[cfg(unix)]
+#[test]
+fn closed_pipes_exit_like_sigpipe() {
+    // Grids this large don't fit in a pipe's buffer, so printing them outlives the reader
+    for args in &[&["-g", "300"][..], &["-g", "300", "--rulers"]] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_hexagony"))
+            .args(*args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        drop(child.stdout.take());
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(output.status.code(), Some(141), "{:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+        assert!(output.stderr.is_empty());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn ctrl_c_interrupts_a_program_waiting_for_input() {
+    let path = std::env::temp_dir().join(format!("hexagony-interrupt-{}.hxg", std::process::id()));
+    std::fs::write(&path, ",!@").unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hexagony"))
+        .arg("--dump-on-interrupt")
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Keep the input open, so the program blocks reading it until Ctrl-C
+    let stdin = child.stdin.take();
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGINT);
+    }
+    let output = child.wait_with_output().unwrap();
+    drop(stdin);
+    std::fs::remove_file(&path).unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(130), "{}", stderr);
+    assert!(stderr.contains("Interrupted"), "{}", stderr);
+    assert!(output.stdout.is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn sigusr1_dumps_the_state_without_stopping() {
+    let path = std::env::temp_dir().join(format!("hexagony-dump-{}.hxg", std::process::id()));
+    // Increments the memory edge forever
+    std::fs::write(&path, ")").unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hexagony"))
+        .arg(&path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGUSR1);
+    }
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    assert!(child.try_wait().unwrap().is_none(), "the program stopped after the dump");
+    child.kill().unwrap();
+    let output = child.wait_with_output().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Tick "), "{}", stderr);
+    assert!(stderr.contains("! 0: (0, 0), E (next command `)`)"), "{}", stderr);
+    assert!(stderr.contains("Memory: "), "{}", stderr);
+}
+
+#[test]
+fn followed_ips_must_exist() {
+    let path = std::env::temp_dir().join(format!("hexagony-follow-{}.hxg", std::process::id()));
+    std::fs::write(&path, "@").unwrap();
+    let run = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_hexagony")).args(args).arg(&path).output().unwrap();
+    let output = run(&["--follow-ip", "6"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("The IP to follow must be from 0 to 5"));
+    assert!(run(&["--ips", "8", "--follow-ip", "7"]).status.success());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn followed_ips_show_only_their_ticks() {
+    let path = std::env::temp_dir().join(format!("hexagony-follow-ticks-{}.hxg", std::process::id()));
+    std::fs::write(&path, "])[@").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_hexagony")).args(["-e", "--follow-ip", "1"]).arg(&path).output().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let expected = "-- IP 1 takes control at tick 1 --\n\
+                    Tick 1: IP 1 at (1, -1): `)`: increment current edge from 0 to 1\n\
+                    Tick 2: IP 1 at (1, 0): `.`: do nothing\n\
+                    Tick 3: IP 1 at (0, -1): `]`: switch to next IP 2\n\
+                    -- IP 1 hands control to IP 2 at tick 3 --\n";
+    assert_eq!(String::from_utf8_lossy(&output.stderr), expected);
+}
+
+#[cfg(unix)]
+#[test]
+fn isolated_programs_stop_at_the_output_limit_on_a_pipe() {
+    let path = std::env::temp_dir().join(format!("hexagony-isolate-{}.hxg", std::process::id()));
+    // Prints 0 forever
+    std::fs::write(&path, "!").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_hexagony"))
+        .args(["--isolate", "--max-output", "5"])
+        .arg(&path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert_eq!(output.stdout, b"00000");
+    assert!(stderr.contains("Output limit exceeded"), "{}", stderr);
+}
+
+#[cfg(unix)]
+#[test]
+fn output_can_go_to_an_inherited_descriptor() {
+    let path = std::env::temp_dir().join(format!("hexagony-output-fd-{}.hxg", std::process::id()));
+    std::fs::write(&path, "12!@").unwrap();
+    let run = |fd: &str| Command::new(env!("CARGO_BIN_EXE_hexagony")).args(["--output-fd", fd]).arg(&path).output().unwrap();
+    let output = run("1");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(output.stdout, b"12");
+    let output = run("1000");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("File descriptor 1000 is not open"));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn debugger_lists_ip_statistics() {
+    use std::io::Write;
+    let path = std::env::temp_dir().join(format!("hexagony-debugger-{}.hxg", std::process::id()));
+    std::fs::write(&path, "..@").unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hexagony"))
+        .arg("-i")
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"s 2\nips\nq\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("! 0: (-1, 0), E (2 ticks, 2 cells visited)"), "{}", stderr);
+    assert!(stderr.contains("  1: (1, -1), SE (0 ticks, 0 cells visited)"), "{}", stderr);
+}
+
+#[test]
+fn runtime_errors_open_the_debugger() {
+    use std::io::Write;
+    let path = std::env::temp_dir().join(format!("hexagony-debug-on-error-{}.hxg", std::process::id()));
+    std::fs::write(&path, "1:@").unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hexagony"))
+        .arg("--debug-on-error")
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"m\ns\nq\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains("Tick 1: IP 0 at (1, -1) facing E, next command `:`"), "{}", stderr);
+    assert!(stderr.contains("(hxg) (0, 0, E): 1\n"), "{}", stderr);
+    assert!(stderr.contains("(hxg) The program stopped with an error."), "{}", stderr);
+}
+
+#[test]
+fn pipes_connect_the_output_of_one_program_to_the_next() {
+    let dir = std::env::temp_dir();
+    let write = |name: &str, src: &str| {
+        let path = dir.join(format!("hexagony-pipe-{}-{}.hxg", name, std::process::id()));
+        std::fs::write(&path, src).unwrap();
+        path
+    };
+    // Prints 12, and a program that adds 1 to the number it reads
+    let (counter, increment) = (write("counter", "1!)!@"), write("increment", "?)!@"));
+    let run = |a: &std::path::Path, args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_hexagony")).arg("pipe").args(args).arg(a).arg(&increment).stdin(Stdio::null()).output().unwrap()
+    };
+    let output = run(&counter, &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(output.stdout, b"13");
+    // A program that never writes anything is stopped, and then reads as EOF
+    let looping = write("looping", ")");
+    let output = run(&looping, &["--max-ticks", "100"]);
+    assert!(!output.status.success());
+    assert_eq!(output.stdout, b"1");
+    for path in &[counter, increment, looping] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn rulers_number_the_columns_around_templates_and_formatted_programs() {
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_hexagony")).args(args).output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let template = run(&["-g", "6", "--rulers"]);
+    let lines: Vec<_> = template.lines().collect();
+    assert_eq!(lines.len(), 15);
+    assert_eq!(&lines[..3], ["      0         1         2", "      012345678901234567890", "r=-5       . . . . . .        q=0..5"]);
+    assert_eq!(&lines[13..], &lines[..2]);
+    // Debug flags stay on their cells, and the q ranges keep their dots
+    let path = std::env::temp_dir().join(format!("hexagony-rulers-{}.hxg", std::process::id()));
+    std::fs::write(&path, "1`!@").unwrap();
+    let formatted = run(&["fmt", "--rulers", path.to_str().unwrap()]);
+    assert_eq!(formatted, "      01234\nr=-1   1`!    q=0..1\nr=0   @ . .   q=-1..1\nr=1    . .    q=-1..0\n      01234\n");
+    std::fs::remove_file(&path).unwrap();
+}
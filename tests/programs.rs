@@ -0,0 +1,3 @@
+fn main() {
+    hexagony::harness::Harness::new().dir("tests/programs").max_ticks(100_000).run();
+}